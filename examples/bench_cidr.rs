@@ -0,0 +1,59 @@
+//! 对比判断IP网段的耗时：每次调用都重新编译正则（旧的写法） vs patterns 中只编译一次的正则
+//!
+//! 运行：cargo run --release --example bench_cidr [行数]
+
+#[allow(dead_code)]
+#[path = "../src/build/patterns.rs"]
+mod patterns;
+
+use regex::Regex;
+use std::time::Instant;
+
+/// 旧的写法：每判断一行都重新编译两个正则
+fn is_cidr_recompile(s: &str) -> bool {
+    let re_ipv4 = Regex::new(patterns::RE_IPV4_CIDR.as_str()).unwrap();
+    let re_ipv6 = Regex::new(patterns::RE_IPV6_CIDR.as_str()).unwrap();
+    re_ipv4.is_match(s) || re_ipv6.is_match(s)
+}
+
+fn is_cidr_static(s: &str) -> bool {
+    patterns::RE_IPV4_CIDR.is_match(s) || patterns::RE_IPV6_CIDR.is_match(s)
+}
+
+/// 模拟规则文件的内容：IPv4、IPv6 网段和域名各占一部分
+fn sample_lines(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| match i % 3 {
+            0 => format!("{}.{}.{}.0/24", i % 223 + 1, i / 256 % 256, i % 256),
+            1 => format!("2001:db8:{:x}::/48", i % 65536),
+            _ => format!("domain{}.example.com", i),
+        })
+        .collect()
+}
+
+fn bench(name: &str, lines: &[String], f: fn(&str) -> bool) {
+    let start = Instant::now();
+    let matched = lines.iter().filter(|line| f(line)).count();
+    let elapsed = start.elapsed();
+    println!(
+        "{:<10} {:>8} 行，匹配 {:>8} 行，耗时 {:>10.2?}，每行 {:>8.2?}",
+        name,
+        lines.len(),
+        matched,
+        elapsed,
+        elapsed / lines.len().max(1) as u32
+    );
+}
+
+fn main() {
+    let count: usize = std::env::args()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(2_000);
+    let lines = sample_lines(count);
+
+    // 先初始化静态正则，不计入耗时
+    is_cidr_static("1.1.1.1/32");
+    bench("recompile", &lines, is_cidr_recompile);
+    bench("static", &lines, is_cidr_static);
+}
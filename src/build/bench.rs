@@ -0,0 +1,152 @@
+use crate::{
+    build::{
+        download, ini as MyIni,
+        providers::Behavior,
+        rules::{self, RulesOptions},
+        sort as MySort,
+    },
+    utils::alloc::{self, AllocStats},
+};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// 一个阶段多次运行的统计
+#[derive(Debug, Serialize)]
+pub struct StageStats {
+    pub stage: &'static str,
+    pub items: usize, // 这个阶段输出的数量：规则集、规则数或字节数
+    #[serde(serialize_with = "serialize_millis")]
+    pub runs: Vec<Duration>,
+    pub alloc: AllocStats, // 最后一次运行的分配统计
+}
+
+fn serialize_millis<S: serde::Serializer>(runs: &[Duration], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(runs.iter().map(|d| d.as_secs_f64() * 1000.0))
+}
+
+impl StageStats {
+    fn new(stage: &'static str) -> Self {
+        StageStats {
+            stage,
+            items: 0,
+            runs: Vec::new(),
+            alloc: AllocStats::default(),
+        }
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.runs.iter().sum::<Duration>() / self.runs.len().max(1) as u32
+    }
+
+    pub fn min(&self) -> Duration {
+        self.runs.iter().min().copied().unwrap_or_default()
+    }
+}
+
+/// 运行一个阶段，记录耗时和分配统计
+fn measure<T>(stats: &mut StageStats, f: impl FnOnce() -> T) -> T {
+    alloc::reset();
+    let start = Instant::now();
+    let result = f();
+    stats.runs.push(start.elapsed());
+    stats.alloc = alloc::snapshot();
+    result
+}
+
+/// 读取到的规则集：(在ini中的序号, 代理组名称, 声明的类型, 规则文本)
+type Loaded = Vec<(usize, String, Option<Behavior>, String)>;
+
+/// 只从本地读取规则：网络规则集读取之前下载保存的文件（不联网），返回读取到的规则集和缺少的规则集
+fn load_rules(ruleset: &[MyIni::RuleSet], options: &RulesOptions) -> (Loaded, Vec<String>) {
+    let save_dir = Path::new(&options.save_rules_dir);
+    let mut loaded = Vec::new();
+    let mut missing = Vec::new();
+    for (index, item) in ruleset.iter().enumerate() {
+        let (path, data) = if !item.net_rule_path.is_empty() {
+            let data = fs::read(save_dir.join(download::cache_file_name(&item.net_rule_path)))
+                .or_else(|_| fs::read(save_dir.join(download::url_file_name(&item.net_rule_path))));
+            (&item.net_rule_path, data)
+        } else if !item.local_rule_path.is_empty() {
+            (&item.local_rule_path, fs::read(&item.local_rule_path))
+        } else {
+            continue;
+        };
+        match data {
+            Ok(data) => loaded.push((
+                index,
+                item.rule_name.clone(),
+                item.rule_type,
+                rules::rules_text(path, data),
+            )),
+            Err(_) => missing.push(path.clone()),
+        }
+    }
+    (loaded, missing)
+}
+
+/// 分阶段运行规则的处理流程：读取（只用本地缓存）、解析、排序去重、序列化，每个阶段运行 runs 次
+///
+/// 返回每个阶段的统计，以及没有缓存的规则集
+pub fn bench_rules(
+    ruleset: &[MyIni::RuleSet],
+    options: &RulesOptions,
+    runs: usize,
+) -> (Vec<StageStats>, Vec<String>) {
+    let mut load = StageStats::new("load");
+    let mut parse = StageStats::new("parse");
+    let mut sort = StageStats::new("sort");
+    let mut serialize = StageStats::new("serialize");
+    let mut missing = Vec::new();
+
+    for _ in 0..runs.max(1) {
+        let (loaded, not_found) = measure(&mut load, || load_rules(ruleset, options));
+        load.items = loaded.len();
+        missing = not_found;
+
+        let parsed: Vec<String> = measure(&mut parse, || {
+            let mut per_ruleset: Vec<(usize, Vec<String>)> = loaded
+                .par_iter()
+                .map(|(index, name, behavior, text)| {
+                    let lines = text
+                        .par_lines()
+                        .map(|line| {
+                            rules::format_rules(
+                                line.to_string(),
+                                name,
+                                *behavior,
+                                &options.rule_filter,
+                            )
+                        })
+                        .filter(|rule| !rule.is_empty())
+                        .collect();
+                    (*index, lines)
+                })
+                .collect();
+            per_ruleset.sort_by_key(|(index, _)| *index);
+            per_ruleset
+                .into_iter()
+                .flat_map(|(_, lines)| lines)
+                .collect()
+        });
+        parse.items = parsed.len();
+        drop(loaded);
+
+        let sorted = measure(&mut sort, || {
+            if options.keep_rule_order {
+                rules::optimize_rules(MySort::dedup_rules(parsed), options)
+            } else {
+                MySort::sort_rules(rules::optimize_rules(parsed, options))
+            }
+        });
+        sort.items = sorted.len();
+
+        let yaml = measure(&mut serialize, || rules::rules_to_yaml(sorted));
+        serialize.items = yaml.len();
+    }
+    (vec![load, parse, sort, serialize], missing)
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+/// 以 start 开头、大小为 2^size 的块的最后一个地址
+fn block_last(start: u128, size: u32) -> u128 {
+    if size >= 128 {
+        u128::MAX
+    } else {
+        start + ((1u128 << size) - 1)
+    }
+}
+
+/// 将IP范围拆分为最少数量的CIDR，返回 (起始地址, 前缀长度)
+pub fn range_to_cidrs(from: u128, to: u128, bits: u32) -> Vec<(u128, u32)> {
+    let mut result = Vec::new();
+    let mut start = from;
+    loop {
+        // start 对齐允许的最大块，同时不能超出 to
+        let mut size = start.trailing_zeros().min(bits);
+        while size > 0 && block_last(start, size) > to {
+            size -= 1;
+        }
+        result.push((start, bits - size));
+
+        let last = block_last(start, size);
+        if last >= to {
+            break;
+        }
+        start = last + 1;
+    }
+    result
+}
+
+/// 解析 "1.2.3.0/24"，返回 (是否IPv6, 起始地址, 结束地址)
+pub fn cidr_range(cidr: &str) -> Option<(bool, u128, u128)> {
+    let (ip, prefix) = cidr.split_once('/')?;
+    let prefix: u32 = prefix.parse().ok()?;
+    let (is_v6, addr, bits) = match ip.parse::<IpAddr>().ok()? {
+        IpAddr::V4(v4) => (false, u32::from(v4) as u128, 32),
+        IpAddr::V6(v6) => (true, u128::from(v6), 128),
+    };
+    if prefix > bits {
+        return None;
+    }
+    let host_bits = bits - prefix;
+    let host_mask = if host_bits >= 128 {
+        u128::MAX
+    } else {
+        (1u128 << host_bits) - 1
+    };
+    let start = addr & !host_mask;
+    Some((is_v6, start, start | host_mask))
+}
+
+/// 合并后规则的位置：原样保留的规则，或者合并后的某一组IP规则
+enum Slot {
+    Rule(String),
+    Merged(usize),
+}
+
+/// 可以合并的IP规则：(规则类型, 是否IPv6, 代理组及后面的参数)
+type RuleKey = (String, bool, String);
+
+/// 已经出现的IP规则：(是否IPv6, 起始地址, 结束地址) => [(规则的位置, 合并的分组)]
+type SeenRanges = BTreeMap<(bool, u128, u128), Vec<(usize, usize)>>;
+
+/// 是否有其它分组的规则在 since 之后出现、并且跟这个网段有重叠（CIDR之间只有包含或者不相交两种关系）
+fn separated(seen: &SeenRanges, range: (bool, u128, u128), since: usize, group: usize) -> bool {
+    let (is_v6, start, end) = range;
+    let bits = if is_v6 { 128 } else { 32 };
+    let conflicts = |entries: &Vec<(usize, usize)>| {
+        entries
+            .iter()
+            .any(|&(position, other)| position > since && other != group)
+    };
+    // 包含这个网段的（更短的前缀）
+    let prefix = bits - (end - start).count_ones();
+    let containing = (0..prefix).any(|p| {
+        let host_mask = block_last(0, bits - p);
+        seen.get(&(is_v6, start & !host_mask, start | host_mask))
+            .is_some_and(conflicts)
+    });
+    // 这个网段包含的（包括相同的）
+    containing
+        || seen
+            .range((is_v6, start, start)..=(is_v6, end, end))
+            .any(|(&(_, _, e), entries)| e <= end && conflicts(entries))
+}
+
+/// 合并IP规则：同一代理组（且参数相同）的 IP-CIDR/IP-CIDR6 规则，相邻或者包含的网段合并为更少的CIDR
+///
+/// 其它规则保持不变，合并后的规则放在该组第一条IP规则所在的位置；
+/// 中间有其它代理组的规则跟后面的网段重叠时，前移会改变匹配结果，这样的网段不合并到前面的组中（另起一组）。
+pub fn merge_cidr_rules(rules: Vec<String>) -> Vec<String> {
+    // 合并的每一组的所有网段
+    let mut groups: Vec<(RuleKey, Vec<(u128, u128)>)> = Vec::new();
+    // 所有的分组：(分组, 分组所在的位置)
+    let mut runs: HashMap<RuleKey, Vec<(usize, usize)>> = HashMap::new();
+    let mut seen: SeenRanges = BTreeMap::new();
+    let mut slots: Vec<Slot> = Vec::with_capacity(rules.len());
+
+    for rule in rules {
+        let mut parts = rule.splitn(3, ',');
+        let kind = parts.next().unwrap_or("");
+        let cidr = parts.next().unwrap_or("");
+        let rest = parts.next().unwrap_or("");
+        let parsed = (kind == "IP-CIDR" || kind == "IP-CIDR6")
+            .then(|| cidr_range(cidr))
+            .flatten();
+        let Some((is_v6, start, end)) = parsed else {
+            slots.push(Slot::Rule(rule));
+            continue;
+        };
+        let key = (kind.to_string(), is_v6, rest.to_string());
+        // 合并到最前面的、前移不会越过重叠规则的分组，合并的规则移到分组所在的位置
+        let key_runs = runs.entry(key.clone()).or_default();
+        let found = key_runs
+            .iter()
+            .copied()
+            .find(|&(index, since)| !separated(&seen, (is_v6, start, end), since, index));
+        let (index, position) = found.unwrap_or_else(|| {
+            let (index, position) = (groups.len(), slots.len());
+            groups.push((key, Vec::new()));
+            key_runs.push((index, position));
+            slots.push(Slot::Merged(index));
+            (index, position)
+        });
+        groups[index].1.push((start, end));
+        seen.entry((is_v6, start, end))
+            .or_default()
+            .push((position, index));
+    }
+
+    let mut merged: Vec<Vec<String>> = groups
+        .into_iter()
+        .map(|((kind, is_v6, rest), ranges)| {
+            let bits = if is_v6 { 128 } else { 32 };
+            merge_ranges(ranges)
+                .into_iter()
+                .flat_map(|(from, to)| range_to_cidrs(from, to, bits))
+                .map(|(start, prefix)| {
+                    let ip = if is_v6 {
+                        IpAddr::V6(Ipv6Addr::from(start))
+                    } else {
+                        IpAddr::V4(Ipv4Addr::from(start as u32))
+                    };
+                    if rest.is_empty() {
+                        format!("{},{}/{}", kind, ip, prefix)
+                    } else {
+                        format!("{},{}/{},{}", kind, ip, prefix, rest)
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    slots
+        .into_iter()
+        .flat_map(|slot| match slot {
+            Slot::Rule(rule) => vec![rule],
+            Slot::Merged(index) => std::mem::take(&mut merged[index]),
+        })
+        .collect()
+}
+
+/// 合并重叠或相邻的范围
+fn merge_ranges(mut ranges: Vec<(u128, u128)>) -> Vec<(u128, u128)> {
+    ranges.sort_unstable();
+    let mut result: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match result.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => result.push((start, end)),
+        }
+    }
+    result
+}
@@ -0,0 +1,55 @@
+use crate::build::conflict::{domain_suffixes, parse_domain_rule};
+use std::collections::{HashMap, HashSet};
+
+/// 精简域名规则：
+/// - 去掉已经被同一代理组的 DOMAIN-SUFFIX 覆盖的 DOMAIN 和下级 DOMAIN-SUFFIX 规则
+/// - 合并重复的后缀（大小写不同、带前导点号），只保留第一条
+///
+/// 如果某个域名在不同的代理组之间有冲突，就不精简它，避免改变匹配结果。
+pub fn compact_domain_rules(rules: Vec<String>) -> Vec<String> {
+    // 域名 => 指向的所有代理组
+    let mut suffix_groups: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut domain_groups: HashMap<String, HashSet<String>> = HashMap::new();
+    for rule in &rules {
+        if let Some((kind, domain, group)) = parse_domain_rule(rule) {
+            let groups = if kind == "DOMAIN" {
+                &mut domain_groups
+            } else {
+                &mut suffix_groups
+            };
+            groups.entry(domain).or_default().insert(group.to_string());
+        }
+    }
+
+    let mut seen: HashSet<(String, String, String)> = HashSet::new();
+    rules
+        .into_iter()
+        .filter(|rule| {
+            let Some((kind, domain, group)) = parse_domain_rule(rule) else {
+                return true;
+            };
+            if !seen.insert((kind.to_string(), domain.clone(), group.to_string())) {
+                return false; // 重复的规则
+            }
+
+            // 能匹配到该域名的其它规则所指向的代理组
+            let is_domain = kind == "DOMAIN";
+            let mut covered = false;
+            let mut groups: HashSet<&str> = HashSet::new();
+            if is_domain {
+                groups.extend(domain_groups[&domain].iter().map(String::as_str));
+            }
+            for suffix in domain_suffixes(&domain) {
+                let Some(suffix_group) = suffix_groups.get(suffix) else {
+                    continue;
+                };
+                // DOMAIN-SUFFIX 不能被它自己覆盖
+                if is_domain || suffix != domain {
+                    covered |= suffix_group.contains(group);
+                }
+                groups.extend(suffix_group.iter().map(String::as_str));
+            }
+            !(covered && groups.len() == 1)
+        })
+        .collect()
+}
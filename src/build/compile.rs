@@ -0,0 +1,191 @@
+use crate::{build::mrs, utils::i18n::tr};
+use indexmap::IndexMap;
+use std::collections::HashSet;
+
+/// 域名规则编译成的二进制格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainFormat {
+    Mrs,     // 每个代理组一个 mihomo 的 mrs 规则集，规则使用 RULE-SET 引用
+    Geosite, // 所有代理组放入一个 geosite.dat，每个代理组一个标签，规则使用 GEOSITE 引用
+}
+
+/// 解析 --compile-domains 参数
+pub fn parse_domain_format(s: &str) -> Result<DomainFormat, String> {
+    match s.trim().to_lowercase().as_str() {
+        "mrs" => Ok(DomainFormat::Mrs),
+        "geosite" => Ok(DomainFormat::Geosite),
+        _ => Err(tr!(
+            "无效的格式：{}，可选 mrs、geosite",
+            "invalid format: {}, expected mrs or geosite",
+            s
+        )),
+    }
+}
+
+/// 一个代理组编译后的域名集合
+#[derive(Debug, Clone)]
+pub struct CompiledSet {
+    pub tag: String,   // mrs 为规则集名称，geosite 为标签
+    pub group: String, // 代理组
+    pub rules: usize,  // 编译进去的规则数
+    domains: Vec<(DomainKind, String)>,
+}
+
+/// geosite 中域名的类型（对应 v2ray 的 Domain.Type）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DomainKind {
+    Keyword = 0, // Plain
+    Regex = 1,
+    Suffix = 2, // Domain(RootDomain)
+    Full = 3,
+}
+
+/// 拆分可以编译的规则为 (域名类型, 内容, 代理组)，mrs 只支持 DOMAIN 和 DOMAIN-SUFFIX
+fn parse_rule(rule: &str, format: DomainFormat) -> Option<(DomainKind, String, &str)> {
+    let mut parts = rule.splitn(3, ',');
+    let kind = match (parts.next()?, format) {
+        ("DOMAIN", _) => DomainKind::Full,
+        ("DOMAIN-SUFFIX", _) => DomainKind::Suffix,
+        ("DOMAIN-KEYWORD", DomainFormat::Geosite) => DomainKind::Keyword,
+        ("DOMAIN-REGEX", DomainFormat::Geosite) => DomainKind::Regex,
+        _ => return None,
+    };
+    let value = parts.next()?.trim();
+    let group = parts.next()?.trim();
+    // 带有其它参数的规则（例如 DOMAIN,a.com,代理组,no-resolve）保持原样
+    if value.is_empty() || group.is_empty() || group.contains(',') {
+        return None;
+    }
+    let value = match kind {
+        DomainKind::Regex => value.to_string(),
+        _ => value.trim_start_matches('.').to_lowercase(),
+    };
+    Some((kind, value, group))
+}
+
+/// 规则集名称/标签：代理组名称中的英文字母和数字，没有时使用 group，重复的加上序号
+fn unique_tag(group: &str, used: &HashSet<String>) -> String {
+    let slug: String = group
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    let base = if slug.is_empty() {
+        "group".to_string()
+    } else {
+        slug
+    };
+    let mut tag = base.clone();
+    let mut n = 1;
+    while used.contains(&tag) {
+        n += 1;
+        tag = format!("{}_{}", base, n);
+    }
+    tag
+}
+
+/// 把域名规则按代理组编译成二进制的域名集合：每个代理组的域名规则替换为一条引用规则
+/// （RULE-SET,标签,代理组 或 GEOSITE,标签,代理组），放在该代理组第一条域名规则的位置
+///
+/// 返回新的规则和编译的集合，集合的数据用 mrs_data、geosite_data 生成
+pub fn compile_domain_rules(
+    rules: Vec<String>,
+    format: DomainFormat,
+) -> (Vec<String>, Vec<CompiledSet>) {
+    let mut result: Vec<String> = Vec::with_capacity(rules.len());
+    // 代理组 => (引用规则的位置, 集合)
+    let mut sets: IndexMap<String, (usize, CompiledSet)> = IndexMap::new();
+    let mut tags: HashSet<String> = HashSet::new();
+    for rule in rules {
+        let Some((kind, value, group)) = parse_rule(&rule, format) else {
+            result.push(rule);
+            continue;
+        };
+        if !sets.contains_key(group) {
+            let tag = unique_tag(group, &tags);
+            tags.insert(tag.clone());
+            sets.insert(
+                group.to_string(),
+                (
+                    result.len(),
+                    CompiledSet {
+                        tag,
+                        group: group.to_string(),
+                        rules: 0,
+                        domains: Vec::new(),
+                    },
+                ),
+            );
+            result.push(String::new()); // 之后替换为引用规则
+        }
+        let (_, set) = sets.get_mut(group).unwrap();
+        set.rules += 1;
+        set.domains.push((kind, value));
+    }
+
+    let rule_type = match format {
+        DomainFormat::Mrs => "RULE-SET",
+        DomainFormat::Geosite => "GEOSITE",
+    };
+    let sets: Vec<CompiledSet> = sets
+        .into_values()
+        .map(|(position, set)| {
+            result[position] = format!("{},{},{}", rule_type, set.tag, set.group);
+            set
+        })
+        .collect();
+    (result, sets)
+}
+
+impl CompiledSet {
+    /// 编码为 mihomo 的 domain 类型 mrs 规则集
+    pub fn mrs_data(&self) -> Vec<u8> {
+        let mut domains = Vec::new();
+        for (kind, value) in &self.domains {
+            domains.push(value.clone());
+            if *kind == DomainKind::Suffix {
+                domains.push(format!("+.{}", value));
+            }
+        }
+        mrs::encode_domain_mrs(&domains, self.rules)
+    }
+}
+
+// ————————————————————————————————————————————————————————————————————————————————————————————————————
+// geosite.dat：protobuf 编码的 GeoSiteList { repeated GeoSite entry = 1; }
+//   GeoSite { string country_code = 1; repeated Domain domain = 2; }
+//   Domain { Type type = 1; string value = 2; }
+// ————————————————————————————————————————————————————————————————————————————————————————————————————
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// 长度前缀的字段（wire type 2）
+fn write_bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(out, (field << 3) | 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// 所有集合编码为一个 geosite.dat，标签为 country_code（大写）
+pub fn geosite_data(sets: &[CompiledSet]) -> Vec<u8> {
+    let mut list = Vec::new();
+    for set in sets {
+        let mut site = Vec::new();
+        write_bytes_field(&mut site, 1, set.tag.to_uppercase().as_bytes());
+        for (kind, value) in &set.domains {
+            let mut domain = Vec::new();
+            write_varint(&mut domain, 1 << 3); // type，varint
+            write_varint(&mut domain, *kind as u64);
+            write_bytes_field(&mut domain, 2, value.as_bytes());
+            write_bytes_field(&mut site, 2, &domain);
+        }
+        write_bytes_field(&mut list, 1, &site);
+    }
+    list
+}
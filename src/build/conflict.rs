@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+/// 规则之间的问题类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    Conflict,   // 同一个域名（或域名后缀）指向不同的代理组
+    Shadowed,   // 被前面更宽泛的 DOMAIN-SUFFIX 规则覆盖，永远不会被匹配到
+    Overridden, // 相同内容的规则指向不同的代理组，按规则集的优先级（--rule-precedence）丢弃
+}
+
+impl IssueKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueKind::Conflict => "conflict",
+            IssueKind::Shadowed => "shadowed",
+            IssueKind::Overridden => "overridden",
+        }
+    }
+}
+
+/// 检测到的一条问题：rule 因为前面的 by 而不会生效
+#[derive(Debug, Clone)]
+pub struct RuleIssue {
+    pub kind: IssueKind,
+    pub rule: String,
+    pub rule_source: Option<usize>, // rule 所属规则集在ini中的序号
+    pub by: String,
+    pub by_source: Option<usize>,
+}
+
+/// 拆分规则为 (类型, 域名, 代理组)，只处理 DOMAIN 和 DOMAIN-SUFFIX
+pub fn parse_domain_rule(rule: &str) -> Option<(&str, String, &str)> {
+    let mut parts = rule.splitn(4, ',');
+    let kind = parts.next()?;
+    if kind != "DOMAIN" && kind != "DOMAIN-SUFFIX" {
+        return None;
+    }
+    let domain = parts.next()?.trim().trim_start_matches('.').to_lowercase();
+    let group = parts.next()?;
+    Some((kind, domain, group))
+}
+
+/// 域名本身以及它的所有上级域名，例如 a.b.com => a.b.com、b.com、com
+pub fn domain_suffixes(domain: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(domain), |d| d.split_once('.').map(|(_, rest)| rest))
+}
+
+/// 按最终规则的顺序（即 clash 的匹配顺序）检测冲突和被覆盖的规则
+///
+/// origins 记录每条规则来自哪个规则集（ini中的序号），用于输出问题的来源。
+pub fn find_rule_issues(rules: &[String], origins: &HashMap<String, usize>) -> Vec<RuleIssue> {
+    // 域名 => 第一次出现的规则
+    let mut domains: HashMap<String, &str> = HashMap::new();
+    let mut suffixes: HashMap<String, &str> = HashMap::new();
+    let mut issues = Vec::new();
+
+    let group_of = |rule: &str| parse_domain_rule(rule).map(|(_, _, group)| group.to_string());
+
+    for rule in rules {
+        let Some((kind, domain, group)) = parse_domain_rule(rule) else {
+            continue;
+        };
+
+        let same_kind = if kind == "DOMAIN" {
+            &domains
+        } else {
+            &suffixes
+        };
+        let conflict = same_kind
+            .get(&domain)
+            .filter(|first| group_of(first).as_deref() != Some(group));
+        // DOMAIN-SUFFIX 自身也算：DOMAIN-SUFFIX,a.com 覆盖 DOMAIN,a.com
+        let shadowed = || {
+            domain_suffixes(&domain)
+                .skip(usize::from(kind == "DOMAIN-SUFFIX"))
+                .find_map(|suffix| suffixes.get(suffix))
+                .filter(|first| group_of(first).as_deref() != Some(group))
+        };
+
+        let found = match conflict {
+            Some(first) => Some((IssueKind::Conflict, *first)),
+            None => shadowed().map(|first| (IssueKind::Shadowed, *first)),
+        };
+        if let Some((issue_kind, by)) = found {
+            issues.push(RuleIssue {
+                kind: issue_kind,
+                rule: rule.clone(),
+                rule_source: origins.get(rule).copied(),
+                by: by.to_string(),
+                by_source: origins.get(by).copied(),
+            });
+        }
+
+        let same_kind = if kind == "DOMAIN" {
+            &mut domains
+        } else {
+            &mut suffixes
+        };
+        same_kind.entry(domain).or_insert(rule);
+    }
+    issues
+}
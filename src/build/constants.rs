@@ -43,3 +43,32 @@ pub const FILTER_KEY: [&str; 7] = [
     "payload:",
     "GEOSITE",
 ];
+
+// mihomo(meta内核)才支持的规则类型，--meta 模式下保留
+pub const META_KEY: [&str; 4] = ["GEOSITE,", "DOMAIN-REGEX,", "IP-ASN,", "DOMAIN-WILDCARD,"];
+
+// Stash 不支持的规则类型，--target stash 时过滤掉
+pub const STASH_DROP_KEY: [&str; 14] = [
+    "DOMAIN-REGEX,",
+    "SRC-GEOIP,",
+    "SRC-IP-ASN,",
+    "SRC-IP-CIDR,",
+    "IP-SUFFIX,",
+    "IN-PORT,",
+    "IN-TYPE,",
+    "IN-USER,",
+    "IN-NAME,",
+    "DSCP,",
+    "UID,",
+    "PROCESS-NAME-REGEX,",
+    "PROCESS-PATH-REGEX,",
+    "SUB-RULE,",
+];
+
+// 内置的特殊策略，不是节点也不是代理组，作为代理组的成员时总是有效的
+pub const SPECIAL_PROXIES: [&str; 5] = ["DIRECT", "REJECT", "REJECT-DROP", "PASS", "COMPATIBLE"];
+
+// 需要健康检查的代理组类型，ini中没有写测试地址或间隔时使用 mihomo 的默认值
+pub const HEALTH_CHECK_TYPES: [&str; 3] = ["url-test", "fallback", "load-balance"];
+pub const DEFAULT_TEST_URL: &str = "https://www.gstatic.com/generate_204";
+pub const DEFAULT_TEST_INTERVAL: u32 = 300;
@@ -0,0 +1,164 @@
+use crate::{
+    build::{conflict::parse_domain_rule, rules::RulesetReport},
+    utils::i18n::tr,
+};
+use serde_yaml::{Mapping, Value as YamlValue};
+
+// 直接写入 nameserver-policy 的键，不需要从规则集中提取域名
+const PASSTHROUGH_PREFIX: [&str; 2] = ["geosite:", "rule-set:"];
+
+/// --dns-policy 参数：规则集 => DNS服务器
+#[derive(Debug, Clone)]
+pub struct DnsPolicy {
+    pub selector: String, // ini中的代理组名称、规则集路径（或其中一部分），也可以是 geosite:cn 这样的写法
+    pub nameservers: Vec<String>,
+}
+
+/// 解析 --dns-policy 参数，例如 "ChinaDomain=https://doh.pub/dns-query,https://dns.alidns.com/dns-query"
+pub fn parse_dns_policy(s: &str) -> Result<DnsPolicy, String> {
+    let (selector, nameservers) = s.split_once('=').ok_or_else(|| {
+        tr!(
+            "格式错误，应为 规则集=DNS服务器：{}",
+            "invalid format, expected ruleset=nameserver: {}",
+            s
+        )
+    })?;
+    let nameservers: Vec<String> = nameservers
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect();
+    if selector.trim().is_empty() || nameservers.is_empty() {
+        return Err(tr!(
+            "格式错误，应为 规则集=DNS服务器：{}",
+            "invalid format, expected ruleset=nameserver: {}",
+            s
+        ));
+    }
+    Ok(DnsPolicy {
+        selector: selector.trim().to_string(),
+        nameservers,
+    })
+}
+
+fn is_passthrough(selector: &str) -> bool {
+    PASSTHROUGH_PREFIX.iter().any(|p| selector.starts_with(p))
+}
+
+/// 规则集是否被选中：代理组名称相同，或者路径中含有 selector
+pub fn is_selected(selector: &str, report: &RulesetReport) -> bool {
+    !is_passthrough(selector) && (report.name == selector || report.path.contains(selector))
+}
+
+/// 规则转为 dns 中使用的域名写法：DOMAIN => "a.com"，DOMAIN-SUFFIX => "+.a.com"
+pub fn domain_pattern(rule: &str) -> Option<String> {
+    let (kind, domain, _) = parse_domain_rule(rule)?;
+    if kind == "DOMAIN-SUFFIX" {
+        Some(format!("+.{}", domain))
+    } else {
+        Some(domain)
+    }
+}
+
+/// 选中的规则集中的所有域名（去重，保持顺序）
+fn selected_domains(selector: &str, reports: &[RulesetReport]) -> Vec<String> {
+    let mut domains: indexmap::IndexSet<&String> = indexmap::IndexSet::new();
+    for report in reports.iter().filter(|r| is_selected(selector, r)) {
+        domains.extend(report.domains.iter());
+    }
+    domains.into_iter().cloned().collect()
+}
+
+fn nameserver_value(nameservers: &[String]) -> YamlValue {
+    match nameservers {
+        [one] => YamlValue::String(one.clone()),
+        _ => YamlValue::Sequence(nameservers.iter().cloned().map(YamlValue::String).collect()),
+    }
+}
+
+/// 把 nameserver-policy 和 fake-ip-filter 写入 base.yaml 的 dns 中，已有的内容保留
+pub fn apply_dns(
+    base: &mut YamlValue,
+    policies: &[DnsPolicy],
+    fake_ip_filter: &[String],
+    reports: &[RulesetReport],
+) {
+    if policies.is_empty() && fake_ip_filter.is_empty() {
+        return;
+    }
+    let Some(root) = base.as_mapping_mut() else {
+        return;
+    };
+    let dns = root
+        .entry(YamlValue::String("dns".to_string()))
+        .or_insert_with(|| YamlValue::Mapping(Mapping::new()));
+    if !dns.is_mapping() {
+        *dns = YamlValue::Mapping(Mapping::new());
+    }
+    let dns = dns.as_mapping_mut().unwrap();
+
+    if !policies.is_empty() {
+        let policy = dns
+            .entry(YamlValue::String("nameserver-policy".to_string()))
+            .or_insert_with(|| YamlValue::Mapping(Mapping::new()));
+        if !policy.is_mapping() {
+            *policy = YamlValue::Mapping(Mapping::new());
+        }
+        let policy = policy.as_mapping_mut().unwrap();
+        for item in policies {
+            let value = nameserver_value(&item.nameservers);
+            let keys = if is_passthrough(&item.selector) {
+                vec![item.selector.clone()]
+            } else {
+                selected_domains(&item.selector, reports)
+            };
+            if keys.is_empty() {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "--dns-policy {} 没有匹配到任何域名",
+                        "--dns-policy {} matched no domains",
+                        item.selector
+                    )
+                );
+            }
+            for key in keys {
+                policy.insert(YamlValue::String(key), value.clone());
+            }
+        }
+    }
+
+    if !fake_ip_filter.is_empty() {
+        let filter = dns
+            .entry(YamlValue::String("fake-ip-filter".to_string()))
+            .or_insert_with(|| YamlValue::Sequence(Vec::new()));
+        if !filter.is_sequence() {
+            *filter = YamlValue::Sequence(Vec::new());
+        }
+        let filter = filter.as_sequence_mut().unwrap();
+        for selector in fake_ip_filter {
+            let domains = if is_passthrough(selector) {
+                vec![selector.clone()]
+            } else {
+                selected_domains(selector, reports)
+            };
+            if domains.is_empty() {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "--fake-ip-filter {} 没有匹配到任何域名",
+                        "--fake-ip-filter {} matched no domains",
+                        selector
+                    )
+                );
+            }
+            for domain in domains {
+                let domain = YamlValue::String(domain);
+                if !filter.contains(&domain) {
+                    filter.push(domain);
+                }
+            }
+        }
+    }
+}
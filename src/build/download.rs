@@ -1,100 +1,506 @@
+use crate::utils::i18n::tr;
 use blake3;
-use reqwest::Client;
-use std::{fs, path::Path, sync::Arc};
-use tokio::sync::Mutex;
+use once_cell::sync::OnceCell;
+use reqwest::{
+    Client, StatusCode,
+    header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
 
-// 多线程分片下载网络资源，所下载文件以字节数组形式返回
+// 下载订阅和规则共用的客户端（带上 --user-agent、--header 设置的请求头）
+static HTTP_CLIENT: OnceCell<Client> = OnceCell::new();
+// webhook、上传等发往其它主机的请求使用的客户端，不带 --header（可能是订阅的凭据）
+static PLAIN_CLIENT: OnceCell<Client> = OnceCell::new();
+
+/// 解析 --header 参数，例如 "Authorization: Bearer xxx"
+pub fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s.split_once(':').ok_or_else(|| {
+        tr!(
+            "格式错误，应为 名称:值：{}",
+            "invalid format, expected name:value: {}",
+            s
+        )
+    })?;
+    let (name, value) = (name.trim(), value.trim());
+    HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| tr!("无效的请求头名称：{}", "invalid header name: {}", name))?;
+    HeaderValue::from_str(value)
+        .map_err(|_| tr!("无效的请求头的值：{}", "invalid header value: {}", value))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// 设置所有请求使用的 User-Agent 和请求头，只在启动时调用一次
+pub fn init_client(user_agent: Option<&str>, headers: &[(String, String)]) {
+    let mut header_map = HeaderMap::new();
+    if let Some(user_agent) = user_agent {
+        header_map.insert(USER_AGENT, HeaderValue::from_str(user_agent).unwrap());
+    }
+    for (name, value) in headers {
+        header_map.insert(
+            HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_str(value).unwrap(),
+        );
+    }
+    // https 通过 ALPN 协商 HTTP/2，同一主机的请求复用一个连接（多路复用），不再每个请求各开一个连接
+    let client = Client::builder()
+        .default_headers(header_map)
+        .http2_adaptive_window(true)
+        .build()
+        .unwrap_or_else(|e| {
+            panic!(
+                "{}",
+                tr!(
+                    "创建HTTP客户端失败：{}",
+                    "failed to create the HTTP client: {}",
+                    e
+                )
+            )
+        });
+    let _ = HTTP_CLIENT.set(client);
+}
+
+pub fn http_client() -> Client {
+    HTTP_CLIENT.get_or_init(Client::new).clone()
+}
+
+/// 不带 --user-agent、--header 请求头的客户端，用于下载订阅和规则之外的请求
+pub fn plain_client() -> Client {
+    PLAIN_CLIENT.get_or_init(Client::new).clone()
+}
+
+/// 单个规则集下载的限制：超时时间和最大字节数，超过时跳过（有之前保存的规则文件时使用保存的）
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DownloadLimits {
+    pub timeout: Option<Duration>,
+    pub max_bytes: Option<u64>,
+}
+
+/// 解析文件大小，支持 "512K"、"64M"、"1G"（1024进制，可以带 B/iB 后缀），纯数字按字节处理
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let upper = s.to_uppercase();
+    let number = upper.trim_end_matches('B').trim_end_matches('I').trim_end();
+    let (digits, unit) = match number.char_indices().last() {
+        Some((i, 'K')) => (&number[..i], 1u64 << 10),
+        Some((i, 'M')) => (&number[..i], 1 << 20),
+        Some((i, 'G')) => (&number[..i], 1 << 30),
+        _ => (number, 1),
+    };
+    let value: u64 = digits.trim().parse().map_err(|_| {
+        tr!(
+            "无效的大小：{}，例如 512K、64M、1G",
+            "invalid size: {}, e.g. 512K, 64M, 1G",
+            s
+        )
+    })?;
+    match value.checked_mul(unit) {
+        Some(0) => Err(tr!("大小必须大于0", "size must be greater than 0")),
+        Some(size) => Ok(size),
+        None => Err(tr!("大小超出范围：{}", "size out of range: {}", s)),
+    }
+}
+
+fn too_large(url: &str, max_bytes: u64) -> Box<dyn std::error::Error + Send + Sync> {
+    tr!(
+        "{} 超过大小限制（{} 字节），跳过",
+        "{} exceeds the size limit ({} bytes), skipped",
+        url,
+        max_bytes
+    )
+    .into()
+}
+
+// 读取响应的内容，超过 max_bytes 时中止，不把整个文件读进内存
+async fn read_body(
+    url: &str,
+    mut resp: reqwest::Response,
+    max_bytes: Option<u64>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(max_bytes) = max_bytes else {
+        return Ok(resp.bytes().await?.to_vec());
+    };
+    if resp.content_length().is_some_and(|len| len > max_bytes) {
+        return Err(too_large(url, max_bytes));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > max_bytes {
+            return Err(too_large(url, max_bytes));
+        }
+    }
+    Ok(body)
+}
+
+/// 按 limits 下载规则集：整个下载（包括所有分片）超过超时时间或者最大字节数时返回错误
+pub async fn download_ruleset(
+    url: &str,
+    thread: usize,
+    chunk_min_size: u64,
+    limits: DownloadLimits,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let download = download_multi_threaded(url, thread, chunk_min_size, limits.max_bytes);
+    match limits.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, download).await.map_err(|_| {
+            tr!(
+                "{} 下载超时（超过 {} 秒），跳过",
+                "{} download timed out (over {} seconds), skipped",
+                url,
+                timeout.as_secs_f64()
+            )
+        })?,
+        None => download.await,
+    }
+}
+
+// 分片下载失败或者长度不对时，重试的次数
+const CHUNK_RETRIES: usize = 3;
+
+// 下载一个分片，检查返回的字节数是否跟请求的范围一致，不一致时重试
+async fn download_chunk(
+    client: &Client,
+    url: &str,
+    start: u64,
+    end: u64,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let expected = (end - start + 1) as usize;
+    let mut last_error = String::new();
+    for _ in 0..=CHUNK_RETRIES {
+        let resp = client
+            .get(url)
+            .header("Range", format!("bytes={}-{}", start, end))
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status());
+        match resp {
+            Ok(resp) => match resp.bytes().await {
+                Ok(bytes) if bytes.len() == expected => return Ok(bytes.to_vec()),
+                Ok(bytes) => {
+                    last_error = tr!(
+                        "分片 {}-{} 的长度不对：应为 {} 字节，实际 {} 字节",
+                        "chunk {}-{} has the wrong length: expected {} bytes, got {} bytes",
+                        start,
+                        end,
+                        expected,
+                        bytes.len()
+                    )
+                }
+                Err(e) => last_error = e.to_string(),
+            },
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(tr!(
+        "{} 下载失败（已重试 {} 次）：{}",
+        "{} download failed (retried {} times): {}",
+        url,
+        CHUNK_RETRIES,
+        last_error
+    )
+    .into())
+}
+
+// 多线程分片下载网络资源，所下载文件以字节数组形式返回；
+// 只有分片数大于1并且文件不小于 chunk_min_size 时才分片，其余的直接整个下载
 pub async fn download_multi_threaded(
     url: &str,
     thread: usize,
+    chunk_min_size: u64,
+    max_bytes: Option<u64>,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
-    // let file_name = PathBuf::from(&url)
-    //     .file_name()
-    //     .unwrap_or_else(|| std::ffi::OsStr::new("unknown"))
-    //     .to_string_lossy()
-    //     .into_owned();
-    let client = Client::new();
-
-    // 获取文件大小
+    let client = http_client();
+    if thread <= 1 {
+        let resp = client.get(url).send().await?.error_for_status()?;
+        return read_body(url, resp, max_bytes).await;
+    }
+
+    // 获取文件大小，以及服务器是否支持分片下载
     let res = client.head(url).send().await?;
-    let total_size = res
-        .headers()
-        .get("content-length")
-        .ok_or("Missing content-length")?
-        .to_str()?
-        .parse::<u64>()?;
+    let header = |name: &str| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_lowercase())
+    };
+    let no_ranges = header("accept-ranges").as_deref() == Some("none");
+    let total_size = header("content-length").and_then(|v| v.parse::<u64>().ok());
+    // 不支持 Range、不知道文件大小或者文件不大时，直接整个下载
+    let Some(total_size) = total_size.filter(|size| !no_ranges && *size >= chunk_min_size.max(1))
+    else {
+        let resp = client.get(url).send().await?.error_for_status()?;
+        return read_body(url, resp, max_bytes).await;
+    };
+    if let Some(max_bytes) = max_bytes
+        && total_size > max_bytes
+    {
+        return Err(too_large(url, max_bytes));
+    }
 
-    // println!(
-    //     "🔗 {} 文件大小共 {} bytes，开始下载中...",
-    //     file_name, total_size
-    // );
+    // 分片数不能超过文件的字节数，否则会出现空的范围
+    let thread = (thread as u64).clamp(1, total_size);
+    let chunk_size = total_size / thread;
 
-    // 初始化共享缓冲区
-    let buffer = Arc::new(Mutex::new(vec![0u8; total_size as usize]));
-    let chunk_size = total_size / (thread as u64);
+    // 先请求第一个分片：服务器忽略 Range 时返回 200 和整个文件，不能再分片下载，
+    // 否则同一个文件会被拼接多次
+    let first_end = if thread == 1 {
+        total_size - 1
+    } else {
+        chunk_size - 1
+    };
+    let resp = client
+        .get(url)
+        .header("Range", format!("bytes=0-{}", first_end))
+        .send()
+        .await?
+        .error_for_status()?;
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        let body = read_body(url, resp, max_bytes).await?;
+        if body.len() as u64 != total_size {
+            return Err(tr!(
+                "{} 下载的大小不对：应为 {} 字节，实际 {} 字节",
+                "{} downloaded the wrong size: expected {} bytes, got {} bytes",
+                url,
+                total_size,
+                body.len()
+            )
+            .into());
+        }
+        return Ok(body);
+    }
+    let first = resp.bytes().await?.to_vec();
+    let first = if first.len() as u64 == first_end + 1 {
+        first
+    } else {
+        download_chunk(&client, url, 0, first_end).await?
+    };
 
     let mut handles = Vec::default();
 
-    for i in 0..thread {
-        let start = (i as u64) * chunk_size;
+    for i in 1..thread {
+        let start = i * chunk_size;
         let end = if i == thread - 1 {
             total_size - 1
         } else {
-            ((i + 1) as u64) * chunk_size - 1
+            (i + 1) * chunk_size - 1
         };
         let url = url.to_string();
         let client = client.clone();
-        let buffer = buffer.clone();
-
-        // println!("🔢 线程 {} ⬇️{}-{} bytes", i, start, end);
 
         let handle = tokio::spawn(async move {
-            let resp = client
-                .get(&url)
-                .header("Range", format!("bytes={}-{}", start, end))
-                .send()
-                .await?;
-            let bytes = resp.bytes().await?;
-            let mut buffer = buffer.lock().await;
-            buffer[start as usize..=end as usize].copy_from_slice(&bytes);
-
-            // println!("✅ 线程 {} 执行完毕", i);
-
-            Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+            let bytes = download_chunk(&client, &url, start, end).await?;
+            Ok::<(u64, Vec<u8>), Box<dyn std::error::Error + Send + Sync>>((start, bytes))
         });
         handles.push(handle);
     }
 
+    // 按分片的位置拼接
+    let mut buffer = vec![0u8; total_size as usize];
+    let mut received = first.len();
+    buffer[..first.len()].copy_from_slice(&first);
     for handle in handles {
-        handle.await??;
+        let (start, bytes) = handle.await??;
+        received += bytes.len();
+        buffer[start as usize..start as usize + bytes.len()].copy_from_slice(&bytes);
     }
+    if received as u64 != total_size {
+        return Err(tr!(
+            "{} 下载的大小不对：应为 {} 字节，实际 {} 字节",
+            "{} downloaded the wrong size: expected {} bytes, got {} bytes",
+            url,
+            total_size,
+            received
+        )
+        .into());
+    }
+
+    Ok(buffer)
+}
 
-    let final_buffer = Arc::try_unwrap(buffer)
-        .expect("Arc unwrap failed")
-        .into_inner();
+// 校验发布在 url.sha256 中的 sha256 值（文件内容的第一段），没有发布时跳过校验
+pub async fn verify_published_sha256(url: &str, data: &[u8]) -> Result<(), String> {
+    let Ok(published) = download_single(&format!("{}.sha256", url)).await else {
+        return Ok(());
+    };
+    let published = String::from_utf8_lossy(&published);
+    let Some(expected) = published.split_whitespace().next() else {
+        return Ok(());
+    };
+    let actual = format!("{:x}", Sha256::digest(data));
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(tr!(
+            "{} 的 sha256 校验失败：应为 {}，实际 {}",
+            "{} failed sha256 verification: expected {}, got {}",
+            url,
+            expected,
+            actual
+        ))
+    }
+}
+
+// 单线程下载网络资源（例如订阅链接），以字节数组形式返回
+pub async fn download_single(
+    url: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let resp = http_client().get(url).send().await?.error_for_status()?;
+    Ok(resp.bytes().await?.to_vec())
+}
+
+/// 一次下载的大小和耗时
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Transfer {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Transfer {
+    /// 平均速度（字节/秒）
+    pub fn speed(&self) -> u64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            (self.bytes as f64 / secs) as u64
+        } else {
+            self.bytes
+        }
+    }
+}
 
-    Ok(final_buffer)
+// 下载订阅链接，同时返回响应头中的 subscription-userinfo（流量信息）和下载的大小、耗时；
+// 每收到一段内容调用一次 on_progress(已下载的字节数, 总字节数)
+pub async fn download_subscription(
+    url: &str,
+    mut on_progress: impl FnMut(u64, Option<u64>),
+) -> Result<(Vec<u8>, Option<String>, Transfer), Box<dyn std::error::Error + Send + Sync>> {
+    let start_time = Instant::now();
+    let mut resp = http_client().get(url).send().await?.error_for_status()?;
+    let userinfo = resp
+        .headers()
+        .get("subscription-userinfo")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let total = resp.content_length();
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        body.extend_from_slice(&chunk);
+        on_progress(body.len() as u64, total);
+    }
+    let transfer = Transfer {
+        bytes: body.len() as u64,
+        elapsed: start_time.elapsed(),
+    };
+    Ok((body, userinfo, transfer))
 }
 
 // 保存网络文件到本地，如果本地文件存在，则比较hash值，如果一致，则不保存，如果不一致，则保存
-pub fn save_net_file(net_content: Vec<u8>, file_path: &str) -> String {
+pub fn save_net_file<P: AsRef<Path>>(net_content: Vec<u8>, file_path: P) -> String {
+    let file_path = file_path.as_ref();
     if !net_content.is_empty() {
-        let path = Path::new(file_path);
-        if path.exists() {
-            let local_content = fs::read(file_path).expect("读取文件失败");
+        if file_path.exists() {
+            let local_content = fs::read(file_path).unwrap_or_else(|e| {
+                panic!("{}", tr!("读取文件失败：{}", "failed to read file: {}", e))
+            });
             let local_hash = blake3::hash(&local_content);
             let net_hash = blake3::hash(&net_content);
             if local_hash == net_hash {
-                return format!("{} 文件与网络文件一致，无需保存！", file_path);
+                tr!(
+                    "{} 文件与网络文件一致，无需保存！",
+                    "{} is identical to the downloaded file, not saved!",
+                    file_path.display()
+                )
             } else {
                 fs::write(file_path, &net_content).unwrap();
-                return format!("{} 文件与网络文件不一致，已保存本地！", file_path);
+                tr!(
+                    "{} 文件与网络文件不一致，已保存本地！",
+                    "{} differs from the downloaded file, saved!",
+                    file_path.display()
+                )
             }
         } else {
             fs::write(file_path, &net_content).unwrap();
-            return format!("{} 文件不存在，已保存本地！", file_path);
+            tr!(
+                "{} 文件不存在，已保存本地！",
+                "{} did not exist, saved!",
+                file_path.display()
+            )
         }
     } else {
-        return format!("要写入的数据为空！");
+        tr!("要写入的数据为空！", "nothing to write!")
+    }
+}
+
+// Windows 中不能作为文件名的名称（不区分大小写，带扩展名也不行）
+const RESERVED_FILE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// 百分号解码，例如 %E8%A7%84%E5%88%99 => 规则，无效的编码保持不变
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// 规则集链接保存到本地的文件名：取路径的最后一段（不含查询参数），百分号解码，
+/// 替换 Windows 文件名中不能使用的字符，避开 CON、NUL 等保留名称
+pub fn url_file_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let last = path
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    let name: String = percent_decode(last)
+        .chars()
+        .map(|c| {
+            if c.is_control() || "<>:\"/\\|?*".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    // Windows 会去掉文件名末尾的点和空格
+    let name = name.trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        return "unknown".to_string();
+    }
+    let stem = name.split('.').next().unwrap_or_default().to_uppercase();
+    if RESERVED_FILE_NAMES.contains(&stem.as_str()) {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// 规则集链接在本地保存的文件名：原文件名加上完整链接的哈希，例如 reject_1a2b3c4d.txt，
+/// 不同仓库中同名的规则集不会互相覆盖
+pub fn cache_file_name(url: &str) -> String {
+    let name = url_file_name(url);
+    let hash = blake3::hash(url.as_bytes()).to_hex();
+    let hash = &hash[..8];
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}_{}.{}", stem, hash, ext),
+        _ => format!("{}_{}", name, hash),
     }
 }
@@ -0,0 +1,244 @@
+use crate::{
+    build::{
+        constants::{DEFAULT_TEST_INTERVAL, DEFAULT_TEST_URL, HEALTH_CHECK_TYPES},
+        ini::{self as MyIni, SelectGroup},
+        mathrule::RuleLine,
+    },
+    utils::i18n::tr,
+};
+use fancy_regex::Regex as FancyRegex;
+use serde::Deserialize;
+use serde_yaml::Value as YamlValue;
+use std::fs;
+
+/// 额外的规则放在哪里
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RulePosition {
+    Top, // 所有规则的最前面，优先级最高
+    #[default]
+    BeforeFinal, // 最后的 MATCH 规则（ini中的 []FINAL）之前
+}
+
+/// 用户自己维护的规则（--extra-rules），不用修改ini就能覆盖上游规则集的结果
+#[derive(Debug, Clone, Default)]
+pub struct ExtraRules {
+    pub position: RulePosition,
+    pub rules: Vec<String>,
+}
+
+/// 额外规则的文件：规则列表，或者带有位置的 {position: top, rules: [...]}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExtraRulesFile {
+    List(Vec<String>),
+    Full {
+        #[serde(default)]
+        position: RulePosition,
+        rules: Vec<String>,
+    },
+}
+
+/// 读取额外的规则，例如：
+/// position: top
+/// rules:
+///   - DOMAIN-SUFFIX,example.com,DIRECT
+///
+/// 每条规则都要写代理组（或者 DIRECT、REJECT），注释和空行被忽略
+pub fn read_extra_rules(path: &str) -> Result<ExtraRules, String> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        tr!(
+            "读取额外的规则失败 {}：{}",
+            "failed to read the extra rules {}: {}",
+            path,
+            e
+        )
+    })?;
+    let file: ExtraRulesFile = serde_yaml::from_str(&content).map_err(|e| {
+        tr!(
+            "额外的规则格式错误 {}：{}",
+            "invalid extra rules {}: {}",
+            path,
+            e
+        )
+    })?;
+    let (position, lines) = match file {
+        ExtraRulesFile::List(rules) => (RulePosition::default(), rules),
+        ExtraRulesFile::Full { position, rules } => (position, rules),
+    };
+    let mut rules = Vec::new();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let rule = RuleLine::parse(line)
+            .filter(|rule| rule.group.is_some())
+            .ok_or_else(|| {
+                tr!(
+                    "{}：无效的规则(需要写代理组)：{}",
+                    "{}: invalid rule (a group is required): {}",
+                    path,
+                    line
+                )
+            })?;
+        let rule = rule.to_string();
+        if !rules.contains(&rule) {
+            rules.push(rule);
+        }
+    }
+    Ok(ExtraRules { position, rules })
+}
+
+/// --extra-groups 文件中的一个代理组：字段跟 clash 的 proxy-groups 相同，
+/// 另外可以用 filter、exclude-filter（正则）选择节点，跟ini中代理组的正则一样
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ExtraGroup {
+    name: String,
+    #[serde(rename = "type")]
+    select_type: String,
+    #[serde(default)]
+    proxies: Vec<String>,
+    filter: Option<String>,
+    exclude_filter: Option<String>,
+    #[serde(rename = "use", default)]
+    use_providers: Vec<String>,
+    url: Option<String>,
+    interval: Option<u32>,
+    timeout: Option<u32>,
+    tolerance: Option<u32>,
+    strategy: Option<String>,
+    lazy: Option<bool>,
+    expected_status: Option<YamlValue>, // 可以写为 204 或者 "200/302"
+    disable_udp: Option<bool>,
+    hidden: Option<bool>,
+    icon: Option<String>,
+    limit: Option<YamlValue>, // 20 或者 "20:alpha"
+}
+
+/// 额外代理组的文件：代理组列表，或者跟 clash 配置一样写在 proxy-groups 下面
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExtraGroupsFile {
+    List(Vec<ExtraGroup>),
+    Config {
+        #[serde(rename = "proxy-groups")]
+        groups: Vec<ExtraGroup>,
+    },
+}
+
+fn scalar_string(value: YamlValue) -> Option<String> {
+    match value {
+        YamlValue::String(s) => Some(s),
+        YamlValue::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+impl ExtraGroup {
+    fn into_select_group(self, path: &str) -> Result<SelectGroup, String> {
+        for pattern in [&self.filter, &self.exclude_filter].into_iter().flatten() {
+            FancyRegex::new(pattern).map_err(|e| {
+                tr!(
+                    "{}：代理组 {} 的正则无效：{}",
+                    "{}: invalid regex in group {}: {}",
+                    path,
+                    self.name,
+                    e
+                )
+            })?;
+        }
+        let limit = self
+            .limit
+            .map(|v| {
+                scalar_string(v)
+                    .ok_or_else(|| tr!("无效的节点数量限制", "invalid node limit"))
+                    .and_then(|s| MyIni::parse_node_limit(&s))
+                    .map_err(|e| format!("{}：{}：{}", path, self.name, e))
+            })
+            .transpose()?;
+        // 需要健康检查的代理组，跟ini中的一样补上测试地址和间隔
+        let (url, interval) = if HEALTH_CHECK_TYPES.contains(&self.select_type.as_str()) {
+            (
+                self.url.or_else(|| Some(DEFAULT_TEST_URL.to_string())),
+                self.interval.or(Some(DEFAULT_TEST_INTERVAL)),
+            )
+        } else {
+            (self.url, self.interval)
+        };
+        Ok(SelectGroup {
+            name: self.name,
+            select_type: self.select_type,
+            url,
+            interval,
+            timeout: self.timeout,
+            tolerance: self.tolerance,
+            strategy: self.strategy,
+            lazy: self.lazy,
+            expected_status: self.expected_status.and_then(scalar_string),
+            disable_udp: self.disable_udp,
+            hidden: self.hidden,
+            icon: self.icon,
+            use_providers: self.use_providers,
+            proxies: self.proxies,
+            proxies_regexp: self.filter,
+            exclude_regexp: self.exclude_filter,
+            limit,
+        })
+    }
+}
+
+/// 读取额外的代理组，例如：
+/// - name: 🎮 Gaming
+///   type: url-test
+///   filter: (港|HK|日本|JP)
+///   tolerance: 50
+pub fn read_extra_groups(path: &str) -> Result<Vec<SelectGroup>, String> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        tr!(
+            "读取额外的代理组失败 {}：{}",
+            "failed to read the extra groups {}: {}",
+            path,
+            e
+        )
+    })?;
+    let file: ExtraGroupsFile = serde_yaml::from_str(&content).map_err(|e| {
+        tr!(
+            "额外的代理组格式错误 {}：{}",
+            "invalid extra groups {}: {}",
+            path,
+            e
+        )
+    })?;
+    let groups = match file {
+        ExtraGroupsFile::List(groups) | ExtraGroupsFile::Config { groups } => groups,
+    };
+    groups
+        .into_iter()
+        .map(|group| group.into_select_group(path))
+        .collect()
+}
+
+/// 额外的代理组合并到ini的代理组中：名称相同的替换掉ini中的（位置不变），其它的加在后面
+///
+/// 返回替换和新加的代理组数量
+pub fn merge_extra_groups(
+    groups: &mut Vec<SelectGroup>,
+    extra: Vec<SelectGroup>,
+) -> (usize, usize) {
+    let (mut replaced, mut added) = (0, 0);
+    for group in extra {
+        match groups.iter_mut().find(|g| g.name == group.name) {
+            Some(existing) => {
+                *existing = group;
+                replaced += 1;
+            }
+            None => {
+                groups.push(group);
+                added += 1;
+            }
+        }
+    }
+    (replaced, added)
+}
@@ -0,0 +1,106 @@
+use crate::{
+    build::constants::{FILTER_KEY, INCLUDE_KEY},
+    utils::i18n::tr,
+};
+use serde::Deserialize;
+
+// 不是规则类型，但是无论如何都要过滤掉的内容（注释、yaml的payload行）
+const ALWAYS_FILTER: [&str; 2] = ["#", "payload:"];
+
+/// 规则类型的过滤配置，默认使用 INCLUDE_KEY 和 FILTER_KEY
+#[derive(Debug, Clone)]
+pub struct RuleFilter {
+    pub include: Vec<String>, // 保留的规则类型
+    pub filter: Vec<String>,  // 过滤掉的规则类型
+}
+
+/// 规则类型配置文件（yaml），没有写的字段使用默认值
+#[derive(Deserialize, Debug, Default)]
+struct RuleFilterFile {
+    include: Option<Vec<String>>,
+    filter: Option<Vec<String>>,
+}
+
+impl Default for RuleFilter {
+    fn default() -> Self {
+        RuleFilter {
+            include: INCLUDE_KEY.iter().map(|s| s.to_string()).collect(),
+            filter: FILTER_KEY.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+/// 规则类型统一加上逗号，例如 "USER-AGENT" => "USER-AGENT,"，避免误匹配其它类型
+fn type_key(rule_type: &str) -> String {
+    let rule_type = rule_type.trim().trim_end_matches(',').to_uppercase();
+    format!("{},", rule_type)
+}
+
+impl RuleFilter {
+    /// 从yaml文件读取，例如：
+    /// include: [DOMAIN, DOMAIN-SUFFIX, IP-CIDR]
+    /// filter: [USER-AGENT, URL-REGEX]
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            tr!(
+                "读取规则类型配置文件失败 {}：{}",
+                "failed to read the rule type config {}: {}",
+                path,
+                e
+            )
+        })?;
+        let file: RuleFilterFile = serde_yaml::from_str(&content).map_err(|e| {
+            tr!(
+                "规则类型配置文件格式错误 {}：{}",
+                "invalid rule type config {}: {}",
+                path,
+                e
+            )
+        })?;
+
+        let mut rule_filter = RuleFilter::default();
+        if let Some(include) = file.include {
+            rule_filter.include = include.iter().map(|s| type_key(s)).collect();
+        }
+        if let Some(filter) = file.filter {
+            rule_filter.filter = filter.iter().map(|s| type_key(s)).collect();
+            rule_filter
+                .filter
+                .extend(ALWAYS_FILTER.iter().map(|s| s.to_string()));
+        }
+        Ok(rule_filter)
+    }
+
+    /// 保留这些规则类型（从过滤列表中移除，并加入保留列表）
+    pub fn keep_types<S: AsRef<str>>(&mut self, types: &[S]) {
+        for rule_type in types {
+            let key = type_key(rule_type.as_ref());
+            self.filter
+                .retain(|kw| ALWAYS_FILTER.contains(&kw.as_str()) || !key.starts_with(kw.as_str()));
+            if !self.include.contains(&key) {
+                self.include.push(key);
+            }
+        }
+    }
+
+    /// 过滤掉这些规则类型
+    pub fn drop_types<S: AsRef<str>>(&mut self, types: &[S]) {
+        for rule_type in types {
+            let key = type_key(rule_type.as_ref());
+            self.include.retain(|kw| kw != &key);
+            if !self.filter.contains(&key) {
+                self.filter.push(key);
+            }
+        }
+    }
+
+    /// 是否含有需要过滤掉的内容
+    pub fn is_filtered(&self, s: &str) -> bool {
+        self.filter.iter().any(|kw| s.contains(kw.as_str()))
+    }
+
+    /// 是否为需要保留的规则类型
+    pub fn is_included(&self, s: &str) -> bool {
+        self.include.iter().any(|kw| s.contains(kw.as_str()))
+    }
+}
@@ -1,19 +1,66 @@
-use crate::build::patterns;
-use yaml_rust::{YamlEmitter, YamlLoader};
+use serde::Serialize;
+use serde_yaml::Value as YamlValue;
+use yaml_rust::{Yaml, YamlEmitter, yaml::Hash};
 
-// 调整yaml缩进，并去掉多余的引号。如果传入的ymal字符串比较大，会耗费大量时间，特别是几万行以上的。(慎用)
-pub fn fix_yaml_indent(yaml_str: &str) -> String {
-    if let Ok(docs) = YamlLoader::load_from_str(yaml_str) {
-        if let Some(doc) = docs.first() {
-            let mut out = String::with_capacity(yaml_str.len()); // 减少多次扩容
-            if YamlEmitter::new(&mut out).dump(doc).is_ok() {
-                let stripped = out.strip_prefix("---\n").unwrap_or(&out);
-
-                // 使用 Cow 避免不必要复制
-                let result = patterns::RE_INDENT_QUOTES.replace_all(stripped, "$1$3");
-                return result.into_owned(); // 只有在需要的时候才分配
+/// serde_yaml 的值直接转为 yaml_rust 的值，不再先输出字符串再重新解析（大的yaml非常耗时）
+fn to_yaml_rust(value: &YamlValue) -> Yaml {
+    match value {
+        YamlValue::Null => Yaml::Null,
+        YamlValue::Bool(b) => Yaml::Boolean(*b),
+        YamlValue::Number(n) => match n.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(serde_yaml::to_string(n).unwrap().trim().to_string()),
+        },
+        YamlValue::String(s) => Yaml::String(s.clone()),
+        YamlValue::Sequence(seq) => Yaml::Array(seq.iter().map(to_yaml_rust).collect()),
+        YamlValue::Mapping(map) => {
+            let mut hash = Hash::new();
+            for (k, v) in map {
+                hash.insert(to_yaml_rust(k), to_yaml_rust(v));
             }
+            Yaml::Hash(hash)
+        }
+        YamlValue::Tagged(tagged) => to_yaml_rust(&tagged.value),
+    }
+}
+
+/// 去掉数组中一项的引号，例如 `  - "DIRECT"` => `  - DIRECT`，除非内容中含有 *
+fn strip_item_quotes(line: &str) -> Option<String> {
+    let trimmed = line.trim_start();
+    let rest = trimmed.strip_prefix('-')?;
+    let mut chars = rest.chars();
+    let space = chars.next().filter(|c| c.is_whitespace())?;
+    let quoted = chars.as_str();
+    let quote = quoted.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let content = quoted[1..].strip_suffix(quote)?;
+    if content.is_empty() || content.contains(['\'', '"', '*']) {
+        return None;
+    }
+    let indent = &line[..line.len() - trimmed.len()];
+    Some(format!("{}-{}{}", indent, space, content))
+}
+
+/// 序列化为缩进正确的yaml（数组相对上一级缩进两个空格），并去掉数组中多余的引号
+pub fn to_indented_yaml<T: Serialize>(value: &T) -> String {
+    let value = serde_yaml::to_value(value).unwrap();
+    let mut out = String::new();
+    if YamlEmitter::new(&mut out)
+        .dump(&to_yaml_rust(&value))
+        .is_err()
+    {
+        return "Error: Invalid YAML input".to_string();
+    }
+    let stripped = out.strip_prefix("---\n").unwrap_or(&out);
+
+    let mut result = String::with_capacity(stripped.len());
+    for (i, line) in stripped.split('\n').enumerate() {
+        if i > 0 {
+            result.push('\n');
+        }
+        match strip_item_quotes(line) {
+            Some(line) => result.push_str(&line),
+            None => result.push_str(line),
         }
     }
-    "Error: Invalid YAML input".to_string()
+    result
 }
@@ -1,16 +1,30 @@
-use crate::build::patterns;
+use crate::{
+    build::{
+        constants::{DEFAULT_TEST_INTERVAL, DEFAULT_TEST_URL, HEALTH_CHECK_TYPES, SPECIAL_PROXIES},
+        indent, patterns,
+        providers::Behavior,
+    },
+    utils::{
+        exit::{ErrorKind, Failure},
+        i18n::tr,
+        links,
+    },
+};
 
 use fancy_regex::Regex as FancyRegex;
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
 use ini::Ini;
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct RuleSet {
-    pub rule_name: String,       // 规则集名称
-    pub net_rule_path: String,   // 网络规则路径(url)
-    pub local_rule_path: String, // 本地规则路径(相对路径)
-    pub final_rule: String,      // 最后兜底的规则
+    pub rule_name: String,           // 规则集名称
+    pub net_rule_path: String,       // 网络规则路径(url)
+    pub local_rule_path: String,     // 本地规则路径(相对路径)
+    pub final_rule: String,          // 最后兜底的规则
+    pub rule_type: Option<Behavior>, // ini中声明的规则集类型（clash-domain:、clash-ipcidr:、clash-classic:）
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -26,22 +40,230 @@ pub struct SelectGroup {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub interval: Option<u32>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u32>, // 健康检查的超时（毫秒），ini中按 subconverter 的写法以秒为单位
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tolerance: Option<u32>,
 
-    pub proxies: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<String>, // load-balance：consistent-hashing、round-robin、sticky-sessions
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lazy: Option<bool>,
+
+    #[serde(rename = "expected-status", skip_serializing_if = "Option::is_none")]
+    pub expected_status: Option<String>, // 健康检查期望的状态码，例如 204、200/302、200-299
+
+    #[serde(rename = "disable-udp", skip_serializing_if = "Option::is_none")]
+    pub disable_udp: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+
+    #[serde(rename = "use", default, skip_serializing_if = "Vec::is_empty")]
+    pub use_providers: Vec<String>, // 引用 base.yaml 中的 proxy-providers，ini中写为 `use=provider1,provider2
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proxies: Vec<String>, // 只引用 proxy-providers 的代理组可以为空
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxies_regexp: Option<String>, // 这个是正则表达式，用于过滤节点到 proxies 中
+
+    #[serde(skip)]
+    pub exclude_regexp: Option<String>, // 匹配到的节点不放入 proxies 中，ini中写为 `exclude=正则 或者 `!!正则
+
+    #[serde(skip)]
+    pub limit: Option<NodeLimit>, // 最多放入多少个（正则匹配到的）节点
+}
+
+impl SelectGroup {
+    /// 有节点（或者代理组），或者引用了 proxy-providers
+    pub fn has_members(&self) -> bool {
+        !self.proxies.is_empty() || !self.use_providers.is_empty()
+    }
+}
+
+/// 节点数量超过限制时，选取节点的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NodeOrder {
+    #[default]
+    First, // 按订阅中的顺序，取前面的
+    Alpha, // 按名称排序，取前面的
+}
+
+/// 代理组的节点数量限制，ini中写为 `limit=20` 或者 `limit=20:alpha`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeLimit {
+    pub count: usize,
+    pub order: NodeOrder,
+}
+
+/// 解析节点数量限制，例如 "20"、"20:alpha"、"20:first"
+pub fn parse_node_limit(s: &str) -> Result<NodeLimit, String> {
+    let (count, order) = s.split_once(':').unwrap_or((s, "first"));
+    let count: usize = count
+        .trim()
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| tr!("无效的节点数量限制：{}", "invalid node limit: {}", s))?;
+    let order = match order.trim() {
+        "first" => NodeOrder::First,
+        "alpha" => NodeOrder::Alpha,
+        other => {
+            return Err(tr!(
+                "无效的选取方式 '{}'，可选：first、alpha",
+                "invalid pick order '{}', expected first or alpha",
+                other
+            ));
+        }
+    };
+    Ok(NodeLimit { count, order })
+}
+
+impl NodeLimit {
+    /// 按选取方式保留前 count 个节点
+    fn apply(&self, mut names: Vec<String>) -> Vec<String> {
+        if self.order == NodeOrder::Alpha {
+            names.sort();
+        }
+        names.truncate(self.count);
+        names
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ProxyGroup {
+/// 代理组中 `key=value 形式的选项，例如 `strategy=consistent-hashing、`lazy=true
+fn group_option<'a>(parts: &[&'a str], key: &str) -> Option<&'a str> {
+    parts
+        .iter()
+        .find_map(|s| s.strip_prefix(key)?.strip_prefix('='))
+        .map(str::trim)
+}
+
+/// 代理组的健康检查参数：间隔（秒）、超时（毫秒）、容差（毫秒）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct GroupTimes {
+    interval: Option<u32>,
+    timeout: Option<u32>,
+    tolerance: Option<u32>,
+}
+
+/// 解析代理组中 subconverter 写法的 `interval,timeout,tolerance，例如 `300,,50、`300,5,50、`180
+///
+/// 超时以秒为单位，转为 mihomo 使用的毫秒；也可以单独写为 `interval=300、`timeout=5、`tolerance=50，优先于前面的写法
+fn group_times(parts: &[&str]) -> Result<GroupTimes, String> {
+    let number = |s: &str| s.parse::<u32>().ok();
+    let mut times = parts
+        .iter()
+        .skip(2)
+        .find_map(|s| patterns::RE_INI_COORDS.captures(s.trim()))
+        .map(|caps| GroupTimes {
+            interval: number(&caps[1]),
+            timeout: caps.get(2).and_then(|m| number(m.as_str())),
+            tolerance: caps.get(3).and_then(|m| number(m.as_str())),
+        })
+        .unwrap_or_default();
+    for (key, field) in [
+        ("interval", &mut times.interval),
+        ("timeout", &mut times.timeout),
+        ("tolerance", &mut times.tolerance),
+    ] {
+        if let Some(value) = group_option(parts, key) {
+            *field = Some(
+                number(value).ok_or_else(|| tr!("无效的 {}：{}", "invalid {}: {}", key, value))?,
+            );
+        }
+    }
+    times.timeout = times.timeout.map(|secs| secs.saturating_mul(1000));
+    Ok(times)
+}
+
+/// 布尔值选项，只写 key 不写值（例如 `hidden）也当作 true
+fn group_flag(parts: &[&str], key: &str) -> Option<bool> {
+    if parts.contains(&key) {
+        return Some(true);
+    }
+    group_option(parts, key).and_then(|v| match v {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    })
+}
+
+/// 正则没有匹配到任何节点（代理组为空）时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyGroupPolicy {
+    #[default]
+    Remove, // 移除该代理组，指向它的规则改为指向兜底的代理组
+    Direct, // 保留，只放入 DIRECT
+    All,    // 保留，放入所有节点
+}
+
+/// 解析 --empty-group 参数：remove、direct、all
+pub fn parse_empty_group_policy(s: &str) -> Result<EmptyGroupPolicy, String> {
+    match s.trim() {
+        "remove" => Ok(EmptyGroupPolicy::Remove),
+        "direct" => Ok(EmptyGroupPolicy::Direct),
+        "all" => Ok(EmptyGroupPolicy::All),
+        other => Err(tr!(
+            "无效的处理方式 '{}'，可选：remove、direct、all",
+            "invalid policy '{}', expected remove, direct or all",
+            other
+        )),
+    }
+}
+
+/// 有规则指向的代理组被移除后，这些规则改为指向的代理组
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PruneFallback {
+    #[default]
+    Final, // 最后一个规则集的代理组（一般是兜底的 MATCH）
+    Main, // 第一个 select 类型的代理组（一般是节点选择）
+    Direct,
+}
+
+/// 解析 --prune-fallback 参数：final、main、direct
+pub fn parse_prune_fallback(s: &str) -> Result<PruneFallback, String> {
+    match s.trim() {
+        "final" => Ok(PruneFallback::Final),
+        "main" => Ok(PruneFallback::Main),
+        "direct" => Ok(PruneFallback::Direct),
+        other => Err(tr!(
+            "无效的兜底代理组 '{}'，可选：final、main、direct",
+            "invalid fallback '{}', expected final, main or direct",
+            other
+        )),
+    }
+}
+
+/// 是否为 DIRECT、REJECT 等内置的特殊策略
+pub fn is_special_proxy(name: &str) -> bool {
+    SPECIAL_PROXIES.contains(&name)
+}
+
+/// 特殊策略统一为大写，例如 "[]direct" => "DIRECT"
+fn normalize_special(name: &str) -> String {
+    let name = name.trim();
+    SPECIAL_PROXIES
+        .iter()
+        .find(|s| s.eq_ignore_ascii_case(name))
+        .map_or_else(|| name.to_string(), |s| s.to_string())
+}
+
+#[derive(Serialize, Debug)]
+struct ProxyGroup<'a> {
     #[serde(rename = "proxy-groups")]
-    group: Vec<SelectGroup>,
+    group: &'a [SelectGroup],
 }
 
-pub fn read_ini(config: Ini) -> (Vec<String>, Vec<RuleSet>, Vec<SelectGroup>) {
+/// read_ini 的结果：规则集名称、规则集、自定义代理组
+pub type IniConfig = (Vec<String>, Vec<RuleSet>, Vec<SelectGroup>);
+
+pub fn read_ini(config: Ini) -> Result<IniConfig, Failure> {
     // 规则集名称
     let mut ruleset_names: IndexSet<String> = IndexSet::new();
     // 规则集
@@ -55,10 +277,18 @@ pub fn read_ini(config: Ini) -> (Vec<String>, Vec<RuleSet>, Vec<SelectGroup>) {
                 let parts = value.splitn(2, ',').collect::<Vec<_>>();
                 if parts.len() == 2 {
                     let ruleset_name = parts[0].to_string();
-                    let mut ruleset_value = parts[1].to_string();
-                    let remove_list = vec!["clash-classic:", "clash-ipcidr:", "clash-domain:"];
-                    for target in &remove_list {
-                        ruleset_value = ruleset_value.replace(target, "").trim().to_string();
+                    let mut ruleset_value = parts[1].trim().to_string();
+                    // 记录声明的规则集类型，后续按照这个类型解析规则
+                    let mut rule_type = None;
+                    for (prefix, behavior) in [
+                        ("clash-classic:", Behavior::Classical),
+                        ("clash-ipcidr:", Behavior::IpCidr),
+                        ("clash-domain:", Behavior::Domain),
+                    ] {
+                        if let Some(rest) = ruleset_value.strip_prefix(prefix) {
+                            ruleset_value = rest.trim().to_string();
+                            rule_type = Some(behavior);
+                        }
                     }
                     if ["https://", "http://"]
                         .iter()
@@ -68,6 +298,7 @@ pub fn read_ini(config: Ini) -> (Vec<String>, Vec<RuleSet>, Vec<SelectGroup>) {
                         ruleset.push(RuleSet {
                             rule_name: ruleset_name.clone(),
                             net_rule_path: ruleset_value,
+                            rule_type,
                             ..Default::default()
                         });
                     } else if !ruleset_value.contains("[]") {
@@ -75,6 +306,7 @@ pub fn read_ini(config: Ini) -> (Vec<String>, Vec<RuleSet>, Vec<SelectGroup>) {
                         ruleset.push(RuleSet {
                             rule_name: ruleset_name.clone(),
                             local_rule_path: ruleset_value,
+                            rule_type,
                             ..Default::default()
                         });
                     } else if ruleset_value.contains("[]") {
@@ -90,16 +322,12 @@ pub fn read_ini(config: Ini) -> (Vec<String>, Vec<RuleSet>, Vec<SelectGroup>) {
             }
             if key == "custom_proxy_group" {
                 let parts: Vec<&str> = value.split('`').collect();
-                let (interval, tolerance) = parts
-                    .iter()
-                    .find_map(|s| patterns::RE_INI_COORDS.captures(s))
-                    .map(|caps| {
-                        (
-                            caps[1].parse().ok(),
-                            caps.get(2).and_then(|m| m.as_str().parse().ok()),
-                        )
-                    })
-                    .unwrap_or((None, None));
+                let GroupTimes {
+                    interval,
+                    timeout,
+                    tolerance,
+                } = group_times(&parts)
+                    .map_err(|e| Failure::new(ErrorKind::Config, format!("{}：{}", e, value)))?;
                 let name = parts[0].to_string();
                 let select_type = parts[1].to_string();
                 let url = parts
@@ -110,74 +338,266 @@ pub fn read_ini(config: Ini) -> (Vec<String>, Vec<RuleSet>, Vec<SelectGroup>) {
                             .any(|p| ele.starts_with(p))
                     })
                     .map(|s| s.replacen("benchmark-url=", "", 1).to_string());
-                let group_regular = parts
+                let exclude_regular = parts
                     .iter()
-                    .find(|ele| patterns::RE_INI_GROUP.is_match(ele))
+                    .find_map(|s| s.strip_prefix("exclude=").or_else(|| s.strip_prefix("!!")))
                     .map(|s| s.to_string());
+                // 可能有多个正则（例如 `(港|HK)`^((?!流量).)*$），节点需要同时匹配它们
+                let group_regulars: Vec<&str> = parts
+                    .iter()
+                    .skip(2)
+                    .filter(|ele| !ele.starts_with("[]") && !ele.starts_with("exclude="))
+                    .filter(|ele| !ele.starts_with("!!"))
+                    .filter(|ele| patterns::RE_INI_GROUP.is_match(ele))
+                    .copied()
+                    .collect();
+                let group_regular = match group_regulars.as_slice() {
+                    [] => None,
+                    [one] => Some(one.to_string()),
+                    many => Some(
+                        many.iter()
+                            .map(|re| format!("(?=.*?(?:{}))", re))
+                            .collect::<String>(),
+                    ),
+                };
                 let any_regular = parts
                     .iter()
+                    .filter(|ele| !ele.starts_with("exclude=") && !ele.starts_with("!!"))
                     .find(|ele| ele.contains(".*"))
                     .map(|s| s.to_string());
+                let limit = group_option(&parts, "limit")
+                    .map(|s| {
+                        parse_node_limit(s).map_err(|e| {
+                            Failure::new(ErrorKind::Config, format!("{}：{}", e, value))
+                        })
+                    })
+                    .transpose()?;
                 let square_brackets_rules: Vec<String> = parts
                     .iter()
                     .filter(|s| s.contains("[]"))
-                    .map(|s| s.replacen("[]", "", 1))
+                    .map(|s| normalize_special(&s.replacen("[]", "", 1)))
                     .collect();
+                // 需要健康检查的代理组，测试地址和间隔总是同时存在
+                let (url, interval) = if HEALTH_CHECK_TYPES.contains(&select_type.as_str()) {
+                    (
+                        url.or_else(|| Some(DEFAULT_TEST_URL.to_string())),
+                        interval.or(Some(DEFAULT_TEST_INTERVAL)),
+                    )
+                } else {
+                    (url, interval)
+                };
                 custom_proxy_group.push(SelectGroup {
                     name,
                     select_type,
                     url,
                     interval,
+                    timeout,
                     tolerance,
+                    strategy: group_option(&parts, "strategy").map(str::to_string),
+                    lazy: group_flag(&parts, "lazy"),
+                    expected_status: group_option(&parts, "expected-status").map(str::to_string),
+                    use_providers: group_option(&parts, "use")
+                        .map(|s| {
+                            s.split(',')
+                                .map(str::trim)
+                                .filter(|s| !s.is_empty())
+                                .map(str::to_string)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    disable_udp: group_flag(&parts, "disable-udp"),
+                    hidden: group_flag(&parts, "hidden"),
+                    icon: group_option(&parts, "icon").map(str::to_string),
                     proxies: square_brackets_rules,
                     proxies_regexp: group_regular.or(any_regular),
-                    ..Default::default()
+                    exclude_regexp: exclude_regular,
+                    limit,
                 });
+                // 正则在填充代理组时才使用，提前检查，错误的正则不会在后面 panic
+                if let Some(group) = custom_proxy_group.last() {
+                    for pattern in [&group.proxies_regexp, &group.exclude_regexp]
+                        .into_iter()
+                        .flatten()
+                    {
+                        if let Err(e) = FancyRegex::new(pattern) {
+                            return Err(Failure::new(
+                                ErrorKind::Config,
+                                tr!(
+                                    "无效的正则：{}，{}：{}",
+                                    "invalid regex {}: {}: {}",
+                                    pattern,
+                                    e,
+                                    value
+                                ),
+                            ));
+                        }
+                    }
+                }
             }
         }
     }
     // 转换为 Vec
     let ruleset_names_vec: Vec<String> = ruleset_names.into_iter().collect();
 
-    (ruleset_names_vec, ruleset, custom_proxy_group)
+    Ok((ruleset_names_vec, ruleset, custom_proxy_group))
 }
 
-pub fn modify_proxy_groups(
-    pending_proxy_group: Vec<SelectGroup>,
-    proxy_names: Vec<String>,
-    ruleset_names: Vec<String>,
-) -> String {
-    let mut custom_proxy_group = pending_proxy_group.clone();
-    let mut remove_proxy_group_proxies_names: Vec<String> = Vec::new();
-
-    // 用于填充含有规则且proxies为空的
-    let mut default_names = if let Some(last) = ruleset_names.last().cloned() {
-        vec![last, "DIRECT".to_string()]
+/// ini 中额外的节点：`[Proxy]` 段中的 名称=节点，以及任意段中的 custom_proxy=节点，加在合并后的节点列表后面
+///
+/// 节点可以是分享链接（ss://、vmess:// 等）、yaml/json 格式的一个节点（例如 {type: socks5, server: 192.168.1.2, port: 1080}），
+/// 或者 direct（本机直连的节点）；[Proxy] 段中的节点使用 key 作为名称，custom_proxy 的节点需要自带名称
+pub fn read_extra_proxies(config: &Ini) -> Result<Vec<YamlValue>, Failure> {
+    let mut proxies = Vec::new();
+    for (sec, prop) in config {
+        let is_proxy_section = sec.is_some_and(|s| s.eq_ignore_ascii_case("proxy"));
+        for (key, value) in prop.iter() {
+            let name = if is_proxy_section {
+                Some(key)
+            } else if key == "custom_proxy" {
+                None
+            } else {
+                continue;
+            };
+            let proxy = parse_extra_proxy(name, value).map_err(|e| {
+                Failure::new(ErrorKind::Config, format!("{}：{}={}", e, key, value))
+            })?;
+            proxies.push(proxy);
+        }
+    }
+    Ok(proxies)
+}
+
+/// 解析一个额外的节点，name 不为空时替换节点中的名称，名称总是放在第一个字段
+fn parse_extra_proxy(name: Option<&str>, value: &str) -> Result<YamlValue, String> {
+    let value = value.trim();
+    let node = if value.eq_ignore_ascii_case("direct") {
+        Mapping::from_iter([("type".into(), "direct".into())])
+    } else if links::is_share_link(value) {
+        match links::parse_link(value)? {
+            YamlValue::Mapping(map) => map,
+            _ => Mapping::new(),
+        }
     } else {
-        vec!["DIRECT".to_string()] // 理论这个分支不可能出现？除非ruleset_names为空
+        match serde_yaml::from_str(value) {
+            Ok(YamlValue::Mapping(map)) => map,
+            _ => Mapping::new(),
+        }
+    };
+    if !node.contains_key("type") {
+        return Err(tr!(
+            "无效的节点(需要分享链接、带有 type 的 yaml 节点或者 direct)",
+            "invalid proxy (expected a share link, a yaml proxy with a type, or direct)"
+        ));
+    }
+    let name = match name {
+        Some(name) => name.trim().into(),
+        None => node
+            .get("name")
+            .filter(|n| n.as_str().is_some_and(|n| !n.trim().is_empty()))
+            .cloned()
+            .ok_or_else(|| tr!("节点缺少名称", "the proxy has no name"))?,
+    };
+    let mut proxy = Mapping::from_iter([("name".into(), name)]);
+    proxy.extend(node.into_iter().filter(|(k, _)| k.as_str() != Some("name")));
+    Ok(YamlValue::Mapping(proxy))
+}
+
+/// 将代理组序列化为 proxy-groups 的yaml字符串（已调整缩进）
+pub fn proxy_groups_to_yaml(custom_proxy_group: &[SelectGroup]) -> String {
+    // 使用结构体，方便序列化后，字段的顺序保持一致
+    let proxy_group_struct = ProxyGroup {
+        group: custom_proxy_group,
     };
-    default_names.extend(proxy_names.clone());
+
+    indent::to_indented_yaml(&proxy_group_struct)
+}
+
+/// 给代理组 `use 引用的 proxy-providers 加上 health-check，测试地址、间隔、超时、lazy、expected-status 跟代理组一致
+///
+/// 多个代理组引用同一个 proxy-provider 时使用第一个，base.yaml 中已经写了 health-check 的不修改
+pub fn apply_provider_health_checks(config: &mut YamlValue, groups: &[SelectGroup]) {
+    let Some(providers) = config
+        .get_mut("proxy-providers")
+        .and_then(|v| v.as_mapping_mut())
+    else {
+        return;
+    };
+    for group in groups {
+        let (Some(url), Some(interval)) = (&group.url, group.interval) else {
+            continue;
+        };
+        for name in &group.use_providers {
+            let Some(provider) = providers
+                .get_mut(name.as_str())
+                .and_then(|v| v.as_mapping_mut())
+            else {
+                continue;
+            };
+            if provider.contains_key("health-check") {
+                continue;
+            }
+            let mut health_check = Mapping::new();
+            health_check.insert("enable".into(), true.into());
+            health_check.insert("url".into(), url.as_str().into());
+            health_check.insert("interval".into(), interval.into());
+            if let Some(timeout) = group.timeout {
+                health_check.insert("timeout".into(), timeout.into());
+            }
+            if let Some(lazy) = group.lazy {
+                health_check.insert("lazy".into(), lazy.into());
+            }
+            if let Some(status) = &group.expected_status {
+                health_check.insert("expected-status".into(), status.as_str().into());
+            }
+            provider.insert("health-check".into(), YamlValue::Mapping(health_check));
+        }
+    }
+}
+
+/// 根据节点名称填充代理组，并按 policy 处理没有节点的代理组
+///
+/// 返回处理后的代理组，以及被移除的、有规则指向的代理组 => 规则应该改为指向的代理组
+pub fn resolve_proxy_groups(
+    pending_proxy_group: Vec<SelectGroup>,
+    proxy_names: &[&str],
+    ruleset_names: &[String],
+    policy: EmptyGroupPolicy,
+    fallback: PruneFallback,
+) -> (Vec<SelectGroup>, HashMap<String, String>) {
+    let mut custom_proxy_group = pending_proxy_group;
 
     for proxy_group in &mut custom_proxy_group {
         let pattern_option = proxy_group.proxies_regexp.clone().unwrap_or_default();
 
         if !pattern_option.is_empty() {
-            let re = FancyRegex::new(&pattern_option).unwrap();
-            let filter_node_names: Vec<String> = proxy_names
+            // read_ini 中已经检查过正则
+            let re = FancyRegex::new(&pattern_option).expect("无效的正则");
+            let exclude_re = proxy_group
+                .exclude_regexp
+                .as_ref()
+                .map(|p| FancyRegex::new(p).expect("无效的正则"));
+            let mut filter_node_names: Vec<String> = proxy_names
                 .iter()
                 .filter(|name| re.is_match(name).unwrap_or(false))
+                .filter(|name| {
+                    exclude_re
+                        .as_ref()
+                        .is_none_or(|re| !re.is_match(name).unwrap_or(false))
+                })
                 .map(|name| name.to_string())
                 .collect();
+            if let Some(limit) = proxy_group.limit {
+                filter_node_names = limit.apply(filter_node_names);
+            }
             proxy_group.proxies.extend(filter_node_names);
         }
-        // 确保有规则对应的分组，proxies不为空，如果实际为空，则移除该分组
-        if proxy_group.proxies.is_empty() && !ruleset_names.contains(&proxy_group.name) {
-            if ruleset_names.contains(&proxy_group.name) {
-                // 防止有规则的分组，没有对应的proxies（先添加它，防止后面误判将整个分组都删除）
-                proxy_group.proxies.extend(default_names.clone());
-            } else {
-                // 没有规则的分组，可以移除，但是在其它分组的proxies内中有这个分组名称
-                remove_proxy_group_proxies_names.push(proxy_group.name.clone());
+        if !proxy_group.has_members() {
+            match policy {
+                EmptyGroupPolicy::Direct => proxy_group.proxies.push("DIRECT".to_string()),
+                EmptyGroupPolicy::All => proxy_group
+                    .proxies
+                    .extend(proxy_names.iter().map(|name| name.to_string())),
+                EmptyGroupPolicy::Remove => {} // 下面统一移除
             }
         }
 
@@ -185,25 +605,116 @@ pub fn modify_proxy_groups(
         proxy_group.proxies_regexp = None;
     }
 
-    // 移除proxies为空的代理分组
-    custom_proxy_group.retain(|selectgroup| !selectgroup.proxies.is_empty());
-    // 移除proxies内无效的分组名称
-    custom_proxy_group.iter_mut().for_each(|selectgroup| {
-        selectgroup
-            .proxies
-            .retain(|pn| !remove_proxy_group_proxies_names.contains(pn));
-        // 这个防止本来proxies不为空，但是删除分组名称，导致为空了
-        if selectgroup.proxies.is_empty() {
-            selectgroup.proxies.extend(default_names.clone());
+    // 移除proxies为空的代理分组，同时去掉其它分组proxies内它的名称；
+    // 因此变为空的分组也要移除，直到没有空的分组（DIRECT、REJECT 等特殊策略总是有效的）
+    let mut removed_names: Vec<String> = Vec::new();
+    loop {
+        let before = removed_names.len();
+        custom_proxy_group.retain(|selectgroup| {
+            let keep = selectgroup.has_members();
+            if !keep {
+                removed_names.push(selectgroup.name.clone());
+            }
+            keep
+        });
+        if removed_names.len() == before {
+            break;
         }
-    });
+        custom_proxy_group.iter_mut().for_each(|selectgroup| {
+            selectgroup
+                .proxies
+                .retain(|pn| is_special_proxy(pn) || !removed_names.contains(pn));
+        });
+    }
 
-    // 使用结构体，方便序列化后，字段的顺序保持一致
-    let proxy_group_struct = ProxyGroup {
-        group: custom_proxy_group,
-    };
+    // 被移除的有规则的代理组，规则改为指向兜底的代理组，它也被移除了就用 DIRECT
+    let fallback = match fallback {
+        PruneFallback::Final => ruleset_names.last(),
+        PruneFallback::Main => custom_proxy_group
+            .iter()
+            .find(|g| g.select_type == "select")
+            .map(|g| &g.name),
+        PruneFallback::Direct => None,
+    }
+    .filter(|name| custom_proxy_group.iter().any(|g| &g.name == *name))
+    .cloned()
+    .unwrap_or_else(|| "DIRECT".to_string());
+    let rewrites: HashMap<String, String> = removed_names
+        .into_iter()
+        .filter(|name| ruleset_names.contains(name))
+        .map(|name| (name, fallback.clone()))
+        .collect();
 
-    let proxy_group_string = serde_yaml::to_string(&proxy_group_struct).unwrap();
+    (custom_proxy_group, rewrites)
+}
+
+/// 检查代理组之间的引用，存在循环引用时返回错误（mihomo 会拒绝这样的配置）
+///
+/// reorder 为 true 时按依赖关系排序：被引用的代理组排在引用它的代理组前面，其它的保持原来的顺序。
+pub fn order_proxy_groups(
+    groups: Vec<SelectGroup>,
+    reorder: bool,
+) -> Result<Vec<SelectGroup>, String> {
+    let index: IndexMap<&str, usize> = groups
+        .iter()
+        .enumerate()
+        .map(|(i, g)| (g.name.as_str(), i))
+        .collect();
+    // 每个代理组引用的其它代理组
+    let deps: Vec<Vec<usize>> = groups
+        .iter()
+        .map(|g| {
+            g.proxies
+                .iter()
+                .filter_map(|p| index.get(p.as_str()).copied())
+                .collect()
+        })
+        .collect();
+
+    let mut done = vec![false; groups.len()];
+    let mut order: Vec<usize> = Vec::with_capacity(groups.len());
+    while order.len() < groups.len() {
+        // 每次取原来顺序中最靠前、引用的代理组都已经排好的
+        let next = (0..groups.len()).find(|&i| !done[i] && deps[i].iter().all(|&d| done[d]));
+        match next {
+            Some(i) => {
+                done[i] = true;
+                order.push(i);
+            }
+            None => {
+                let start = (0..groups.len()).find(|&i| !done[i]).unwrap();
+                return Err(tr!(
+                    "代理组之间存在循环引用：{}",
+                    "reference cycle between groups: {}",
+                    find_cycle(start, &deps, &done)
+                        .iter()
+                        .map(|&i| groups[i].name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                ));
+            }
+        }
+    }
 
-    proxy_group_string
+    if !reorder {
+        return Ok(groups);
+    }
+    let mut slots: Vec<Option<SelectGroup>> = groups.into_iter().map(Some).collect();
+    Ok(order.into_iter().filter_map(|i| slots[i].take()).collect())
+}
+
+/// 从 start 出发沿着未排好的引用一直走，直到回到走过的代理组，返回这个环
+fn find_cycle(start: usize, deps: &[Vec<usize>], done: &[bool]) -> Vec<usize> {
+    let mut path = vec![start];
+    let mut current = start;
+    loop {
+        // 没有排好的代理组，一定还引用着没有排好的代理组
+        current = *deps[current].iter().find(|&&d| !done[d]).unwrap();
+        if let Some(pos) = path.iter().position(|&i| i == current) {
+            let mut cycle = path.split_off(pos);
+            cycle.push(current);
+            return cycle;
+        }
+        path.push(current);
+    }
 }
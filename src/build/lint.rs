@@ -0,0 +1,279 @@
+use crate::{
+    build::{
+        cidr,
+        conflict::{self, IssueKind},
+        constants::SPECIAL_PROXIES,
+        ini::{self as MyIni, SelectGroup},
+        mathrule,
+    },
+    utils::i18n::tr,
+};
+use indexmap::IndexSet;
+use serde_yaml::Value as YamlValue;
+use std::collections::HashMap;
+
+/// 问题的严重程度：error 会导致 mihomo 拒绝加载配置，warning 只是不符合预期
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Error,
+    Warning,
+}
+
+impl LintLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LintLevel::Error => "error",
+            LintLevel::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LintIssue {
+    pub level: LintLevel,
+    pub message: String,
+}
+
+/// 配置中某个键的所有字符串，例如所有节点的名称
+fn names_of(config: &YamlValue, key: &str) -> Vec<String> {
+    config
+        .get(key)
+        .and_then(|v| v.as_sequence())
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("name").and_then(|v| v.as_str()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// 配置中某个映射的所有键，例如 rule-providers 中的规则集名称
+fn keys_of(config: &YamlValue, key: &str) -> IndexSet<String> {
+    config
+        .get(key)
+        .and_then(|v| v.as_mapping())
+        .into_iter()
+        .flatten()
+        .filter_map(|(k, _)| k.as_str().map(str::to_string))
+        .collect()
+}
+
+fn string_list(value: Option<&YamlValue>) -> Vec<String> {
+    value
+        .and_then(|v| v.as_sequence())
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str().map(str::to_string))
+        .collect()
+}
+
+/// 检查任意的 clash/mihomo 配置（不限于本工具生成的）：重复的名称、引用了不存在的节点/代理组/规则集、
+/// 代理组的循环引用、无效的CIDR、永远不会生效的规则
+pub fn lint_config(config: &YamlValue) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+    let mut push = |level: LintLevel, message: String| issues.push(LintIssue { level, message });
+
+    // 节点
+    let nodes = names_of(config, "proxies");
+    let mut node_set = IndexSet::new();
+    for name in &nodes {
+        if !node_set.insert(name.as_str()) {
+            push(
+                LintLevel::Error,
+                tr!("节点名称重复：{}", "duplicate node name: {}", name),
+            );
+        }
+    }
+
+    // 代理组
+    let group_values: Vec<&YamlValue> = config
+        .get("proxy-groups")
+        .and_then(|v| v.as_sequence())
+        .into_iter()
+        .flatten()
+        .collect();
+    let proxy_providers = keys_of(config, "proxy-providers");
+    let mut group_set = IndexSet::new();
+    for name in names_of(config, "proxy-groups") {
+        if node_set.contains(name.as_str()) {
+            push(
+                LintLevel::Error,
+                tr!(
+                    "代理组跟节点的名称相同：{}",
+                    "group has the same name as a node: {}",
+                    name
+                ),
+            );
+        }
+        if !group_set.insert(name.clone()) {
+            push(
+                LintLevel::Error,
+                tr!("代理组名称重复：{}", "duplicate group name: {}", name),
+            );
+        }
+    }
+    let is_policy = |name: &str| {
+        node_set.contains(name) || group_set.contains(name) || SPECIAL_PROXIES.contains(&name)
+    };
+
+    let mut groups = Vec::new();
+    for group in &group_values {
+        let name = group
+            .get("name")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let members = string_list(group.get("proxies"));
+        let uses = string_list(group.get("use"));
+        for member in members.iter().filter(|m| !is_policy(m)) {
+            push(
+                LintLevel::Error,
+                tr!(
+                    "代理组 {} 引用了不存在的节点或代理组：{}",
+                    "group {} references a missing node or group: {}",
+                    name,
+                    member
+                ),
+            );
+        }
+        for provider in uses.iter().filter(|p| !proxy_providers.contains(*p)) {
+            push(
+                LintLevel::Error,
+                tr!(
+                    "代理组 {} 引用了不存在的 proxy-provider：{}",
+                    "group {} references a missing proxy-provider: {}",
+                    name,
+                    provider
+                ),
+            );
+        }
+        let include_all = [
+            "include-all",
+            "include-all-proxies",
+            "include-all-providers",
+        ]
+        .iter()
+        .any(|key| group.get(key).and_then(|v| v.as_bool()) == Some(true));
+        if members.is_empty() && uses.is_empty() && !include_all {
+            push(
+                LintLevel::Warning,
+                tr!("代理组 {} 没有任何节点", "group {} has no nodes", name),
+            );
+        }
+        groups.push(SelectGroup {
+            name,
+            proxies: members,
+            ..Default::default()
+        });
+    }
+    if let Err(e) = MyIni::order_proxy_groups(groups, false) {
+        push(LintLevel::Error, e);
+    }
+
+    // 规则（包括 sub-rules 中的）
+    let rule_providers = keys_of(config, "rule-providers");
+    let sub_rules = keys_of(config, "sub-rules");
+    let mut rule_lists: Vec<(String, Vec<String>)> =
+        vec![("rules".to_string(), string_list(config.get("rules")))];
+    for name in &sub_rules {
+        rule_lists.push((
+            format!("sub-rules.{}", name),
+            string_list(config.get("sub-rules").and_then(|v| v.get(name))),
+        ));
+    }
+    for (section, rules) in &rule_lists {
+        let mut after_match = false;
+        for (i, rule) in rules.iter().enumerate() {
+            let at = tr!("{} 第 {} 条 {}", "{} #{} {}", section, i + 1, rule);
+            if after_match {
+                push(
+                    LintLevel::Warning,
+                    tr!(
+                        "{}：在 MATCH 之后，不会生效",
+                        "{}: after MATCH, never takes effect",
+                        at
+                    ),
+                );
+                after_match = false; // 只提示第一条
+            }
+            let Some(parsed) = mathrule::RuleLine::parse(rule) else {
+                push(
+                    LintLevel::Error,
+                    tr!("{}：无法解析的规则", "{}: unparsable rule", at),
+                );
+                continue;
+            };
+            let kind = parsed.kind.to_uppercase();
+            if kind == "MATCH" || kind == "FINAL" {
+                after_match = section == "rules" && i + 1 < rules.len();
+            }
+            let Some(target) = parsed.group.as_deref() else {
+                push(
+                    LintLevel::Error,
+                    tr!("{}：缺少代理组", "{}: missing group", at),
+                );
+                continue;
+            };
+
+            if kind == "SUB-RULE" {
+                if !sub_rules.contains(target) {
+                    push(
+                        LintLevel::Error,
+                        tr!(
+                            "{}：不存在的 sub-rule：{}",
+                            "{}: missing sub-rule: {}",
+                            at,
+                            target
+                        ),
+                    );
+                }
+            } else if !is_policy(target) {
+                push(
+                    LintLevel::Error,
+                    tr!(
+                        "{}：不存在的代理组或节点：{}",
+                        "{}: missing group or node: {}",
+                        at,
+                        target
+                    ),
+                );
+            }
+            let payload = parsed.payload.as_str();
+            match kind.as_str() {
+                "RULE-SET" if !rule_providers.contains(payload) => push(
+                    LintLevel::Error,
+                    tr!(
+                        "{}：不存在的 rule-provider：{}",
+                        "{}: missing rule-provider: {}",
+                        at,
+                        payload
+                    ),
+                ),
+                "IP-CIDR" | "IP-CIDR6" | "SRC-IP-CIDR" if cidr::cidr_range(payload).is_none() => {
+                    push(
+                        LintLevel::Error,
+                        tr!("{}：无效的CIDR：{}", "{}: invalid CIDR: {}", at, payload),
+                    )
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // 冲突和被覆盖的域名规则
+    for issue in conflict::find_rule_issues(&rule_lists[0].1, &HashMap::new()) {
+        let reason = match issue.kind {
+            IssueKind::Conflict => tr!("跟前面的规则冲突", "conflicts with an earlier rule"),
+            IssueKind::Shadowed => tr!("被前面的规则覆盖", "shadowed by an earlier rule"),
+            IssueKind::Overridden => tr!(
+                "被优先级更高的规则集覆盖",
+                "overridden by a higher priority ruleset"
+            ),
+        };
+        push(
+            LintLevel::Warning,
+            tr!("{}：{}：{}", "{}: {}: {}", issue.rule, reason, issue.by),
+        );
+    }
+
+    issues
+}
@@ -1,13 +1,14 @@
-use crate::build::{constants, patterns};
+use crate::build::{filter::RuleFilter, patterns, providers::Behavior};
+use std::{borrow::Cow, fmt, net::IpAddr};
 
 #[derive(Debug, PartialEq, Eq)]
-enum CidrType {
+pub enum CidrType {
     V4,
     V6,
 }
 
 impl CidrType {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             CidrType::V4 => "IP-CIDR",
             CidrType::V6 => "IP-CIDR6",
@@ -15,51 +16,243 @@ impl CidrType {
     }
 }
 
-fn get_cidr_type(s: &str) -> Option<CidrType> {
-    let ipv4_cidr = r"^(?x)
-        (?:
-            (25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.
-            (25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.
-            (25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.
-            (25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)
-        )
-        /
-        (3[0-2]|[12]?\d)
-        $";
-
-    let ipv6_cidr = r"^(?x)
-        (
-            (
-                ([0-9A-Fa-f]{1,4}:){7}[0-9A-Fa-f]{1,4}|
-                ([0-9A-Fa-f]{1,4}:){1,7}:|
-                :(:[0-9A-Fa-f]{1,4}){1,7}|
-                ([0-9A-Fa-f]{1,4}:){1,6}:[0-9A-Fa-f]{1,4}|
-                ([0-9A-Fa-f]{1,4}:){1,5}(:[0-9A-Fa-f]{1,4}){1,2}|
-                ([0-9A-Fa-f]{1,4}:){1,4}(:[0-9A-Fa-f]{1,4}){1,3}|
-                ([0-9A-Fa-f]{1,4}:){1,3}(:[0-9A-Fa-f]{1,4}){1,4}|
-                ([0-9A-Fa-f]{1,4}:){1,2}(:[0-9A-Fa-f]{1,4}){1,5}|
-                [0-9A-Fa-f]{1,4}:((:[0-9A-Fa-f]{1,4}){1,6})|
-                :((:[0-9A-Fa-f]{1,4}){1,7}|:)
-            )
-        )
-        /
-        (12[0-8]|1[01][0-9]|[1-9]?[0-9])
-        $";
-
-    let re_ipv4 = regex::Regex::new(ipv4_cidr).unwrap();
-    let re_ipv6 = regex::Regex::new(ipv6_cidr).unwrap();
-
-    if re_ipv4.is_match(s) {
+pub fn get_cidr_type(s: &str) -> Option<CidrType> {
+    if patterns::RE_IPV4_CIDR.is_match(s) {
         Some(CidrType::V4)
-    } else if re_ipv6.is_match(s) {
+    } else if patterns::RE_IPV6_CIDR.is_match(s) {
         Some(CidrType::V6)
     } else {
         None
     }
 }
 
-pub fn extraction_rules(line: &str) -> String {
-    let match_content: Option<&str> = match patterns::RE_YAML_RULES.captures(line) {
+// 没有内容的规则类型
+const NO_PAYLOAD: [&str; 2] = ["MATCH", "FINAL"];
+
+// 逻辑规则，内容中含有嵌套的规则
+const LOGIC_RULES: [&str; 3] = ["AND", "OR", "NOT"];
+const SUB_RULE: &str = "SUB-RULE";
+
+// Surge、QuantumultX 的规则类型 => clash 的规则类型（不区分大小写）
+const DIALECT_TYPES: [(&str, &str); 11] = [
+    ("host", "DOMAIN"),
+    ("host-suffix", "DOMAIN-SUFFIX"),
+    ("host-keyword", "DOMAIN-KEYWORD"),
+    ("host-wildcard", "DOMAIN-WILDCARD"),
+    ("ip-cidr", "IP-CIDR"),
+    ("ip6-cidr", "IP-CIDR6"),
+    ("geoip", "GEOIP"),
+    ("ip-asn", "IP-ASN"),
+    ("user-agent", "USER-AGENT"),
+    ("dest-port", "DST-PORT"),
+    ("src-ip", "SRC-IP-CIDR"),
+];
+
+/// 括号是否成对出现
+fn is_balanced(s: &str) -> bool {
+    let mut depth = 0i32;
+    for c in s.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    depth == 0
+}
+
+/// 按逗号拆分，括号里面的逗号不拆分，例如 "AND,((DOMAIN,x),(DST-PORT,443))" => ["AND", "((DOMAIN,x),(DST-PORT,443))"]
+pub fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// 规则末尾的附加参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleFlag {
+    NoResolve,
+    Src,
+}
+
+impl RuleFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleFlag::NoResolve => "no-resolve",
+            RuleFlag::Src => "src",
+        }
+    }
+
+    fn parse(s: &str) -> Option<RuleFlag> {
+        match s {
+            "no-resolve" => Some(RuleFlag::NoResolve),
+            "src" => Some(RuleFlag::Src),
+            _ => None,
+        }
+    }
+}
+
+/// 一条规则：类型、内容、代理组、附加参数，输出时代理组在内容和参数之间
+///
+/// 例如 "IP-CIDR,1.1.1.1/32,代理组,no-resolve"；规则文件中的规则没有代理组（或者带有会被替换掉的策略），
+/// SUB-RULE 的代理组为子规则的名称
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleLine {
+    pub kind: String,
+    pub payload: String, // MATCH 没有内容
+    pub group: Option<String>,
+    pub flags: Vec<RuleFlag>,
+}
+
+impl RuleLine {
+    /// 解析一行规则：yaml（"- 'xxx'"）或 list 格式，兼容 Surge、QuantumultX 的规则类型，
+    /// 只有域名（"+." 开头的为后缀）或 IP 段的行分别转为 DOMAIN/DOMAIN-SUFFIX、IP-CIDR/IP-CIDR6 规则
+    ///
+    /// 空行、注释、yaml 的 payload 行以及无法识别的内容返回 None
+    pub fn parse(line: &str) -> Option<RuleLine> {
+        let content = if line.trim_start().starts_with("- ") {
+            match patterns::RE_YAML_RULES.captures(line) {
+                Ok(Some(captures)) => captures.get(2).or(captures.get(3))?.as_str(),
+                _ => line,
+            }
+        } else {
+            line
+        };
+        let content = content.trim();
+        if content.is_empty() || content.starts_with('#') || content.starts_with("payload:") {
+            return None;
+        }
+        Self::parse_rule(&translate_dialect(content))
+    }
+
+    /// 解析已经去掉 yaml 格式、转换了规则类型写法的一条规则
+    fn parse_rule(rule: &str) -> Option<RuleLine> {
+        if rule.contains(',') {
+            return Self::parse_fields(rule);
+        }
+        if let Some(suffix) = rule.strip_prefix("+.").filter(|s| !s.is_empty()) {
+            return Some(RuleLine::new("DOMAIN-SUFFIX", suffix));
+        }
+        if patterns::RE_YAML_DOMAIN.is_match(rule).unwrap_or_default() {
+            return Some(RuleLine::new("DOMAIN", rule));
+        }
+        if let Some(cidr_type) = get_cidr_type(rule) {
+            let mut rule = RuleLine::new(cidr_type.as_str(), rule);
+            rule.flags.push(RuleFlag::NoResolve);
+            return Some(rule);
+        }
+        Self::parse_fields(rule)
+    }
+
+    fn new(kind: &str, payload: &str) -> RuleLine {
+        RuleLine {
+            kind: kind.to_string(),
+            payload: payload.to_string(),
+            group: None,
+            flags: Vec::new(),
+        }
+    }
+
+    /// 解析逗号分隔的 类型,内容[,代理组][,参数]，多余的字段被丢弃
+    fn parse_fields(s: &str) -> Option<RuleLine> {
+        let mut parts = split_top_level(s.trim()).into_iter().map(str::trim);
+        let kind = parts.next().filter(|k| !k.is_empty())?.to_string();
+        if NO_PAYLOAD.contains(&kind.as_str()) {
+            return Some(RuleLine {
+                kind,
+                payload: String::new(),
+                group: parts.next().filter(|g| !g.is_empty()).map(str::to_string),
+                flags: Vec::new(),
+            });
+        }
+        let payload = parts.next().filter(|p| !p.is_empty())?.to_string();
+        let balanced = payload.starts_with('(') && payload.ends_with(')') && is_balanced(&payload);
+        // 逻辑规则和 SUB-RULE 的内容必须是完整的括号，例如 ((DOMAIN,x),(DST-PORT,443))
+        if (LOGIC_RULES.contains(&kind.as_str()) || kind == SUB_RULE) && !balanced {
+            return None;
+        }
+        let mut group = None;
+        let mut flags = Vec::new();
+        for part in parts {
+            match RuleFlag::parse(part) {
+                Some(flag) => flags.push(flag),
+                None if group.is_none() && !part.is_empty() => group = Some(part.to_string()),
+                None => {}
+            }
+        }
+        if kind == SUB_RULE {
+            // SUB-RULE,(条件),子规则名称：目标是子规则而不是代理组
+            flags.clear();
+            group.as_ref()?;
+        }
+        Some(RuleLine {
+            kind,
+            payload,
+            group,
+            flags,
+        })
+    }
+
+    /// 换成这个代理组，输出完整的规则（SUB-RULE 指向子规则，原样输出）
+    pub fn with_group(&self, group: &str) -> String {
+        if self.kind == SUB_RULE {
+            return self.to_string();
+        }
+        RuleLine {
+            group: Some(group.to_string()),
+            ..self.clone()
+        }
+        .to_string()
+    }
+
+    /// 去掉代理组之后的内容，例如 "DOMAIN,a.com,代理组" => "DOMAIN,a.com"（SUB-RULE 原样输出）
+    pub fn without_group(&self) -> String {
+        if self.kind == SUB_RULE {
+            return self.to_string();
+        }
+        RuleLine {
+            group: None,
+            ..self.clone()
+        }
+        .to_string()
+    }
+}
+
+impl fmt::Display for RuleLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.kind)?;
+        if !self.payload.is_empty() {
+            write!(f, ",{}", self.payload)?;
+        }
+        if let Some(group) = &self.group {
+            write!(f, ",{}", group)?;
+        }
+        for flag in &self.flags {
+            write!(f, ",{}", flag.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// 提取yaml格式（"- 'xxx'"）或者list格式规则文件中一行的内容
+fn match_content<'a>(line: &'a str, rule_filter: &RuleFilter) -> Option<&'a str> {
+    match patterns::RE_YAML_RULES.captures(line) {
         Ok(Some(captures)) => {
             if captures.get(2).is_some() {
                 // 存在引号
@@ -73,27 +266,194 @@ pub fn extraction_rules(line: &str) -> String {
         }
         _ => {
             // 匹配失败或其他错误，暂时过滤掉FILTER_KEY不要的内容，后续再次处理
-            match constants::FILTER_KEY.iter().all(|kw| !line.contains(kw)) {
+            match !rule_filter.is_filtered(line) {
                 true => Some(line),
                 false => None,
             }
         }
+    }
+}
+
+/// 通配符域名转为 DOMAIN-REGEX 规则，例如："*.example.com" => "DOMAIN-REGEX,^[^.]+\.example\.com$"
+pub fn domain_wildcard_rule(domain: &str) -> String {
+    let regex = domain.replace('.', r"\.").replace('*', r"[^.]+");
+    format!("DOMAIN-REGEX,^{}$", regex)
+}
+
+/// 按照ini中声明的规则集类型（clash-domain:、clash-ipcidr:）提取规则，不再根据内容猜测
+pub fn extraction_typed_rules(
+    line: &str,
+    behavior: Option<Behavior>,
+    rule_filter: &RuleFilter,
+) -> Option<RuleLine> {
+    let rule = match behavior {
+        Some(Behavior::Domain) | Some(Behavior::IpCidr) => {
+            match_content(line, rule_filter).unwrap_or_default().trim()
+        }
+        _ => return extraction_rules(line, rule_filter),
     };
-    let rule: &str = match_content.unwrap_or_default();
-    if !rule.is_empty() {
-        if constants::INCLUDE_KEY.iter().any(|kw| rule.contains(kw)) {
-            rule.to_string()
-        } else if rule.starts_with("+.") {
-            format!("DOMAIN-SUFFIX,{}", rule.trim_start_matches("+."))
-        } else if !rule.is_empty() && patterns::RE_YAML_DOMAIN.is_match(rule).unwrap_or_default() {
-            format!("DOMAIN,{}", rule).to_string()
-        } else if get_cidr_type(rule).is_some() {
-            let ip_cidr: &str = get_cidr_type(rule).map(|ct| ct.as_str()).unwrap_or("");
-            format!("{},{},no-resolve", ip_cidr, rule)
+    // 内容本身已经是完整的规则（例如 mrs 解码后的规则），按普通规则处理
+    if rule.is_empty() || rule.contains(',') {
+        return extraction_rules(line, rule_filter);
+    }
+
+    if behavior == Some(Behavior::Domain) {
+        if let Some(suffix) = rule.strip_prefix("+.").or_else(|| rule.strip_prefix('.')) {
+            Some(RuleLine::new("DOMAIN-SUFFIX", suffix))
+        } else if rule.contains('*') {
+            RuleLine::parse_rule(&domain_wildcard_rule(rule))
         } else {
-            "".to_string()
+            Some(RuleLine::new("DOMAIN", rule))
         }
     } else {
-        "".to_string()
+        // 单独的IP地址补全前缀长度
+        let cidr = match rule.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => format!("{}/32", rule),
+            Ok(IpAddr::V6(_)) => format!("{}/128", rule),
+            Err(_) => rule.to_string(),
+        };
+        let cidr_type = get_cidr_type(&cidr)?;
+        let mut rule = RuleLine::new(cidr_type.as_str(), &cidr);
+        rule.flags.push(RuleFlag::NoResolve);
+        Some(rule)
+    }
+}
+
+/// 其它客户端的规则写法转为 clash 的写法：QuantumultX 的 host-suffix 等类型，
+/// 以及 Surge DOMAIN-SET 中 . 开头的域名（转为 DOMAIN-SUFFIX）
+fn translate_dialect(rule: &str) -> Cow<'_, str> {
+    if let Some(suffix) = rule.strip_prefix('.')
+        && patterns::RE_YAML_DOMAIN
+            .is_match(suffix)
+            .unwrap_or_default()
+    {
+        return Cow::Owned(format!("DOMAIN-SUFFIX,{}", suffix));
+    }
+    let Some((kind, rest)) = rule.split_once(',') else {
+        return Cow::Borrowed(rule);
+    };
+    match DIALECT_TYPES
+        .iter()
+        .find(|(dialect, _)| dialect.eq_ignore_ascii_case(kind.trim()))
+    {
+        Some((_, clash_kind)) => Cow::Owned(format!("{},{}", clash_kind, rest.trim_start())),
+        None => Cow::Borrowed(rule),
+    }
+}
+
+/// 提取规则文件中的一行规则，被过滤掉的内容和不需要的规则类型返回 None
+pub fn extraction_rules(line: &str, rule_filter: &RuleFilter) -> Option<RuleLine> {
+    let rule = translate_dialect(match_content(line, rule_filter)?);
+    let parsed = RuleLine::parse_rule(rule.trim())?;
+    // 只有域名、IP段的行（没有类型）按内容补全类型，完整的规则只保留需要的类型
+    let inferred = !rule.contains(',') && !parsed.payload.is_empty();
+    (inferred || rule_filter.is_included(&rule)).then_some(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 解析后再输出，跟原来的规则相同
+    fn round_trip(rule: &str) -> RuleLine {
+        let parsed = RuleLine::parse(rule).unwrap_or_else(|| panic!("无法解析：{}", rule));
+        assert_eq!(parsed.to_string(), rule);
+        parsed
+    }
+
+    #[test]
+    fn flags_stay_after_the_group() {
+        let rule = round_trip("IP-CIDR,1.1.1.1/32,代理组,no-resolve");
+        assert_eq!(rule.group.as_deref(), Some("代理组"));
+        assert_eq!(rule.flags, [RuleFlag::NoResolve]);
+        assert_eq!(
+            rule.with_group("其它"),
+            "IP-CIDR,1.1.1.1/32,其它,no-resolve"
+        );
+        assert_eq!(rule.without_group(), "IP-CIDR,1.1.1.1/32,no-resolve");
+        round_trip("IP-CIDR6,2001:db8::/32,代理组,no-resolve,src");
+
+        // 规则文件中没有代理组的规则
+        let rule = round_trip("IP-CIDR,10.0.0.0/8,no-resolve");
+        assert_eq!(rule.group, None);
+        assert_eq!(
+            rule.with_group("DIRECT"),
+            "IP-CIDR,10.0.0.0/8,DIRECT,no-resolve"
+        );
+
+        // 只有 IP 段的行补全类型和 no-resolve
+        assert_eq!(
+            RuleLine::parse("192.168.0.0/16").unwrap().to_string(),
+            "IP-CIDR,192.168.0.0/16,no-resolve"
+        );
+    }
+
+    #[test]
+    fn logic_rules_keep_nested_commas() {
+        let rule = "AND,((DOMAIN,a.com),(OR,((DST-PORT,443),(NETWORK,UDP)))),代理组";
+        assert_eq!(
+            split_top_level(rule),
+            [
+                "AND",
+                "((DOMAIN,a.com),(OR,((DST-PORT,443),(NETWORK,UDP))))",
+                "代理组"
+            ]
+        );
+        let parsed = round_trip(rule);
+        assert_eq!(parsed.kind, "AND");
+        assert_eq!(
+            parsed.with_group("其它"),
+            "AND,((DOMAIN,a.com),(OR,((DST-PORT,443),(NETWORK,UDP)))),其它"
+        );
+        round_trip("NOT,((DOMAIN-SUFFIX,ads.com)),REJECT");
+        round_trip("OR,((NETWORK,UDP),(DST-PORT,53))");
+
+        // 括号不完整的逻辑规则无效
+        assert_eq!(RuleLine::parse("AND,((DOMAIN,a.com),代理组"), None);
+        assert_eq!(RuleLine::parse("OR,DOMAIN,a.com"), None);
+    }
+
+    #[test]
+    fn sub_rule_and_match() {
+        let rule = round_trip("SUB-RULE,(NETWORK,tcp),sub1");
+        assert_eq!(rule.with_group("其它"), "SUB-RULE,(NETWORK,tcp),sub1");
+        assert_eq!(RuleLine::parse("SUB-RULE,(NETWORK,tcp)"), None);
+
+        let rule = round_trip("MATCH,代理组");
+        assert_eq!(rule.payload, "");
+        assert_eq!(RuleLine::parse("MATCH").unwrap().with_group("G"), "MATCH,G");
+    }
+
+    #[test]
+    fn dialects_are_translated() {
+        for (dialect, clash) in [
+            (
+                "host-suffix, example.com, 代理组",
+                "DOMAIN-SUFFIX,example.com,代理组",
+            ),
+            ("HOST,example.com,代理组", "DOMAIN,example.com,代理组"),
+            ("host-keyword,google", "DOMAIN-KEYWORD,google"),
+            (
+                "ip6-cidr,2001:db8::/32,代理组",
+                "IP-CIDR6,2001:db8::/32,代理组",
+            ),
+            ("dest-port,443,代理组", "DST-PORT,443,代理组"),
+            (".example.com", "DOMAIN-SUFFIX,example.com"),
+            ("+.example.com", "DOMAIN-SUFFIX,example.com"),
+            ("example.com", "DOMAIN,example.com"),
+            ("  - 'DOMAIN,example.com'", "DOMAIN,example.com"),
+            ("  - host,example.com", "DOMAIN,example.com"),
+            ("PROCESS-NAME,curl,DIRECT", "PROCESS-NAME,curl,DIRECT"),
+        ] {
+            assert_eq!(
+                RuleLine::parse(dialect).map(|r| r.to_string()).as_deref(),
+                Some(clash),
+                "{}",
+                dialect
+            );
+        }
+        for ignored in ["", "# 注释", "payload:", "  - "] {
+            assert_eq!(RuleLine::parse(ignored), None, "{:?}", ignored);
+        }
     }
 }
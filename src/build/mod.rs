@@ -1,8 +1,21 @@
+pub mod bench;
+pub mod cidr;
+pub mod compact;
+pub mod compile;
+pub mod conflict;
 pub mod constants;
+pub mod dns;
 pub mod download;
+pub mod extra;
+pub mod filter;
 pub mod indent;
 pub mod ini;
+pub mod lint;
 pub mod mathrule;
+pub mod mrs;
 pub mod patterns;
+pub mod precedence;
+pub mod presets;
+pub mod providers;
 pub mod rules;
 pub mod sort;
@@ -0,0 +1,325 @@
+use crate::{
+    build::{cidr::range_to_cidrs, mathrule},
+    utils::i18n::tr,
+};
+use ruzstd::{
+    decoding::StreamingDecoder,
+    encoding::{CompressionLevel, compress_to_vec},
+};
+use std::{
+    io::Read,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+// mihomo 二进制规则集(.mrs)：zstd压缩，解压后以 "MRS\x01" 开头
+const MRS_MAGIC: [u8; 4] = [b'M', b'R', b'S', 1];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+// behavior 字节
+pub const BEHAVIOR_DOMAIN: u8 = 0;
+pub const BEHAVIOR_IPCIDR: u8 = 1;
+
+/// 根据文件名或内容判断是否为 mrs 规则集
+pub fn is_mrs(path: &str, data: &[u8]) -> bool {
+    path.to_lowercase().ends_with(".mrs") || data.starts_with(&ZSTD_MAGIC)
+}
+
+/// 读取 mrs 规则集的 behavior（domain 或 ipcidr）
+pub fn mrs_behavior(data: &[u8]) -> Option<u8> {
+    let mut reader = StreamingDecoder::new(data).ok()?;
+    let header = read_bytes(&mut reader, 5).ok()?;
+    (header[..4] == MRS_MAGIC).then_some(header[4])
+}
+
+/// 读取 len 个字节：长度来自文件，不能先按长度分配内存（损坏的文件可能写着很大的长度），只读取实际有的内容
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    reader
+        .take(len as u64)
+        .read_to_end(&mut buf)
+        .map_err(|e| tr!("mrs 文件不完整：{}", "incomplete mrs file: {}", e))?;
+    if buf.len() < len {
+        return Err(tr!(
+            "mrs 文件不完整：需要 {} 字节，只有 {} 字节",
+            "incomplete mrs file: expected {} bytes, got {}",
+            len,
+            buf.len()
+        ));
+    }
+    Ok(buf)
+}
+
+fn read_i64<R: Read>(reader: &mut R) -> Result<i64, String> {
+    let buf = read_bytes(reader, 8)?;
+    Ok(i64::from_be_bytes(buf.try_into().unwrap()))
+}
+
+fn read_len<R: Read>(reader: &mut R) -> Result<usize, String> {
+    let len = read_i64(reader)?;
+    match usize::try_from(len) {
+        Ok(len) if len >= 1 => Ok(len),
+        _ => Err(tr!(
+            "mrs 文件中的长度无效：{}",
+            "invalid length in mrs file: {}",
+            len
+        )),
+    }
+}
+
+fn read_version<R: Read>(reader: &mut R) -> Result<(), String> {
+    match read_bytes(reader, 1)?[0] {
+        1 => Ok(()),
+        v => Err(tr!(
+            "不支持的 mrs 数据版本：{}",
+            "unsupported mrs data version: {}",
+            v
+        )),
+    }
+}
+
+/// 解码 mrs 规则集，转为普通的规则行（例如 "DOMAIN-SUFFIX,google.com"）
+pub fn decode_mrs(data: &[u8]) -> Result<Vec<String>, String> {
+    let mut reader = StreamingDecoder::new(data)
+        .map_err(|e| tr!("mrs 解压失败：{}", "failed to decompress mrs: {}", e))?;
+
+    if read_bytes(&mut reader, 4)? != MRS_MAGIC {
+        return Err(tr!("不是有效的 mrs 文件", "not a valid mrs file"));
+    }
+    let behavior = read_bytes(&mut reader, 1)?[0];
+    let _count = read_i64(&mut reader)?;
+
+    // 预留的扩展数据，直接跳过
+    let extra = usize::try_from(read_i64(&mut reader)?).map_err(|_| {
+        tr!(
+            "mrs 文件中的扩展数据长度无效",
+            "invalid extension data length in mrs file"
+        )
+    })?;
+    read_bytes(&mut reader, extra)?;
+
+    match behavior {
+        BEHAVIOR_DOMAIN => decode_domain_set(&mut reader),
+        BEHAVIOR_IPCIDR => decode_ipcidr_set(&mut reader),
+        other => Err(tr!(
+            "不支持的 mrs behavior：{}",
+            "unsupported mrs behavior: {}",
+            other
+        )),
+    }
+}
+
+// ————————————————————————————————————————————————————————————————————————————————————————————————————
+// domain：按层级存储（LOUDS）的字典树，每个key都是倒序的域名
+// ————————————————————————————————————————————————————————————————————————————————————————————————————
+
+struct DomainSet {
+    leaves: Vec<u64>,
+    label_bitmap: Vec<u64>,
+    labels: Vec<u8>,
+    ones: Vec<usize>, // label_bitmap 中所有为1的位置，用于 rank/select
+}
+
+fn get_bit(bm: &[u64], i: usize) -> bool {
+    bm.get(i >> 6).is_some_and(|w| w & (1u64 << (i & 63)) != 0)
+}
+
+impl DomainSet {
+    /// [0, i) 之间0的个数
+    fn count_zeros(&self, i: usize) -> usize {
+        i - self.ones.partition_point(|&p| p < i)
+    }
+
+    fn collect(&self, node_id: usize, bm_idx: usize, key: &mut Vec<u8>, out: &mut Vec<String>) {
+        if get_bit(&self.leaves, node_id) {
+            let domain: String = String::from_utf8_lossy(key).chars().rev().collect();
+            out.push(domain);
+        }
+        let mut bm_idx = bm_idx;
+        while !get_bit(&self.label_bitmap, bm_idx) {
+            let Some(&label) = self.labels.get(bm_idx - node_id) else {
+                return;
+            };
+            key.push(label);
+            let next_node = self.count_zeros(bm_idx + 1);
+            if let Some(&pos) = self.ones.get(next_node - 1) {
+                self.collect(next_node, pos + 1, key, out);
+            }
+            key.pop();
+            bm_idx += 1;
+        }
+    }
+}
+
+fn read_u64s<R: Read>(reader: &mut R) -> Result<Vec<u64>, String> {
+    let len = read_len(reader)?;
+    let bytes = len.checked_mul(8).ok_or_else(|| {
+        tr!(
+            "mrs 文件中的长度无效：{}",
+            "invalid length in mrs file: {}",
+            len
+        )
+    })?;
+    let buf = read_bytes(reader, bytes)?;
+    Ok(buf
+        .chunks_exact(8)
+        .map(|c| u64::from_be_bytes(c.try_into().unwrap()))
+        .collect())
+}
+
+fn decode_domain_set<R: Read>(reader: &mut R) -> Result<Vec<String>, String> {
+    read_version(reader)?;
+    let leaves = read_u64s(reader)?;
+    let label_bitmap = read_u64s(reader)?;
+    let labels_len = read_len(reader)?;
+    let labels = read_bytes(reader, labels_len)?;
+
+    let ones = (0..label_bitmap.len() * 64)
+        .filter(|&i| get_bit(&label_bitmap, i))
+        .collect();
+    let set = DomainSet {
+        leaves,
+        label_bitmap,
+        labels,
+        ones,
+    };
+
+    let mut domains = Vec::new();
+    set.collect(0, 0, &mut Vec::new(), &mut domains);
+
+    // "+.a.com" 已经包含了 "a.com"，不需要再单独输出
+    let suffixes: std::collections::HashSet<&str> = domains
+        .iter()
+        .filter_map(|d| d.strip_prefix("+."))
+        .collect();
+    let rules = domains
+        .iter()
+        .filter_map(|d| {
+            if let Some(suffix) = d.strip_prefix("+.") {
+                Some(format!("DOMAIN-SUFFIX,{}", suffix))
+            } else if d.contains('*') {
+                // 通配符域名，转为正则
+                Some(mathrule::domain_wildcard_rule(d))
+            } else if suffixes.contains(d.as_str()) {
+                None
+            } else {
+                Some(format!("DOMAIN,{}", d))
+            }
+        })
+        .collect();
+    Ok(rules)
+}
+
+fn set_bit(bm: &mut Vec<u64>, i: usize) {
+    if bm.len() <= i >> 6 {
+        bm.resize((i >> 6) + 1, 0);
+    }
+    bm[i >> 6] |= 1u64 << (i & 63);
+}
+
+fn write_u64s(out: &mut Vec<u8>, values: &[u64]) {
+    out.extend((values.len() as i64).to_be_bytes());
+    for v in values {
+        out.extend(v.to_be_bytes());
+    }
+}
+
+/// 编码 domain 类型的 mrs 规则集（跟 mihomo 生成的结构相同），count 为规则数
+///
+/// domains 使用 mihomo 域名树中的写法：DOMAIN a.com => "a.com"，DOMAIN-SUFFIX a.com => "a.com" 和 "+.a.com"
+pub fn encode_domain_mrs(domains: &[String], count: usize) -> Vec<u8> {
+    // 倒序的域名按字典序排列，相同前缀的连续
+    let mut keys: Vec<Vec<u8>> = domains
+        .iter()
+        .map(|d| d.chars().rev().collect::<String>().into_bytes())
+        .collect();
+    keys.sort();
+    keys.dedup();
+
+    // 按层级（广度优先）生成字典树
+    let mut leaves = Vec::new();
+    let mut label_bitmap = Vec::new();
+    let mut labels = Vec::new();
+    let mut label_index = 0;
+    let mut queue = vec![(0, keys.len(), 0)];
+    let mut node_id = 0;
+    while node_id < queue.len() {
+        let (mut start, end, col) = queue[node_id];
+        if col == keys[start].len() {
+            start += 1;
+            set_bit(&mut leaves, node_id);
+        }
+        let mut j = start;
+        while j < end {
+            let from = j;
+            while j < end && keys[j][col] == keys[from][col] {
+                j += 1;
+            }
+            queue.push((from, j, col + 1));
+            labels.push(keys[from][col]);
+            label_index += 1;
+        }
+        set_bit(&mut label_bitmap, label_index);
+        label_index += 1;
+        node_id += 1;
+    }
+
+    let mut data = Vec::new();
+    data.extend(MRS_MAGIC);
+    data.push(BEHAVIOR_DOMAIN);
+    data.extend((count as i64).to_be_bytes());
+    data.extend(0i64.to_be_bytes()); // 没有扩展数据
+    data.push(1); // 数据版本
+    write_u64s(&mut data, &leaves);
+    write_u64s(&mut data, &label_bitmap);
+    data.extend((labels.len() as i64).to_be_bytes());
+    data.extend(labels);
+    compress_to_vec(&data[..], CompressionLevel::Fastest)
+}
+
+// ————————————————————————————————————————————————————————————————————————————————————————————————————
+// ipcidr：存储的是IP范围(from, to)，需要拆分为CIDR
+// ————————————————————————————————————————————————————————————————————————————————————————————————————
+
+fn to_ip(bytes: [u8; 16]) -> IpAddr {
+    let v6 = Ipv6Addr::from(bytes);
+    match v6.to_ipv4_mapped() {
+        Some(v4) => IpAddr::V4(v4),
+        None => IpAddr::V6(v6),
+    }
+}
+
+fn decode_ipcidr_set<R: Read>(reader: &mut R) -> Result<Vec<String>, String> {
+    read_version(reader)?;
+    let len = read_len(reader)?;
+
+    let mut rules = Vec::new();
+    for _ in 0..len {
+        let from = to_ip(read_bytes(reader, 16)?.try_into().unwrap());
+        let to = to_ip(read_bytes(reader, 16)?.try_into().unwrap());
+        match (from, to) {
+            (IpAddr::V4(from), IpAddr::V4(to)) => {
+                for (start, prefix) in
+                    range_to_cidrs(u32::from(from) as u128, u32::from(to) as u128, 32)
+                {
+                    let ip = Ipv4Addr::from(start as u32);
+                    rules.push(format!("IP-CIDR,{}/{},no-resolve", ip, prefix));
+                }
+            }
+            (from, to) => {
+                let from = match from {
+                    IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+                    IpAddr::V6(v6) => v6,
+                };
+                let to = match to {
+                    IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+                    IpAddr::V6(v6) => v6,
+                };
+                for (start, prefix) in range_to_cidrs(u128::from(from), u128::from(to), 128) {
+                    let ip = Ipv6Addr::from(start);
+                    rules.push(format!("IP-CIDR6,{}/{},no-resolve", ip, prefix));
+                }
+            }
+        }
+    }
+    Ok(rules)
+}
@@ -2,8 +2,9 @@ use fancy_regex::Regex as FancyRegex;
 use once_cell::sync::Lazy;
 use regex::Regex;
 
-// 匹配坐标样子的数字: "300,,50"或者"180"（数字分别代表：interval、tolerance）
-pub static RE_INI_COORDS: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\d+)(?:,,(\d+))?$").unwrap());
+// 匹配坐标样子的数字: "300,,50"、"300,5,50"、"300,5"或者"180"（数字分别代表：interval、timeout、tolerance，后两个可以不写）
+pub static RE_INI_COORDS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(\d+)(?:,(\d*))?(?:,(\d*))?$").unwrap());
 
 // 匹配 (||||) 这种 正则表达式的字符串，粗略判断是否为正则表达式
 pub static RE_INI_GROUP: Lazy<Regex> =
@@ -12,21 +13,63 @@ pub static RE_INI_GROUP: Lazy<Regex> =
 // 匹配yaml中，每一行以若干空格开头，紧跟着"- "的行
 pub static RE_DASH_LINE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^( *)- ").unwrap());
 
+// IPv4 网段，例如 "1.1.1.0/24"（每条规则都要判断，只编译一次）
+pub static RE_IPV4_CIDR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^(?x)
+        (?:
+            (25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.
+            (25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.
+            (25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.
+            (25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)
+        )
+        /
+        (3[0-2]|[12]?\d)
+        $",
+    )
+    .unwrap()
+});
+
+// IPv6 网段，例如 "2001:db8::/32"
+pub static RE_IPV6_CIDR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^(?x)
+        (
+            (
+                ([0-9A-Fa-f]{1,4}:){7}[0-9A-Fa-f]{1,4}|
+                ([0-9A-Fa-f]{1,4}:){1,7}:|
+                :(:[0-9A-Fa-f]{1,4}){1,7}|
+                ([0-9A-Fa-f]{1,4}:){1,6}:[0-9A-Fa-f]{1,4}|
+                ([0-9A-Fa-f]{1,4}:){1,5}(:[0-9A-Fa-f]{1,4}){1,2}|
+                ([0-9A-Fa-f]{1,4}:){1,4}(:[0-9A-Fa-f]{1,4}){1,3}|
+                ([0-9A-Fa-f]{1,4}:){1,3}(:[0-9A-Fa-f]{1,4}){1,4}|
+                ([0-9A-Fa-f]{1,4}:){1,2}(:[0-9A-Fa-f]{1,4}){1,5}|
+                [0-9A-Fa-f]{1,4}:((:[0-9A-Fa-f]{1,4}){1,6})|
+                :((:[0-9A-Fa-f]{1,4}){1,7}|:)
+            )
+        )
+        /
+        (12[0-8]|1[01][0-9]|[1-9]?[0-9])
+        $",
+    )
+    .unwrap()
+});
+
 // ————————————————————————————————————————————————————————————————————————————————————————————————————
 // 下面是使用FancyRegex处理复杂的正则表达式（regex::Regex无法处理），处理速度稍慢一点
 // ————————————————————————————————————————————————————————————————————————————————————————————————————
 
-// 去掉缩进yaml字符串中数组内的 双引号，除非引号后面跟着*
-pub static RE_INDENT_QUOTES: Lazy<FancyRegex> = Lazy::new(|| {
-    FancyRegex::new(r#"(?m)^(\s*-\s)(['"])((?![^'"\n]*\*[^'"\n]*)[^'"\n]+)\2$"#).unwrap()
-});
-
 // 提取可能是yaml格式规则文件的规则内容，也能提取list规则文件的规则内容
 pub static RE_YAML_RULES: Lazy<FancyRegex> = Lazy::new(|| {
-    FancyRegex::new(r#"^\s*- (?:(['\"])((?:[^'\"]|\\'|\\")*)\1|([^\s'\"]+))$"#).unwrap()
+    FancyRegex::new(r#"^\s*- (?:(['\"])((?:[^'\"]|\\'|\\")*)\1|([^\s'\"][^'\"]*?))\s*$"#).unwrap()
 });
 
 // 匹配YAML的域名（包括子域名）
 pub static RE_YAML_DOMAIN: Lazy<FancyRegex> = Lazy::new(|| {
     FancyRegex::new(r#"^(?:(?!-)[A-Za-z0-9-]{1,63}(?<!-)\.)+[A-Za-z]{2,6}$"#).unwrap()
 });
+
+// 路径中的环境变量：${HOME} 或 %APPDATA%(Windows 的变量名可以带括号，例如 %ProgramFiles(x86)%)
+pub static RE_ENV_VAR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}|%([A-Za-z_][A-Za-z0-9_()]*)%").unwrap()
+});
@@ -0,0 +1,105 @@
+use crate::{
+    build::{
+        conflict::{IssueKind, RuleIssue},
+        mathrule,
+    },
+    utils::i18n::tr,
+};
+use std::collections::HashMap;
+
+/// 不同规则集中出现相同内容（类型+内容，不含代理组）的规则时，保留哪一个规则集的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RulePrecedence {
+    First, // ini中排在前面的规则集优先（与clash从上往下匹配的结果一致）
+    Last,  // ini中排在后面的规则集优先，例如自己写的规则集覆盖前面下载的广告规则集
+}
+
+/// 解析 --rule-precedence 参数
+pub fn parse_rule_precedence(s: &str) -> Result<RulePrecedence, String> {
+    match s.trim().to_lowercase().as_str() {
+        "first" => Ok(RulePrecedence::First),
+        "last" => Ok(RulePrecedence::Last),
+        _ => Err(tr!(
+            "无效的优先级：{}，可选 first、last",
+            "invalid precedence: {}, expected first or last",
+            s
+        )),
+    }
+}
+
+/// 每个规则集的优先级（越小越优先）：preferred 中选中的规则集按选项的顺序排在最前面，
+/// 其它的按 precedence 决定ini中的顺序
+pub fn ruleset_ranks(count: usize, precedence: RulePrecedence, preferred: &[usize]) -> Vec<usize> {
+    (0..count)
+        .map(|index| match preferred.iter().position(|i| *i == index) {
+            Some(pos) => pos,
+            None if precedence == RulePrecedence::First => preferred.len() + index,
+            None => preferred.len() + (count - 1 - index),
+        })
+        .collect()
+}
+
+/// 规则去掉代理组之后的内容，例如 "DOMAIN,a.com,代理组" => "DOMAIN,a.com"
+fn payload_key(rule: &str) -> String {
+    match mathrule::RuleLine::parse(rule) {
+        Some(rule) => rule.without_group(),
+        None => rule.to_string(),
+    }
+}
+
+/// 相同内容的规则只保留优先级最高的规则集中的，不改变规则的顺序
+///
+/// 返回保留的规则、每个规则集被覆盖（丢弃）的规则数量，
+/// 以及丢弃的规则中跟保留的规则指向不同代理组的（解决的冲突）
+pub fn resolve_precedence(
+    rules: Vec<(usize, String)>,
+    ranks: &[usize],
+) -> (Vec<(usize, String)>, Vec<usize>, Vec<RuleIssue>) {
+    // 内容 => 优先级最高的规则集
+    let mut winners: HashMap<String, usize> = HashMap::new();
+    let keys: Vec<String> = rules.iter().map(|(_, rule)| payload_key(rule)).collect();
+    for ((index, _), key) in rules.iter().zip(&keys) {
+        let winner = winners.entry(key.clone()).or_insert(*index);
+        if ranks[*index] < ranks[*winner] {
+            *winner = *index;
+        }
+    }
+
+    // 内容 => 保留的规则
+    let mut kept_rules: HashMap<&str, &str> = HashMap::new();
+    for ((index, rule), key) in rules.iter().zip(&keys) {
+        if winners[key] == *index {
+            kept_rules.entry(key).or_insert(rule);
+        }
+    }
+    let resolved: Vec<RuleIssue> = rules
+        .iter()
+        .zip(&keys)
+        .filter_map(|((index, rule), key)| {
+            let winner = winners[key];
+            let kept = kept_rules[key.as_str()];
+            (winner != *index && kept != rule).then(|| RuleIssue {
+                kind: IssueKind::Overridden,
+                rule: rule.clone(),
+                rule_source: Some(*index),
+                by: kept.to_string(),
+                by_source: Some(winner),
+            })
+        })
+        .collect();
+
+    let mut overridden = vec![0; ranks.len()];
+    let kept = rules
+        .into_iter()
+        .zip(keys)
+        .filter(|((index, _), key)| {
+            let keep = winners[key] == *index;
+            if !keep {
+                overridden[*index] += 1;
+            }
+            keep
+        })
+        .map(|(rule, _)| rule)
+        .collect();
+    (kept, overridden, resolved)
+}
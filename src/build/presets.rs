@@ -0,0 +1,114 @@
+use crate::utils::{
+    exit::{ErrorKind, Failure},
+    i18n::tr,
+};
+use ini::Ini;
+use std::path::{Path, PathBuf};
+
+/// -c 的文件不存在时使用的默认 ini（ACL4SSR 在线版，规则从网上下载，不需要本地的规则文件）
+pub const DEFAULT_INI: &str = include_str!("../../config/ACL4SSR_Online.ini");
+
+/// -b 的文件不存在时使用的默认 base.yaml
+pub const DEFAULT_BASE_YAML: &str = include_str!("../../mihomo/base.yaml");
+
+/// 内置的代理组和规则集方案（编译时嵌入），不需要 ini 文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    Minimal,     // 不下载规则，只按 IP 地址分流（局域网、中国大陆直连）
+    Acl4ssrMini, // ACL4SSR 在线精简版：去广告、自动测速
+    Full,        // ACL4SSR 在线全分组版：流媒体、各地区节点等分组
+}
+
+impl Preset {
+    /// 方案的 ini 内容
+    pub fn ini_text(&self) -> &'static str {
+        match self {
+            Preset::Minimal => include_str!("../../config/Minimal.ini"),
+            Preset::Acl4ssrMini => include_str!("../../config/ACL4SSR_Online_Mini.ini"),
+            Preset::Full => include_str!("../../config/ACL4SSR_Online_Full.ini"),
+        }
+    }
+}
+
+/// 解析 --preset 参数
+pub fn parse_preset(s: &str) -> Result<Preset, String> {
+    match s.trim().to_lowercase().as_str() {
+        "minimal" => Ok(Preset::Minimal),
+        "acl4ssr-mini" => Ok(Preset::Acl4ssrMini),
+        "full" => Ok(Preset::Full),
+        _ => Err(tr!(
+            "无效的内置方案：{}，可选 minimal、acl4ssr-mini、full",
+            "invalid preset: {}, expected minimal, acl4ssr-mini or full",
+            s
+        )),
+    }
+}
+
+/// 内置的 ini 内容：使用了 --preset 时为方案的内容，ini 文件不存在时为默认的 ini，否则为 None（读取文件）
+pub fn embedded_ini(preset: Option<Preset>, ini_file_path: &str) -> Option<&'static str> {
+    match preset {
+        Some(preset) => Some(preset.ini_text()),
+        None if !Path::new(ini_file_path).exists() => Some(DEFAULT_INI),
+        None => None,
+    }
+}
+
+/// 读取代理组和规则集的配置：使用了 --preset 时为内置的方案，ini 文件不存在时使用内置的默认 ini
+pub fn load_ini(preset: Option<Preset>, ini_file_path: &str) -> Result<Ini, Failure> {
+    match embedded_ini(preset, ini_file_path) {
+        Some(text) => {
+            if preset.is_none() {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "ini配置文件不存在：{}，使用内置的默认配置(ACL4SSR在线版)，可以用 --dump-defaults 写出后修改",
+                        "ini file not found: {}, using the built-in default (ACL4SSR online), write it out with --dump-defaults to edit",
+                        ini_file_path
+                    )
+                );
+            }
+            Ok(Ini::load_from_str(text).unwrap_or_else(|e| {
+                panic!(
+                    "{}",
+                    tr!("内置的ini格式错误：{}", "invalid built-in ini: {}", e)
+                )
+            }))
+        }
+        None => Ini::load_from_file(ini_file_path).map_err(|e| {
+            Failure::new(
+                ErrorKind::Config,
+                tr!(
+                    "读取ini文件失败：{}，{}",
+                    "failed to read the ini file {}: {}",
+                    ini_file_path,
+                    e
+                ),
+            )
+        }),
+    }
+}
+
+/// --dump-defaults：内置的默认 ini 和 base.yaml 写到 -c、-b 的路径，已经存在的文件不覆盖
+///
+/// 返回每个文件的路径和是否写入
+pub fn dump_defaults<'a>(
+    ini_file_path: &str,
+    base_paths: impl Iterator<Item = &'a str>,
+) -> std::io::Result<Vec<(PathBuf, bool)>> {
+    let targets = std::iter::once((ini_file_path, DEFAULT_INI))
+        .chain(base_paths.map(|path| (path, DEFAULT_BASE_YAML)));
+    let mut written = Vec::new();
+    for (path, content) in targets {
+        let path = PathBuf::from(path);
+        if path.exists() {
+            written.push((path, false));
+            continue;
+        }
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, content)?;
+        written.push((path, true));
+    }
+    Ok(written)
+}
@@ -0,0 +1,136 @@
+use crate::build::{mathrule, mrs, patterns};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// 规则提供者的 behavior
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Behavior {
+    Domain,
+    IpCidr,
+    Classical,
+}
+
+/// 规则提供者的 format
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Yaml,
+    Text,
+    Mrs,
+}
+
+impl Format {
+    /// 规则提供者缓存文件的扩展名
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Yaml => "yaml",
+            Format::Text => "list",
+            Format::Mrs => "mrs",
+        }
+    }
+}
+
+/// rule-providers 中的一项
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleProvider {
+    #[serde(rename = "type")]
+    pub provider_type: String,
+    pub behavior: Behavior,
+    pub format: Format,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u32>,
+}
+
+impl RuleProvider {
+    pub fn new(name: &str, url: &str, behavior: Behavior, format: Format) -> Self {
+        RuleProvider {
+            provider_type: "http".to_string(),
+            behavior,
+            format,
+            url: Some(url.to_string()),
+            path: format!("./ruleset/{}.{}", name, format.extension()),
+            interval: Some(86400),
+        }
+    }
+
+    /// 本地文件的规则提供者（例如 --compile-domains 生成的 mrs），文件放在 mihomo 配置目录的 ruleset 文件夹中
+    pub fn file(name: &str, behavior: Behavior, format: Format) -> Self {
+        RuleProvider {
+            provider_type: "file".to_string(),
+            behavior,
+            format,
+            url: None,
+            path: format!("./ruleset/{}.{}", name, format.extension()),
+            interval: None,
+        }
+    }
+}
+
+/// 规则集中的一条内容属于哪种类型
+fn entry_behavior(entry: &str) -> Behavior {
+    let domain = entry
+        .strip_prefix("+.")
+        .or_else(|| entry.strip_prefix("*."))
+        .or_else(|| entry.strip_prefix('.'))
+        .unwrap_or(entry);
+    if mathrule::get_cidr_type(entry).is_some() {
+        Behavior::IpCidr
+    } else if patterns::RE_YAML_DOMAIN
+        .is_match(domain)
+        .unwrap_or_default()
+    {
+        Behavior::Domain
+    } else {
+        Behavior::Classical
+    }
+}
+
+/// 根据规则集的内容推断 behavior 和 format：
+/// 全是域名 => domain，全是CIDR => ipcidr，其它 => classical；含有 payload: 的是 yaml，否则是 text
+pub fn infer_provider(path: &str, data: &[u8]) -> (Behavior, Format) {
+    if mrs::is_mrs(path, data) {
+        let behavior = match mrs::mrs_behavior(data) {
+            Some(mrs::BEHAVIOR_IPCIDR) => Behavior::IpCidr,
+            _ => Behavior::Domain,
+        };
+        return (behavior, Format::Mrs);
+    }
+
+    let text = String::from_utf8_lossy(data);
+    let is_yaml = text
+        .lines()
+        .any(|line| line.trim_start().starts_with("payload:"));
+    let format = if is_yaml { Format::Yaml } else { Format::Text };
+
+    let mut entries = text.lines().filter_map(|line| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("payload:") {
+            return None;
+        }
+        let entry = match patterns::RE_YAML_RULES.captures(line) {
+            Ok(Some(caps)) => caps.get(2).or(caps.get(3)).map(|m| m.as_str()),
+            _ => Some(line),
+        };
+        entry.map(entry_behavior)
+    });
+
+    let behavior = match entries.next() {
+        Some(first) if first != Behavior::Classical && entries.all(|b| b == first) => first,
+        _ => Behavior::Classical,
+    };
+    (behavior, format)
+}
+
+/// 规则提供者的名称，取自url的文件名，例如 ".../ProxyGFWlist.list" => "ProxyGFWlist"
+pub fn provider_name(url: &str) -> String {
+    let stem = url.split(['?', '#']).next().unwrap_or(url);
+    Path::new(stem)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "ruleset".to_string())
+}
@@ -1,13 +1,31 @@
-use crate::build::{constants, download, ini as MyIni, mathrule, patterns, sort as MySort};
+use crate::{
+    build::{
+        cidr, compact,
+        compile::{self, CompiledSet, DomainFormat},
+        conflict::{self, RuleIssue},
+        dns, download,
+        extra::{ExtraRules, RulePosition},
+        filter::RuleFilter,
+        ini as MyIni,
+        mathrule::{self, RuleFlag},
+        mrs, patterns,
+        precedence::{self, RulePrecedence},
+        providers::{self, Behavior, Format, RuleProvider},
+        sort as MySort,
+    },
+    utils::i18n::tr,
+};
 use futures::future::join_all;
+use indexmap::IndexMap;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::{
-    ffi::OsStr,
-    fs::File,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs::{self, File},
     io::{BufRead, BufReader},
-    path::Path,
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 const NO_RESOLVE: &str = ",no-resolve";
@@ -19,221 +37,856 @@ struct Rules {
 
 #[derive(Debug)]
 struct RuleSets {
+    index: usize, // 在ini中的序号，用于统计
     name: String,
     rule: String,
+    behavior: Option<Behavior>, // ini中声明的规则集类型
+}
+
+/// 规则的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleSource {
+    Url,
+    Local,
+    Final,
+}
+
+impl RuleSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleSource::Url => "url",
+            RuleSource::Local => "local",
+            RuleSource::Final => "final",
+        }
+    }
+}
+
+/// 规则的获取状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchStatus {
+    Ok,     // 正常（下载成功或读取本地文件成功）
+    Cache,  // 下载失败，使用了之前保存的规则文件
+    Failed, // 下载失败或者文件不存在
+}
+
+impl FetchStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FetchStatus::Ok => "ok",
+            FetchStatus::Cache => "cache",
+            FetchStatus::Failed => "failed",
+        }
+    }
+}
+
+/// 每个规则集（ini中的每一行ruleset）的处理情况
+#[derive(Debug, Clone)]
+pub struct RulesetReport {
+    pub name: String,
+    pub source: RuleSource,
+    pub path: String,
+    pub rules: usize,               // 贡献的规则数（去重前）
+    pub duration: Option<Duration>, // 下载耗时
+    pub status: FetchStatus,
+    pub provider: Option<(Behavior, Format)>, // 根据内容推断的 behavior 和 format
+    pub rule_type: Option<Behavior>,          // ini中声明的规则集类型
+    pub domains: Vec<String>, // 被 dns 选项选中时，规则集中的域名（"a.com"、"+.a.com"）
+    pub overridden: usize,    // 被优先级更高的规则集覆盖而丢弃的规则数
+}
+
+impl RulesetReport {
+    fn new(item: &MyIni::RuleSet) -> Self {
+        let (source, path) = if !item.net_rule_path.is_empty() {
+            (RuleSource::Url, item.net_rule_path.clone())
+        } else if !item.local_rule_path.is_empty() {
+            (RuleSource::Local, item.local_rule_path.clone())
+        } else {
+            (RuleSource::Final, item.final_rule.clone())
+        };
+        RulesetReport {
+            name: item.rule_name.clone(),
+            source,
+            path,
+            rules: 0,
+            duration: None,
+            status: FetchStatus::Ok,
+            provider: None,
+            rule_type: item.rule_type,
+            domains: Vec::new(),
+            overridden: 0,
+        }
+    }
+}
+
+/// 构建规则时的选项
+#[derive(Debug, Clone, Default)]
+pub struct RulesOptions {
+    pub save_rules_dir: String,                    // 用于存储下载的规则文件
+    pub chunk: usize,                              // 同一URL分片下载的份数
+    pub chunk_min_size: u64,                       // 不小于这个大小的文件才分片下载
+    pub download_limits: download::DownloadLimits, // 单个规则集下载的超时时间和最大字节数
+    pub write_cache: bool,                         // 是否保存下载的规则文件
+    pub rule_providers: bool, // 网络规则集输出为 rule-providers，规则中使用 RULE-SET 引用
+    pub rule_filter: RuleFilter, // 保留/过滤的规则类型
+    pub keep_rule_order: bool, // 保持规则原有的顺序，只去重不排序
+    pub check_rules: bool,    // 检测冲突和被覆盖的规则
+    pub merge_cidr: bool,     // 合并同一代理组相邻或包含的IP网段
+    pub compact_domains: bool, // 去掉已被同一代理组 DOMAIN-SUFFIX 覆盖的域名规则
+    pub dns_rulesets: Vec<String>, // 需要提取域名用于生成 dns 的规则集
+    pub rule_precedence: Option<RulePrecedence>, // 相同内容的规则只保留优先级最高的规则集中的
+    pub prefer_rulesets: Vec<String>, // 优先级最高的规则集（代理组名称或路径的一部分）
+    pub verify_sha256: bool,  // 校验规则集发布的 sha256（url.sha256）
+    pub ruleset_banners: bool, // 规则按ini中的规则集分块，每块前面加上注释
+    pub compile_domains: Option<DomainFormat>, // 域名规则编译为 mrs/geosite.dat
+    pub compile_dir: Option<PathBuf>, // 编译的文件保存的目录（试运行时为 None，不保存）
+    pub extra_rules: Option<ExtraRules>, // 用户自己维护的规则（--extra-rules）
+}
+
+#[derive(Serialize, Debug)]
+struct Providers {
+    #[serde(rename = "rule-providers")]
+    providers: IndexMap<String, RuleProvider>,
 }
 
 pub async fn build_rules(
     ruleset: Vec<MyIni::RuleSet>, // 节点名称
-    save_rules_dir: String,       // 用于存储下载的规则文件
-    chunk: usize,
-) -> (String, usize) {
+    options: &RulesOptions,
+) -> (String, usize, Vec<RulesetReport>, Vec<RuleIssue>) {
+    let mut reports: Vec<RulesetReport> = ruleset.iter().map(RulesetReport::new).collect();
+
     let down_rules_vec: Vec<RuleSets> = ruleset
         .iter()
-        .map(|item| RuleSets {
+        .enumerate()
+        .filter(|(_, item)| !item.net_rule_path.is_empty())
+        .map(|(index, item)| RuleSets {
+            index,
             name: item.rule_name.clone(),
             rule: item.net_rule_path.clone(),
+            behavior: item.rule_type,
         })
         .collect();
     let local_rules_vec: Vec<RuleSets> = ruleset
         .iter()
-        .map(|item| RuleSets {
+        .enumerate()
+        .map(|(index, item)| RuleSets {
+            index,
             name: item.rule_name.clone(),
             rule: item.local_rule_path.clone(),
+            behavior: item.rule_type,
         })
         .collect();
     let final_rule_vec: Vec<RuleSets> = ruleset
         .iter()
-        .map(|item| RuleSets {
+        .enumerate()
+        .map(|(index, item)| RuleSets {
+            index,
             name: item.rule_name.clone(),
             rule: item.final_rule.clone(),
+            behavior: None,
         })
         .collect();
 
-    let mut down_rules: Vec<String> = Vec::new();
+    // 每条规则都带有所属规则集在ini中的序号
+    let mut down_rules: Vec<(usize, String)> = Vec::new();
     if !down_rules_vec.is_empty() {
-        down_rules = process_download_rules(down_rules_vec, save_rules_dir, chunk).await;
+        down_rules = process_download_rules(down_rules_vec, options, &mut reports).await;
     }
-    let local_rules: Vec<String> = process_local_rules(local_rules_vec);
-    let final_rules: Vec<String> = process_final_rules(final_rule_vec);
+    let rule_filter = &options.rule_filter;
+    let local_rules = process_local_rules(local_rules_vec, rule_filter, &mut reports);
+    let final_rules: Vec<String> = process_final_rules(final_rule_vec, rule_filter, &mut reports);
 
     // 合并到down_rules中
-    down_rules.extend(local_rules.into_iter());
+    down_rules.extend(local_rules);
 
-    // 排序和去重
-    let mut sorted_and_unique: Vec<String> = MySort::sort_rules(down_rules);
+    // 提取生成 dns 需要的域名，复用已经下载的规则
+    if !options.dns_rulesets.is_empty() {
+        let selected: Vec<bool> = reports
+            .iter()
+            .map(|r| {
+                options
+                    .dns_rulesets
+                    .iter()
+                    .any(|selector| dns::is_selected(selector, r))
+            })
+            .collect();
+        for (index, rule) in &down_rules {
+            if selected[*index]
+                && let Some(domain) = dns::domain_pattern(rule)
+            {
+                reports[*index].domains.push(domain);
+            }
+        }
+    }
 
-    // 合并到unique_rules中
-    sorted_and_unique.extend(final_rules.into_iter());
+    // rule-providers 模式下，网络规则集使用 RULE-SET 引用，不展开规则
+    if options.rule_providers {
+        down_rules.retain(|(index, _)| reports[*index].source != RuleSource::Url);
+    }
 
-    // 规则（已经Ok）
-    let all_rules = Rules {
-        rules: sorted_and_unique.clone(),
+    // 相同内容的规则指向不同的代理组时，按规则集的优先级只保留一个
+    let precedence = match options.rule_precedence {
+        None if !options.prefer_rulesets.is_empty() => Some(RulePrecedence::First),
+        other => other,
     };
+    let mut resolved = Vec::new();
+    if let Some(precedence) = precedence {
+        let preferred: Vec<usize> = options
+            .prefer_rulesets
+            .iter()
+            .flat_map(|selector| {
+                reports
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, r)| dns::is_selected(selector, r))
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let ranks = precedence::ruleset_ranks(reports.len(), precedence, &preferred);
+        let (kept, overridden, conflicts) = precedence::resolve_precedence(down_rules, &ranks);
+        down_rules = kept;
+        resolved = conflicts;
+        for (report, count) in reports.iter_mut().zip(overridden) {
+            report.overridden = count;
+        }
+    }
+
+    // 记录每条规则第一次出现在哪个规则集，用于输出冲突的来源
+    let mut origins: HashMap<String, usize> = HashMap::new();
+    if options.check_rules {
+        for (index, rule) in &down_rules {
+            let origin = origins.entry(rule.clone()).or_insert(*index);
+            *origin = (*origin).min(*index);
+        }
+    }
+
+    // 注释放在第几条规则之前
+    let mut banners: Vec<(usize, String)> = Vec::new();
+    let mut sorted_and_unique: Vec<String> = if options.ruleset_banners {
+        // 按ini中规则集的顺序分块（块内排序或保持原有顺序），重复的规则只保留第一次出现的
+        down_rules.sort_by_key(|(index, _)| *index);
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut layout = Vec::new();
+        for block in down_rules.chunk_by(|a, b| a.0 == b.0) {
+            let report = &reports[block[0].0];
+            let rules = optimize_rules(
+                block.iter().map(|(_, rule)| rule.clone()).collect(),
+                options,
+            );
+            let rules = if options.keep_rule_order {
+                rules
+            } else {
+                MySort::sort_rules(rules)
+            };
+            let rules: Vec<String> = rules
+                .into_iter()
+                .filter(|rule| seen.insert(rule.clone()))
+                .collect();
+            if rules.is_empty() {
+                continue;
+            }
+            banners.push((
+                layout.len(),
+                format!(
+                    "# >>> ruleset: {} ({} rules) {}",
+                    report.name,
+                    rules.len(),
+                    report.path
+                ),
+            ));
+            layout.extend(rules);
+        }
+        layout
+    } else if options.keep_rule_order {
+        // 保持ini中规则集的顺序，以及每个文件内部的顺序，只去重
+        down_rules.sort_by_key(|(index, _)| *index);
+        let unique = MySort::dedup_rules(down_rules.into_iter().map(|(_, rule)| rule));
+        optimize_rules(unique, options)
+    } else {
+        let rules: Vec<String> = down_rules.into_iter().map(|(_, rule)| rule).collect();
+        // 排序和去重
+        MySort::sort_rules(optimize_rules(rules, options))
+    };
+
+    let mut issues = if options.check_rules {
+        conflict::find_rule_issues(&sorted_and_unique, &origins)
+    } else {
+        Vec::new()
+    };
+    // 按优先级解决的冲突总是返回，用于输出报告
+    issues.extend(resolved);
+
+    let mut providers: IndexMap<String, RuleProvider> = IndexMap::new();
+    if let Some(format) = options.compile_domains {
+        let (rules, sets) = compile::compile_domain_rules(sorted_and_unique, format);
+        sorted_and_unique = rules;
+        if format == DomainFormat::Mrs {
+            for set in &sets {
+                providers.insert(
+                    set.tag.clone(),
+                    RuleProvider::file(&set.tag, Behavior::Domain, Format::Mrs),
+                );
+            }
+        }
+        if let Some(dir) = &options.compile_dir {
+            write_compiled_sets(&sets, format, dir);
+        }
+    }
+
+    // rule-providers 模式：网络规则集按ini中的顺序使用 RULE-SET 引用
+    if options.rule_providers {
+        for report in reports.iter().filter(|r| r.source == RuleSource::Url) {
+            let (behavior, format) = report.provider.unwrap_or_else(|| {
+                // 下载失败，只能根据ini声明和url推断
+                let (inferred, format) = providers::infer_provider(&report.path, &[]);
+                (report.rule_type.unwrap_or(inferred), format)
+            });
+            let base_name = providers::provider_name(&report.path);
+            let mut name = base_name.clone();
+            let mut n = 1;
+            while providers.contains_key(&name) {
+                n += 1;
+                name = format!("{}_{}", base_name, n);
+            }
+            let no_resolve = if behavior == Behavior::IpCidr {
+                NO_RESOLVE
+            } else {
+                ""
+            };
+            sorted_and_unique.push(format!("RULE-SET,{},{}{}", name, report.name, no_resolve));
+            providers.insert(
+                name.clone(),
+                RuleProvider::new(&name, &report.path, behavior, format),
+            );
+        }
+    }
+
+    if options.ruleset_banners {
+        if !providers.is_empty() {
+            banners.push((
+                sorted_and_unique.len() - providers.len(),
+                format!("# >>> rule-providers ({} rules)", providers.len()),
+            ));
+        }
+        if !final_rules.is_empty() {
+            banners.push((sorted_and_unique.len(), "# >>> final".to_string()));
+        }
+    }
+
+    // 合并到unique_rules中
+    sorted_and_unique.extend(final_rules);
+
+    if let Some(extra) = &options.extra_rules {
+        insert_extra_rules(
+            &mut sorted_and_unique,
+            &mut banners,
+            extra,
+            rule_filter,
+            options.ruleset_banners,
+        );
+    }
+
+    let rules_count = sorted_and_unique.len();
+    let mut combined = rules_to_yaml(sorted_and_unique);
+
+    if !banners.is_empty() {
+        combined = insert_banners(&combined, &banners);
+    }
+
+    if !providers.is_empty() {
+        let providers_string = serde_yaml::to_string(&Providers { providers }).unwrap();
+        combined = format!("{}{}", providers_string, combined);
+    }
+
+    (combined, rules_count, reports, issues)
+}
+
+/// 规则转为 rules 的yaml字符串（已调整缩进）
+pub fn rules_to_yaml(rules: Vec<String>) -> String {
+    // 规则（已经Ok），直接移入，避免规则很多时复制一份
+    let all_rules = Rules { rules };
 
     // 转换为YAML字符串
     let rules_string = serde_yaml::to_string(&all_rules).unwrap();
+    drop(all_rules);
 
     // 处理yaml字符串中的缩进问题（该方法处理速度比较快）
-    let combined = patterns::RE_DASH_LINE
+    patterns::RE_DASH_LINE
         .replace_all(&rules_string, "  - ")
-        .to_string();
+        .into_owned()
+}
 
-    (combined, sorted_and_unique.len())
+/// 把 build_rules 生成的yaml字符串中的规则列表交给 f 修改，rule-providers 部分不变（规则之间的注释会被去掉）
+///
+/// 返回新的yaml字符串和规则数，f 失败时返回它的错误
+pub fn map_rule_list<E>(
+    combined: &str,
+    f: impl FnOnce(Vec<String>) -> Result<Vec<String>, E>,
+) -> Result<(String, usize), E> {
+    let (providers, rules_part) = match combined.find("\nrules:") {
+        Some(i) => combined.split_at(i + 1),
+        None => ("", combined),
+    };
+    let rules: Rules = serde_yaml::from_str(rules_part).unwrap();
+    let rules = f(rules.rules)?;
+    let count = rules.len();
+    Ok((format!("{}{}", providers, rules_to_yaml(rules)), count))
+}
+
+/// 保存编译的域名集合：mrs 保存到 dir/ruleset/标签.mrs，geosite 保存为 dir/geosite.dat
+fn write_compiled_sets(sets: &[CompiledSet], format: DomainFormat, dir: &Path) {
+    if sets.is_empty() {
+        return;
+    }
+    match format {
+        DomainFormat::Mrs => {
+            let ruleset_dir = dir.join("ruleset");
+            fs::create_dir_all(&ruleset_dir).unwrap_or_else(|e| {
+                panic!(
+                    "{}",
+                    tr!(
+                        "创建 ruleset 目录失败：{}",
+                        "failed to create the ruleset directory: {}",
+                        e
+                    )
+                )
+            });
+            for set in sets {
+                let path = ruleset_dir.join(format!("{}.mrs", set.tag));
+                download::save_net_file(set.mrs_data(), &path);
+            }
+            println!(
+                "{}",
+                tr!(
+                    "域名规则已编译为 {} 个 mrs 规则集，保存在：{}（复制到 mihomo 配置目录的 ruleset 文件夹中）",
+                    "domain rules compiled into {} mrs rulesets in: {} (copy them to the ruleset folder of the mihomo config directory)",
+                    sets.len(),
+                    ruleset_dir.display()
+                )
+            );
+        }
+        DomainFormat::Geosite => {
+            let path = dir.join("geosite.dat");
+            download::save_net_file(compile::geosite_data(sets), &path);
+            println!(
+                "{}",
+                tr!(
+                    "域名规则已编译为：{}（替换 mihomo 配置目录中的 GeoSite.dat，会覆盖原有的 geosite 标签）",
+                    "domain rules compiled into: {} (replaces GeoSite.dat in the mihomo config directory, overriding its geosite tags)",
+                    path.display()
+                )
+            );
+        }
+    }
+    for set in sets {
+        println!(
+            "{}",
+            tr!(
+                "  {}：{} 条规则 => {}",
+                "  {}: {} rules => {}",
+                set.tag,
+                set.rules,
+                set.group
+            )
+        );
+    }
+}
+
+/// 在 rules 列表中插入注释：banners 为 (放在第几条规则之前, 注释)，按位置排列
+fn insert_banners(rules_yaml: &str, banners: &[(usize, String)]) -> String {
+    let mut result = String::with_capacity(rules_yaml.len() + banners.len() * 64);
+    let mut banners = banners.iter().peekable();
+    let mut position = 0;
+    for line in rules_yaml.lines() {
+        if line.starts_with("  - ") {
+            while let Some((_, banner)) = banners.next_if(|(pos, _)| *pos <= position) {
+                result.push_str("  ");
+                result.push_str(banner);
+                result.push('\n');
+            }
+            position += 1;
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
+/// 加入额外的规则（--extra-rules）：放在最前面，或者最后的 MATCH 规则之前，其它地方完全相同的规则被去掉；
+/// 被过滤的规则类型（例如 --target stash 不支持的）同样不输出
+fn insert_extra_rules(
+    rules: &mut Vec<String>,
+    banners: &mut Vec<(usize, String)>,
+    extra: &ExtraRules,
+    rule_filter: &RuleFilter,
+    with_banner: bool,
+) {
+    let extra_rules: Vec<String> = extra
+        .rules
+        .iter()
+        .filter(|rule| !rule_filter.is_filtered(rule))
+        .cloned()
+        .collect();
+    if extra_rules.is_empty() {
+        return;
+    }
+    let extra_set: HashSet<&str> = extra_rules.iter().map(String::as_str).collect();
+    // 注释的位置要减去前面被去掉的规则
+    for (position, _) in banners.iter_mut() {
+        *position -= rules[..*position]
+            .iter()
+            .filter(|rule| extra_set.contains(rule.as_str()))
+            .count();
+    }
+    rules.retain(|rule| !extra_set.contains(rule.as_str()));
+
+    let at = match extra.position {
+        RulePosition::Top => 0,
+        RulePosition::BeforeFinal => rules
+            .last()
+            .filter(|rule| rule.starts_with("MATCH,"))
+            .map_or(rules.len(), |_| rules.len() - 1),
+    };
+    for (position, _) in banners.iter_mut().filter(|(position, _)| *position >= at) {
+        *position += extra_rules.len();
+    }
+    if with_banner {
+        banners.push((
+            at,
+            format!("# >>> extra rules ({} rules)", extra_rules.len()),
+        ));
+        banners.sort_by_key(|(position, _)| *position);
+    }
+    rules.splice(at..at, extra_rules);
+}
+
+/// 可选的规则精简：合并IP网段、去掉被覆盖的域名规则
+pub fn optimize_rules(mut rules: Vec<String>, options: &RulesOptions) -> Vec<String> {
+    if options.merge_cidr {
+        rules = cidr::merge_cidr_rules(rules);
+    }
+    if options.compact_domains {
+        rules = compact::compact_domain_rules(rules);
+    }
+    rules
 }
 
 // 处理下载的规则
 async fn process_download_rules(
     down_urls: Vec<RuleSets>,
-    save_rules_dir: String,
-    chunk: usize,
-) -> Vec<String> {
+    options: &RulesOptions,
+    reports: &mut [RulesetReport],
+) -> Vec<(usize, String)> {
     if down_urls.is_empty() {
         return Vec::new();
     }
     let download_tasks = down_urls
         .iter()
         .map(|item| {
+            let index = item.index;
+            let behavior = item.behavior;
             let name = item.name.clone();
             let url_clone = item.rule.clone();
-            let save_pth = save_rules_dir.clone();
+            let save_dir = Path::new(&options.save_rules_dir);
+            let path = save_dir.join(download::cache_file_name(&item.rule));
+            // 之前的版本只用文件名保存，下载失败时也查找这样保存的文件
+            let legacy_path = save_dir.join(download::url_file_name(&item.rule));
+            let chunk = options.chunk;
+            let chunk_min_size = options.chunk_min_size;
+            let limits = options.download_limits;
+            let write_cache = options.write_cache;
+            let verify_sha256 = options.verify_sha256;
             tokio::spawn(async move {
-                let data = download::download_multi_threaded(&url_clone, chunk)
-                    .await
-                    .unwrap_or_default();
-
-                let file_name = Path::new(&url_clone)
-                    .file_name()
-                    .unwrap_or_else(|| OsStr::new("unknown"))
-                    .to_string_lossy()
-                    .into_owned();
-                let path = format!("{}/{}", save_pth, file_name);
-
-                // 计算hash值跟本地文件的hash值是否相等，不同就写入操作
-                let _write_state = download::save_net_file(data.clone(), &path);
-
-                RuleSets {
-                    name,
-                    rule: String::from_utf8(data).unwrap_or_default(),
+                let start_time = Instant::now();
+                let mut data =
+                    download::download_ruleset(&url_clone, chunk, chunk_min_size, limits)
+                        .await
+                        .unwrap_or_else(|e| {
+                            eprintln!("{}", e);
+                            Vec::new()
+                        });
+                if verify_sha256
+                    && !data.is_empty()
+                    && let Err(e) = download::verify_published_sha256(&url_clone, &data).await
+                {
+                    // 校验失败的内容不能使用，按下载失败处理
+                    eprintln!("{}", e);
+                    data.clear();
                 }
+                let duration = start_time.elapsed();
+
+                let status = if !data.is_empty() {
+                    // 计算hash值跟本地文件的hash值是否相等，不同就写入操作
+                    if write_cache {
+                        let _write_state = download::save_net_file(data.clone(), &path);
+                    }
+                    FetchStatus::Ok
+                } else if let Ok(cached) = fs::read(&path).or_else(|_| fs::read(&legacy_path)) {
+                    // 下载失败，使用之前保存的规则文件
+                    data = cached;
+                    FetchStatus::Cache
+                } else {
+                    FetchStatus::Failed
+                };
+                // ini中声明了类型的，以声明的为准
+                let provider = (status != FetchStatus::Failed).then(|| {
+                    let (inferred, format) = providers::infer_provider(&url_clone, &data);
+                    (behavior.unwrap_or(inferred), format)
+                });
+
+                (
+                    RuleSets {
+                        index,
+                        name,
+                        rule: rules_text(&url_clone, data),
+                        behavior,
+                    },
+                    duration,
+                    status,
+                    provider,
+                )
             })
         })
         .collect::<Vec<_>>();
 
-    // 等待所有下载任务完成
-    let results = join_all(download_tasks).await;
-
-    let line_rules = Arc::new(Mutex::new(Vec::new()));
+    // 等待所有下载任务完成，任务中的 panic（程序内部错误）在这里继续抛出，不能当作没有这个规则集
+    let results: Vec<_> = join_all(download_tasks)
+        .await
+        .into_iter()
+        .map(|result| match result {
+            Ok(fetched) => fetched,
+            Err(e) => match e.try_into_panic() {
+                Ok(payload) => std::panic::resume_unwind(payload),
+                Err(e) => panic!("{}", e),
+            },
+        })
+        .collect();
 
-    // 遍历下载结果，将规则添加到规则列表中
-    results.into_par_iter().for_each(|result| {
-        if let Ok(item) = result {
+    // 每个规则集并行处理，规则集内部的行也并行处理，collect 保持行的原始顺序
+    let rule_filter = &options.rule_filter;
+    let mut per_ruleset: Vec<_> = results
+        .into_par_iter()
+        .map(|(item, duration, status, provider)| {
             let name_str = item.name;
-            let rule_str: String = item.rule;
-            rule_str.lines().for_each(|line| {
-                let mut rules_lock = line_rules.lock().unwrap();
-                let rule_str = format_rules(line.to_string(), &name_str);
-                if !rule_str.is_empty() {
-                    rules_lock.push(rule_str);
-                }
-            });
-        }
-    });
-
-    // 合并所有线程的结果
-    let rules = Arc::try_unwrap(line_rules).unwrap().into_inner().unwrap();
+            let lines: Vec<String> = item
+                .rule
+                .par_lines()
+                .map(|line| format_rules(line.to_string(), &name_str, item.behavior, rule_filter))
+                .filter(|rule| !rule.is_empty())
+                .collect();
+            (item.index, lines, duration, status, provider)
+        })
+        .collect();
 
+    // 按ini中规则集的顺序合并
+    per_ruleset.sort_by_key(|(index, ..)| *index);
+    let mut rules = Vec::new();
+    for (index, lines, duration, status, provider) in per_ruleset {
+        let report = &mut reports[index];
+        report.rules = lines.len();
+        report.duration = Some(duration);
+        report.status = status;
+        report.provider = provider;
+        rules.extend(lines.into_iter().map(|rule| (index, rule)));
+    }
     rules
 }
 
+/// 规则文件的内容转为文本，mrs 二进制规则集需要先解码
+pub fn rules_text(path: &str, data: Vec<u8>) -> String {
+    if mrs::is_mrs(path, &data) {
+        match mrs::decode_mrs(&data) {
+            Ok(lines) => lines.join("\n"),
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    tr!("{} 解析失败：{}", "failed to parse {}: {}", path, e)
+                );
+                String::new()
+            }
+        }
+    } else {
+        String::from_utf8(data).unwrap_or_default()
+    }
+}
+
 // 处理本地的规则
-fn process_local_rules(rulesets: Vec<RuleSets>) -> Vec<String> {
-    rulesets
+fn process_local_rules(
+    rulesets: Vec<RuleSets>,
+    rule_filter: &RuleFilter,
+    reports: &mut [RulesetReport],
+) -> Vec<(usize, String)> {
+    let per_file: Vec<(usize, Option<Vec<String>>)> = rulesets
         .into_par_iter()
-        .flat_map(|item| {
+        .filter(|item| !item.rule.is_empty())
+        .map(|item| {
             let name_str = item.name;
             let rule_path = item.rule;
 
-            if rule_path.is_empty() {
-                return Vec::new();
+            if rule_path.to_lowercase().ends_with(".mrs") {
+                let Ok(data) = fs::read(&rule_path) else {
+                    return (item.index, None);
+                };
+                let lines = rules_text(&rule_path, data)
+                    .lines()
+                    .map(|line| {
+                        format_rules(line.to_string(), &name_str, item.behavior, rule_filter)
+                    })
+                    .filter(|line| !line.is_empty())
+                    .collect::<Vec<String>>();
+                return (item.index, Some(lines));
             }
 
             let file = File::open(rule_path);
             if file.is_err() {
-                return Vec::new();
+                return (item.index, None);
             }
 
             let reader = BufReader::new(file.unwrap());
 
-            reader
-                .lines()
-                .filter_map(Result::ok)
-                .map(|line| format_rules(line, &name_str))
+            // 不是 UTF-8 的行跳过，继续读取后面的规则（读取出错时才停止）
+            let lines = reader
+                .split(b'\n')
+                .map_while(Result::ok)
+                .filter_map(|mut line| {
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    String::from_utf8(line).ok()
+                })
+                .map(|line| format_rules(line, &name_str, item.behavior, rule_filter))
                 .filter(|line| !line.is_empty())
-                .collect::<Vec<String>>() // 每个文件产生一个 Vec
+                .collect::<Vec<String>>(); // 每个文件产生一个 Vec
+            (item.index, Some(lines))
         })
-        .collect() // 汇总所有 Vec<String> 成一个 Vec
+        .collect();
+
+    // 汇总所有 Vec<String> 成一个 Vec
+    let mut rules = Vec::new();
+    for (index, lines) in per_file {
+        match lines {
+            Some(lines) => {
+                reports[index].rules = lines.len();
+                rules.extend(lines.into_iter().map(|rule| (index, rule)));
+            }
+            None => reports[index].status = FetchStatus::Failed,
+        }
+    }
+    rules
 }
 
-fn process_final_rules(rulesets: Vec<RuleSets>) -> Vec<String> {
+fn process_final_rules(
+    rulesets: Vec<RuleSets>,
+    rule_filter: &RuleFilter,
+    reports: &mut [RulesetReport],
+) -> Vec<String> {
     let mut final_rules: Vec<String> = Vec::new();
     rulesets.into_iter().for_each(|ruleset| {
+        let before = final_rules.len();
+        let index = ruleset.index;
         let name_str = ruleset.name;
         let rule_str = ruleset.rule;
         if rule_str.contains("[]") {
-            let rule = rule_str.replacen("[]", "", 1);
-            let mut s = String::with_capacity(rule.len() + name_str.len() + 2);
-            if rule.contains(NO_RESOLVE) {
-                if let Some(pos) = rule.find(NO_RESOLVE) {
-                    s.push_str(&rule[..pos]);
-                    s.push_str(",");
-                    s.push_str(&name_str);
-                    s.push_str(&rule[pos..]);
-                    final_rules.push(s);
+            let rule_text = rule_str.replacen("[]", "", 1);
+            if let Some(mut rule) = mathrule::RuleLine::parse(&rule_text) {
+                if rule.kind == "FINAL" {
+                    rule.kind = "MATCH".to_string();
+                    final_rules.push(rule.with_group(&name_str));
+                } else if !rule.flags.is_empty() || !rule_filter.is_filtered(&rule_text) {
+                    final_rules.push(rule.with_group(&name_str));
                 }
-            } else if vec!["FINAL", "GEOSITE,", NO_RESOLVE]
-                .iter()
-                .all(|s| !rule.contains(s))
-            {
-                s.push_str(&rule);
-                s.push_str(",");
-                s.push_str(&name_str);
-                final_rules.push(s);
-            } else if rule.contains("FINAL") {
-                s.push_str("MATCH,");
-                s.push_str(&name_str);
-                final_rules.push(s);
             }
         }
+        reports[index].rules += final_rules.len() - before;
     });
     final_rules
 }
 
-fn format_rules(item: String, name_str: &String) -> String {
+/// 一行规则（yaml 或 list 格式）转为 类型,值,代理组，被过滤或无法解析时返回空字符串
+pub fn format_rules(
+    item: String,
+    name_str: &str,
+    behavior: Option<Behavior>,
+    rule_filter: &RuleFilter,
+) -> String {
     // 既能处理yaml的规则，也能处理list的规则
-    let rule = mathrule::extraction_rules(&item);
-    if constants::FILTER_KEY.iter().all(|p| !rule.contains(p)) {
-        if rule.starts_with("IP-CIDR") {
-            let mut new_rule = String::with_capacity(rule.len() + name_str.len() + 1);
-            if let Some(pos) = rule.find(NO_RESOLVE) {
-                new_rule.push_str(&rule[..pos]);
-                new_rule.push(',');
-                new_rule.push_str(name_str);
-                new_rule.push_str(&rule[pos..]);
-            } else {
-                new_rule.push_str(&rule);
-                new_rule.push(',');
-                new_rule.push_str(name_str);
-            }
-            if !new_rule.is_empty() {
-                return new_rule;
-            }
-        } else {
-            let stripped_rule = rule.strip_suffix(NO_RESOLVE).unwrap_or(&rule);
-            if !stripped_rule.is_empty() {
-                return format!("{},{}", stripped_rule, name_str);
-            }
+    let Some(mut rule) = mathrule::extraction_typed_rules(&item, behavior, rule_filter) else {
+        return String::new();
+    };
+    if rule_filter.is_filtered(&rule.to_string()) {
+        return String::new();
+    }
+    // 只有IP类的规则才保留 no-resolve
+    if !["IP-CIDR", "IP-ASN"]
+        .iter()
+        .any(|p| rule.kind.starts_with(p))
+    {
+        rule.flags.retain(|f| *f != RuleFlag::NoResolve);
+    }
+    rule.with_group(name_str)
+}
+
+/// 替换一条规则中的代理组，找不到代理组的位置（例如 SUB-RULE）时原样返回
+///
+/// 返回替换后的规则和原来的代理组
+fn rewrite_rule_group<'a>(
+    rule: &str,
+    rewrites: &'a HashMap<String, String>,
+) -> Option<(String, &'a str)> {
+    let mut rule = mathrule::RuleLine::parse(rule).filter(|r| r.kind != "SUB-RULE")?;
+    let (old_group, group) = rewrites.get_key_value(rule.group.as_deref()?)?;
+    rule.group = Some(group.clone());
+    Some((rule.to_string(), old_group))
+}
+
+/// 规则（yaml字符串）中的一行指向被移除的代理组时，返回改写后的行和原来的代理组
+fn rewrite_rule_line<'a>(
+    line: &str,
+    rewrites: &'a HashMap<String, String>,
+) -> Option<(String, &'a str)> {
+    let value = line
+        .strip_prefix("  - ")
+        .filter(|value| rewrites.keys().any(|name| value.contains(name.as_str())))?;
+    // 带引号的规则需要先解析出原本的字符串
+    let rule: String = serde_yaml::from_str(value).ok()?;
+    let (rule, old_group) = rewrite_rule_group(&rule, rewrites)?;
+    let value = serde_yaml::to_string(&rule).ok()?;
+    Some((format!("  - {}", value.trim_end()), old_group))
+}
+
+/// 规则（yaml字符串）中指向被移除的代理组的，改为指向其它代理组；没有被移除的代理组时直接借用
+pub fn rewrite_rule_groups<'a>(
+    rules_yaml: &'a str,
+    rewrites: &HashMap<String, String>,
+) -> Cow<'a, str> {
+    if rewrites.is_empty() {
+        return Cow::Borrowed(rules_yaml);
+    }
+    let mut result = String::with_capacity(rules_yaml.len());
+    for line in rules_yaml.lines() {
+        match rewrite_rule_line(line, rewrites) {
+            Some((new_line, _)) => result.push_str(&new_line),
+            None => result.push_str(line),
         }
+        result.push('\n');
+    }
+    Cow::Owned(result)
+}
+
+/// 每个被移除的代理组有多少条规则需要改写（不生成改写后的规则）
+pub fn count_rule_rewrites(
+    rules_yaml: &str,
+    rewrites: &HashMap<String, String>,
+) -> IndexMap<String, usize> {
+    let mut counts = IndexMap::new();
+    if rewrites.is_empty() {
+        return counts;
+    }
+    for (_, old_group) in rules_yaml
+        .lines()
+        .filter_map(|line| rewrite_rule_line(line, rewrites))
+    {
+        *counts.entry(old_group.to_string()).or_insert(0) += 1;
     }
-    String::new()
+    counts
 }
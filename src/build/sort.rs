@@ -1,5 +1,5 @@
 use rayon::prelude::*;
-use std::net::IpAddr;
+use std::{collections::HashSet, net::IpAddr};
 
 /// 将 IP 地址统一转换为 u128 排序键
 fn ip_to_u128(ip_str: &str) -> Option<u128> {
@@ -43,3 +43,12 @@ pub fn sort_rules(lines: Vec<String>) -> Vec<String> {
 
     result
 }
+
+/// 去重：保留第一次出现的规则，不改变顺序
+pub fn dedup_rules(lines: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut seen: HashSet<String> = HashSet::new();
+    lines
+        .into_iter()
+        .filter(|line| seen.insert(line.clone()))
+        .collect()
+}
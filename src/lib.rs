@@ -0,0 +1,32 @@
+//! clash 订阅构建工具的库：命令行程序使用的规则、节点处理模块，其它程序也可以直接使用，
+//! 例如按 clash 的写法解析规则行：
+//!
+//! ```
+//! use clash_subscription_tool::RuleLine;
+//!
+//! let rule = RuleLine::parse("host-suffix, example.com, 代理组").unwrap();
+//! assert_eq!(rule.kind, "DOMAIN-SUFFIX");
+//! assert_eq!(rule.to_string(), "DOMAIN-SUFFIX,example.com,代理组");
+//! ```
+//!
+//! 重名节点的改名方式也可以自己实现：
+//!
+//! ```
+//! use clash_subscription_tool::NameDeduper;
+//!
+//! struct Letter;
+//!
+//! impl NameDeduper for Letter {
+//!     fn rename(&self, name: &str, index: usize) -> String {
+//!         format!("{}-{}", name, (b'a' + index as u8) as char)
+//!     }
+//! }
+//!
+//! assert_eq!(Letter.rename("HK-01", 1), "HK-01-b");
+//! ```
+
+pub mod build;
+pub mod utils;
+
+pub use build::mathrule::{RuleFlag, RuleLine};
+pub use utils::paginate::{Counter, EmojiCounter, HashSuffix, NameDeduper, dedup_and_paginate};
@@ -1,165 +1,1966 @@
-mod build;
-mod utils;
+use clash_subscription_tool::{build, utils};
 
-use build::{indent, ini as MyIni, rules};
-use clap::{CommandFactory, Parser};
-use ini::Ini;
-use serde::{Deserialize, Serialize};
+use build::{
+    bench, compile, constants, dns, download, extra, filter::RuleFilter, indent, ini as MyIni,
+    lint, precedence, presets, rules,
+};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use rayon::prelude::*;
+use serde::Serialize;
 use serde_yaml::{self, Value as YamlValue};
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     fs::File,
     io::{BufWriter, Write},
-    time::Instant,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use utils::{
+    alloc, archive, blocklist, diff, encrypt, exit, expand, filename, fingerprint, hook,
+    i18n::{self, tr},
+    latency, manifest,
+    model::Proxy,
+    output, overrides, paginate,
+    plugin::{self, PluginStage},
+    profile, proxy, read, redact, rename, report, sanitize, schedule, script, selection, server,
+    split, stats, tui, upload, userinfo, watch,
 };
-use utils::{filename, paginate, proxy, read};
+
+// bench 子命令统计内存分配，平时不统计
+#[global_allocator]
+static GLOBAL: alloc::CountingAlloc = alloc::CountingAlloc;
 
 /// 功能：该工具用于clash订阅文件的代理组和规则重新构建，支持合并多个clash订阅文件再次重新构建。
 #[derive(Parser, Debug, Clone)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_override_self = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // 不写子命令时为构建，参数跟 build 子命令相同（兼容之前的用法）
+    #[command(flatten)]
+    build: BuildArgs,
+
+    /// 使用配置方案文件中的一个方案(一组参数)，命令行中同时指定的参数优先，格式见 config/profiles.example.toml，
+    /// 例如：--profile work
+    #[arg(long = "profile", value_name = "name", global = true)]
+    profile: Option<String>,
+
+    /// 配置方案文件
+    #[arg(long = "profiles", value_name = "profiles.toml", default_value = profile::DEFAULT_PROFILES_FILE, global = true)]
+    profiles_file: String,
+
+    /// 下载订阅和规则时使用的 User-Agent，有些订阅需要 clash、mihomo 的 UA 才会返回节点
+    #[arg(long = "user-agent", value_name = "ua", global = true)]
+    user_agent: Option<String>,
+
+    /// 下载订阅和规则时附加的请求头(可以多次使用)，例如：--header "Authorization: Bearer xxx"
+    #[arg(long = "header", value_name = "name:value", value_parser = download::parse_header, global = true)]
+    headers: Vec<(String, String)>,
+
+    /// 隐藏日志和清单(输出文件名.index.json、webhook)中的凭据：订阅链接只保留域名，无效节点的内容中 uuid、password、psk 等字段显示为 ***；
+    /// 终端中输出的配置总是隐藏凭据，生成的文件不受影响
+    #[arg(long = "redact", global = true)]
+    redact: bool,
+
+    /// 错误和警告输出为一行一个的 json 对象(level、kind、code、message、source)，方便脚本处理；
+    /// 退出码：0 成功、1 其它错误、2 配置错误、3 网络错误、4 部分成功(有规则集下载失败被跳过)、5 没有可输出的内容
+    #[arg(long = "json-errors", global = true)]
+    json_errors: bool,
+
+    /// 帮助、进度和错误信息的语言：zh、en，不使用时中文环境(LANG 等环境变量)为中文，其它为英文
+    #[arg(long = "lang", value_name = "zh|en", value_parser = i18n::parse_lang, global = true)]
+    lang: Option<i18n::Lang>,
+
+    /// 输出 man 手册(roff 格式，根据命令行参数的定义生成)，例如：--man > clash_subscription_tool.1
+    #[arg(long = "man")]
+    man: bool,
+}
+
+/// 构建配置的参数：build、serve、bench 子命令以及不写子命令时使用
+#[derive(clap::Args, Debug, Clone)]
+struct BuildArgs {
     /// ini配置文件
-    #[arg(short = 'c', default_value = "config/ACL4SSR.ini")]
+    #[arg(short = 'c', long = "ini", default_value = "config/ACL4SSR.ini")]
     ini_file_path: String,
 
-    /// clash配置的头信息
-    #[arg(short = 'b', default_value = "mihomo/base.yaml")]
+    /// 使用内置的代理组和规则集方案(不需要ini文件)：minimal(不下载规则，局域网和中国大陆IP直连)、
+    /// acl4ssr-mini(ACL4SSR在线精简版)、full(ACL4SSR在线全分组版)
+    #[arg(long = "preset", value_name = "minimal|acl4ssr-mini|full", value_parser = presets::parse_preset, conflicts_with = "ini_file_path")]
+    preset: Option<presets::Preset>,
+
+    /// clash配置的头信息，支持多个(用英文逗号隔开)，每个都会生成一套配置，文件名中带有该文件的名称
+    #[arg(short = 'b', long = "base", default_value = "mihomo/base.yaml")]
     header_file_path: String,
 
-    /// 输入含有proxies节点的clash配置文件，支持多个配置文件(用英文逗号隔开)
-    #[arg(short = 'f', default_value = "clash.yaml")]
-    proxies_file_path: String,
+    /// 内置的默认ini(ACL4SSR在线版)和base.yaml写到 -c、-b 的路径(已经存在的文件不覆盖)，方便修改后使用；
+    /// 不使用时 -c、-b 的文件不存在会自动使用内置的默认配置
+    #[arg(long = "dump-defaults")]
+    dump_defaults: bool,
+
+    #[command(flatten)]
+    nodes: NodeArgs,
 
     /// 生成的clash文件输出路径
-    #[arg(short = 'o', default_value = "output.yaml")]
+    #[arg(short = 'o', long = "output", default_value = "output.yaml")]
     output_file_path: String,
 
     /// 网上下载的规则，保存的文件夹路径
-    #[arg(short = 's', default_value = "rules/download/")]
+    #[arg(short = 's', long = "rules-dir", default_value = "rules/download/")]
     save_rules_dir: String,
 
     /// 数据分页，每个配置最大节点数
-    #[arg(short = 'n', value_name = "page_size", default_value_t = 50)]
+    #[arg(
+        short = 'n',
+        long = "page-size",
+        value_name = "page_size",
+        default_value_t = 50
+    )]
     page_size: usize,
 
-    /// 设置同一URL分片下载的份数(缩短下载时间)，有概率致使只有两条规则
-    #[arg(short = 'k', value_name = "down_chunk_size", default_value_t = 50)]
+    /// 设置同一URL分片下载的份数(默认1，不分片)，只对不小于 --chunk-min-size 的文件分片(Range)下载，
+    /// 服务器不支持分片时自动改为整个下载；同一主机的规则通过 HTTP/2 复用一个连接，一般不需要分片
+    #[arg(
+        short = 'k',
+        long = "chunks",
+        value_name = "down_chunk_size",
+        default_value_t = 1
+    )]
     down_chunk_size: usize,
+
+    /// 使用 -k 分片下载时，只对不小于这个大小的文件分片，例如：512K、4M
+    #[arg(long = "chunk-min-size", value_name = "size", default_value = "4M", value_parser = download::parse_size)]
+    chunk_min_size: u64,
+
+    /// 将所有分页生成的配置打包到一个压缩文件中(.zip 或 .tar.gz)，不再逐个输出yaml文件
+    #[arg(long = "archive", value_name = "archive_path")]
+    archive: Option<String>,
+
+    /// 加密生成的配置(其中有节点的密码等凭据)，使用 age 公钥(age1...，可以多次使用，任意一个对应的私钥都可以解密)，
+    /// 文件名加上 .age，可以用 age 命令行工具或 decrypt 子命令解密；加密的文件不能用于 --diff 的比较
+    #[arg(long = "encrypt-age", value_name = "recipient", value_parser = encrypt::parse_recipient, conflicts_with = "encrypt_pass")]
+    encrypt_age: Vec<encrypt::Recipient>,
+
+    /// 使用口令加密生成的配置(age 的 scrypt 格式)，口令从环境变量 CLASH_TOOL_PASSPHRASE 读取
+    #[arg(long = "encrypt-pass")]
+    encrypt_pass: bool,
+
+    /// 监听模式：proxies文件、ini配置文件、base.yaml发生变化时自动重新构建
+    #[arg(long = "watch")]
+    watch: bool,
+
+    /// 守护进程模式：每隔一段时间重新下载订阅和规则并重新构建，例如 6h、30m、1h30m
+    #[arg(long = "interval", value_name = "duration", value_parser = schedule::parse_interval)]
+    interval: Option<Duration>,
+
+    /// 提供跟 subconverter 兼容的转换接口(serve 子命令)：GET /sub?target=clash&url=订阅链接&config=ini的链接，
+    /// 其它参数跟 serve 的相同；只写端口时只监听本机，例如 25500、0.0.0.0:25500
+    #[arg(long = "listen", value_name = "addr", value_parser = server::parse_listen)]
+    listen: Option<SocketAddr>,
+
+    // /sub 请求中的ini只能使用网络规则集，不能读取服务器上的本地文件
+    #[arg(skip)]
+    remote_rulesets_only: bool,
+
+    /// 试运行：只解析、去重、处理代理组和统计规则，不写入任何文件，最后输出统计报告
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    /// 网络规则集输出为 rule-providers（自动推断 behavior 和 format），规则中使用 RULE-SET 引用，不再展开
+    #[arg(long = "rule-providers")]
+    rule_providers: bool,
+
+    /// meta模式：保留 GEOSITE、DOMAIN-REGEX、IP-ASN 等 mihomo(meta内核)才支持的规则类型
+    #[arg(long = "meta")]
+    meta: bool,
+
+    /// 过滤掉这些规则类型(用英文逗号隔开)，例如：USER-AGENT,URL-REGEX
+    #[arg(long = "drop-rule-types", value_name = "types", value_delimiter = ',')]
+    drop_rule_types: Vec<String>,
+
+    /// 保留这些规则类型(用英文逗号隔开)，例如：GEOSITE,IP-ASN
+    #[arg(long = "keep-rule-types", value_name = "types", value_delimiter = ',')]
+    keep_rule_types: Vec<String>,
+
+    /// 规则类型配置文件(yaml，含 include 和 filter 两个列表)，替换内置的保留/过滤列表
+    #[arg(long = "rule-types-file", value_name = "file")]
+    rule_types_file: Option<String>,
+
+    /// 自己维护的代理组文件(yaml，字段跟 proxy-groups 相同，可以用 filter、exclude-filter 正则选择节点)：
+    /// 名称跟ini中相同的代理组被替换，其它的加在后面，例如 --extra-groups groups.yaml
+    #[arg(long = "extra-groups", value_name = "file")]
+    extra_groups: Option<String>,
+
+    /// 自己维护的规则文件(yaml 列表，或者 {position: top|before-final, rules: [...]})，不用修改ini就能加入规则：
+    /// top 放在所有规则的最前面(优先级最高)，before-final(默认)放在最后的 MATCH 规则之前，例如 --extra-rules my_rules.yaml
+    #[arg(long = "extra-rules", value_name = "file")]
+    extra_rules: Option<String>,
+
+    /// 保持ini中规则集的顺序和每个规则文件内部的顺序，只去重不排序
+    #[arg(long = "keep-rule-order")]
+    keep_rule_order: bool,
+
+    /// 规则按ini中的规则集分块输出(不再全部一起排序)，每块前面加上注释，例如 # >>> ruleset: 🎯 全球直连 (1234 rules) 规则集路径，
+    /// 方便查看很长的规则列表
+    #[arg(long = "ruleset-banners")]
+    ruleset_banners: bool,
+
+    /// 域名规则(DOMAIN、DOMAIN-SUFFIX，geosite 还包括 DOMAIN-KEYWORD、DOMAIN-REGEX)按代理组编译为二进制的域名集合，保存在输出目录：
+    /// mrs(每个代理组一个 ruleset/标签.mrs，规则改为 RULE-SET,标签,代理组)、geosite(geosite.dat，规则改为 GEOSITE,标签,代理组)
+    #[arg(long = "compile-domains", value_name = "mrs|geosite", value_parser = compile::parse_domain_format, conflicts_with = "ruleset_banners")]
+    compile_domains: Option<compile::DomainFormat>,
+
+    /// 检测规则冲突（同一域名指向不同代理组）和被前面的 DOMAIN-SUFFIX 覆盖的规则，输出它们所在的规则集
+    #[arg(long = "check-rules")]
+    check_rules: bool,
+
+    /// 合并IP规则：同一代理组相邻或包含的 IP-CIDR/IP-CIDR6 网段合并为更少的规则
+    #[arg(long = "merge-cidr")]
+    merge_cidr: bool,
+
+    /// 精简域名规则：去掉已被同一代理组 DOMAIN-SUFFIX 覆盖的 DOMAIN/DOMAIN-SUFFIX 规则，合并重复的后缀
+    #[arg(long = "compact-domains")]
+    compact_domains: bool,
+
+    /// 修改base.yaml中的值(可以多次使用)，键使用点号分隔的路径，例如：--override mixed-port=7893 --override dns.enable=true
+    #[arg(long = "override", value_name = "key=value", value_parser = overrides::parse_override)]
+    overrides: Vec<(String, YamlValue)>,
+
+    /// 生成dns的nameserver-policy(可以多次使用)：规则集(ini中的代理组名称或规则集路径的一部分)=DNS服务器，多个DNS服务器用英文逗号隔开，
+    /// 例如：--dns-policy "ChinaDomain=https://doh.pub/dns-query"，也可以直接写 geosite:cn、rule-set:名称
+    #[arg(long = "dns-policy", value_name = "ruleset=nameserver", value_parser = dns::parse_dns_policy)]
+    dns_policies: Vec<dns::DnsPolicy>,
+
+    /// 将这些规则集(ini中的代理组名称或规则集路径的一部分)中的域名加入dns的fake-ip-filter(可以多次使用)
+    #[arg(long = "fake-ip-filter", value_name = "ruleset")]
+    fake_ip_filter: Vec<String>,
+
+    /// url-test/fallback/load-balance 代理组最多放入的节点数，可选按名称排序后选取，例如 20 或 20:alpha（ini中可以用 `limit=20 单独设置）
+    #[arg(long = "group-limit", value_name = "count[:first|alpha]", value_parser = MyIni::parse_node_limit)]
+    group_limit: Option<MyIni::NodeLimit>,
+
+    /// 按引用关系排序代理组：被引用的代理组排在前面（无论是否使用该选项，存在循环引用时都会报错）
+    #[arg(long = "order-groups")]
+    order_groups: bool,
+
+    /// 代理组没有匹配到任何节点时：remove(移除，规则改为指向兜底的代理组)、direct(只放入DIRECT)、all(放入所有节点)
+    #[arg(long = "empty-group", value_name = "policy", default_value = "remove", value_parser = MyIni::parse_empty_group_policy)]
+    empty_group: MyIni::EmptyGroupPolicy,
+
+    /// 有规则指向的代理组被移除时，规则改为指向：final(最后一个规则集的代理组)、main(第一个select代理组)、direct(DIRECT)
+    #[arg(long = "prune-fallback", value_name = "final|main|direct", default_value = "final", value_parser = MyIni::parse_prune_fallback)]
+    prune_fallback: MyIni::PruneFallback,
+
+    /// 代理组的节点来源：page(只使用当前页的节点)、all(使用所有节点，每一页的proxies也会包含所有节点)
+    #[arg(long = "groups-from", value_name = "page|all", default_value = "page", value_parser = ["page", "all"])]
+    groups_from: String,
+
+    /// 输出格式：yaml、json(完整配置序列化为json，-o 的扩展名为 .yaml/.yml 时自动改为 .json)
+    #[arg(long = "output-format", value_name = "yaml|json", default_value = "yaml", value_parser = ["yaml", "json"])]
+    output_format: String,
+
+    /// 目标客户端：mihomo、stash(去掉 Stash 不支持的节点/代理组字段和规则类型)
+    #[arg(long = "target", value_name = "mihomo|stash", default_value = "mihomo", value_parser = output::parse_target)]
+    target: output::Target,
+
+    /// 不同规则集中内容相同(不含代理组)的规则只保留一个：first(ini中排在前面的规则集优先)、last(排在后面的优先)，
+    /// 不使用时全部保留；指向不同代理组的(冲突)会输出解决的报告
+    #[arg(long = "rule-precedence", value_name = "first|last", value_parser = precedence::parse_rule_precedence)]
+    rule_precedence: Option<precedence::RulePrecedence>,
+
+    /// 优先级最高的规则集(可以多次使用，ini中的代理组名称或规则集路径的一部分)，例如：--prefer-ruleset custom_direct.list
+    #[arg(long = "prefer-ruleset", value_name = "ruleset")]
+    prefer_rulesets: Vec<String>,
+
+    /// 忽略构建指纹，总是重新生成（默认所有输入都没有变化时跳过生成，内容没有变化的文件不重新写入）
+    #[arg(long = "force")]
+    force: bool,
+
+    /// 下载规则集后，校验同一地址发布的 sha256(url.sha256)，校验失败时使用之前保存的规则文件
+    #[arg(long = "verify-sha256")]
+    verify_sha256: bool,
+
+    /// 单个规则集下载的超时时间(包括所有分片)，超时的跳过，有之前保存的规则文件时使用保存的，例如：30s、2m
+    #[arg(long = "rule-timeout", value_name = "duration", default_value = "60s", value_parser = schedule::parse_interval)]
+    rule_timeout: Duration,
+
+    /// 单个规则集的最大大小，超过的跳过，有之前保存的规则文件时使用保存的，例如：512K、64M
+    #[arg(long = "rule-max-size", value_name = "size", default_value = "64M", value_parser = download::parse_size)]
+    rule_max_size: u64,
+
+    /// 订阅链接返回了流量信息(subscription-userinfo)时，在节点最前面加入一个显示剩余流量和到期时间的节点(不能连接)
+    #[arg(long = "traffic-node")]
+    traffic_node: bool,
+
+    /// 节点分配到各页的方式：sequential(按输入顺序填满一页再到下一页)、round-robin(按输入顺序轮流放入每一页)、
+    /// balanced(按地区轮流放入每一页，识别不了地区的按协议类型，每一页都可以单独使用)、
+    /// stable(按 server:port 的哈希分页，每次构建同一个节点尽量留在同一个输出文件中)
+    #[arg(long = "distribute", value_name = "mode", default_value = "sequential", value_parser = paginate::parse_distribution)]
+    distribute: paginate::Distribution,
+
+    /// 每页内节点的排序方式：none(保持输入的顺序)、name(按名称，数字按大小)、region(按地区，同一地区的按名称)、
+    /// latency(按本机到节点服务器的TCP连接耗时，无法连接的排在最后)；流量信息节点总是在最前面
+    #[arg(long = "sort-nodes", value_name = "none|name|region|latency", default_value = "none", value_parser = proxy::parse_node_sort)]
+    sort_nodes: proxy::NodeSort,
+
+    /// 输出文件名模板(文件放在 -o 所在的目录)，可用变量：{stem}、{ext}(-o的文件名和扩展名)、{index}(页码)、{total}(总页数)、
+    /// {date}(构建日期)、{base}(base.yaml的名称)、{region}(这一页节点最多的地区)，数字可以补零，
+    /// 例如：--name-template "{stem}-{region}-{index:02}.{ext}"，不使用时为 output_snap_1.yaml 的格式
+    #[arg(long = "name-template", value_name = "template", value_parser = filename::parse_name_template)]
+    name_template: Option<String>,
+
+    /// 历史文件(上次生成、这次没有再生成的)移动到这个目录(输出目录下的相对路径，不能有 ..)下以构建时间命名的子目录，而不是删除，例如：history
+    #[arg(long = "history-dir", value_name = "dir", value_parser = filename::parse_history_dir)]
+    history_dir: Option<String>,
+
+    /// 历史目录中保留最近 N 次构建的文件，更早的删除(没有指定 --history-dir 时使用 history 目录)
+    #[arg(long = "keep-history", value_name = "N")]
+    keep_history: Option<usize>,
+
+    /// 构建成功后执行的命令，生成的文件(打包模式为压缩包)依次作为参数传给命令，例如：--on-success ./upload.sh
+    #[arg(long = "on-success", value_name = "command")]
+    on_success: Option<String>,
+
+    /// 插件(可以多次使用，同一阶段的按顺序运行)：阶段=命令，命令从 stdin 读取 json，把修改后的 json 输出到 stdout；
+    /// 阶段：post-merge(合并后的节点列表)、post-group(每一页的代理组，页码在环境变量 CLASH_TOOL_PAGE 中)、
+    /// pre-write(写入之前的规则列表)。WASM 插件可以通过 WASI 运行时执行，例如：--plugin "post-merge=wasmtime run filter.wasm"
+    #[arg(long = "plugin", value_name = "stage=command", value_parser = plugin::parse_plugin)]
+    plugins: Vec<plugin::Plugin>,
+
+    /// 构建成功后 POST 到这个地址，内容为 json 格式的生成文件列表和清单(输出文件名.index.json)
+    #[arg(long = "webhook", value_name = "url")]
+    webhook: Option<String>,
+
+    /// 构建成功后上传生成的文件(WebDAV、S3兼容的对象存储、GitHub Gist)，目标写在yaml配置文件中，格式见 config/upload.example.yaml
+    #[arg(long = "upload", value_name = "upload.yaml")]
+    upload: Option<String>,
+
+    /// 写入之前跟上次构建的输出文件比较，输出新增/删除/改名的节点、有变化的代理组和规则数的变化
+    #[arg(long = "diff")]
+    diff: bool,
+
+    /// 构建之前在终端中列出合并后的节点(地区、协议、是否重复)，勾选要放入配置的节点，
+    /// 选择结果保存到 --selection 的文件(默认 node_selection.json)，之后的构建(包括 --watch、--interval)直接使用
+    #[arg(long = "interactive")]
+    interactive: bool,
+
+    /// 保存的节点选择文件(--interactive 生成)，构建时去掉其中记录为不要的节点，之后新增的节点默认保留
+    #[arg(long = "selection", value_name = "file")]
+    selection: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Proxies {
-    proxies: Vec<YamlValue>,
+/// 读取和处理节点的参数，构建和 proxies 子命令共用
+#[derive(clap::Args, Debug, Clone)]
+struct NodeArgs {
+    /// 输入含有proxies节点的clash配置文件或订阅链接，支持多个(用英文逗号隔开，排在前面的优先)，
+    /// 可以用 路径:数量 限制每个输入最多取的节点数，例如：main.yaml:100,backup.yaml:20；
+    /// - 表示从标准输入读取，例如：curl 订阅链接 | clash_subscription_tool -f -
+    #[arg(short = 'f', long = "inputs", default_value = "clash.yaml")]
+    proxies_file_path: String,
+
+    // /sub 接口的输入：每一项是一个订阅链接或文件，不按逗号拆分(订阅链接中可以有逗号)，有值时代替 -f
+    #[arg(skip)]
+    input_list: Vec<String>,
+
+    /// 规范化节点：port转为整数、type转为小写、补全udp、去掉不认识的字段，丢弃结构无效的节点
+    #[arg(long = "sanitize")]
+    sanitize: bool,
+
+    /// 只保留这些协议类型的节点(用英文逗号隔开)，例如：vmess,vless,trojan
+    #[arg(long = "allow-types", value_name = "types", value_delimiter = ',')]
+    allow_types: Vec<String>,
+
+    /// 去掉这些协议类型的节点(用英文逗号隔开)，例如：ssr,http
+    #[arg(long = "deny-types", value_name = "types", value_delimiter = ',')]
+    deny_types: Vec<String>,
+
+    /// 只保留支持 UDP 转发的节点(udp: true，或者 hysteria、hysteria2、tuic、wireguard 等基于 UDP 的协议)，
+    /// 用于游戏、QUIC 等需要 UDP 的场景；没有 udp 字段的节点可以先用 --sanitize 按协议补全
+    #[arg(long = "require-udp")]
+    require_udp: bool,
+
+    /// 只保留开启了 TCP Fast Open 的节点(tfo: true)
+    #[arg(long = "require-tfo")]
+    require_tfo: bool,
+
+    /// 去掉服务器在黑名单中的节点(用英文逗号隔开)，可以是CIDR、IP或域名(同时匹配子域名)，
+    /// 例如：--block-servers 127.0.0.0/8,10.0.0.0/8,example.com
+    #[arg(long = "block-servers", value_name = "cidr-or-domain-list", value_delimiter = ',', value_parser = blocklist::parse_server_pattern)]
+    block_servers: Vec<blocklist::ServerPattern>,
+
+    /// 去掉端口在黑名单中的节点(用英文逗号隔开)，可以是端口范围，例如：--block-ports 25,465,6881-6889
+    #[arg(long = "block-ports", value_name = "ports", value_delimiter = ',', value_parser = blocklist::parse_port_range)]
+    block_ports: Vec<(u16, u16)>,
+
+    /// 节点改名规则(可以多次使用，按顺序执行)，在分组之前生效，例如：--rename 's/香港/HK/' --rename '\s*\|\s*@-'
+    #[arg(long = "rename", value_name = "s/regex/replacement/", value_parser = rename::parse_rename_rule)]
+    rename_rules: Vec<rename::RenameRule>,
+
+    /// 节点转换脚本(rhai)，在改名之后对每个节点运行一次：变量 proxy 为节点(可以修改、改名)，index 为序号，
+    /// 脚本最后的值为 false 时丢弃该节点；可以使用 regex_match、regex_replace，例如：--script transform.rhai
+    #[arg(long = "script", value_name = "transform.rhai")]
+    script: Option<String>,
+
+    /// 合并多个输入时，给节点名称加上来源的前缀，例如 [A] 香港01；可以按 -f 的顺序指定标签(用英文逗号隔开)，
+    /// 不指定的使用文件名(订阅链接使用域名)，例如：--tag-source A,B
+    #[arg(long = "tag-source", value_name = "labels", num_args = 0..=1, default_missing_value = "", value_delimiter = ',')]
+    tag_source: Option<Vec<String>>,
+
+    /// 重名节点的改名方式：hash(加上哈希后缀，例如 HK-01-a1B2c3)、counter(加上序号，例如 HK-01-2)、emoji(加上emoji序号，例如 HK-01 2️⃣)
+    #[arg(long = "dedup-names", value_name = "hash|counter|emoji", default_value = "hash", value_parser = paginate::parse_dedup_style)]
+    dedup_names: paginate::DedupStyle,
+}
+
+impl NodeArgs {
+    /// 所有的输入：-f 按逗号拆分，或者 /sub 接口的输入
+    fn sources(&self) -> Vec<&str> {
+        if self.input_list.is_empty() {
+            self.proxies_file_path.split(',').collect()
+        } else {
+            self.input_list.iter().map(String::as_str).collect()
+        }
+    }
+}
+
+/// 子命令，不使用时为构建配置
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// 构建配置(不写子命令时也是构建)
+    #[command(args_override_self = true)]
+    Build(BuildArgs),
+
+    /// 构建配置，然后一直运行：--watch 监听文件变化、--interval 定时重新下载订阅和规则后重新构建、
+    /// --listen 提供跟 subconverter 兼容的转换接口(至少使用其中一个)
+    #[command(args_override_self = true)]
+    Serve(BuildArgs),
+
+    /// 检查已有的 clash/mihomo 配置(不限于本工具生成的)：重复的名称、引用了不存在的代理组/规则集、循环引用、无效的CIDR等，
+    /// 有错误时退出码为 1
+    Lint {
+        /// 要检查的配置文件
+        config: String,
+    },
+
+    /// 只合并、去重、过滤节点，输出节点列表(不生成代理组和规则)，节点数量输出到 stderr，方便用管道交给其它工具；
+    /// 订阅链接、分享链接等格式都转为 clash 的节点列表
+    #[command(alias = "proxies")]
+    Convert {
+        #[command(flatten)]
+        nodes: NodeArgs,
+
+        /// 输出格式：yaml、json
+        #[arg(long = "output-format", value_name = "yaml|json", default_value = "yaml", value_parser = ["yaml", "json"])]
+        output_format: String,
+    },
+
+    /// 按节点的协议类型、地区或者任意字段拆分一个配置，每个值输出一个文件(只含有该值的节点，其它内容不变)
+    Split {
+        /// 要拆分的配置文件(例如合并后的订阅)
+        config: String,
+
+        /// 拆分依据：type(协议类型)、region(根据名称识别的地区)，或者节点的字段(用点号分隔)，例如 network、ws-opts.path
+        #[arg(long = "by", value_name = "selector", default_value = "type")]
+        by: String,
+
+        /// 输出路径，文件名中会加上字段的值，例如 split_vmess.yaml
+        #[arg(short = 'o', long = "output", default_value = "split.yaml")]
+        output: String,
+    },
+
+    /// 分阶段运行规则的处理流程(使用 -c、-s 和规则相关的参数)：读取(只用 -s 中已下载的规则，不联网)、解析、排序去重、序列化，
+    /// 输出每个阶段的耗时和内存分配，用于性能优化和比较不同版本
+    #[command(args_override_self = true)]
+    Bench {
+        #[command(flatten)]
+        build: BuildArgs,
+
+        /// 每个阶段运行的次数
+        #[arg(long = "runs", default_value_t = 3)]
+        runs: usize,
+
+        /// 输出json（默认为表格）
+        #[arg(long = "json")]
+        json: bool,
+    },
+
+    /// 解密 --encrypt-age、--encrypt-pass 生成的文件(age 格式)，使用口令加密的文件从环境变量 CLASH_TOOL_PASSPHRASE 读取口令
+    Decrypt {
+        /// 要解密的文件
+        file: String,
+
+        /// age 私钥文件(age-keygen 生成的格式，可以多次使用)
+        #[arg(short = 'i', long = "identity", value_name = "key.txt")]
+        identities: Vec<String>,
+
+        /// 输出文件，不使用时输出到终端
+        #[arg(short = 'o', long = "output")]
+        output: Option<String>,
+    },
+
+    /// 输出 shell 的自动补全脚本(根据命令行参数的定义生成)，例如：completions bash > /etc/bash_completion.d/clash_subscription_tool
+    Completions {
+        /// bash、zsh、fish、powershell、elvish
+        shell: clap_complete::Shell,
+    },
+
+    /// 统计每个输入的节点：总数、各协议和地区的数量、重复的节点、缺少必要字段的节点，方便比较不同的订阅
+    Stats {
+        /// 输入的clash配置文件或订阅链接，多个用英文逗号隔开，可以用 路径:数量 只统计前面的节点，- 表示标准输入
+        #[arg(short = 'f', long = "inputs", default_value = "clash.yaml")]
+        inputs: String,
+
+        /// 输出json（默认为表格）
+        #[arg(long = "json")]
+        json: bool,
+    },
+}
+
+/// 序列化时只借用节点，不复制
+#[derive(Serialize, Debug)]
+struct Proxies<'a> {
+    proxies: Vec<&'a YamlValue>,
+}
+
+/// 一页的节点和代理组（各页并行生成）；规则所有页共用，这一页需要改写的在输出时才改写，不保存副本
+struct PageBuild {
+    proxies_indent: String,
+    proxy_groups: Vec<MyIni::SelectGroup>,
+    proxy_group_indent: String,
+    rule_rewrites: HashMap<String, String>, // 被移除的代理组 -> 规则改为指向的代理组
+    rewrites: Vec<(String, usize, String)>, // 被移除的代理组、改写的规则数、改为指向的代理组
+    json_docs: Vec<String>,                 // json 格式时，每个 base.yaml 的完整配置
+}
+
+impl PageBuild {
+    /// 这一页的规则：没有需要改写的规则时直接借用共用的规则
+    fn rules<'a>(&self, all_rules: &'a str) -> Cow<'a, str> {
+        rules::rewrite_rule_groups(all_rules, &self.rule_rewrites)
+    }
+
+    /// 第 b 个 base.yaml 的配置依次写出的各部分，json 格式为完整的配置
+    fn parts<'a>(&'a self, b: usize, base_yaml_indent: &'a str, rules: &'a str) -> Vec<&'a str> {
+        if self.json_docs.is_empty() {
+            vec![
+                base_yaml_indent,
+                &self.proxies_indent,
+                &self.proxy_group_indent,
+                rules,
+            ]
+        } else {
+            vec![&self.json_docs[b]]
+        }
+    }
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 8)]
 async fn main() {
-    let cli = Args::try_parse().unwrap_or_else(|_err| {
-        Args::command().print_help().unwrap();
+    // 读取配置方案时的错误也要按 --json-errors 输出，所以在解析参数之前检查
+    let args: Vec<String> = std::env::args().collect();
+    let json_errors = args.iter().any(|arg| arg == "--json-errors");
+    exit::install_hook(json_errors);
+    // 帮助信息在解析参数时就要用到，所以也在解析之前确定语言
+    i18n::set(i18n::detect(&args));
+    let argv = profile::apply_profile(args, &Args::command()).unwrap_or_else(|e| exit::exit(e));
+    let parsed = i18n::localize_command(Args::command())
+        .try_get_matches_from(argv)
+        .and_then(|matches| Args::from_arg_matches(&matches));
+    let mut cli = parsed.unwrap_or_else(|err| {
+        // --help、--version 由 clap 输出(子命令的 --help 输出该子命令的帮助)
+        if matches!(
+            err.kind(),
+            clap::error::ErrorKind::DisplayHelp | clap::error::ErrorKind::DisplayVersion
+        ) {
+            err.exit();
+        }
+        if json_errors {
+            exit::exit(exit::Failure::new(
+                exit::ErrorKind::Other,
+                err.to_string().trim_end(),
+            ));
+        }
+        i18n::localize_command(Args::command())
+            .print_help()
+            .unwrap();
         println!();
         std::process::exit(1);
     });
+    // 配置方案中也可以指定语言
+    if let Some(lang) = cli.lang {
+        i18n::set(lang);
+    }
+    expand_cli_paths(&mut cli);
+    if cli.redact {
+        redact::enable();
+    }
+
+    if cli.man {
+        // 输出给 head 等提前关闭管道的程序时，忽略写入错误
+        let _ = clap_mangen::Man::new(i18n::localize_command(Args::command()))
+            .render(&mut std::io::stdout());
+        return;
+    }
+
+    download::init_client(cli.user_agent.as_deref(), &cli.headers);
+    let mut cli = match cli.command {
+        Some(Command::Build(build)) => build,
+        Some(Command::Serve(build)) => {
+            if !build.watch && build.interval.is_none() && build.listen.is_none() {
+                exit::exit(exit::Failure::new(
+                    exit::ErrorKind::Config,
+                    tr!(
+                        "serve 子命令需要 --watch、--interval 或 --listen",
+                        "the serve subcommand needs --watch, --interval or --listen"
+                    ),
+                ));
+            }
+            build
+        }
+        Some(Command::Lint { config }) => std::process::exit(lint_command(&config)),
+        Some(Command::Convert {
+            nodes,
+            output_format,
+        }) => {
+            proxies_command(&nodes, output_format == "json")
+                .await
+                .unwrap_or_else(|e| exit::exit(e));
+            return;
+        }
+        Some(Command::Split { config, by, output }) => {
+            split_command(&config, &by, &output);
+            return;
+        }
+        Some(Command::Bench { build, runs, json }) => {
+            bench_command(&build, runs, json).unwrap_or_else(|e| exit::exit(e));
+            return;
+        }
+        Some(Command::Stats { inputs, json }) => {
+            stats_command(&inputs, json)
+                .await
+                .unwrap_or_else(|e| exit::exit(e));
+            return;
+        }
+        Some(Command::Completions { shell }) => {
+            let mut command = i18n::localize_command(Args::command());
+            let name = command.get_name().to_string();
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut command, name, &mut script);
+            let _ = std::io::stdout().lock().write_all(&script);
+            return;
+        }
+        Some(Command::Decrypt {
+            file,
+            identities,
+            output,
+        }) => std::process::exit(decrypt_command(&file, &identities, output.as_deref())),
+        None => cli.build,
+    };
+    if cli.dump_defaults {
+        let written = presets::dump_defaults(&cli.ini_file_path, base_paths(&cli.header_file_path))
+            .unwrap_or_else(|e| {
+                panic!(
+                    "{}",
+                    tr!(
+                        "写出默认配置失败：{}",
+                        "failed to write the defaults: {}",
+                        e
+                    )
+                )
+            });
+        for (path, is_written) in written {
+            if is_written {
+                println!("{}", tr!("已写出：{}", "written: {}", path.display()));
+            } else {
+                println!(
+                    "{}",
+                    tr!(
+                        "文件已存在，没有覆盖：{}",
+                        "file exists, not overwritten: {}",
+                        path.display()
+                    )
+                );
+            }
+        }
+        return;
+    }
+    if cli.interactive && cli.selection.is_none() {
+        cli.selection = Some("node_selection.json".to_string());
+    }
+    // 只提供转换接口时，不构建 -f 的输入
+    let code = if cli.listen.is_some() && !cli.watch && cli.interval.is_none() {
+        0
+    } else {
+        run_guarded(cli.clone()).await
+    };
+    // 只在第一次构建时选择，之后的构建使用保存的选择
+    cli.interactive = false;
+
+    let watch_task = async {
+        if cli.watch {
+            let mut watch_paths: Vec<PathBuf> = cli
+                .nodes
+                .sources()
+                .into_iter()
+                .map(|s| proxy::parse_source(s).0)
+                .filter(|s| !proxy::is_url(s) && !proxy::is_stdin(s)) // 订阅链接、标准输入无法监听
+                .map(PathBuf::from)
+                .collect();
+            // 内置的方案和默认配置不需要监听
+            if presets::embedded_ini(cli.preset, &cli.ini_file_path).is_none() {
+                watch_paths.push(PathBuf::from(&cli.ini_file_path));
+            }
+            watch_paths.extend(
+                [&cli.extra_groups, &cli.extra_rules, &cli.nodes.script]
+                    .into_iter()
+                    .flatten()
+                    .map(PathBuf::from),
+            );
+            for path in base_paths(&cli.header_file_path) {
+                // base.yaml 中引用的片段变化时也重新构建
+                if Path::new(path).exists() {
+                    watch_paths.push(PathBuf::from(path));
+                    if let Ok((_, fragments)) = read::read_yaml_with_includes(path) {
+                        watch_paths.extend(fragments);
+                    }
+                }
+            }
+
+            watch::watch_files(&watch_paths, || async {
+                run_guarded(cli.clone()).await;
+            })
+            .await;
+        }
+    };
+    let daemon_task = async {
+        if let Some(interval) = cli.interval {
+            schedule::run_every(interval, || run_guarded(cli.clone())).await;
+        }
+    };
+    let listen_task = async {
+        if let Some(addr) = cli.listen {
+            // 同时只构建一个请求，构建时会修改全局状态(例如下载的规则)
+            let lock = Arc::new(tokio::sync::Mutex::new(()));
+            let cli = cli.clone();
+            server::listen(addr, move |request| {
+                let (cli, lock) = (cli.clone(), lock.clone());
+                async move {
+                    let _guard = lock.lock().await;
+                    sub_build(cli, request).await
+                }
+            })
+            .await
+            .unwrap_or_else(|e| exit::exit(e));
+        }
+    };
+    tokio::join!(watch_task, daemon_task, listen_task);
+    // 只构建一次时，使用这次构建的退出码
+    std::process::exit(code);
+}
+
+/// /sub 接口的一次转换：使用 serve 的参数，输入换成请求中的订阅链接和ini，所有节点生成一个配置(不分页)
+async fn sub_build(cli: BuildArgs, request: server::SubRequest) -> Result<String, String> {
+    static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let work_dir = std::env::temp_dir().join(format!(
+        "clash_tool_sub_{}_{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&work_dir).map_err(|e| e.to_string())?;
+    let result = sub_build_in(cli, request, &work_dir).await;
+    let _ = std::fs::remove_dir_all(&work_dir);
+    result
+}
+
+async fn sub_build_in(
+    mut cli: BuildArgs,
+    request: server::SubRequest,
+    work_dir: &Path,
+) -> Result<String, String> {
+    let mut inputs = request.urls;
+    if !request.links.is_empty() {
+        let links_path = work_dir.join("links.txt");
+        std::fs::write(&links_path, request.links.join("\n")).map_err(|e| e.to_string())?;
+        inputs.push(links_path.to_string_lossy().into_owned());
+    }
+    // 请求中的ini(只能是链接)不能引用服务器上的本地规则集
+    if let Some(config) = request.config {
+        let data = download::download_single(&config).await.map_err(|e| {
+            tr!(
+                "下载ini失败 {}：{}",
+                "failed to download the ini {}: {}",
+                config,
+                e
+            )
+        })?;
+        let ini_path = work_dir.join("config.ini");
+        std::fs::write(&ini_path, data).map_err(|e| e.to_string())?;
+        cli.ini_file_path = ini_path.to_string_lossy().into_owned();
+        cli.preset = None;
+        cli.remote_rulesets_only = true;
+    }
+    cli.nodes.input_list = inputs;
+    cli.target = request.target;
+    cli.output_file_path = work_dir.join("output.yaml").to_string_lossy().into_owned();
+    cli.output_format = "yaml".to_string();
+    cli.page_size = usize::MAX;
+    cli.name_template = None;
+    // 结果只返回给请求方，不写出其它文件、不通知
+    (cli.watch, cli.interval, cli.listen) = (false, None, None);
+    (cli.archive, cli.encrypt_age, cli.encrypt_pass) = (None, Vec::new(), false);
+    (cli.history_dir, cli.upload, cli.webhook, cli.on_success) = (None, None, None, None);
+    (cli.dry_run, cli.diff, cli.interactive) = (false, false, false);
+
+    let code = run_guarded(cli).await;
+    let mut outputs: Vec<PathBuf> = std::fs::read_dir(work_dir)
+        .map_err(|e| e.to_string())?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "yaml"))
+        .collect();
+    outputs.sort();
+    match outputs.first() {
+        Some(path) => std::fs::read_to_string(path).map_err(|e| e.to_string()),
+        None => Err(tr!(
+            "转换失败(退出码 {})，详细信息见服务端的日志",
+            "conversion failed (exit code {}), see the server log for details",
+            code
+        )),
+    }
+}
+
+/// 展开路径参数(-c/-b/-f/-o/-s 以及子命令中的路径)中的环境变量和 ~，同一份命令或配置方案可以在 Linux/Windows 上使用
+fn expand_cli_paths(cli: &mut Args) {
+    expand_build_paths(&mut cli.build);
+    match &mut cli.command {
+        Some(Command::Build(build) | Command::Serve(build) | Command::Bench { build, .. }) => {
+            expand_build_paths(build)
+        }
+        Some(Command::Lint { config }) => *config = expand::expand_path(config),
+        Some(Command::Convert { nodes, .. }) => {
+            nodes.proxies_file_path = expand::expand_path_list(&nodes.proxies_file_path)
+        }
+        Some(Command::Split { config, output, .. }) => {
+            *config = expand::expand_path(config);
+            *output = expand::expand_path(output);
+        }
+        Some(Command::Stats { inputs, .. }) => *inputs = expand::expand_path_list(inputs),
+        Some(Command::Decrypt {
+            file,
+            identities,
+            output,
+        }) => {
+            *file = expand::expand_path(file);
+            identities
+                .iter_mut()
+                .for_each(|path| *path = expand::expand_path(path));
+            if let Some(output) = output {
+                *output = expand::expand_path(output);
+            }
+        }
+        Some(Command::Completions { .. }) | None => {}
+    }
+}
+
+fn expand_build_paths(cli: &mut BuildArgs) {
+    cli.ini_file_path = expand::expand_path(&cli.ini_file_path);
+    cli.header_file_path = expand::expand_path_list(&cli.header_file_path);
+    cli.nodes.proxies_file_path = expand::expand_path_list(&cli.nodes.proxies_file_path);
+    cli.output_file_path = expand::expand_path(&cli.output_file_path);
+    cli.save_rules_dir = expand::expand_path(&cli.save_rules_dir);
+}
+
+/// lint 子命令：输出配置中的问题，返回退出码（有 error 时为 1）
+fn lint_command(config_path: &str) -> i32 {
+    let config = read::read_yaml(config_path);
+    let issues = lint::lint_config(&config);
+    for issue in &issues {
+        println!("[{}] {}", issue.level.as_str(), issue.message);
+    }
+    let errors = issues
+        .iter()
+        .filter(|i| i.level == lint::LintLevel::Error)
+        .count();
+    println!(
+        "{}",
+        tr!(
+            "{}：{} 个错误，{} 个警告",
+            "{}: {} errors, {} warnings",
+            config_path,
+            errors,
+            issues.len() - errors
+        )
+    );
+    i32::from(errors > 0)
+}
+
+/// decrypt 子命令：解密 age 格式的文件，返回退出码（解密失败时为 1）
+fn decrypt_command(file: &str, identity_paths: &[String], output: Option<&str>) -> i32 {
+    let data = std::fs::read(file).unwrap_or_else(|e| {
+        panic!(
+            "{}",
+            tr!("读取文件失败：{}，{}", "failed to read {}: {}", file, e)
+        )
+    });
+    let mut identities = Vec::new();
+    for path in identity_paths {
+        let content = std::fs::read_to_string(path).unwrap_or_else(|e| {
+            panic!(
+                "{}",
+                tr!(
+                    "读取私钥文件失败：{}，{}",
+                    "failed to read key file {}: {}",
+                    path,
+                    e
+                )
+            )
+        });
+        identities.extend(
+            encrypt::parse_identities(&content).unwrap_or_else(|e| panic!("{}：{}", path, e)),
+        );
+    }
+    let passphrase = encrypt::passphrase_from_env().ok();
+    match encrypt::decrypt(&data, &identities, passphrase.as_deref()) {
+        Ok(plaintext) => {
+            match output {
+                Some(output) => {
+                    std::fs::write(output, &plaintext).unwrap_or_else(|e| {
+                        panic!(
+                            "{}",
+                            tr!("写入文件失败：{}，{}", "failed to write {}: {}", output, e)
+                        )
+                    });
+                    eprintln!("{}", tr!("已解密到：{}", "decrypted to: {}", output));
+                }
+                // 输出给 head 等提前关闭管道的程序时，忽略写入错误
+                None => {
+                    let _ = std::io::stdout().lock().write_all(&plaintext);
+                }
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!(
+                "{}",
+                tr!("解密失败：{}，{}", "failed to decrypt {}: {}", file, e)
+            );
+            1
+        }
+    }
+}
+
+/// 读取、合并所有输入的节点，再加上来源标签、规范化、按类型过滤和改名（提示信息输出到 stderr）
+async fn load_proxies(
+    nodes: &NodeArgs,
+) -> Result<
+    (
+        Vec<YamlValue>,
+        Vec<(String, usize)>,
+        Vec<proxy::SourceFetch>,
+    ),
+    exit::Failure,
+> {
+    let (merge_proxies, source_counts, subscriptions) =
+        proxy::extract_and_merge_proxies(&nodes.sources(), "proxies").await?;
+    // 过滤、改名使用强类型的节点，最后再转回 yaml
+    let mut merge_proxies: Vec<Proxy> = merge_proxies.into_iter().map(Proxy::from).collect();
+    if let Some(labels) = &nodes.tag_source {
+        proxy::tag_proxies_by_source(&mut merge_proxies, &source_counts, labels);
+    }
+    let merge_proxies = if nodes.sanitize {
+        let values = merge_proxies.into_iter().map(YamlValue::from).collect();
+        sanitize::sanitize_proxies(values)
+            .into_iter()
+            .map(Proxy::from)
+            .collect()
+    } else {
+        merge_proxies
+    };
+    let merge_proxies = if nodes.allow_types.is_empty() && nodes.deny_types.is_empty() {
+        merge_proxies
+    } else {
+        let (kept, dropped) =
+            proxy::filter_proxy_types(merge_proxies, &nodes.allow_types, &nodes.deny_types);
+        if dropped > 0 {
+            eprintln!(
+                "{}",
+                tr!(
+                    "按协议类型过滤掉 {} 个节点",
+                    "filtered out {} nodes by protocol",
+                    dropped
+                )
+            );
+        }
+        kept
+    };
+    let merge_proxies = if nodes.block_servers.is_empty() && nodes.block_ports.is_empty() {
+        merge_proxies
+    } else {
+        let (kept, dropped) =
+            blocklist::filter_blocked(merge_proxies, &nodes.block_servers, &nodes.block_ports);
+        if dropped > 0 {
+            eprintln!(
+                "{}",
+                tr!(
+                    "按服务器/端口黑名单过滤掉 {} 个节点",
+                    "filtered out {} nodes by the server/port blocklist",
+                    dropped
+                )
+            );
+        }
+        kept
+    };
+    let mut merge_proxies = if nodes.require_udp || nodes.require_tfo {
+        let (kept, dropped) =
+            proxy::filter_capabilities(merge_proxies, nodes.require_udp, nodes.require_tfo);
+        if dropped > 0 {
+            eprintln!(
+                "{}",
+                tr!(
+                    "按 UDP/TFO 要求过滤掉 {} 个节点",
+                    "filtered out {} nodes by the UDP/TFO requirement",
+                    dropped
+                )
+            );
+        }
+        kept
+    } else {
+        merge_proxies
+    };
+    if !nodes.rename_rules.is_empty() {
+        let renamed = rename::rename_proxies(&mut merge_proxies, &nodes.rename_rules);
+        eprintln!(
+            "{}",
+            tr!(
+                "改名规则修改了 {} 个节点的名称",
+                "rename rules renamed {} nodes",
+                renamed
+            )
+        );
+    }
+    let merge_proxies: Vec<YamlValue> = merge_proxies.into_iter().map(YamlValue::from).collect();
+    let merge_proxies = match &nodes.script {
+        Some(path) => {
+            let node_script = script::NodeScript::load(path)
+                .map_err(exit::Failure::with(exit::ErrorKind::Config))?;
+            let (kept, stats) = node_script
+                .apply(merge_proxies)
+                .map_err(exit::Failure::with(exit::ErrorKind::Config))?;
+            eprintln!(
+                "{}",
+                tr!(
+                    "节点脚本修改了 {} 个节点，丢弃了 {} 个节点",
+                    "the node script changed {} nodes and dropped {}",
+                    stats.changed,
+                    stats.dropped
+                )
+            );
+            kept
+        }
+        None => merge_proxies,
+    };
+    Ok((merge_proxies, source_counts, subscriptions))
+}
+
+/// 节点去重后分页，重名的节点按 deduper 改名
+fn paginate_proxies(
+    proxies: Vec<YamlValue>,
+    page_size: usize,
+    distribution: paginate::Distribution,
+    deduper: &dyn paginate::NameDeduper,
+) -> Vec<paginate::Page<YamlValue>> {
+    paginate::dedup_and_paginate(
+        proxies,
+        page_size,
+        distribution,
+        deduper,
+        |item: &YamlValue| match distribution {
+            paginate::Distribution::Stable => proxy::stable_key(item),
+            _ => proxy::balance_key(item),
+        },
+        &["name", "skip-cert-verify"], // 暂时移除的key-value，移除它们再计算hash，判断是否跟其它的节点重复
+        |item: &mut YamlValue| match item.get_mut("name") {
+            Some(YamlValue::String(name)) => Some(name), // 如果发现name字段跟其它节点的name重复，就改为其它name名称
+            _ => None,
+        },
+    )
+}
+
+/// proxies 子命令：只输出处理后的节点列表
+async fn proxies_command(nodes: &NodeArgs, output_json: bool) -> Result<(), exit::Failure> {
+    let (merge_proxies, _, _) = load_proxies(nodes).await?;
+    let total = merge_proxies.len();
+    let pages = paginate_proxies(
+        merge_proxies,
+        total.max(1),
+        paginate::Distribution::Sequential,
+        &nodes.dedup_names,
+    );
+    let proxies = Proxies {
+        proxies: pages.iter().flat_map(|page| page.items.iter()).collect(),
+    };
+    let content = if output_json {
+        serde_json::to_string_pretty(&proxies).unwrap()
+    } else {
+        indent::to_indented_yaml(&proxies)
+    };
+    // 输出给 head 等提前关闭管道的程序时，忽略写入错误
+    let _ = writeln!(std::io::stdout().lock(), "{}", content);
+    eprintln!(
+        "{}",
+        tr!(
+            "节点合计：{} 个，去除重复后：{} 个",
+            "nodes: {} in total, {} after deduplication",
+            total,
+            proxies.proxies.len()
+        )
+    );
+    Ok(())
+}
+
+/// split 子命令：按节点字段的值拆分配置
+fn split_command(config_path: &str, selector: &str, output: &str) {
+    let config = read::read_yaml(config_path);
+    for (key, part, count) in split::split_config(&config, selector) {
+        let path = split::split_filename(output, &key);
+        std::fs::write(&path, indent::to_indented_yaml(&part)).unwrap();
+        println!(
+            "{}",
+            tr!(
+                "已生成：{}（{} 个节点）",
+                "generated: {} ({} nodes)",
+                path.display(),
+                count
+            )
+        );
+    }
+}
 
+/// stats 子命令：统计每个输入的节点
+async fn stats_command(inputs: &str, output_json: bool) -> Result<(), exit::Failure> {
+    let mut all_stats = Vec::new();
+    for (path, cap) in inputs.split(',').map(proxy::parse_source) {
+        all_stats.push(stats::source_stats(path, cap).await?);
+    }
+    if output_json {
+        println!("{}", serde_json::to_string_pretty(&all_stats).unwrap());
+    } else {
+        stats::print_stats_table(&all_stats);
+    }
+    Ok(())
+}
+
+/// bench 子命令：分阶段运行规则的处理流程，输出耗时和内存分配
+fn bench_command(cli: &BuildArgs, runs: usize, output_json: bool) -> Result<(), exit::Failure> {
+    let ini_config = presets::load_ini(cli.preset, &cli.ini_file_path)?;
+    let (_, ruleset, _) = MyIni::read_ini(ini_config)?;
+    let options = rules::RulesOptions {
+        save_rules_dir: cli.save_rules_dir.clone(),
+        rule_filter: build_rule_filter(cli)?,
+        keep_rule_order: cli.keep_rule_order,
+        merge_cidr: cli.merge_cidr,
+        compact_domains: cli.compact_domains,
+        ..Default::default()
+    };
+    let (stages, missing) = bench::bench_rules(&ruleset, &options, runs);
+    for path in &missing {
+        eprintln!(
+            "{}",
+            tr!(
+                "没有已下载的规则，跳过：{}",
+                "no downloaded rules, skipped: {}",
+                path
+            )
+        );
+    }
+    if output_json {
+        println!("{}", serde_json::to_string_pretty(&stages).unwrap());
+        return Ok(());
+    }
+    if i18n::is_zh() {
+        println!(
+            "{:<8} {:>8} {:>8} {:>10} {:>8} {:>10} {:>8}",
+            "阶段", "平均", "最短", "数量", "分配次数", "分配", "峰值"
+        );
+    } else {
+        println!(
+            "{:<10} {:>10} {:>10} {:>12} {:>12} {:>12} {:>10}",
+            "stage", "mean", "min", "items", "allocs", "allocated", "peak"
+        );
+    }
+    for stage in &stages {
+        println!(
+            "{:<10} {:>10} {:>10} {:>12} {:>12} {:>12} {:>10}",
+            stage.stage,
+            format!("{:.2?}", stage.mean()),
+            format!("{:.2?}", stage.min()),
+            stage.items,
+            stage.alloc.allocations,
+            report::human_size(stage.alloc.allocated_bytes),
+            report::human_size(stage.alloc.peak_bytes)
+        );
+    }
+    println!(
+        "{}",
+        tr!(
+            "共 {} 个规则集（{} 个没有已下载的规则），每个阶段运行 {} 次",
+            "{} rulesets ({} without downloaded rules), {} runs per stage",
+            ruleset.len(),
+            missing.len(),
+            runs.max(1)
+        )
+    );
+    Ok(())
+}
+
+/// 在单独的任务中构建，监听/守护模式下即使这次构建失败，也不影响后续的重新构建；返回这次构建的退出码
+async fn run_guarded(cli: BuildArgs) -> i32 {
+    match tokio::spawn(run(cli)).await {
+        Ok(Ok(())) => 0,
+        Ok(Err(failure)) => {
+            exit::report(&failure);
+            failure.kind.code()
+        }
+        // 程序内部错误(panic)，内容已经由 panic 钩子输出
+        Err(e) => {
+            if !exit::json_errors() {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "本次构建失败，请检查输入文件！",
+                        "this build failed, please check the input files!"
+                    )
+                );
+            }
+            match e.try_into_panic() {
+                Ok(payload) => exit::from_panic(&*payload).kind.code(),
+                Err(_) => exit::ErrorKind::Other.code(),
+            }
+        }
+    }
+}
+
+/// -b 参数中的多个 base.yaml 路径
+fn base_paths(header_file_path: &str) -> impl Iterator<Item = &str> {
+    header_file_path
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
+
+/// 根据命令行参数构建规则类型的过滤配置：配置文件 -> meta模式 -> 保留 -> 过滤
+fn build_rule_filter(cli: &BuildArgs) -> Result<RuleFilter, exit::Failure> {
+    let mut rule_filter = match &cli.rule_types_file {
+        Some(path) => {
+            RuleFilter::from_file(path).map_err(exit::Failure::with(exit::ErrorKind::Config))?
+        }
+        None => RuleFilter::default(),
+    };
+    if cli.meta {
+        rule_filter.keep_types(&constants::META_KEY);
+    }
+    rule_filter.keep_types(&cli.keep_rule_types);
+    rule_filter.drop_types(&cli.drop_rule_types);
+    rule_filter.drop_types(cli.target.drop_rule_types());
+    Ok(rule_filter)
+}
+
+/// 构建一次，失败（包括部分成功、没有可输出的内容）时返回对应类型的 Failure
+async fn run(cli: BuildArgs) -> Result<(), exit::Failure> {
+    // 记录当前时间（整个构建的耗时包括下载订阅和规则）
+    let start_time = Instant::now();
+    // 命令行参数也是构建指纹的一部分
+    let args_debug = format!("{:?} redact={}", cli, redact::is_enabled());
+    let rule_filter = build_rule_filter(&cli)?;
     let ini_file_path = cli.ini_file_path;
     let base_yaml_path = cli.header_file_path;
-    let node_file_path = cli.proxies_file_path;
-    let output_yaml_path = cli.output_file_path;
+    let output_json = cli.output_format == "json";
+    let output_yaml_path = match Path::new(&cli.output_file_path).extension() {
+        Some(ext) if output_json && (ext == "yaml" || ext == "yml") => {
+            Path::new(&cli.output_file_path)
+                .with_extension("json")
+                .to_string_lossy()
+                .into_owned()
+        }
+        _ => cli.output_file_path,
+    };
     let save_rules_dir = cli.save_rules_dir;
     let page_size = cli.page_size;
     let down_chunk_size = cli.down_chunk_size;
+    let archive_path = cli.archive;
+    let dry_run = cli.dry_run;
+    let encryption = if cli.encrypt_pass {
+        let passphrase =
+            encrypt::passphrase_from_env().map_err(exit::Failure::with(exit::ErrorKind::Other))?;
+        Some(encrypt::Encryption::Passphrase(passphrase))
+    } else if !cli.encrypt_age.is_empty() {
+        Some(encrypt::Encryption::Recipients(cli.encrypt_age.clone()))
+    } else {
+        None
+    };
 
-    // 删除上次运行输出的历史文件
-    filename::delete_old_files_by_pattern(&output_yaml_path).unwrap();
+    // 读取 base.yaml 文件，多个时文件名中加上各自的名称
+    let base_list: Vec<&str> = base_paths(&base_yaml_path).collect();
+    let mut base_configs: Vec<(Option<String>, YamlValue)> = base_list
+        .iter()
+        .map(|path| {
+            let (mut base_config, _) = read::read_base_yaml(path)?;
+            for (key, value) in &cli.overrides {
+                overrides::apply_override(&mut base_config, key, value.clone());
+            }
+            let base_name = (base_list.len() > 1).then(|| {
+                Path::new(path)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+            Ok((base_name, base_config))
+        })
+        .collect::<Result<_, exit::Failure>>()?;
 
-    // 读取 base.yaml 文件
-    let base_config: YamlValue = read::read_yaml(&base_yaml_path);
-    let base_yaml_str = serde_yaml::to_string(&base_config).unwrap();
-    let base_yaml_indent = indent::fix_yaml_indent(&base_yaml_str);
+    // 读取ini配置文件的信息
+    let ini_config = presets::load_ini(cli.preset, &ini_file_path)?;
 
-    // 提取和合并多个proxies的值
-    let merge_proxies = proxy::extract_and_merge_proxies(&node_file_path, "proxies");
+    // 提取和合并多个proxies的值，ini 中手动维护的节点加在后面
+    let (mut merge_proxies, source_counts, subscriptions) = load_proxies(&cli.nodes).await?;
+    let extra_proxies = MyIni::read_extra_proxies(&ini_config)?;
+    if !extra_proxies.is_empty() {
+        println!(
+            "{}",
+            tr!(
+                "ini 中额外的节点：{} 个",
+                "extra proxies from the ini: {}",
+                extra_proxies.len()
+            )
+        );
+        merge_proxies.extend(extra_proxies);
+    }
+    let mut merge_proxies =
+        plugin::run_stage(&cli.plugins, PluginStage::PostMerge, merge_proxies, None)?;
+    let subscription_info =
+        userinfo::SubscriptionInfo::merge(subscriptions.iter().filter_map(|s| s.info.as_ref()));
     if merge_proxies.is_empty() {
-        return;
+        return Err(exit::Failure::new(
+            exit::ErrorKind::NothingToOutput,
+            tr!("没有可输出的节点", "no nodes to output"),
+        ));
+    }
+    let selection = match cli.selection.as_deref() {
+        Some(path) => selection::read_selection(path)?,
+        None => None,
+    };
+    if cli.interactive {
+        let rows = tui::node_rows(&merge_proxies);
+        let checked = merge_proxies
+            .iter()
+            .map(|p| selection.as_ref().is_none_or(|s| s.is_selected(p)))
+            .collect();
+        let Some(checked) = tui::select_nodes(&rows, checked).map_err(|e| {
+            exit::Failure::new(
+                exit::ErrorKind::Other,
+                tr!("终端界面出错：{}", "terminal UI error: {}", e),
+            )
+        })?
+        else {
+            println!("{}", tr!("已取消构建", "build cancelled"));
+            return Ok(());
+        };
+        let chosen = selection::Selection::from_checked(&merge_proxies, &checked);
+        if let Some(path) = &cli.selection {
+            selection::write_selection(path, &chosen).map_err(|e| {
+                exit::Failure::new(
+                    exit::ErrorKind::Other,
+                    tr!(
+                        "保存节点选择失败：{}，{}",
+                        "failed to save the node selection {}: {}",
+                        path,
+                        e
+                    ),
+                )
+            })?;
+            println!(
+                "{}",
+                tr!("节点选择已保存到：{}", "node selection saved to: {}", path)
+            );
+        }
+        merge_proxies = chosen.apply(merge_proxies).0;
+    } else if let Some(selection) = &selection {
+        let dropped;
+        (merge_proxies, dropped) = selection.apply(merge_proxies);
+        println!(
+            "{}",
+            tr!(
+                "按保存的节点选择去掉了 {} 个节点",
+                "removed {} nodes by the saved selection",
+                dropped
+            )
+        );
+    }
+    if merge_proxies.is_empty() {
+        return Err(exit::Failure::new(
+            exit::ErrorKind::NothingToOutput,
+            tr!("没有选择任何节点", "no nodes were selected"),
+        ));
+    }
+    if cli.traffic_node
+        && let Some(info) = &subscription_info
+    {
+        merge_proxies.insert(0, userinfo::info_node(info));
     }
 
-    // 对merge_proxies节点进行分页
-    let paginated_pages = paginate::dedup_and_paginate(
+    let latencies = if cli.sort_nodes == proxy::NodeSort::Latency {
+        let latencies = latency::measure(&merge_proxies).await;
+        let unreachable = merge_proxies
+            .iter()
+            .filter(|p| !latencies.contains_key(&proxy::stable_key(p)))
+            .count();
+        println!(
+            "{}",
+            tr!(
+                "测试节点延迟：{} 个节点，{} 个无法连接（超时 {:?}）",
+                "node latency: {} nodes, {} unreachable (timeout {:?})",
+                merge_proxies.len(),
+                unreachable,
+                latency::CONNECT_TIMEOUT
+            )
+        );
+        latencies
+    } else {
+        HashMap::new()
+    };
+
+    // 对merge_proxies节点进行分页，每页内的节点再排序
+    let mut paginated_pages = paginate_proxies(
         merge_proxies,
         page_size,
-        &["name", "skip-cert-verify"], // 暂时移除的key-value，移除它们再计算hash，判断是否跟其它的节点重复
-        |item: &YamlValue| {
-            item.get("name") // 获取名为"name"的字段，提到外面
-                .and_then(|v| v.as_str()) // 如果字段存在且是字符串，就取出来
-                .map(|s| s.to_string())
-        },
-        |item: &mut YamlValue, new_name| {
-            if let YamlValue::Mapping(map) = item {
-                map.insert(
-                    YamlValue::String("name".to_string()), // 如果发现name字段跟其它节点的name重复，就改为其它name名称
-                    YamlValue::String(new_name),
-                );
-            }
-        },
+        cli.distribute,
+        &cli.nodes.dedup_names,
     );
+    for page in &mut paginated_pages {
+        proxy::sort_page(page, cli.sort_nodes, &latencies);
+    }
 
-    // 读取ini配置文件的信息
-    let ini_config: Ini = Ini::load_from_file(&ini_file_path).unwrap();
-    let (ruleset_names, ruleset, pending_proxy_group) = MyIni::read_ini(ini_config);
+    let (ruleset_names, ruleset, mut pending_proxy_group) = MyIni::read_ini(ini_config)?;
+    if cli.remote_rulesets_only
+        && let Some(local) = ruleset.iter().find(|r| !r.local_rule_path.is_empty())
+    {
+        return Err(exit::Failure::new(
+            exit::ErrorKind::Config,
+            tr!(
+                "请求中的ini不能使用本地规则集：{}",
+                "the requested ini can't use local rulesets: {}",
+                local.local_rule_path
+            ),
+        ));
+    }
+    if let Some(path) = &cli.extra_groups {
+        let extra_groups =
+            extra::read_extra_groups(path).map_err(exit::Failure::with(exit::ErrorKind::Config))?;
+        let (replaced, added) = extra::merge_extra_groups(&mut pending_proxy_group, extra_groups);
+        println!(
+            "{}",
+            tr!(
+                "额外的代理组：替换了 {} 个，新加了 {} 个",
+                "extra groups: {} replaced, {} added",
+                replaced,
+                added
+            )
+        );
+    }
+    // 需要测速的代理组，节点太多会频繁请求测速地址，ini中没有单独设置的使用命令行的限制
+    if let Some(limit) = cli.group_limit {
+        pending_proxy_group
+            .iter_mut()
+            .filter(|g| g.limit.is_none())
+            .filter(|g| constants::HEALTH_CHECK_TYPES.contains(&g.select_type.as_str()))
+            .for_each(|g| g.limit = Some(limit));
+    }
 
-    // 记录当前时间
-    let start_time = Instant::now();
+    let rules_options = rules::RulesOptions {
+        save_rules_dir,
+        chunk: down_chunk_size,
+        chunk_min_size: cli.chunk_min_size,
+        download_limits: download::DownloadLimits {
+            timeout: Some(cli.rule_timeout),
+            max_bytes: Some(cli.rule_max_size),
+        },
+        write_cache: !dry_run, // 试运行不保存下载的规则
+        rule_providers: cli.rule_providers,
+        rule_filter,
+        keep_rule_order: cli.keep_rule_order,
+        check_rules: cli.check_rules,
+        merge_cidr: cli.merge_cidr,
+        compact_domains: cli.compact_domains,
+        dns_rulesets: cli
+            .dns_policies
+            .iter()
+            .map(|p| p.selector.clone())
+            .chain(cli.fake_ip_filter.iter().cloned())
+            .collect(),
+        rule_precedence: cli.rule_precedence,
+        prefer_rulesets: cli.prefer_rulesets.clone(),
+        verify_sha256: cli.verify_sha256,
+        ruleset_banners: cli.ruleset_banners,
+        compile_domains: cli.compile_domains,
+        compile_dir: (!dry_run)
+            .then(|| filename::output_dir(Path::new(&output_yaml_path)).to_path_buf()),
+        extra_rules: cli
+            .extra_rules
+            .as_deref()
+            .map(extra::read_extra_rules)
+            .transpose()
+            .map_err(exit::Failure::with(exit::ErrorKind::Config))?,
+    };
+    let (mut all_rules, mut rules_count, ruleset_reports, rule_issues) =
+        rules::build_rules(ruleset, &rules_options).await;
+    if cli.plugins.iter().any(|p| p.stage == PluginStage::PreWrite) {
+        (all_rules, rules_count) = rules::map_rule_list(&all_rules, |rules| {
+            plugin::run_stage(&cli.plugins, PluginStage::PreWrite, rules, None)
+        })?;
+    }
+
+    // 根据规则集生成dns的内容，然后才转为yaml字符串
+    let bases: Vec<(Option<String>, String)> = base_configs
+        .iter_mut()
+        .map(|(base_name, base_config)| {
+            dns::apply_dns(
+                base_config,
+                &cli.dns_policies,
+                &cli.fake_ip_filter,
+                &ruleset_reports,
+            );
+            MyIni::apply_provider_health_checks(base_config, &pending_proxy_group);
+            (base_name.clone(), indent::to_indented_yaml(&base_config))
+        })
+        .collect();
+    if !dry_run {
+        report::print_ruleset_table(&ruleset_reports, rules_count);
+    }
+    if cli.check_rules {
+        report::print_rule_issues(&rule_issues, &ruleset_reports);
+    }
+    report::print_resolved_conflicts(&rule_issues, &ruleset_reports);
 
-    let (all_rules, rules_count) =
-        rules::build_rules(ruleset, save_rules_dir, down_chunk_size).await;
+    // 有规则集下载失败(并且没有保存的规则文件)被跳过时，构建完成后以“部分成功”退出
+    let skipped: Vec<&rules::RulesetReport> = ruleset_reports
+        .iter()
+        .filter(|r| r.status == rules::FetchStatus::Failed)
+        .collect();
+    for report in &skipped {
+        exit::report_warning(
+            exit::ErrorKind::Partial,
+            &tr!("规则集下载失败，已跳过", "ruleset download failed, skipped"),
+            Some(&redact::source(&report.path)),
+        );
+    }
+    let partial = match skipped.len() {
+        0 => Ok(()),
+        count => Err(exit::Failure::new(
+            exit::ErrorKind::Partial,
+            tr!(
+                "有 {} 个规则集下载失败，已跳过",
+                "{} rulesets failed to download and were skipped",
+                count
+            ),
+        )),
+    };
 
-    // 构建分页的yaml文件
-    for (i, page) in paginated_pages.iter().enumerate() {
-        let proxies = Proxies {
-            proxies: page.items.clone(),
+    // 所有输入的指纹：跟上次构建的相同、并且上次的输出文件都还在时，跳过生成
+    let build_fingerprint = {
+        let mut fp = fingerprint::Fingerprint::new();
+        fp.update(args_debug.as_bytes());
+        match presets::embedded_ini(cli.preset, &ini_file_path) {
+            Some(text) => fp.update(text.as_bytes()),
+            None => fp.update_file(&ini_file_path),
         };
-        let yaml_string = serde_yaml::to_string(&proxies).unwrap();
-        let proxies_indent = indent::fix_yaml_indent(&yaml_string);
-
-        // 修改代理组
-        let proxy_group_string = MyIni::modify_proxy_groups(
-            pending_proxy_group.clone(),
-            page.names.clone(),
-            ruleset_names.clone(),
-        );
-        let proxy_group_indent = indent::fix_yaml_indent(&proxy_group_string);
-
-        let clash_yaml = format!(
-            "{}\n{}\n{}\n{}",
-            base_yaml_indent,
-            proxies_indent.clone(),
-            proxy_group_indent,
-            all_rules
-        );
-        println!("{}", clash_yaml);
-
-        // 构建输出文件名
-        let output_path = filename::rename_output_filename(
-            &output_yaml_path,
-            i,
-            paginated_pages.len(),
-            Some("snap"), // 自定义数字的前缀
-            None,         // 自定义数字的后缀
-        );
-        // 创建并写入 yaml 文件
-        let file = File::create(&output_path).unwrap();
-        let mut writer = BufWriter::new(file);
-
-        writer.write_all(base_yaml_indent.as_bytes()).unwrap();
-        writer.write_all("\n".as_bytes()).unwrap();
-        writer.write_all(proxies_indent.as_bytes()).unwrap();
-        writer.write_all("\n".as_bytes()).unwrap();
-        writer.write_all(proxy_group_indent.as_bytes()).unwrap();
-        writer.write_all("\n".as_bytes()).unwrap();
-        writer.write_all(all_rules.as_bytes()).unwrap();
+        // 额外的代理组替换了ini中的，文件修改后也要重新生成
+        if let Some(path) = &cli.extra_groups {
+            fp.update_file(path);
+        }
+        // 使用合并片段后的内容，base.yaml 引用的片段修改后也会重新生成
+        for (_, base_yaml_indent) in &bases {
+            fp.update(base_yaml_indent.as_bytes());
+        }
+        for page in &paginated_pages {
+            fp.update(serde_yaml::to_string(&*page.items).unwrap().as_bytes());
+        }
+        fp.update(all_rules.as_bytes());
+        fp.to_hex()
+    };
+    // 插件的输出不在指纹中（插件脚本修改了也无法知道），使用插件时总是重新生成
+    if !dry_run
+        && archive_path.is_none()
+        && !cli.force
+        && cli.plugins.is_empty()
+        && fingerprint::read_fingerprint(&output_yaml_path).as_deref() == Some(&build_fingerprint)
+        && manifest::outputs_exist(&output_yaml_path)
+    {
+        println!(
+            "{}",
+            tr!(
+                "输入没有变化，跳过生成（使用 --force 强制重新生成）",
+                "inputs unchanged, build skipped (use --force to rebuild)"
+            )
+        );
+        return partial;
+    }
+
+    let mut dry_run_report = report::DryRunReport {
+        sources: source_counts,
+        unique_nodes: paginated_pages.iter().map(|p| p.items.len()).sum(),
+        pages: paginated_pages.len(),
+        groups_kept: pending_proxy_group
+            .iter()
+            .map(|g| (g.name.clone(), 0))
+            .collect(),
+        rulesets: ruleset_reports,
+        rules_count,
+        ..Default::default()
+    };
+
+    // 上次构建的摘要，需要在覆盖输出文件之前读取
+    let previous_build = if cli.diff && archive_path.is_none() {
+        diff::read_previous(&output_yaml_path)
+    } else {
+        None
+    };
+    let mut current_build = diff::BuildSummary {
+        rules: rules_count,
+        ..Default::default()
+    };
+
+    // 打包模式下，先收集所有分页的内容，最后统一写入压缩包
+    let mut archive_entries: Vec<archive::ArchiveEntry> = Vec::new();
+    // 记录每个输出文件的信息，最后生成清单（输出文件名.index.json）
+    let mut build_manifest = manifest::Manifest::new();
+    build_manifest.subscription_userinfo = subscription_info.as_ref().map(|i| i.to_header());
+    build_manifest.subscriptions = subscriptions
+        .iter()
+        .filter_map(|s| {
+            Some(manifest::ManifestSubscription {
+                source: redact::source(&s.source).into_owned(),
+                info: s.info.clone()?,
+            })
+        })
+        .collect();
+    build_manifest.fetches = subscriptions
+        .iter()
+        .map(|s| manifest::ManifestFetch {
+            source: redact::source(&s.source).into_owned(),
+            bytes: s.transfer.bytes,
+            elapsed_ms: s.transfer.elapsed.as_millis() as u64,
+            speed: s.transfer.speed(),
+        })
+        .collect();
+    // 本次生成的文件，删除历史文件时保留
+    let mut output_paths: Vec<PathBuf> = Vec::new();
+
+    // 文件名模板中的 {date}
+    let build_date = schedule::format_date(schedule::unix_now());
+
+    // --groups-from all：代理组使用所有节点，每一页都要定义所有节点（当前页的节点排在前面）
+    let groups_from_all = cli.groups_from == "all";
+
+    // 去掉目标客户端不支持的节点字段，每页只处理一次（不需要处理时直接共享分页中的节点）
+    let page_proxies: Vec<Arc<[YamlValue]>> = paginated_pages
+        .iter()
+        .map(|page| cli.target.strip_proxies(&page.items))
+        .collect();
+
+    // 构建分页的yaml文件：各页的代理组、节点和规则互不影响，并行生成
+    let page_builds: Vec<PageBuild> = paginated_pages
+        .par_iter()
+        .enumerate()
+        .map(|(i, page)| -> Result<PageBuild, exit::Failure> {
+            let mut page_items: Vec<&YamlValue> = page_proxies[i].iter().collect();
+            let mut group_names: Vec<&str> = page.names.iter().map(String::as_str).collect();
+            if groups_from_all {
+                for (j, other) in paginated_pages.iter().enumerate() {
+                    if j != i {
+                        page_items.extend(page_proxies[j].iter());
+                        group_names.extend(other.names.iter().map(String::as_str));
+                    }
+                }
+            }
+            let proxies = Proxies {
+                proxies: page_items,
+            };
+            let proxies_indent = indent::to_indented_yaml(&proxies);
+
+            // 修改代理组
+            let (proxy_groups, rule_rewrites) = MyIni::resolve_proxy_groups(
+                pending_proxy_group.clone(),
+                &group_names,
+                &ruleset_names,
+                cli.empty_group,
+                cli.prune_fallback,
+            );
+            // 规则指向的代理组被移除了，这一页的规则需要改为指向其它代理组（这里只统计，输出时再改写）
+            let rewrites = rules::count_rule_rewrites(&all_rules, &rule_rewrites)
+                .into_iter()
+                .map(|(group, count)| {
+                    let target = rule_rewrites[&group].clone();
+                    (group, count, target)
+                })
+                .collect();
+            let mut proxy_groups = MyIni::order_proxy_groups(proxy_groups, cli.order_groups)
+                .map_err(exit::Failure::with(exit::ErrorKind::Config))?;
+            proxy_groups
+                .iter_mut()
+                .for_each(|group| cli.target.strip_group(group));
+            let proxy_groups = plugin::run_stage(
+                &cli.plugins,
+                PluginStage::PostGroup,
+                proxy_groups,
+                Some(i + 1),
+            )?;
+            let proxy_group_indent = MyIni::proxy_groups_to_yaml(&proxy_groups);
+
+            // json 格式需要每个 base.yaml 各自生成完整的配置
+            let json_docs = if output_json {
+                let page_rules = rules::rewrite_rule_groups(&all_rules, &rule_rewrites);
+                base_configs
+                    .iter()
+                    .map(|(_, base_config)| {
+                        output::to_json(&output::build_document(
+                            base_config,
+                            &proxies.proxies,
+                            &proxy_groups,
+                            &page_rules,
+                        ))
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            Ok(PageBuild {
+                proxies_indent,
+                proxy_groups,
+                proxy_group_indent,
+                rule_rewrites,
+                rewrites,
+                json_docs,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    // 按页的顺序输出信息、确定文件名和清单，需要写入的文件最后并行写入(文件路径、页、第几个 base.yaml)
+    let mut pending_writes: Vec<(PathBuf, usize, usize)> = Vec::new();
+    for (i, (page, built)) in paginated_pages.iter().zip(&page_builds).enumerate() {
+        for (group, count, target) in &built.rewrites {
+            println!(
+                "{}",
+                tr!(
+                    "第 {} 页：代理组 {} 没有节点已移除，{} 条规则改为指向 {}",
+                    "page {}: group {} has no nodes and was removed, {} rules now point to {}",
+                    i + 1,
+                    group,
+                    count,
+                    target
+                )
+            );
+        }
+        for group in &built.proxy_groups {
+            if let Some(kept) = dry_run_report.groups_kept.get_mut(&group.name) {
+                *kept += 1;
+            }
+            current_build.add_group(&group.name, group.proxies.iter().map(String::as_str));
+        }
+        current_build.add_proxies(page.items.iter());
+        let page_rules = built.rules(&all_rules);
 
+        // 每个 base.yaml 都生成一份配置，规则只下载和解析一次
+        for (b, (base_name, base_yaml_indent)) in bases.iter().enumerate() {
+            // 配置的各部分依次写出，不再拼接成一个大的字符串（规则可能有几十万行）
+            let parts = built.parts(b, base_yaml_indent, &page_rules);
+            if dry_run {
+                dry_run_report.estimated_bytes += output::parts_len(&parts);
+                continue;
+            }
+            // 终端输出（经常保存在日志中）总是隐藏凭据，文件中的不变
+            let shown: Vec<Cow<str>> = parts.iter().map(|p| redact::redact_text(p)).collect();
+            let shown: Vec<&str> = shown.iter().map(|p| p.as_ref()).collect();
+            output::write_parts(&mut std::io::stdout().lock(), &shown).unwrap();
+            println!();
+
+            // 构建输出文件名，多个 base.yaml 时加上它的名称，例如 output_router_snap_1.yaml
+            let prefix = match base_name {
+                Some(base_name) => format!("{}_snap", base_name),
+                None => "snap".to_string(),
+            };
+            let output_path = match &cli.name_template {
+                Some(template) => filename::render_output_filename(
+                    &output_yaml_path,
+                    template,
+                    &filename::NameVars {
+                        index: i + 1,
+                        total: paginated_pages.len(),
+                        date: &build_date,
+                        base: base_name.as_deref(),
+                        region: proxy::main_region(&page.items),
+                    },
+                ),
+                None => filename::rename_output_filename(
+                    &output_yaml_path,
+                    i,
+                    paginated_pages.len(),
+                    Some(&prefix), // 自定义数字的前缀
+                    None,          // 自定义数字的后缀
+                ),
+            };
+            let output_path = match encryption {
+                Some(_) => encrypt::encrypted_path(&output_path),
+                None => output_path,
+            };
+            let name = output_path
+                .file_name()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            build_manifest.push(manifest::ManifestFile {
+                file: name.clone(),
+                base: base_name.clone(),
+                page: i + 1,
+                node_count: page.items.len(),
+                nodes: page.names.to_vec(),
+                rules_count,
+            });
+
+            if archive_path.is_some() {
+                let mut data = Vec::with_capacity(output::parts_len(&parts));
+                output::write_parts(&mut data, &parts).unwrap();
+                if let Some(encryption) = &encryption {
+                    data = encrypt::encrypt(&data, encryption);
+                }
+                archive_entries.push(archive::ArchiveEntry { name, data });
+            } else {
+                pending_writes.push((output_path.clone(), i, b));
+            }
+            output_paths.push(output_path);
+        }
+    }
+
+    // 写入文件（内容没有变化的跳过），改写的规则每个文件写入时才生成
+    let written: Vec<bool> = pending_writes
+        .par_iter()
+        .map(|(output_path, i, b)| {
+            let built = &page_builds[*i];
+            let page_rules = built.rules(&all_rules);
+            let parts = built.parts(*b, &bases[*b].1, &page_rules);
+            let parts = &parts;
+            // 加密的内容每次都不同，不能跟之前的文件比较
+            if let Some(encryption) = &encryption {
+                let mut data = Vec::with_capacity(output::parts_len(parts));
+                output::write_parts(&mut data, parts).unwrap();
+                std::fs::write(output_path, encrypt::encrypt(&data, encryption)).unwrap();
+                return true;
+            }
+            if fingerprint::is_unchanged(output_path, parts) {
+                return false;
+            }
+            // 创建并写入 yaml 文件
+            let file = File::create(output_path).unwrap();
+            let mut writer = BufWriter::new(file);
+            output::write_parts(&mut writer, parts).unwrap();
+            writer.flush().unwrap();
+            true
+        })
+        .collect();
+    let mut written_count = 0;
+    for ((output_path, ..), written) in pending_writes.iter().zip(written) {
+        if written {
+            written_count += 1;
+        } else {
+            println!(
+                "{}",
+                tr!(
+                    "内容没有变化，跳过写入：{}",
+                    "content unchanged, not rewritten: {}",
+                    output_path.display()
+                )
+            );
+        }
+    }
+    if written_count > 0 {
         println!(
-            "构建的配置耗时: {:?}，规则共：{} 条！",
-            start_time.elapsed(),
-            rules_count
+            "{}",
+            tr!(
+                "构建的配置耗时: {:?}，写入 {} 个文件，规则共：{} 条！",
+                "build took {:?}, wrote {} files, {} rules in total!",
+                start_time.elapsed(),
+                written_count,
+                rules_count
+            )
         );
     }
+    drop(pending_writes);
+
+    if let Some(previous_build) = &previous_build {
+        diff::print_diff(previous_build, &current_build);
+    }
+    if dry_run {
+        dry_run_report.print();
+        return partial;
+    }
+    // 删除上次运行输出的历史文件
+    let history =
+        (cli.history_dir.is_some() || cli.keep_history.is_some()).then(|| filename::History {
+            dir: cli.history_dir.as_deref().unwrap_or("history"),
+            keep: cli.keep_history,
+            stamp: schedule::format_datetime(schedule::unix_now()),
+        });
+    filename::delete_old_files(
+        &output_yaml_path,
+        cli.name_template.as_deref(),
+        &output_paths,
+        history.as_ref(),
+    )
+    .unwrap();
+    build_manifest.elapsed_ms = start_time.elapsed().as_millis() as u64;
+    let generated_files = if let Some(archive_path) = archive_path {
+        archive::write_archive(&archive_path, &archive_entries, &build_manifest.to_json()).unwrap();
+        println!(
+            "{}",
+            tr!(
+                "已将 {} 个配置打包到：{}",
+                "packed {} configs into: {}",
+                archive_entries.len(),
+                archive_path
+            )
+        );
+        vec![PathBuf::from(archive_path)]
+    } else {
+        let path = manifest::write_manifest(&output_yaml_path, &build_manifest).unwrap();
+        println!(
+            "{}",
+            tr!("已生成清单文件：{}", "manifest written: {}", path.display())
+        );
+        fingerprint::write_fingerprint(&output_yaml_path, &build_fingerprint).unwrap();
+        output_paths
+    };
+
+    // 构建成功后的上传、命令和 webhook，失败时只输出错误，不影响这次构建的结果
+    if let Some(path) = &cli.upload {
+        upload::upload_files(&upload::read_upload_config(path)?, &generated_files).await;
+    }
+    if let Some(command) = &cli.on_success
+        && let Err(e) = hook::run_on_success(command, &generated_files).await
+    {
+        eprintln!("{}", e);
+    }
+    if let Some(url) = &cli.webhook {
+        match hook::post_webhook(url, &generated_files, &build_manifest.to_json()).await {
+            Ok(()) => println!("{}", tr!("已通知 webhook：{}", "notified webhook: {}", url)),
+            Err(e) => eprintln!(
+                "{}",
+                tr!(
+                    "通知 webhook {} 失败：{}",
+                    "failed to notify webhook {}: {}",
+                    url,
+                    e
+                )
+            ),
+        }
+    }
+    partial
 }
@@ -0,0 +1,60 @@
+use crate::utils::manifest::MANIFEST_NAME;
+use flate2::{Compression, write::GzEncoder};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+/// 压缩包中的一个文件（文件名 + 内容）
+pub struct ArchiveEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// 根据扩展名判断压缩格式：.zip 或者 .tar.gz/.tgz
+fn is_tar_gz(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_lowercase();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// 将所有生成的配置写入同一个压缩包，并附带一份 index.json 清单
+pub fn write_archive<P: AsRef<Path>>(
+    archive_path: P,
+    entries: &[ArchiveEntry],
+    manifest: &[u8],
+) -> io::Result<()> {
+    let path = archive_path.as_ref();
+    let file = File::create(path)?;
+    if is_tar_gz(path) {
+        let encoder = GzEncoder::new(file, Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (name, data) in entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.data.as_slice()))
+            .chain([(MANIFEST_NAME, manifest)])
+        {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data)?;
+        }
+        builder.into_inner()?.finish()?;
+    } else {
+        let mut zip = ZipWriter::new(file);
+        let options = SimpleFileOptions::default();
+        for (name, data) in entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.data.as_slice()))
+            .chain([(MANIFEST_NAME, manifest)])
+        {
+            zip.start_file(name, options)?;
+            zip.write_all(data)?;
+        }
+        zip.finish()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use crate::{
+    build::cidr,
+    utils::{i18n::tr, model::Proxy},
+};
+use std::net::IpAddr;
+
+/// 服务器黑名单中的一项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerPattern {
+    Cidr { is_v6: bool, start: u128, end: u128 }, // 网段（单个IP为 /32、/128）
+    Domain(String),                               // 域名及其子域名，小写
+}
+
+impl ServerPattern {
+    /// server 为 IP 时跟网段比较，为域名时跟域名比较
+    fn matches(&self, server: &str) -> bool {
+        let server = server.trim().trim_matches(['[', ']']);
+        match (self, server.parse::<IpAddr>()) {
+            (ServerPattern::Cidr { is_v6, start, end }, Ok(ip)) => {
+                let (v6, addr) = ip_value(ip);
+                v6 == *is_v6 && (*start..=*end).contains(&addr)
+            }
+            (ServerPattern::Domain(domain), Err(_)) => {
+                let server = server.trim_end_matches('.').to_lowercase();
+                server == *domain
+                    || server
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+            }
+            _ => false,
+        }
+    }
+}
+
+fn ip_value(ip: IpAddr) -> (bool, u128) {
+    match ip {
+        // IPv4 映射的 IPv6 地址当作 IPv4
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => (false, u32::from(v4) as u128),
+            None => (true, u128::from(v6)),
+        },
+        IpAddr::V4(v4) => (false, u32::from(v4) as u128),
+    }
+}
+
+/// 解析 --block-servers 中的一项：CIDR（1.2.3.0/24）、IP 或域名（同时匹配子域名）
+pub fn parse_server_pattern(s: &str) -> Result<ServerPattern, String> {
+    let s = s.trim();
+    if s.contains('/') {
+        let (is_v6, start, end) =
+            cidr::cidr_range(s).ok_or_else(|| tr!("无效的CIDR：{}", "invalid CIDR: {}", s))?;
+        return Ok(ServerPattern::Cidr { is_v6, start, end });
+    }
+    if let Ok(ip) = s.trim_matches(['[', ']']).parse::<IpAddr>() {
+        let (is_v6, addr) = ip_value(ip);
+        return Ok(ServerPattern::Cidr {
+            is_v6,
+            start: addr,
+            end: addr,
+        });
+    }
+    let domain = s.trim_start_matches("*.").trim_matches('.').to_lowercase();
+    if domain.is_empty()
+        || !domain
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '.')
+    {
+        return Err(tr!(
+            "无效的服务器（需要CIDR、IP或域名）：{}",
+            "invalid server (expected a CIDR, IP or domain): {}",
+            s
+        ));
+    }
+    Ok(ServerPattern::Domain(domain))
+}
+
+/// 解析 --block-ports 中的一项：端口（25）或端口范围（6881-6889）
+pub fn parse_port_range(s: &str) -> Result<(u16, u16), String> {
+    let s = s.trim();
+    let port = |p: &str| match p.trim().parse::<u16>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(tr!("无效的端口：{}", "invalid port: {}", s)),
+    };
+    match s.split_once('-') {
+        Some((from, to)) => {
+            let (from, to) = (port(from)?, port(to)?);
+            if from > to {
+                return Err(tr!("无效的端口范围：{}", "invalid port range: {}", s));
+            }
+            Ok((from, to))
+        }
+        None => port(s).map(|p| (p, p)),
+    }
+}
+
+/// 节点的服务器或端口是否在黑名单中
+pub fn is_blocked(proxy: &Proxy, servers: &[ServerPattern], ports: &[(u16, u16)]) -> bool {
+    let server = proxy.server().unwrap_or("");
+    let blocked_server = !server.is_empty() && servers.iter().any(|p| p.matches(server));
+    let blocked_port = proxy
+        .port()
+        .is_some_and(|port| ports.iter().any(|(from, to)| (*from..=*to).contains(&port)));
+    blocked_server || blocked_port
+}
+
+/// 去掉服务器或端口在黑名单中的节点，返回保留的节点和被过滤掉的数量
+pub fn filter_blocked(
+    proxies: Vec<Proxy>,
+    servers: &[ServerPattern],
+    ports: &[(u16, u16)],
+) -> (Vec<Proxy>, usize) {
+    let total = proxies.len();
+    let kept: Vec<Proxy> = proxies
+        .into_iter()
+        .filter(|proxy| !is_blocked(proxy, servers, ports))
+        .collect();
+    let dropped = total - kept.len();
+    (kept, dropped)
+}
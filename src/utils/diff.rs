@@ -0,0 +1,176 @@
+use crate::utils::{i18n::tr, manifest, proxy};
+use indexmap::{IndexMap, IndexSet};
+use serde_yaml::Value as YamlValue;
+use std::{fs, path::Path};
+
+/// 一次构建的摘要：节点、代理组和规则数，用于跟上次构建比较
+#[derive(Debug, Default)]
+pub struct BuildSummary {
+    pub nodes: IndexMap<String, String>, // 节点标识（类型 server:port） => 节点名称
+    pub groups: IndexMap<String, IndexSet<String>>, // 代理组名称 => 所有页中放入的节点和代理组
+    pub rules: usize,
+}
+
+impl BuildSummary {
+    pub fn add_proxies<'a>(&mut self, proxies: impl IntoIterator<Item = &'a YamlValue>) {
+        for proxy in proxies {
+            if let Some(name) = proxy.get("name").and_then(|v| v.as_str()) {
+                self.nodes
+                    .entry(proxy::node_key(proxy))
+                    .or_insert_with(|| name.to_string());
+            }
+        }
+    }
+
+    pub fn add_group<'a>(&mut self, name: &str, members: impl IntoIterator<Item = &'a str>) {
+        self.groups
+            .entry(name.to_string())
+            .or_default()
+            .extend(members.into_iter().map(str::to_string));
+    }
+}
+
+/// 读取上次构建的输出文件（清单中列出的，同一页只读第一个），没有时返回 None
+///
+/// 需要在写入新的文件之前调用
+pub fn read_previous<P: AsRef<Path>>(output_path: P) -> Option<BuildSummary> {
+    let manifest_path = manifest::manifest_path(&output_path);
+    let data = fs::read(&manifest_path).ok()?;
+    let old_manifest: serde_json::Value = serde_json::from_slice(&data).ok()?;
+
+    let mut summary = BuildSummary::default();
+    let mut pages = IndexSet::new();
+    for file in old_manifest["files"].as_array()? {
+        let (Some(name), Some(page)) = (file["file"].as_str(), file["page"].as_u64()) else {
+            continue;
+        };
+        if !pages.insert(page) {
+            continue;
+        }
+        // yaml 和 json 格式的输出都可以直接用 serde_yaml 解析
+        let Some(config) = fs::read(manifest_path.with_file_name(name))
+            .ok()
+            .and_then(|data| serde_yaml::from_slice::<YamlValue>(&data).ok())
+        else {
+            continue;
+        };
+        if let Some(proxies) = config.get("proxies").and_then(|v| v.as_sequence()) {
+            summary.add_proxies(proxies);
+        }
+        for group in config
+            .get("proxy-groups")
+            .and_then(|v| v.as_sequence())
+            .into_iter()
+            .flatten()
+        {
+            let Some(group_name) = group.get("name").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let members = group
+                .get("proxies")
+                .and_then(|v| v.as_sequence())
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v.as_str());
+            summary.add_group(group_name, members);
+        }
+        summary.rules = config
+            .get("rules")
+            .and_then(|v| v.as_sequence())
+            .map_or(0, |rules| rules.len());
+    }
+    Some(summary)
+}
+
+/// 输出跟上次构建相比的变化：新增/删除/改名的节点、变化的代理组、规则数的变化
+pub fn print_diff(old: &BuildSummary, new: &BuildSummary) {
+    println!(
+        "{}",
+        tr!(
+            "========== 跟上次构建相比 ==========",
+            "========== Compared with the last build =========="
+        )
+    );
+
+    let added: Vec<&String> = new
+        .nodes
+        .iter()
+        .filter(|(key, _)| !old.nodes.contains_key(*key))
+        .map(|(_, name)| name)
+        .collect();
+    let removed: Vec<&String> = old
+        .nodes
+        .iter()
+        .filter(|(key, _)| !new.nodes.contains_key(*key))
+        .map(|(_, name)| name)
+        .collect();
+    let renamed: Vec<(&String, &String)> = new
+        .nodes
+        .iter()
+        .filter_map(|(key, name)| {
+            old.nodes
+                .get(key)
+                .filter(|old_name| *old_name != name)
+                .map(|old_name| (old_name, name))
+        })
+        .collect();
+    println!(
+        "{}",
+        tr!(
+            "节点：新增 {} 个，删除 {} 个，改名 {} 个",
+            "nodes: {} added, {} removed, {} renamed",
+            added.len(),
+            removed.len(),
+            renamed.len()
+        )
+    );
+    for name in added {
+        println!("  + {}", name);
+    }
+    for name in removed {
+        println!("  - {}", name);
+    }
+    for (old_name, name) in renamed {
+        println!("  ~ {} => {}", old_name, name);
+    }
+
+    let mut group_changes = Vec::new();
+    for (name, members) in &new.groups {
+        match old.groups.get(name) {
+            None => group_changes.push(format!("  + {}", name)),
+            Some(old_members) if old_members != members => group_changes.push(tr!(
+                "  ~ {}：增加 {} 个，移除 {} 个",
+                "  ~ {}: {} added, {} removed",
+                name,
+                members.difference(old_members).count(),
+                old_members.difference(members).count()
+            )),
+            Some(_) => {}
+        }
+    }
+    for name in old.groups.keys().filter(|n| !new.groups.contains_key(*n)) {
+        group_changes.push(format!("  - {}", name));
+    }
+    println!(
+        "{}",
+        tr!(
+            "代理组：{} 个有变化",
+            "groups: {} changed",
+            group_changes.len()
+        )
+    );
+    for line in group_changes {
+        println!("{}", line);
+    }
+
+    println!(
+        "{}",
+        tr!(
+            "规则：{} => {} 条（{:+}）",
+            "rules: {} => {} ({:+})",
+            old.rules,
+            new.rules,
+            new.rules as i64 - old.rules as i64
+        )
+    );
+}
@@ -0,0 +1,582 @@
+use crate::utils::i18n::tr;
+use base64::{Engine, engine::general_purpose::STANDARD_NO_PAD};
+use bech32::{FromBase32, Variant};
+use chacha20poly1305::{
+    ChaCha20Poly1305, KeyInit,
+    aead::{Aead, Payload},
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// age 加密文件格式（https://age-encryption.org/v1），可以用 age 命令行工具解密
+const VERSION_LINE: &str = "age-encryption.org/v1";
+const X25519_LABEL: &[u8] = b"age-encryption.org/v1/X25519";
+const SCRYPT_LABEL: &[u8] = b"age-encryption.org/v1/scrypt";
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+
+// scrypt 的工作量（N = 2^15，约 32MB 内存），多个输出文件会并行加密，没有使用 age 默认的 2^18
+const SCRYPT_LOG_N: u8 = 15;
+// 解密时允许的最大工作量，跟 age 一致
+const MAX_SCRYPT_LOG_N: u8 = 22;
+
+/// 加密后的文件加上的扩展名，例如 output_snap_1.yaml.age
+pub const AGE_EXTENSION: &str = "age";
+
+/// 使用口令加密、解密时读取口令的环境变量（口令不在命令行中传递，避免留在 shell 历史和进程列表中）
+pub const PASSPHRASE_ENV: &str = "CLASH_TOOL_PASSPHRASE";
+
+/// age 公钥
+pub type Recipient = PublicKey;
+
+/// 加密方式
+#[derive(Debug, Clone)]
+pub enum Encryption {
+    Recipients(Vec<Recipient>), // age 公钥（age1...），任意一个对应的私钥都可以解密
+    Passphrase(String),         // 口令（age 的 scrypt 方式）
+}
+
+/// 解析 --encrypt-age 的公钥，例如 age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p
+pub fn parse_recipient(s: &str) -> Result<Recipient, String> {
+    let (hrp, data, variant) = bech32::decode(s.trim()).map_err(|e| {
+        tr!(
+            "无效的 age 公钥：{}，{}",
+            "invalid age public key {}: {}",
+            s,
+            e
+        )
+    })?;
+    let bytes = Vec::<u8>::from_base32(&data).map_err(|e| e.to_string())?;
+    let key: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| tr!("无效的 age 公钥：{}", "invalid age public key: {}", s))?;
+    if hrp != "age" || variant != Variant::Bech32 {
+        return Err(tr!(
+            "无效的 age 公钥（应以 age1 开头）：{}",
+            "invalid age public key (should start with age1): {}",
+            s
+        ));
+    }
+    Ok(PublicKey::from(key))
+}
+
+/// 解析 age 私钥文件（age-keygen 生成的格式，# 开头的为注释）
+pub fn parse_identities(content: &str) -> Result<Vec<StaticSecret>, String> {
+    let mut identities = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (hrp, data, _) = bech32::decode(line)
+            .map_err(|e| tr!("无效的 age 私钥：{}", "invalid age private key: {}", e))?;
+        if hrp != "age-secret-key-" {
+            return Err(tr!(
+                "无效的 age 私钥（应以 AGE-SECRET-KEY-1 开头）",
+                "invalid age private key (should start with AGE-SECRET-KEY-1)"
+            ));
+        }
+        let bytes = Vec::<u8>::from_base32(&data).map_err(|e| e.to_string())?;
+        let key: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| tr!("无效的 age 私钥", "invalid age private key"))?;
+        identities.push(StaticSecret::from(key));
+    }
+    if identities.is_empty() {
+        return Err(tr!(
+            "私钥文件中没有 age 私钥",
+            "no age private key in the key file"
+        ));
+    }
+    Ok(identities)
+}
+
+/// 加密后的文件路径：加上 .age
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(AGE_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// 从环境变量读取口令
+pub fn passphrase_from_env() -> Result<String, String> {
+    std::env::var(PASSPHRASE_ENV)
+        .ok()
+        .filter(|p| !p.is_empty())
+        .ok_or_else(|| {
+            tr!(
+                "请在环境变量 {} 中设置口令",
+                "please set the passphrase in the {} environment variable",
+                PASSPHRASE_ENV
+            )
+        })
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).expect("获取随机数失败");
+    bytes
+}
+
+fn hkdf(ikm: &[u8], salt: &[u8], info: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, &mut key)
+        .expect("hkdf 长度错误");
+    key
+}
+
+/// 用零 nonce 加密/解密 file key（每个 wrap key 只使用一次）
+fn wrap(key: &[u8; 32], file_key: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(key.into())
+        .encrypt(&[0u8; 12].into(), file_key)
+        .expect("加密失败")
+}
+
+fn unwrap(key: &[u8; 32], body: &[u8]) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(&[0u8; 12].into(), body)
+        .ok()
+        .filter(|file_key| file_key.len() == 16)
+}
+
+fn scrypt_key(passphrase: &str, salt: &[u8], log_n: u8) -> [u8; 32] {
+    let mut full_salt = SCRYPT_LABEL.to_vec();
+    full_salt.extend_from_slice(salt);
+    let params = scrypt::Params::new(log_n, 8, 1, 32).expect("无效的 scrypt 参数");
+    let mut key = [0u8; 32];
+    scrypt::scrypt(passphrase.as_bytes(), &full_salt, &params, &mut key).expect("scrypt 失败");
+    key
+}
+
+/// 一个 stanza：`-> 类型 参数...`，下面是 base64 的内容（每行 64 个字符，最后一行少于 64 个）
+fn format_stanza(args: &[&str], body: &[u8]) -> String {
+    let mut out = format!("-> {}\n", args.join(" "));
+    let encoded = STANDARD_NO_PAD.encode(body);
+    let mut rest = encoded.as_str();
+    loop {
+        let (line, next) = rest.split_at(rest.len().min(64));
+        out.push_str(line);
+        out.push('\n');
+        if line.len() < 64 {
+            break;
+        }
+        rest = next;
+    }
+    out
+}
+
+fn header_mac(file_key: &[u8], header: &str) -> Hmac<Sha256> {
+    let key = hkdf(file_key, &[], b"header");
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key).expect("hmac 密钥长度错误");
+    mac.update(header.as_bytes());
+    mac
+}
+
+fn stream_nonce(counter: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// 加密为 age 格式
+pub fn encrypt(plaintext: &[u8], encryption: &Encryption) -> Vec<u8> {
+    let file_key: [u8; 16] = random_bytes();
+    let mut header = format!("{}\n", VERSION_LINE);
+    match encryption {
+        Encryption::Recipients(recipients) => {
+            for recipient in recipients {
+                let ephemeral = StaticSecret::from(random_bytes::<32>());
+                let share = PublicKey::from(&ephemeral);
+                let shared = ephemeral.diffie_hellman(recipient);
+                let mut salt = share.as_bytes().to_vec();
+                salt.extend_from_slice(recipient.as_bytes());
+                let key = hkdf(shared.as_bytes(), &salt, X25519_LABEL);
+                let share = STANDARD_NO_PAD.encode(share.as_bytes());
+                header.push_str(&format_stanza(&["X25519", &share], &wrap(&key, &file_key)));
+            }
+        }
+        Encryption::Passphrase(passphrase) => {
+            let salt: [u8; 16] = random_bytes();
+            let key = scrypt_key(passphrase, &salt, SCRYPT_LOG_N);
+            let salt = STANDARD_NO_PAD.encode(salt);
+            let log_n = SCRYPT_LOG_N.to_string();
+            header.push_str(&format_stanza(
+                &["scrypt", &salt, &log_n],
+                &wrap(&key, &file_key),
+            ));
+        }
+    }
+    header.push_str("---");
+    let mac = header_mac(&file_key, &header).finalize().into_bytes();
+    header.push_str(&format!(" {}\n", STANDARD_NO_PAD.encode(mac)));
+
+    // 内容按 64KB 分块加密，最后一块的 nonce 带有结束标记
+    let nonce: [u8; 16] = random_bytes();
+    let payload_key = hkdf(&file_key, &nonce, b"payload");
+    let cipher = ChaCha20Poly1305::new((&payload_key).into());
+    let chunk_count = plaintext.len().div_ceil(CHUNK_SIZE).max(1);
+    let mut out = header.into_bytes();
+    out.reserve(nonce.len() + plaintext.len() + chunk_count * TAG_SIZE);
+    out.extend_from_slice(&nonce);
+    for i in 0..chunk_count {
+        let chunk = &plaintext
+            [(i * CHUNK_SIZE).min(plaintext.len())..((i + 1) * CHUNK_SIZE).min(plaintext.len())];
+        let nonce = stream_nonce(i as u64, i + 1 == chunk_count);
+        out.extend(cipher.encrypt(&nonce.into(), chunk).expect("加密失败"));
+    }
+    out
+}
+
+/// 解析后的 stanza：参数和内容
+struct Stanza<'a> {
+    args: Vec<&'a str>,
+    body: Vec<u8>,
+}
+
+/// 解析后的文件头
+struct Header<'a> {
+    stanzas: Vec<Stanza<'a>>,
+    text: &'a str, // MAC 之前的文件头（到 --- 为止），用于校验 MAC
+    mac: Vec<u8>,
+    payload_start: usize, // 加密内容的起始位置
+}
+
+/// stanza 的参数：不能为空，只能是可见的 ASCII 字符
+fn is_valid_arg(arg: &str) -> bool {
+    !arg.is_empty() && arg.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// 解析 age 文件头，格式不符合规范的都返回错误（跟 age 一致）
+fn parse_header(data: &[u8]) -> Result<Header<'_>, String> {
+    let invalid = || tr!("不是 age 加密的文件", "not an age encrypted file");
+    let malformed = || tr!("age 文件头格式错误", "invalid age header");
+    let end = data
+        .windows(4)
+        .position(|w| w == b"\n---")
+        .ok_or_else(invalid)?;
+    let header = std::str::from_utf8(&data[..end + 4]).map_err(|_| invalid())?;
+    let mac_end = data[end + 4..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map(|p| end + 4 + p)
+        .ok_or_else(invalid)?;
+    // "--- " 后面只能是一个空格和 MAC
+    let mac = std::str::from_utf8(&data[end + 4..mac_end])
+        .ok()
+        .and_then(|line| line.strip_prefix(' '))
+        .and_then(|mac| STANDARD_NO_PAD.decode(mac).ok())
+        .filter(|mac| mac.len() == 32)
+        .ok_or_else(malformed)?;
+
+    // 不能用 lines()，\r\n 结尾的行是错误的格式
+    let mut lines = header.split('\n');
+    if lines.next() != Some(VERSION_LINE) {
+        return Err(invalid());
+    }
+    let mut stanzas = Vec::new();
+    let mut current: Option<(Vec<&str>, String)> = None;
+    for line in lines {
+        if let Some((args, mut body)) = current.take() {
+            // 内容的每行 64 个字符，最后一行少于 64 个（可以是空行）
+            if line.len() > 64 || line.starts_with("-> ") || line == "---" {
+                return Err(malformed());
+            }
+            body.push_str(line);
+            if line.len() < 64 {
+                let body = STANDARD_NO_PAD.decode(&body).map_err(|_| malformed())?;
+                stanzas.push(Stanza { args, body });
+            } else {
+                current = Some((args, body));
+            }
+        } else if let Some(args) = line.strip_prefix("-> ") {
+            let args: Vec<&str> = args.split(' ').collect();
+            if !args.iter().all(|arg| is_valid_arg(arg)) {
+                return Err(malformed());
+            }
+            current = Some((args, String::new()));
+        } else if line == "---" {
+            break;
+        } else {
+            return Err(malformed());
+        }
+    }
+    Ok(Header {
+        stanzas,
+        text: header,
+        mac,
+        payload_start: mac_end + 1,
+    })
+}
+
+/// scrypt 的工作量：十进制数字，不能有 +、前导的 0
+fn parse_log_n(s: &str) -> Option<u8> {
+    if s.starts_with('0') || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// 解密 age 格式的文件：X25519 方式使用 identities 中的私钥，scrypt 方式使用 passphrase
+pub fn decrypt(
+    data: &[u8],
+    identities: &[StaticSecret],
+    passphrase: Option<&str>,
+) -> Result<Vec<u8>, String> {
+    let Header {
+        stanzas,
+        text,
+        mac,
+        payload_start,
+    } = parse_header(data)?;
+    let mut file_key = None;
+    for stanza in &stanzas {
+        match stanza.args.as_slice() {
+            ["X25519", share] => {
+                let share: [u8; 32] = STANDARD_NO_PAD
+                    .decode(share)
+                    .ok()
+                    .and_then(|s| s.try_into().ok())
+                    .ok_or_else(|| tr!("age 文件头格式错误", "invalid age header"))?;
+                let share = PublicKey::from(share);
+                for identity in identities {
+                    let recipient = PublicKey::from(identity);
+                    let shared = identity.diffie_hellman(&share);
+                    // 低阶点得到的共享密钥全为 0，age 规范要求拒绝
+                    if !shared.was_contributory() {
+                        return Err(tr!("age 文件头格式错误", "invalid age header"));
+                    }
+                    let mut salt = share.as_bytes().to_vec();
+                    salt.extend_from_slice(recipient.as_bytes());
+                    let key = hkdf(shared.as_bytes(), &salt, X25519_LABEL);
+                    if let Some(key) = unwrap(&key, &stanza.body) {
+                        file_key = Some(key);
+                        break;
+                    }
+                }
+            }
+            ["scrypt", salt, log_n] => {
+                if stanzas.len() != 1 {
+                    return Err(tr!(
+                        "scrypt 方式的文件只能有一个接收者",
+                        "a scrypt file can only have one recipient"
+                    ));
+                }
+                let passphrase = passphrase.ok_or_else(|| {
+                    tr!(
+                        "文件使用口令加密，需要口令",
+                        "the file is encrypted with a passphrase, a passphrase is needed"
+                    )
+                })?;
+                let salt = STANDARD_NO_PAD
+                    .decode(salt)
+                    .ok()
+                    .filter(|salt| salt.len() == 16)
+                    .ok_or_else(|| tr!("age 文件头格式错误", "invalid age header"))?;
+                let log_n = parse_log_n(log_n)
+                    .ok_or_else(|| tr!("age 文件头格式错误", "invalid age header"))?;
+                if log_n > MAX_SCRYPT_LOG_N {
+                    return Err(tr!(
+                        "scrypt 的工作量太大：2^{}",
+                        "scrypt work factor too large: 2^{}",
+                        log_n
+                    ));
+                }
+                file_key = unwrap(&scrypt_key(passphrase, &salt, log_n), &stanza.body);
+                if file_key.is_none() {
+                    return Err(tr!("口令错误", "wrong passphrase"));
+                }
+            }
+            _ => {}
+        }
+        if file_key.is_some() {
+            break;
+        }
+    }
+    let file_key = file_key.ok_or_else(|| {
+        tr!(
+            "没有可以解密这个文件的私钥",
+            "no private key can decrypt this file"
+        )
+    })?;
+    header_mac(&file_key, text)
+        .verify_slice(&mac)
+        .map_err(|_| tr!("age 文件头校验失败", "age header verification failed"))?;
+
+    let payload = &data[payload_start..];
+    if payload.len() < 16 + TAG_SIZE {
+        return Err(tr!("加密内容不完整", "incomplete encrypted content"));
+    }
+    let (nonce, ciphertext) = payload.split_at(16);
+    let payload_key = hkdf(&file_key, nonce, b"payload");
+    let cipher = ChaCha20Poly1305::new((&payload_key).into());
+    let chunks: Vec<&[u8]> = ciphertext.chunks(CHUNK_SIZE + TAG_SIZE).collect();
+    // 只有一块时最后一块才可以是空的
+    if chunks.len() > 1 && chunks.last().is_some_and(|c| c.len() == TAG_SIZE) {
+        return Err(tr!(
+            "加密内容校验失败（文件损坏或被修改）",
+            "encrypted content verification failed (file damaged or modified)"
+        ));
+    }
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        let nonce = stream_nonce(i as u64, i + 1 == chunks.len());
+        let decrypted = cipher
+            .decrypt(&nonce.into(), Payload::from(*chunk))
+            .map_err(|_| {
+                tr!(
+                    "加密内容校验失败（文件损坏或被修改）",
+                    "encrypted content verification failed (file damaged or modified)"
+                )
+            })?;
+        plaintext.extend(decrypted);
+    }
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Digest;
+
+    /// C2SP/CCTV 的 age 测试向量（tests/fixtures/age/testkit），不支持的 ASCII armor 格式跳过
+    #[test]
+    fn testkit_vectors() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/age/testkit");
+        let mut failures = Vec::new();
+        let mut count = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let data = std::fs::read(&path).unwrap();
+            let split = data.windows(2).position(|w| w == b"\n\n").unwrap();
+            let meta = std::str::from_utf8(&data[..split]).unwrap();
+            let file = &data[split + 2..];
+            let mut expect = "";
+            let mut payload = None;
+            let mut identities = Vec::new();
+            let mut passphrase = None;
+            let mut armored = false;
+            for line in meta.lines() {
+                let (key, value) = line.split_once(": ").unwrap();
+                match key {
+                    "expect" => expect = value,
+                    "payload" => payload = Some(value),
+                    "identity" => identities.extend(parse_identities(value).unwrap()),
+                    "passphrase" => passphrase = Some(value),
+                    "armored" => armored = value == "yes",
+                    _ => {}
+                }
+            }
+            if armored {
+                continue;
+            }
+            count += 1;
+            let result = decrypt(file, &identities, passphrase);
+            let ok = match (expect, &result) {
+                ("success", Ok(plaintext)) => {
+                    payload == Some(hex(&Sha256::digest(plaintext)).as_str())
+                }
+                ("success", Err(_)) => false,
+                (_, result) => result.is_err(),
+            };
+            if !ok {
+                failures.push(format!(
+                    "{}: {} => {:?}",
+                    path.file_name().unwrap().to_string_lossy(),
+                    expect,
+                    result.map(|p| p.len())
+                ));
+            }
+        }
+        failures.sort();
+        assert!(
+            failures.is_empty(),
+            "{}/{}:\n{}",
+            failures.len(),
+            count,
+            failures.join("\n")
+        );
+    }
+
+    /// 长度正好在分块边界上的内容也要能解密
+    const SIZES: [usize; 5] = [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1, 2 * CHUNK_SIZE];
+
+    fn plaintext(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// 生成一对密钥，返回 (私钥文件的内容, 公钥)
+    fn generate_key() -> (String, String) {
+        use age::secrecy::ExposeSecret;
+        let identity = age::x25519::Identity::generate();
+        (
+            identity.to_string().expose_secret().to_string(),
+            identity.to_public().to_string(),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let (key1, public1) = generate_key();
+        let (key2, public2) = generate_key();
+        let recipients = Encryption::Recipients(vec![
+            parse_recipient(&public1).unwrap(),
+            parse_recipient(&public2).unwrap(),
+        ]);
+        let passphrase = Encryption::Passphrase("口令 passphrase".to_string());
+        for len in SIZES {
+            let data = plaintext(len);
+            // 任意一个接收者的私钥都可以解密
+            let encrypted = encrypt(&data, &recipients);
+            for key in [&key1, &key2] {
+                let identities = parse_identities(key).unwrap();
+                assert_eq!(decrypt(&encrypted, &identities, None).unwrap(), data);
+            }
+            let encrypted = encrypt(&data, &passphrase);
+            assert_eq!(
+                decrypt(&encrypted, &[], Some("口令 passphrase")).unwrap(),
+                data
+            );
+            assert!(decrypt(&encrypted, &[], Some("wrong")).is_err());
+        }
+        let (other, _) = generate_key();
+        let encrypted = encrypt(b"data", &recipients);
+        assert!(decrypt(&encrypted, &parse_identities(&other).unwrap(), None).is_err());
+    }
+
+    /// 跟 age 的实现互相加密、解密
+    #[test]
+    fn interoperates_with_age() {
+        use age::secrecy::SecretString;
+        let (key, public) = generate_key();
+        let identity: age::x25519::Identity = key.parse().unwrap();
+        let recipient: age::x25519::Recipient = public.parse().unwrap();
+        let identities = parse_identities(&key).unwrap();
+        let encryption = Encryption::Recipients(vec![parse_recipient(&public).unwrap()]);
+        for len in SIZES {
+            let data = plaintext(len);
+            let encrypted = encrypt(&data, &encryption);
+            assert_eq!(age::decrypt(&identity, &encrypted).unwrap(), data);
+            let encrypted = age::encrypt(&recipient, &data).unwrap();
+            assert_eq!(decrypt(&encrypted, &identities, None).unwrap(), data);
+        }
+
+        let passphrase = "口令 passphrase";
+        let data = plaintext(CHUNK_SIZE + 1);
+        let encrypted = encrypt(&data, &Encryption::Passphrase(passphrase.to_string()));
+        let identity = age::scrypt::Identity::new(SecretString::from(passphrase.to_string()));
+        assert_eq!(age::decrypt(&identity, &encrypted).unwrap(), data);
+        let mut recipient = age::scrypt::Recipient::new(SecretString::from(passphrase.to_string()));
+        recipient.set_work_factor(10);
+        let encrypted = age::encrypt(&recipient, &data).unwrap();
+        assert_eq!(decrypt(&encrypted, &[], Some(passphrase)).unwrap(), data);
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
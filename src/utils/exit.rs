@@ -0,0 +1,144 @@
+use crate::utils::i18n::tr;
+use serde::Serialize;
+use std::{
+    any::Any,
+    panic,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+/// 失败的类型，对应不同的退出码，脚本可以按退出码处理，不用解析日志
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    Other,           // 1：其它错误（命令行参数错误、lint 发现错误、程序内部错误等）
+    Config,          // 2：配置错误（ini、base.yaml、输入文件等不存在或者格式错误）
+    Network,         // 3：网络错误（订阅链接下载失败）
+    Partial,         // 4：部分成功（有规则集下载失败并且没有保存的规则文件，已跳过）
+    NothingToOutput, // 5：没有可输出的内容（过滤后没有节点）
+}
+
+impl ErrorKind {
+    pub fn code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Config => 2,
+            ErrorKind::Network => 3,
+            ErrorKind::Partial => 4,
+            ErrorKind::NothingToOutput => 5,
+        }
+    }
+}
+
+/// 带类型的错误，构建的各个阶段返回 `Result<_, Failure>`，由 run_guarded 和 main 按类型输出和决定退出码
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Failure {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// 用于 map_err，例如 `.map_err(Failure::with(ErrorKind::Config))?`
+    pub fn with<E: ToString>(kind: ErrorKind) -> impl Fn(E) -> Self {
+        move |e| Failure::new(kind, e.to_string())
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// 主线程中（构建之前，例如读取配置方案、检查参数时）的失败：输出后按类型的退出码退出
+pub fn exit(failure: Failure) -> ! {
+    report(&failure);
+    std::process::exit(failure.kind.code())
+}
+
+// --json-errors：错误和警告输出为一行一个的 json 对象
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+pub fn json_errors() -> bool {
+    JSON_ERRORS.load(Ordering::Relaxed)
+}
+
+/// panic 的内容（普通的 panic!、unwrap，即程序内部错误）转为 Other 类型的 Failure
+pub fn from_panic(payload: &(dyn Any + Send)) -> Failure {
+    if let Some(message) = payload.downcast_ref::<String>() {
+        Failure::new(ErrorKind::Other, message.as_str())
+    } else if let Some(message) = payload.downcast_ref::<&str>() {
+        Failure::new(ErrorKind::Other, *message)
+    } else {
+        Failure::new(ErrorKind::Other, tr!("未知错误", "unknown error"))
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    level: &'a str,
+    kind: ErrorKind,
+    code: i32,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+}
+
+fn print_json(level: &str, kind: ErrorKind, message: &str, source: Option<&str>) {
+    let report = Report {
+        level,
+        kind,
+        code: kind.code(),
+        message,
+        source,
+    };
+    eprintln!("{}", serde_json::to_string(&report).unwrap());
+}
+
+/// 输出返回的失败：--json-errors 时为 json，否则为文字
+pub fn report(failure: &Failure) {
+    if json_errors() {
+        let level = if failure.kind == ErrorKind::Partial {
+            "warning"
+        } else {
+            "error"
+        };
+        print_json(level, failure.kind, &failure.message, None);
+    } else {
+        eprintln!("{}", failure.message);
+    }
+}
+
+/// --json-errors 时输出一个警告（例如跳过的规则集），source 为相关的文件或链接；不使用时不输出，文字的提示由调用处输出
+pub fn report_warning(kind: ErrorKind, message: &str, source: Option<&str>) {
+    if json_errors() {
+        print_json("warning", kind, message, source);
+    }
+}
+
+/// 启动时调用一次：panic（程序内部错误）在 --json-errors 时输出为 json，主线程中的 panic 以 Other 的退出码退出；
+/// 构建任务中的 panic 由 run_guarded 处理
+pub fn install_hook(json: bool) {
+    JSON_ERRORS.store(json, Ordering::Relaxed);
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let failure = from_panic(info.payload());
+        if json {
+            print_json("error", failure.kind, &failure.message, None);
+        } else {
+            default_hook(info);
+        }
+        if thread::current().name() == Some("main") {
+            std::process::exit(failure.kind.code());
+        }
+    }));
+}
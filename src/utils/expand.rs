@@ -0,0 +1,31 @@
+use crate::build::patterns::RE_ENV_VAR;
+use std::env;
+
+/// 用户主目录：HOME，Windows 上没有时使用 USERPROFILE
+fn home_dir() -> Option<String> {
+    env::var("HOME").or_else(|_| env::var("USERPROFILE")).ok()
+}
+
+/// 展开路径中的环境变量(${HOME}、%APPDATA%，没有定义的变量保持不变)和开头的 ~(用户主目录)
+pub fn expand_path(path: &str) -> String {
+    let expanded = RE_ENV_VAR.replace_all(path, |caps: &regex::Captures| {
+        let name = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+        env::var(name).unwrap_or_else(|_| caps[0].to_string())
+    });
+    match expanded.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => match home_dir() {
+            Some(home) => format!("{}{}", home, rest),
+            None => expanded.into_owned(),
+        },
+        _ => expanded.into_owned(),
+    }
+}
+
+/// 用英文逗号隔开的多个路径(-b、-f)分别展开，订阅链接中也可以使用环境变量，例如 https://example.com/sub?token=${SUB_TOKEN}
+pub fn expand_path_list(paths: &str) -> String {
+    paths
+        .split(',')
+        .map(|p| expand_path(p.trim()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -1,7 +1,8 @@
+use crate::utils::{encrypt, i18n::tr, manifest};
 use glob::glob;
 use std::{
     ffi::OsStr,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 /// 重命名输出文件名
@@ -46,26 +47,285 @@ pub fn rename_output_filename<P: AsRef<Path>>(
     result
 }
 
-/// 删除所有符合命名规则的旧文件（例如 output_*.yaml）
-pub fn delete_old_files_by_pattern<P: AsRef<Path>>(base_path: P) -> std::io::Result<()> {
+/// 文件名模板中可以使用的变量
+const TEMPLATE_VARS: [&str; 7] = ["stem", "ext", "index", "total", "date", "base", "region"];
+
+/// 文件名模板中一个变量的值
+pub struct NameVars<'a> {
+    pub index: usize,          // 页码（从1开始）
+    pub total: usize,          // 总页数
+    pub date: &'a str,         // 构建日期，例如 2025-01-01
+    pub base: Option<&'a str>, // base.yaml 的名称（-b 指定了多个时才有）
+    pub region: &'a str,       // 这一页节点最多的地区
+}
+
+/// 拆分模板为文字和变量，例如 "{stem}-{index:02}.{ext}" => 文字 "-"、"."，变量 stem、index(宽度2)、ext
+enum Segment<'a> {
+    Text(&'a str),
+    Var(&'a str, Option<usize>),
+}
+
+fn parse_segments(template: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
+        let end = rest[start..].find('}').ok_or_else(|| {
+            tr!(
+                "文件名模板缺少 }}：{}",
+                "file name template is missing }}: {}",
+                template
+            )
+        })? + start;
+        let (name, width) = match rest[start + 1..end].split_once(':') {
+            Some((name, width)) => {
+                let width = width.parse::<usize>().map_err(|_| {
+                    tr!(
+                        "无效的宽度：{{{}}}",
+                        "invalid width: {{{}}}",
+                        &rest[start + 1..end]
+                    )
+                })?;
+                (name, Some(width))
+            }
+            None => (&rest[start + 1..end], None),
+        };
+        if !TEMPLATE_VARS.contains(&name) {
+            return Err(tr!(
+                "文件名模板中不认识的变量：{{{}}}，可选：{}",
+                "unknown variable in file name template: {{{}}}, expected one of: {}",
+                name,
+                TEMPLATE_VARS.join("、")
+            ));
+        }
+        segments.push(Segment::Var(name, width));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    Ok(segments)
+}
+
+/// 解析 --name-template 参数，只检查变量是否正确
+pub fn parse_name_template(s: &str) -> Result<String, String> {
+    let segments = parse_segments(s)?;
+    if !segments
+        .iter()
+        .any(|seg| matches!(seg, Segment::Var("index", _)))
+    {
+        return Err(tr!(
+            "文件名模板中必须有 {{index}}，否则每一页的文件名都相同",
+            "the file name template needs {{index}}, otherwise every page gets the same name"
+        ));
+    }
+    if s.contains(['/', '\\']) {
+        return Err(tr!(
+            "文件名模板中不能有路径分隔符",
+            "the file name template can't contain path separators"
+        ));
+    }
+    Ok(s.to_string())
+}
+
+/// 按模板生成输出文件名，文件放在 base_path 所在的目录，例如 {stem}-{region}-{index:02}.{ext}
+pub fn render_output_filename<P: AsRef<Path>>(
+    base_path: P,
+    template: &str,
+    vars: &NameVars,
+) -> PathBuf {
     let base = base_path.as_ref();
-    let file_stem = base.file_stem().and_then(|s| s.to_str()).unwrap_or("file");
-    let extension = base.extension().and_then(|s| s.to_str());
+    let mut name = String::new();
+    for segment in parse_segments(template).expect("无效的文件名模板") {
+        let (var, width) = match segment {
+            Segment::Text(text) => {
+                name.push_str(text);
+                continue;
+            }
+            Segment::Var(var, width) => (var, width.unwrap_or(0)),
+        };
+        let value = match var {
+            "stem" => base
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .unwrap_or("file")
+                .to_string(),
+            "ext" => base
+                .extension()
+                .and_then(OsStr::to_str)
+                .unwrap_or("yaml")
+                .to_string(),
+            "index" => format!("{:0width$}", vars.index, width = width),
+            "total" => format!("{:0width$}", vars.total, width = width),
+            "date" => vars.date.to_string(),
+            "base" => vars.base.unwrap_or_default().to_string(),
+            _ => vars.region.to_string(),
+        };
+        name.push_str(&value);
+    }
+    base.with_file_name(name)
+}
 
-    // 构造 glob 模式
-    let pattern = match extension {
-        Some(ext) => format!("{}_*.{}", file_stem, ext),
+/// 删除上次生成的历史文件时使用的通配符：默认命名的 output_*.yaml
+fn history_pattern(base: &Path) -> String {
+    let file_stem = base.file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+    let file_stem = glob::Pattern::escape(file_stem);
+    match base.extension().and_then(|s| s.to_str()) {
+        Some(ext) => format!("{}_*.{}", file_stem, glob::Pattern::escape(ext)),
         None => format!("{}_*", file_stem),
-    };
+    }
+}
+
+/// 解析 --history-dir 参数：必须是输出目录下的子目录（不能是绝对路径、不能有 ..、不能是输出目录本身），
+/// 因为 --keep-history 会删除其中较早的构建
+pub fn parse_history_dir(s: &str) -> Result<String, String> {
+    let path = Path::new(s);
+    let components: Vec<Component> = path.components().collect();
+    if path.has_root()
+        || components
+            .iter()
+            .any(|c| matches!(c, Component::ParentDir | Component::Prefix(_)))
+    {
+        return Err(tr!(
+            "历史目录必须是输出目录下的相对路径(不能有 ..)：{}",
+            "the history dir must be a relative path inside the output dir (no ..): {}",
+            s
+        ));
+    }
+    if !components.iter().any(|c| matches!(c, Component::Normal(_))) {
+        return Err(tr!(
+            "历史目录不能是输出目录本身：{}",
+            "the history dir can't be the output dir itself: {}",
+            s
+        ));
+    }
+    Ok(s.to_string())
+}
+
+/// 历史文件移动到 dir（相对于输出目录）下以构建时间命名的子目录，而不是删除
+pub struct History<'a> {
+    pub dir: &'a str,
+    pub keep: Option<usize>, // 只保留最近几次构建的子目录
+    pub stamp: String,       // 这次构建的子目录名称，例如 2025-01-01_120000
+}
 
-    for entry in glob(&pattern).expect("无效的通配符模式") {
-        if let Ok(path) = entry {
-            if path.exists() {
-                println!("正在删除历史文件: {:?}", path);
+/// 输出文件所在的目录，-o 只写了文件名时为当前目录
+pub fn output_dir(base: &Path) -> &Path {
+    base.parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// 删除（或者移动到历史目录）上次构建的输出文件：上次的清单(输出文件名.index.json)中列出的文件，没有使用模板时还包括
+/// 所有符合默认命名的旧文件（例如 output_*.yaml）。模板生成的文件名可能跟其它文件相同，只删除清单中的。
+/// 只查找输出文件所在的目录，keep 中的文件（本次生成的）不删除
+pub fn delete_old_files<P: AsRef<Path>>(
+    base_path: P,
+    template: Option<&str>,
+    keep: &[PathBuf],
+    history: Option<&History>,
+) -> std::io::Result<()> {
+    let base = base_path.as_ref();
+    let dir = output_dir(base);
+    let mut old_files = manifest::previous_outputs(base).unwrap_or_default();
+    if template.is_none() {
+        let pattern = Path::new(&glob::Pattern::escape(&dir.to_string_lossy()))
+            .join(history_pattern(base))
+            .to_string_lossy()
+            .into_owned();
+        // 加密的输出文件（--encrypt-age、--encrypt-pass）多了 .age 扩展名，开启或关闭加密后，之前的文件也要清理
+        let encrypted_pattern = format!("{}.{}", pattern, encrypt::AGE_EXTENSION);
+        for path in glob(&pattern)
+            .expect("无效的通配符模式")
+            .chain(glob(&encrypted_pattern).expect("无效的通配符模式"))
+            .flatten()
+        {
+            if !old_files.contains(&path) {
+                old_files.push(path);
+            }
+        }
+    }
+
+    let mut snapshot_dir: Option<PathBuf> = None;
+    for path in old_files {
+        if !path.is_file() || keep.iter().any(|k| k.file_name() == path.file_name()) {
+            continue;
+        }
+        match history {
+            Some(history) => {
+                let target = match &snapshot_dir {
+                    Some(target) => target,
+                    None => {
+                        let target = dir.join(history.dir).join(&history.stamp);
+                        std::fs::create_dir_all(&target)?;
+                        snapshot_dir.insert(target)
+                    }
+                };
+                let target = target.join(path.file_name().unwrap_or_default());
+                println!(
+                    "{}",
+                    tr!(
+                        "正在移动历史文件: {:?} => {:?}",
+                        "moving old file: {:?} => {:?}",
+                        path,
+                        target
+                    )
+                );
+                std::fs::rename(path, target)?;
+            }
+            None => {
+                println!(
+                    "{}",
+                    tr!("正在删除历史文件: {:?}", "deleting old file: {:?}", path)
+                );
                 std::fs::remove_file(path)?;
             }
         }
     }
 
+    if let Some(history) = history
+        && let Some(count) = history.keep
+    {
+        prune_history(&dir.join(history.dir), count)?;
+    }
+    Ok(())
+}
+
+/// 是否为构建时间命名的子目录，例如 2025-01-01_120000（schedule::format_datetime 的格式）
+fn is_history_stamp(name: &str) -> bool {
+    name.len() == 17
+        && name.bytes().enumerate().all(|(i, b)| match i {
+            4 | 7 => b == b'-',
+            10 => b == b'_',
+            _ => b.is_ascii_digit(),
+        })
+}
+
+/// 历史目录中只保留最近 count 次构建（子目录名称按时间排序），只删除本工具创建的以构建时间命名的子目录
+fn prune_history(history_dir: &Path, count: usize) -> std::io::Result<()> {
+    let Ok(entries) = std::fs::read_dir(history_dir) else {
+        return Ok(());
+    };
+    let mut snapshots: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_str().is_some_and(is_history_stamp))
+        .map(|e| e.path())
+        .filter(|p| p.is_dir())
+        .collect();
+    snapshots.sort();
+    let remove = snapshots.len().saturating_sub(count);
+    for path in &snapshots[..remove] {
+        println!(
+            "{}",
+            tr!(
+                "正在删除历史目录: {:?}",
+                "deleting old directory: {:?}",
+                path
+            )
+        );
+        std::fs::remove_dir_all(path)?;
+    }
     Ok(())
 }
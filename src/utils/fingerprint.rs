@@ -0,0 +1,81 @@
+use blake3::Hasher;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// 构建指纹文件名的后缀，文件名为 .输出文件名(不含扩展名).build_fingerprint，跟输出文件放在同一个目录下
+pub const FINGERPRINT_NAME: &str = ".build_fingerprint";
+
+/// 所有输入（命令行参数、ini、base.yaml、节点、规则）的哈希，输入没有变化时可以跳过生成
+#[derive(Default)]
+pub struct Fingerprint {
+    hasher: Hasher,
+}
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Fingerprint::default()
+    }
+
+    /// 加入一项输入，每项都带上长度，避免不同的拆分得到相同的哈希
+    pub fn update(&mut self, data: &[u8]) -> &mut Self {
+        self.hasher.update(&(data.len() as u64).to_le_bytes());
+        self.hasher.update(data);
+        self
+    }
+
+    /// 加入一个文件的内容，读取失败时只记录路径
+    pub fn update_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        let content = fs::read(&path)
+            .unwrap_or_else(|_| path.as_ref().to_string_lossy().into_owned().into_bytes());
+        self.update(&content)
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.hasher.finalize().to_hex().to_string()
+    }
+}
+
+/// 指纹文件的路径，例如 out/output.yaml 为 out/.output.build_fingerprint；
+/// 不同的 -o 输出到同一个目录时各自使用自己的指纹
+pub fn fingerprint_path<P: AsRef<Path>>(output_path: P) -> PathBuf {
+    let output_path = output_path.as_ref();
+    let stem = output_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    output_path.with_file_name(format!(".{}{}", stem, FINGERPRINT_NAME))
+}
+
+/// 上次构建的指纹，没有时返回 None
+pub fn read_fingerprint<P: AsRef<Path>>(output_path: P) -> Option<String> {
+    fs::read_to_string(fingerprint_path(output_path))
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+pub fn write_fingerprint<P: AsRef<Path>>(output_path: P, fingerprint: &str) -> io::Result<()> {
+    fs::write(fingerprint_path(output_path), fingerprint)
+}
+
+/// 文件的内容已经跟各部分（用换行隔开）相同，不需要再写入（避免修改时间变化导致下游同步工具重复同步）
+pub fn is_unchanged<P: AsRef<Path>>(path: P, parts: &[&str]) -> bool {
+    let Ok(old) = fs::read(path) else {
+        return false;
+    };
+    let mut rest = old.as_slice();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            let Some(r) = rest.strip_prefix(b"\n") else {
+                return false;
+            };
+            rest = r;
+        }
+        let Some(r) = rest.strip_prefix(part.as_bytes()) else {
+            return false;
+        };
+        rest = r;
+    }
+    rest.is_empty()
+}
@@ -0,0 +1,48 @@
+use crate::{build::download, utils::i18n::tr};
+use serde_json::json;
+use std::path::PathBuf;
+use tokio::process::Command;
+
+/// 构建成功后执行的命令，生成的文件作为参数依次传给命令，例如 --on-success "rclone copy" => rclone copy a.yaml b.yaml
+pub async fn run_on_success(command: &str, files: &[PathBuf]) -> Result<(), String> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        // "$@" 保证文件名中有空格时也是一个参数
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(format!("{} \"$@\"", command)).arg("sh");
+        cmd
+    };
+    let status = cmd
+        .args(files)
+        .status()
+        .await
+        .map_err(|e| tr!("执行 {} 失败：{}", "failed to run {}: {}", command, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(tr!("{} 执行失败：{}", "{} failed: {}", command, status))
+    }
+}
+
+/// 构建成功后 POST 到 webhook，内容为生成的文件列表和清单（输出文件名.index.json 的内容）
+pub async fn post_webhook(
+    url: &str,
+    files: &[PathBuf],
+    manifest: &[u8],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let payload = json!({
+        "event": "build_success",
+        "files": files.iter().map(|f| f.to_string_lossy()).collect::<Vec<_>>(),
+        "manifest": serde_json::from_slice::<serde_json::Value>(manifest)?,
+    });
+    download::plain_client()
+        .post(url)
+        .json(&payload)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
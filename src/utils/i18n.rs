@@ -0,0 +1,491 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 按当前语言选择中文或英文的格式字符串，用法跟 format! 相同(两个格式字符串使用相同的参数)，
+/// 例如：tr!("读取文件失败：{}", "failed to read file: {}", path)
+#[macro_export]
+macro_rules! tr {
+    ($zh:literal, $en:literal $(, $arg:expr)* $(,)?) => {
+        if $crate::utils::i18n::is_zh() {
+            format!($zh $(, $arg)*)
+        } else {
+            format!($en $(, $arg)*)
+        }
+    };
+}
+pub use tr;
+
+/// 输出信息(帮助、进度、错误)使用的语言
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Zh,
+    En,
+}
+
+pub fn parse_lang(s: &str) -> Result<Lang, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "zh" | "zh-cn" | "cn" => Ok(Lang::Zh),
+        "en" | "en-us" => Ok(Lang::En),
+        _ => Err(tr!(
+            "无效的语言：{}，可选 zh、en",
+            "invalid language: {}, expected zh or en",
+            s
+        )),
+    }
+}
+
+// 默认中文，启动时根据 --lang 或系统语言设置
+static ENGLISH: AtomicBool = AtomicBool::new(false);
+
+pub fn set(lang: Lang) {
+    ENGLISH.store(lang == Lang::En, Ordering::Relaxed);
+}
+
+pub fn is_zh() -> bool {
+    !ENGLISH.load(Ordering::Relaxed)
+}
+
+/// 命令行中的 --lang 优先，否则按系统语言(LC_ALL、LC_MESSAGES、LANG)：中文环境为中文，其它为英文
+pub fn detect(args: &[String]) -> Lang {
+    let from_args = args.iter().enumerate().find_map(|(i, arg)| {
+        let value = match arg.strip_prefix("--lang") {
+            Some("") => args.get(i + 1)?,
+            Some(rest) => rest.strip_prefix('=')?,
+            None => return None,
+        };
+        parse_lang(value).ok()
+    });
+    from_args.unwrap_or_else(|| {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|key| std::env::var(key).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        if locale.to_ascii_lowercase().starts_with("zh") {
+            Lang::Zh
+        } else {
+            Lang::En
+        }
+    })
+}
+
+/// 英文时替换命令行的帮助信息(中文的帮助写在参数定义的文档注释中)
+pub fn localize_command(command: clap::Command) -> clap::Command {
+    if is_zh() {
+        return command;
+    }
+    localize(command, None)
+}
+
+fn localize(command: clap::Command, subcommand: Option<&str>) -> clap::Command {
+    let about = subcommand.map_or("", |name| name);
+    let command = match ABOUT_EN.iter().find(|(key, _)| *key == about) {
+        Some((_, text)) => command.about(*text),
+        None => command,
+    };
+    let command = command.mut_args(|arg| {
+        let id = arg.get_id().as_str();
+        let scoped = subcommand.map(|name| format!("{} {}", name, id));
+        let text = HELP_EN
+            .iter()
+            .find(|(key, _)| Some(*key) == scoped.as_deref())
+            .or_else(|| HELP_EN.iter().find(|(key, _)| *key == id));
+        match text {
+            Some((_, text)) => arg.help(*text),
+            None => arg,
+        }
+    });
+    if subcommand.is_some() {
+        return command;
+    }
+    let names: Vec<String> = command
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .collect();
+    names.iter().fold(command, |command, name| {
+        command.mut_subcommand(name, |sub| localize(sub, Some(name)))
+    })
+}
+
+// 命令(空字符串为顶层命令)和子命令的说明
+const ABOUT_EN: &[(&str, &str)] = &[
+    (
+        "",
+        "Rebuilds the proxy groups and rules of clash subscriptions, and can merge several subscriptions into one set of configs",
+    ),
+    (
+        "build",
+        "Build the configs (also the default without a subcommand)",
+    ),
+    (
+        "serve",
+        "Build the configs, then keep running: --watch rebuilds when files change, --interval re-downloads subscriptions and rules periodically, --listen serves a subconverter-compatible conversion API (at least one is required)",
+    ),
+    (
+        "lint",
+        "Check an existing clash/mihomo config (not only ones built by this tool): duplicate names, references to missing groups/rule-providers, reference cycles, invalid CIDRs, etc.; exits with 1 when there are errors",
+    ),
+    (
+        "convert",
+        "Only merge, deduplicate and filter nodes and print the node list (no groups or rules), the node count goes to stderr for use in pipes; subscription links and share links are all converted to clash proxies",
+    ),
+    (
+        "split",
+        "Split a config by protocol, region or any node field, writing one file per value (only that value's nodes, everything else unchanged)",
+    ),
+    (
+        "bench",
+        "Run the rule pipeline stage by stage (uses -c, -s and the rule options): read (only rules already downloaded to -s, no network), parse, sort/dedup, serialize; prints the time and allocations of each stage",
+    ),
+    (
+        "decrypt",
+        "Decrypt files made by --encrypt-age or --encrypt-pass (age format); the passphrase is read from CLASH_TOOL_PASSPHRASE",
+    ),
+    (
+        "completions",
+        "Print a shell completion script generated from the CLI, e.g. completions bash > /etc/bash_completion.d/clash_subscription_tool",
+    ),
+    (
+        "stats",
+        "Count the nodes of each input: totals, protocols, regions, duplicates and nodes missing required fields, to compare subscriptions",
+    ),
+];
+
+// 参数的说明，键为参数的 id(字段名)，子命令中含义不同的写成 "子命令 id"
+const HELP_EN: &[(&str, &str)] = &[
+    (
+        "profile",
+        "Use a profile (a set of options) from the profiles file; options given on the command line win. See config/profiles.example.toml, e.g. --profile work",
+    ),
+    ("profiles_file", "Profiles file"),
+    (
+        "user_agent",
+        "User-Agent for downloading subscriptions and rules; some subscriptions only return nodes to clash/mihomo UAs",
+    ),
+    (
+        "headers",
+        "Extra request header for downloads (repeatable), e.g. --header \"Authorization: Bearer xxx\"",
+    ),
+    (
+        "redact",
+        "Hide credentials in logs and manifests (<output>.index.json, webhook): subscription links keep only the host, uuid/password/psk of invalid nodes show as ***; configs printed to the terminal are always redacted, generated files are unchanged",
+    ),
+    (
+        "json_errors",
+        "Print errors and warnings as one json object per line (level, kind, code, message, source); exit codes: 0 success, 1 other error, 2 config error, 3 network error, 4 partial success (failed rulesets skipped), 5 nothing to output",
+    ),
+    (
+        "man",
+        "Print the man page (roff, generated from the CLI), e.g. --man > clash_subscription_tool.1",
+    ),
+    (
+        "lang",
+        "Language of help, progress and error messages; defaults to zh in a Chinese locale, otherwise en",
+    ),
+    ("ini_file_path", "ini config file"),
+    (
+        "preset",
+        "Use a built-in set of groups and rulesets (no ini needed): minimal (no rule downloads, LAN and mainland China IPs direct), acl4ssr-mini (ACL4SSR online mini), full (ACL4SSR online full)",
+    ),
+    (
+        "header_file_path",
+        "Header of the clash config (base.yaml); several may be given (comma separated), each produces its own set of configs with its name in the file names",
+    ),
+    (
+        "dump_defaults",
+        "Write the built-in default ini (ACL4SSR online) and base.yaml to the -c/-b paths (existing files are kept) for editing; without it, missing -c/-b files fall back to the built-in defaults",
+    ),
+    (
+        "output_file_path",
+        "Output path of the generated clash config",
+    ),
+    (
+        "save_rules_dir",
+        "Directory where downloaded rules are saved",
+    ),
+    (
+        "page_size",
+        "Pagination: maximum number of nodes per config",
+    ),
+    (
+        "down_chunk_size",
+        "Number of Range chunks per URL (default 1, no chunking), only for files of at least --chunk-min-size; falls back to a single download when the server has no Range support. Rules from one host share one HTTP/2 connection, so chunking is rarely needed",
+    ),
+    (
+        "chunk_min_size",
+        "With -k, only files at least this large are chunked, e.g. 512K, 4M",
+    ),
+    (
+        "archive",
+        "Pack the configs of all pages into one archive (.zip or .tar.gz) instead of writing yaml files",
+    ),
+    (
+        "encrypt_age",
+        "Encrypt the generated configs (they hold node passwords) to an age public key (age1..., repeatable, any matching private key decrypts); files get a .age suffix and can be decrypted with age or the decrypt subcommand; encrypted files can't be used by --diff",
+    ),
+    (
+        "encrypt_pass",
+        "Encrypt the generated configs with a passphrase (age scrypt format) read from CLASH_TOOL_PASSPHRASE",
+    ),
+    (
+        "watch",
+        "Watch mode: rebuild when the proxies files, the ini file or base.yaml change",
+    ),
+    (
+        "interval",
+        "Daemon mode: re-download subscriptions and rules and rebuild periodically, e.g. 6h, 30m, 1h30m",
+    ),
+    (
+        "listen",
+        "Serve a subconverter-compatible conversion API (serve subcommand): GET /sub?target=clash&url=<subscription>&config=<ini url>, other options are taken from serve; a bare port listens on localhost only, e.g. 25500, 0.0.0.0:25500",
+    ),
+    (
+        "dry_run",
+        "Dry run: only parse, deduplicate, resolve groups and count rules, write nothing, and print a report",
+    ),
+    (
+        "rule_providers",
+        "Output network rulesets as rule-providers (behavior and format inferred) referenced with RULE-SET instead of expanding them",
+    ),
+    (
+        "meta",
+        "Meta mode: keep rule types only mihomo (meta core) supports, such as GEOSITE, DOMAIN-REGEX, IP-ASN",
+    ),
+    (
+        "drop_rule_types",
+        "Drop these rule types (comma separated), e.g. USER-AGENT,URL-REGEX",
+    ),
+    (
+        "keep_rule_types",
+        "Keep these rule types (comma separated), e.g. GEOSITE,IP-ASN",
+    ),
+    (
+        "rule_types_file",
+        "Rule type config file (yaml with include and filter lists) replacing the built-in keep/drop lists",
+    ),
+    (
+        "extra_groups",
+        "Your own proxy groups file (yaml with the fields of proxy-groups; filter and exclude-filter regexes pick nodes): groups named like an ini group replace it, the others are appended, e.g. --extra-groups groups.yaml",
+    ),
+    (
+        "extra_rules",
+        "Your own rules file (a yaml list, or {position: top|before-final, rules: [...]}) added without editing the ini: top puts them before all rules (highest priority), before-final (default) puts them right before the final MATCH rule, e.g. --extra-rules my_rules.yaml",
+    ),
+    (
+        "keep_rule_order",
+        "Keep the ruleset order of the ini and the order inside each rule file; only deduplicate, don't sort",
+    ),
+    (
+        "ruleset_banners",
+        "Output rules in blocks per ini ruleset (not sorted together), each preceded by a comment such as # >>> ruleset: Direct (1234 rules) path, to make long rule lists easier to read",
+    ),
+    (
+        "compile_domains",
+        "Compile domain rules (DOMAIN, DOMAIN-SUFFIX; geosite also DOMAIN-KEYWORD, DOMAIN-REGEX) per group into binary domain sets in the output directory: mrs (one ruleset/tag.mrs per group, rules become RULE-SET,tag,group), geosite (geosite.dat, rules become GEOSITE,tag,group)",
+    ),
+    (
+        "check_rules",
+        "Detect rule conflicts (one domain pointing to different groups) and rules shadowed by an earlier DOMAIN-SUFFIX, showing the rulesets they come from",
+    ),
+    (
+        "merge_cidr",
+        "Merge IP rules: adjacent or nested IP-CIDR/IP-CIDR6 ranges of the same group become fewer rules",
+    ),
+    (
+        "compact_domains",
+        "Compact domain rules: drop DOMAIN/DOMAIN-SUFFIX rules covered by a DOMAIN-SUFFIX of the same group and merge duplicate suffixes",
+    ),
+    (
+        "overrides",
+        "Change a value in base.yaml (repeatable), keys are dotted paths, e.g. --override mixed-port=7893 --override dns.enable=true",
+    ),
+    (
+        "dns_policies",
+        "Generate dns nameserver-policy (repeatable): ruleset (ini group name or part of the ruleset path)=DNS servers (comma separated), e.g. --dns-policy \"ChinaDomain=https://doh.pub/dns-query\"; geosite:cn and rule-set:name work too",
+    ),
+    (
+        "fake_ip_filter",
+        "Add the domains of these rulesets (ini group name or part of the ruleset path) to dns fake-ip-filter (repeatable)",
+    ),
+    (
+        "group_limit",
+        "Maximum nodes in url-test/fallback/load-balance groups, optionally picked after sorting by name, e.g. 20 or 20:alpha (set per group in the ini with `limit=20)",
+    ),
+    (
+        "order_groups",
+        "Order groups by references: referenced groups come first (reference cycles are always an error)",
+    ),
+    (
+        "empty_group",
+        "When a group matches no nodes: remove (rules pointing to it go to the fallback group), direct (only DIRECT), all (all nodes)",
+    ),
+    (
+        "prune_fallback",
+        "Where rules of a removed group point to: final (group of the last ruleset), main (first select group), direct (DIRECT)",
+    ),
+    (
+        "groups_from",
+        "Nodes used by groups: page (only this page's nodes), all (all nodes, every page's proxies also holds all nodes)",
+    ),
+    (
+        "output_format",
+        "Output format: yaml, json (the whole config as json; a .yaml/.yml -o extension becomes .json)",
+    ),
+    (
+        "target",
+        "Target client: mihomo, stash (drops node/group fields and rule types Stash doesn't support)",
+    ),
+    (
+        "rule_precedence",
+        "Keep only one of identical rules (ignoring the group) from different rulesets: first (earlier ini ruleset wins), last (later wins); without it all are kept; conflicts (different groups) are reported",
+    ),
+    (
+        "prefer_rulesets",
+        "Ruleset with the highest priority (repeatable, ini group name or part of the ruleset path), e.g. --prefer-ruleset custom_direct.list",
+    ),
+    (
+        "force",
+        "Ignore the build fingerprint and always rebuild (by default unchanged inputs skip the build and unchanged files aren't rewritten)",
+    ),
+    (
+        "verify_sha256",
+        "After downloading a ruleset, verify the sha256 published next to it (url.sha256); on mismatch use the previously saved rule file",
+    ),
+    (
+        "rule_timeout",
+        "Timeout for downloading one ruleset (all chunks included); timed out rulesets are skipped or use the saved rule file, e.g. 30s, 2m",
+    ),
+    (
+        "rule_max_size",
+        "Maximum size of one ruleset; larger ones are skipped or use the saved rule file, e.g. 512K, 64M",
+    ),
+    (
+        "traffic_node",
+        "When a subscription returns subscription-userinfo, add a (non-connectable) node at the top showing remaining traffic and expiry",
+    ),
+    (
+        "distribute",
+        "How nodes are spread over pages: sequential (fill one page after another in input order), round-robin (deal pages in turn), balanced (deal by region, or protocol when unknown, so each page is usable alone), stable (by hash of server:port, so a node tends to stay in the same file between builds)",
+    ),
+    (
+        "sort_nodes",
+        "Node order within a page: none (input order), name (by name, numbers by value), region (by region, then name), latency (TCP connect time from this machine, unreachable last); the traffic node always stays first",
+    ),
+    (
+        "name_template",
+        "Output file name template (files go to the directory of -o), variables: {stem}, {ext} (name and extension of -o), {index} (page), {total} (pages), {date} (build date), {base} (base.yaml name), {region} (most common region of the page); numbers can be zero padded, e.g. --name-template \"{stem}-{region}-{index:02}.{ext}\"; defaults to the output_snap_1.yaml form",
+    ),
+    (
+        "history_dir",
+        "Move old files (built last time but not this time) into a subdirectory named by build time under this directory (a relative path inside the output directory, no ..) instead of deleting them, e.g. history",
+    ),
+    (
+        "keep_history",
+        "Keep the files of the last N builds in the history directory and delete older ones (uses history when --history-dir isn't given)",
+    ),
+    (
+        "on_success",
+        "Command run after a successful build with the generated files (or the archive) as arguments, e.g. --on-success ./upload.sh",
+    ),
+    (
+        "plugins",
+        "Plugin (repeatable, plugins of one stage run in order): stage=command; the command reads json from stdin and prints the changed json to stdout. Stages: post-merge (merged node list), post-group (groups of each page, page number in CLASH_TOOL_PAGE), pre-write (rule list before writing). WASM plugins can run through a WASI runtime, e.g. --plugin \"post-merge=wasmtime run filter.wasm\"",
+    ),
+    (
+        "webhook",
+        "POST the list of generated files and the manifest (<output>.index.json) as json to this URL after a successful build",
+    ),
+    (
+        "upload",
+        "Upload the generated files after a successful build (WebDAV, S3 compatible storage, GitHub Gist), targets come from a yaml file, see config/upload.example.yaml",
+    ),
+    (
+        "diff",
+        "Compare with the output of the last build before writing: added/removed/renamed nodes, changed groups and the change in rule count",
+    ),
+    (
+        "interactive",
+        "Before building, list the merged nodes in the terminal (region, protocol, duplicates) and pick the ones to include; the choice is saved to the --selection file (default node_selection.json) and reused by later builds (including --watch, --interval)",
+    ),
+    (
+        "selection",
+        "Saved node selection (made by --interactive); nodes recorded as unwanted are removed, new nodes are kept by default",
+    ),
+    (
+        "proxies_file_path",
+        "clash configs or subscription links with proxies, several may be given (comma separated, earlier ones win); path:count limits the nodes taken from an input, e.g. main.yaml:100,backup.yaml:20; - reads from stdin, e.g. curl sub-url | clash_subscription_tool -f -",
+    ),
+    (
+        "sanitize",
+        "Normalize nodes: port as integer, lowercase type, fill in udp, drop unknown fields, discard structurally invalid nodes",
+    ),
+    (
+        "allow_types",
+        "Keep only nodes of these protocols (comma separated), e.g. vmess,vless,trojan",
+    ),
+    (
+        "deny_types",
+        "Drop nodes of these protocols (comma separated), e.g. ssr,http",
+    ),
+    (
+        "require_udp",
+        "Keep only nodes that relay UDP (udp: true, or UDP based protocols such as hysteria, hysteria2, tuic, wireguard), for games and QUIC; use --sanitize to fill in a missing udp field by protocol",
+    ),
+    (
+        "require_tfo",
+        "Keep only nodes with TCP Fast Open enabled (tfo: true)",
+    ),
+    (
+        "block_servers",
+        "Drop nodes whose server is blocklisted (comma separated CIDRs, IPs or domains, subdomains included), e.g. --block-servers 127.0.0.0/8,10.0.0.0/8,example.com",
+    ),
+    (
+        "block_ports",
+        "Drop nodes whose port is blocklisted (comma separated, ranges allowed), e.g. --block-ports 25,465,6881-6889",
+    ),
+    (
+        "rename_rules",
+        "Node rename rule (repeatable, applied in order) before grouping, e.g. --rename 's/Hong Kong/HK/' --rename '\\s*\\|\\s*@-'",
+    ),
+    (
+        "script",
+        "Node transform script (rhai) run once per node after renaming: the variable proxy is the node (may be modified or renamed), index is its position; a final value of false drops the node; regex_match and regex_replace are available, e.g. --script transform.rhai",
+    ),
+    (
+        "tag_source",
+        "When merging inputs, prefix node names with their source, e.g. [A] HK 01; labels may be given in -f order (comma separated), otherwise the file name (or host of a link) is used, e.g. --tag-source A,B",
+    ),
+    (
+        "dedup_names",
+        "How duplicate node names are renamed: hash (hash suffix, e.g. HK-01-a1B2c3), counter (number, e.g. HK-01-2), emoji (emoji number, e.g. HK-01 2️⃣)",
+    ),
+    ("lint config", "Config file to check"),
+    ("convert output_format", "Output format: yaml, json"),
+    (
+        "split config",
+        "Config file to split (e.g. a merged subscription)",
+    ),
+    (
+        "by",
+        "What to split by: type (protocol), region (recognized from the name), or a node field (dotted path), e.g. network, ws-opts.path",
+    ),
+    (
+        "split output",
+        "Output path, the value is added to the file name, e.g. split_vmess.yaml",
+    ),
+    ("runs", "Number of runs per stage"),
+    ("json", "Output json (a table by default)"),
+    ("file", "File to decrypt"),
+    (
+        "identities",
+        "age private key file (age-keygen format, repeatable)",
+    ),
+    (
+        "decrypt output",
+        "Output file; prints to the terminal when not given",
+    ),
+    ("shell", "bash, zsh, fish, powershell, elvish"),
+    (
+        "inputs",
+        "clash configs or subscription links to count, comma separated; path:count only counts the first nodes, - reads from stdin",
+    ),
+];
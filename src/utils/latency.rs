@@ -0,0 +1,41 @@
+use crate::utils::proxy;
+use futures::{StreamExt, stream};
+use serde_yaml::Value as YamlValue;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::net::TcpStream;
+
+// 同时测试的节点数
+const CONCURRENCY: usize = 64;
+
+/// 连接超过这个时间当作无法连接
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 测试连接一个 server:port 的耗时（TCP 握手），无法连接或超时返回 None
+async fn tcp_latency(address: &str) -> Option<Duration> {
+    let start = Instant::now();
+    match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+        Ok(Ok(_)) => Some(start.elapsed()),
+        _ => None,
+    }
+}
+
+/// 测试节点的延迟，返回能连接的节点 server:port => 耗时（相同的地址只测试一次）
+///
+/// 测试的是本机到节点服务器的 TCP 连接，不经过代理，只能大致反映节点的远近
+pub async fn measure(proxies: &[YamlValue]) -> HashMap<String, Duration> {
+    let mut addresses: Vec<String> = proxies.iter().map(proxy::stable_key).collect();
+    addresses.sort();
+    addresses.dedup();
+    stream::iter(addresses)
+        .map(|address| async move {
+            let latency = tcp_latency(&address).await;
+            (address, latency)
+        })
+        .buffer_unordered(CONCURRENCY)
+        .filter_map(|(address, latency)| async move { latency.map(|l| (address, l)) })
+        .collect()
+        .await
+}
@@ -0,0 +1,360 @@
+use crate::{build::download::percent_decode, utils::i18n::tr};
+use base64::{Engine, engine::general_purpose::STANDARD_NO_PAD};
+use reqwest::Url;
+use serde_json::Value as JsonValue;
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::collections::HashMap;
+
+/// 支持转换的分享链接协议
+pub const LINK_SCHEMES: &[&str] = &[
+    "ss://",
+    "vmess://",
+    "vless://",
+    "trojan://",
+    "hysteria2://",
+    "hy2://",
+];
+
+/// 是否为支持的分享链接
+pub fn is_share_link(line: &str) -> bool {
+    LINK_SCHEMES.iter().any(|scheme| line.starts_with(scheme))
+}
+
+/// base64 解码，兼容 url-safe 字符、省略的填充和换行
+pub fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let cleaned: String = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c => c,
+        })
+        .collect();
+    STANDARD_NO_PAD.decode(cleaned.trim_end_matches('=')).ok()
+}
+
+fn decode_base64_str(s: &str) -> Option<String> {
+    decode_base64(s).and_then(|data| String::from_utf8(data).ok())
+}
+
+/// 构造 clash 节点的映射，值为空的字段不写入
+#[derive(Default)]
+struct Node(Mapping);
+
+impl Node {
+    fn set(&mut self, key: &str, value: impl Into<YamlValue>) -> &mut Self {
+        self.0.insert(key.into(), value.into());
+        self
+    }
+
+    fn set_str(&mut self, key: &str, value: Option<&str>) -> &mut Self {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            self.set(key, value);
+        }
+        self
+    }
+
+    fn set_map(&mut self, key: &str, value: Node) -> &mut Self {
+        if !value.0.is_empty() {
+            self.set(key, YamlValue::Mapping(value.0));
+        }
+        self
+    }
+}
+
+/// ws、grpc、h2 的传输层参数
+fn transport_opts(node: &mut Node, network: &str, host: Option<&str>, path: Option<&str>) {
+    match network {
+        "ws" | "httpupgrade" => {
+            let mut opts = Node::default();
+            opts.set_str("path", path);
+            if let Some(host) = host.filter(|h| !h.is_empty()) {
+                let mut headers = Node::default();
+                headers.set("Host", host);
+                opts.set_map("headers", headers);
+            }
+            if network == "httpupgrade" {
+                opts.set("v2ray-http-upgrade", true);
+            }
+            node.set("network", "ws").set_map("ws-opts", opts);
+        }
+        "grpc" => {
+            let mut opts = Node::default();
+            opts.set_str("grpc-service-name", path);
+            node.set("network", "grpc").set_map("grpc-opts", opts);
+        }
+        "h2" | "http" => {
+            let mut opts = Node::default();
+            opts.set_str("path", path);
+            if let Some(host) = host.filter(|h| !h.is_empty()) {
+                opts.set("host", vec![YamlValue::from(host)]);
+            }
+            node.set("network", "h2").set_map("h2-opts", opts);
+        }
+        _ => {}
+    }
+}
+
+/// 链接中 `#` 后面的名称，没有时使用 server:port
+fn link_name(url: &Url, server: &str, port: u16) -> String {
+    url.fragment()
+        .map(percent_decode)
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| format!("{}:{}", server, port))
+}
+
+fn server_port(url: &Url) -> Result<(String, u16), String> {
+    let server = url
+        .host_str()
+        .map(|h| h.trim_matches(['[', ']']).to_string())
+        .filter(|h| !h.is_empty())
+        .ok_or_else(|| tr!("缺少服务器地址", "missing server"))?;
+    let port = url.port().ok_or_else(|| tr!("缺少端口", "missing port"))?;
+    Ok((server, port))
+}
+
+fn query_map(url: &Url) -> HashMap<String, String> {
+    url.query_pairs().into_owned().collect()
+}
+
+fn is_true(value: Option<&String>) -> bool {
+    value.is_some_and(|v| v == "1" || v == "true")
+}
+
+/// ss://base64(cipher:password)@server:port/?plugin=...#name，也支持整体 base64 的旧格式
+fn parse_ss(link: &str) -> Result<Mapping, String> {
+    let body = &link["ss://".len()..];
+    let (main, fragment) = body.split_once('#').unwrap_or((body, ""));
+    let link = if main.contains('@') {
+        link.to_string()
+    } else {
+        let decoded =
+            decode_base64_str(main).ok_or_else(|| tr!("无效的 base64", "invalid base64"))?;
+        format!("ss://{}#{}", decoded, fragment)
+    };
+    let url = Url::parse(&link).map_err(|e| e.to_string())?;
+    let (server, port) = server_port(&url)?;
+    let user = percent_decode(url.username());
+    let userinfo = match url.password() {
+        Some(password) => format!("{}:{}", user, percent_decode(password)),
+        None => decode_base64_str(&user)
+            .ok_or_else(|| tr!("无效的加密方式和密码", "invalid cipher and password"))?,
+    };
+    let (cipher, password) = userinfo
+        .split_once(':')
+        .ok_or_else(|| tr!("缺少加密方式或密码", "missing cipher or password"))?;
+
+    let mut node = Node::default();
+    node.set("name", link_name(&url, &server, port))
+        .set("type", "ss")
+        .set("server", server)
+        .set("port", port)
+        .set("cipher", cipher)
+        .set("password", password)
+        .set("udp", true);
+    let query = query_map(&url);
+    if let Some(plugin) = query.get("plugin") {
+        let mut parts = plugin.split(';');
+        let name = parts.next().unwrap_or_default();
+        let opts: HashMap<&str, &str> = parts
+            .map(|p| p.split_once('=').unwrap_or((p, "true")))
+            .collect();
+        let mut plugin_opts = Node::default();
+        match name {
+            "obfs-local" | "simple-obfs" | "obfs" => {
+                plugin_opts
+                    .set_str("mode", opts.get("obfs").copied())
+                    .set_str("host", opts.get("obfs-host").copied());
+                node.set("plugin", "obfs");
+            }
+            "v2ray-plugin" => {
+                plugin_opts
+                    .set("mode", "websocket")
+                    .set_str("host", opts.get("host").copied())
+                    .set_str("path", opts.get("path").copied());
+                if opts.contains_key("tls") {
+                    plugin_opts.set("tls", true);
+                }
+                node.set("plugin", "v2ray-plugin");
+            }
+            other => return Err(tr!("不支持的插件：{}", "unsupported plugin: {}", other)),
+        }
+        node.set_map("plugin-opts", plugin_opts);
+    }
+    Ok(node.0)
+}
+
+/// vmess://base64(json)，json 为 v2rayN 的格式
+fn parse_vmess(link: &str) -> Result<Mapping, String> {
+    let decoded = decode_base64_str(&link["vmess://".len()..])
+        .ok_or_else(|| tr!("无效的 base64", "invalid base64"))?;
+    let json: JsonValue = serde_json::from_str(&decoded).map_err(|e| e.to_string())?;
+    // 有的字段是数字，有的是字符串
+    let field = |key: &str| -> Option<String> {
+        match json.get(key)? {
+            JsonValue::String(s) => Some(s.clone()),
+            JsonValue::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    };
+    let server = field("add")
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| tr!("缺少服务器地址", "missing server"))?;
+    let port: u16 = field("port")
+        .and_then(|p| p.parse().ok())
+        .ok_or_else(|| tr!("缺少端口", "missing port"))?;
+    let uuid = field("id").ok_or_else(|| tr!("缺少 uuid", "missing uuid"))?;
+
+    let mut node = Node::default();
+    node.set(
+        "name",
+        field("ps")
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(|| format!("{}:{}", server, port)),
+    )
+    .set("type", "vmess")
+    .set("server", server)
+    .set("port", port)
+    .set("uuid", uuid)
+    .set(
+        "alterId",
+        field("aid")
+            .and_then(|a| a.parse::<u32>().ok())
+            .unwrap_or(0),
+    )
+    .set(
+        "cipher",
+        field("scy")
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "auto".to_string()),
+    )
+    .set("udp", true);
+    if field("tls").as_deref() == Some("tls") {
+        node.set("tls", true)
+            .set_str("servername", field("sni").as_deref())
+            .set_str("client-fingerprint", field("fp").as_deref());
+    }
+    let network = field("net").unwrap_or_default();
+    transport_opts(
+        &mut node,
+        &network,
+        field("host").as_deref(),
+        field("path").as_deref(),
+    );
+    Ok(node.0)
+}
+
+/// vless://uuid@server:port?security=tls|reality&type=ws&...#name
+fn parse_vless(link: &str) -> Result<Mapping, String> {
+    let url = Url::parse(link).map_err(|e| e.to_string())?;
+    let (server, port) = server_port(&url)?;
+    let query = query_map(&url);
+    let get = |key: &str| query.get(key).map(String::as_str);
+
+    let mut node = Node::default();
+    node.set("name", link_name(&url, &server, port))
+        .set("type", "vless")
+        .set("server", server)
+        .set("port", port)
+        .set("uuid", percent_decode(url.username()))
+        .set("udp", true)
+        .set_str("flow", get("flow"));
+    match get("security") {
+        Some("tls") | Some("reality") => {
+            node.set("tls", true)
+                .set_str("servername", get("sni"))
+                .set_str("client-fingerprint", get("fp"));
+            if get("security") == Some("reality") {
+                let mut reality = Node::default();
+                reality
+                    .set_str("public-key", get("pbk"))
+                    .set_str("short-id", get("sid"));
+                node.set_map("reality-opts", reality);
+            }
+        }
+        _ => {}
+    }
+    let path = get("serviceName").or(get("path"));
+    transport_opts(
+        &mut node,
+        get("type").unwrap_or_default(),
+        get("host"),
+        path,
+    );
+    Ok(node.0)
+}
+
+/// trojan://password@server:port?sni=...&type=ws#name
+fn parse_trojan(link: &str) -> Result<Mapping, String> {
+    let url = Url::parse(link).map_err(|e| e.to_string())?;
+    let (server, port) = server_port(&url)?;
+    let query = query_map(&url);
+    let get = |key: &str| query.get(key).map(String::as_str);
+
+    let mut node = Node::default();
+    node.set("name", link_name(&url, &server, port))
+        .set("type", "trojan")
+        .set("server", server)
+        .set("port", port)
+        .set("password", percent_decode(url.username()))
+        .set("udp", true)
+        .set_str("sni", get("sni").or(get("peer")))
+        .set_str("client-fingerprint", get("fp"));
+    if is_true(query.get("allowInsecure")) {
+        node.set("skip-cert-verify", true);
+    }
+    let path = get("serviceName").or(get("path"));
+    transport_opts(
+        &mut node,
+        get("type").unwrap_or_default(),
+        get("host"),
+        path,
+    );
+    Ok(node.0)
+}
+
+/// hysteria2://password@server:port?sni=...&obfs=salamander&obfs-password=...#name
+fn parse_hysteria2(link: &str) -> Result<Mapping, String> {
+    let url = Url::parse(link).map_err(|e| e.to_string())?;
+    let (server, port) = server_port(&url)?;
+    let query = query_map(&url);
+    let get = |key: &str| query.get(key).map(String::as_str);
+    let password = match url.password() {
+        Some(password) => format!(
+            "{}:{}",
+            percent_decode(url.username()),
+            percent_decode(password)
+        ),
+        None => percent_decode(url.username()),
+    };
+
+    let mut node = Node::default();
+    node.set("name", link_name(&url, &server, port))
+        .set("type", "hysteria2")
+        .set("server", server)
+        .set("port", port)
+        .set("password", password)
+        .set_str("sni", get("sni"))
+        .set_str("obfs", get("obfs"))
+        .set_str("obfs-password", get("obfs-password"));
+    if is_true(query.get("insecure")) {
+        node.set("skip-cert-verify", true);
+    }
+    Ok(node.0)
+}
+
+/// 分享链接转为 clash 节点
+pub fn parse_link(link: &str) -> Result<YamlValue, String> {
+    let link = link.trim();
+    let node = match link.split_once("://").map(|(scheme, _)| scheme) {
+        Some("ss") => parse_ss(link),
+        Some("vmess") => parse_vmess(link),
+        Some("vless") => parse_vless(link),
+        Some("trojan") => parse_trojan(link),
+        Some("hysteria2") | Some("hy2") => parse_hysteria2(link),
+        Some(other) => Err(tr!("不支持的协议：{}", "unsupported protocol: {}", other)),
+        None => Err(tr!("不是分享链接", "not a share link")),
+    }?;
+    Ok(YamlValue::Mapping(node))
+}
@@ -0,0 +1,117 @@
+use crate::utils::userinfo::SubscriptionInfo;
+use serde::Serialize;
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// 清单文件名：压缩包中的清单使用这个名称，输出目录中的为 输出文件名(不含扩展名).index.json
+pub const MANIFEST_NAME: &str = "index.json";
+
+/// 单个输出文件的信息
+#[derive(Serialize, Debug, Clone)]
+pub struct ManifestFile {
+    pub file: String,       // 输出文件名
+    pub page: usize,        // 页码（从1开始）
+    pub node_count: usize,  // 节点数量
+    pub nodes: Vec<String>, // 节点名称
+    pub rules_count: usize, // 规则数量
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base: Option<String>, // 使用的 base.yaml 名称（-b 指定了多个时才有）
+}
+
+/// 订阅链接的流量信息（来自响应头 subscription-userinfo）
+#[derive(Serialize, Debug, Clone)]
+pub struct ManifestSubscription {
+    pub source: String, // 订阅链接
+    #[serde(flatten)]
+    pub info: SubscriptionInfo,
+}
+
+/// 订阅链接的下载耗时
+#[derive(Serialize, Debug, Clone)]
+pub struct ManifestFetch {
+    pub source: String,  // 订阅链接
+    pub bytes: u64,      // 下载的大小
+    pub elapsed_ms: u64, // 下载耗时（毫秒）
+    pub speed: u64,      // 平均速度（字节/秒）
+}
+
+/// 整个构建的清单，方便其它程序直接读取，不用解析yaml
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Manifest {
+    pub generated_at: u64, // 生成时间（unix时间戳，秒）
+    pub total_pages: usize,
+    pub files: Vec<ManifestFile>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subscriptions: Vec<ManifestSubscription>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription_userinfo: Option<String>, // 所有订阅合并后的流量信息（响应头格式）
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fetches: Vec<ManifestFetch>,
+    pub elapsed_ms: u64, // 整个构建的耗时（毫秒，包括下载订阅和规则）
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        let generated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Manifest {
+            generated_at,
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, file: ManifestFile) {
+        // 多个 base.yaml 时，同一页会有多个文件
+        self.total_pages = self.total_pages.max(file.page);
+        self.files.push(file);
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+}
+
+/// 清单文件跟输出文件放在同一个目录下，例如 out/output.yaml 为 out/output.index.json；
+/// 不同的 -o 输出到同一个目录时各自使用自己的清单
+pub fn manifest_path<P: AsRef<Path>>(output_path: P) -> PathBuf {
+    let output_path = output_path.as_ref();
+    let stem = output_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    output_path.with_file_name(format!("{}.{}", stem, MANIFEST_NAME))
+}
+
+/// 写入清单文件
+pub fn write_manifest<P: AsRef<Path>>(output_path: P, manifest: &Manifest) -> io::Result<PathBuf> {
+    let path = manifest_path(output_path);
+    fs::write(&path, manifest.to_json())?;
+    Ok(path)
+}
+
+/// 上次构建的清单中列出的输出文件（跟清单在同一个目录下），没有清单时返回 None
+pub fn previous_outputs<P: AsRef<Path>>(output_path: P) -> Option<Vec<PathBuf>> {
+    let path = manifest_path(&output_path);
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+    let files = manifest["files"].as_array()?;
+    Some(
+        files
+            .iter()
+            .filter_map(|f| f["file"].as_str())
+            // 只接受不带路径的文件名，清单被修改过时也不会删除其它目录中的文件
+            .filter(|name| Path::new(name).file_name() == Some(OsStr::new(name)))
+            .map(|name| path.with_file_name(name))
+            .collect(),
+    )
+}
+
+/// 上次构建的清单中列出的输出文件是否都还在
+pub fn outputs_exist<P: AsRef<Path>>(output_path: P) -> bool {
+    previous_outputs(output_path).is_some_and(|files| files.iter().all(|f| f.exists()))
+}
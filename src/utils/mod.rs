@@ -1,4 +1,39 @@
+pub mod alloc;
+pub mod archive;
+pub mod blocklist;
+pub mod diff;
+pub mod encrypt;
+pub mod exit;
+pub mod expand;
 pub mod filename;
+pub mod fingerprint;
+pub mod hook;
+pub mod i18n;
+pub mod latency;
+pub mod links;
+pub mod manifest;
+pub mod model;
+pub mod output;
+pub mod overrides;
 pub mod paginate;
+pub mod plugin;
+pub mod profile;
+pub mod progress;
 pub mod proxy;
 pub mod read;
+pub mod redact;
+pub mod rename;
+pub mod report;
+pub mod sanitize;
+pub mod schedule;
+pub mod script;
+pub mod selection;
+pub mod server;
+pub mod sniff;
+pub mod split;
+pub mod stats;
+pub mod tui;
+pub mod upload;
+pub mod userinfo;
+pub mod validate;
+pub mod watch;
@@ -0,0 +1,609 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_yaml::{Mapping, Value as YamlValue};
+
+// 基于 QUIC/UDP 的协议，本身就支持 UDP 转发，不需要 udp 字段
+const NATIVE_UDP_TYPES: [&str; 4] = ["hysteria", "hysteria2", "tuic", "wireguard"];
+
+/// 端口：数字或者数字的字符串（有的订阅把端口写成字符串）
+pub fn parse_port(value: &YamlValue) -> Option<u16> {
+    match value {
+        YamlValue::Number(n) => n.as_u64().and_then(|n| u16::try_from(n).ok()),
+        YamlValue::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn get_str(map: &Mapping, key: &str) -> Option<String> {
+    map.get(key).and_then(|v| v.as_str()).map(str::to_string)
+}
+
+fn get_bool(map: &Mapping, key: &str) -> Option<bool> {
+    map.get(key).and_then(|v| v.as_bool())
+}
+
+// 写回映射时只修改值有变化的字段，没有修改的字段保持原来的写法（例如字符串形式的端口）
+fn put_str(map: &mut Mapping, key: &str, value: &str) {
+    if map.get(key).and_then(|v| v.as_str()) != Some(value) {
+        map.insert(key.into(), value.into());
+    }
+}
+
+fn put_opt_str(map: &mut Mapping, key: &str, value: Option<&str>) {
+    match value {
+        Some(value) => put_str(map, key, value),
+        None if map.get(key).is_some_and(|v| v.is_string()) => {
+            map.shift_remove(key);
+        }
+        None => {}
+    }
+}
+
+fn put_opt_bool(map: &mut Mapping, key: &str, value: Option<bool>) {
+    match value {
+        Some(value) if get_bool(map, key) != Some(value) => {
+            map.insert(key.into(), value.into());
+        }
+        None if map.get(key).is_some_and(|v| v.is_bool()) => {
+            map.shift_remove(key);
+        }
+        _ => {}
+    }
+}
+
+/// 所有协议都有的字段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Common {
+    pub name: String,
+    pub server: String,
+    pub port: u16,
+    pub udp: Option<bool>,
+    pub tfo: Option<bool>,
+}
+
+impl Common {
+    fn read(map: &Mapping) -> Option<Common> {
+        Some(Common {
+            name: get_str(map, "name").filter(|n| !n.trim().is_empty())?,
+            server: get_str(map, "server").filter(|s| !s.is_empty())?,
+            port: map.get("port").and_then(parse_port).filter(|p| *p > 0)?,
+            udp: get_bool(map, "udp"),
+            tfo: get_bool(map, "tfo"),
+        })
+    }
+
+    fn write(&self, map: &mut Mapping) {
+        put_str(map, "name", &self.name);
+        put_str(map, "server", &self.server);
+        if map.get("port").and_then(parse_port) != Some(self.port) {
+            map.insert("port".into(), self.port.into());
+        }
+        put_opt_bool(map, "udp", self.udp);
+        put_opt_bool(map, "tfo", self.tfo);
+    }
+}
+
+/// 每种协议特有的字段：从映射读取（缺少必须的字段时返回 None），以及写回映射
+pub trait Fields: Sized {
+    fn read(map: &Mapping) -> Option<Self>;
+    fn write(&self, map: &mut Mapping);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ss {
+    pub cipher: String,
+    pub password: String,
+    pub plugin: Option<String>,
+}
+
+impl Fields for Ss {
+    fn read(map: &Mapping) -> Option<Self> {
+        Some(Ss {
+            cipher: get_str(map, "cipher")?,
+            password: get_str(map, "password")?,
+            plugin: get_str(map, "plugin"),
+        })
+    }
+
+    fn write(&self, map: &mut Mapping) {
+        put_str(map, "cipher", &self.cipher);
+        put_str(map, "password", &self.password);
+        put_opt_str(map, "plugin", self.plugin.as_deref());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ssr {
+    pub cipher: String,
+    pub password: String,
+    pub obfs: String,
+    pub protocol: String,
+}
+
+impl Fields for Ssr {
+    fn read(map: &Mapping) -> Option<Self> {
+        Some(Ssr {
+            cipher: get_str(map, "cipher")?,
+            password: get_str(map, "password")?,
+            obfs: get_str(map, "obfs")?,
+            protocol: get_str(map, "protocol")?,
+        })
+    }
+
+    fn write(&self, map: &mut Mapping) {
+        put_str(map, "cipher", &self.cipher);
+        put_str(map, "password", &self.password);
+        put_str(map, "obfs", &self.obfs);
+        put_str(map, "protocol", &self.protocol);
+    }
+}
+
+/// TLS 和传输层的字段（vmess、vless、trojan 共用）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transport {
+    pub tls: Option<bool>,
+    pub servername: Option<String>, // trojan 为 sni 字段
+    pub network: Option<String>,
+}
+
+impl Transport {
+    fn read(map: &Mapping, sni_key: &str) -> Transport {
+        Transport {
+            tls: get_bool(map, "tls"),
+            servername: get_str(map, sni_key),
+            network: get_str(map, "network"),
+        }
+    }
+
+    fn write(&self, map: &mut Mapping, sni_key: &str) {
+        put_opt_bool(map, "tls", self.tls);
+        put_opt_str(map, sni_key, self.servername.as_deref());
+        put_opt_str(map, "network", self.network.as_deref());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vmess {
+    pub uuid: String,
+    pub cipher: Option<String>,
+    pub transport: Transport,
+}
+
+impl Fields for Vmess {
+    fn read(map: &Mapping) -> Option<Self> {
+        Some(Vmess {
+            uuid: get_str(map, "uuid")?,
+            cipher: get_str(map, "cipher"),
+            transport: Transport::read(map, "servername"),
+        })
+    }
+
+    fn write(&self, map: &mut Mapping) {
+        put_str(map, "uuid", &self.uuid);
+        put_opt_str(map, "cipher", self.cipher.as_deref());
+        self.transport.write(map, "servername");
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vless {
+    pub uuid: String,
+    pub flow: Option<String>,
+    pub transport: Transport,
+}
+
+impl Fields for Vless {
+    fn read(map: &Mapping) -> Option<Self> {
+        Some(Vless {
+            uuid: get_str(map, "uuid")?,
+            flow: get_str(map, "flow"),
+            transport: Transport::read(map, "servername"),
+        })
+    }
+
+    fn write(&self, map: &mut Mapping) {
+        put_str(map, "uuid", &self.uuid);
+        put_opt_str(map, "flow", self.flow.as_deref());
+        self.transport.write(map, "servername");
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trojan {
+    pub password: String,
+    pub transport: Transport,
+}
+
+impl Fields for Trojan {
+    fn read(map: &Mapping) -> Option<Self> {
+        Some(Trojan {
+            password: get_str(map, "password")?,
+            transport: Transport::read(map, "sni"),
+        })
+    }
+
+    fn write(&self, map: &mut Mapping) {
+        put_str(map, "password", &self.password);
+        self.transport.write(map, "sni");
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hysteria2 {
+    pub password: String,
+    pub sni: Option<String>,
+    pub obfs: Option<String>,
+}
+
+impl Fields for Hysteria2 {
+    fn read(map: &Mapping) -> Option<Self> {
+        Some(Hysteria2 {
+            password: get_str(map, "password")?,
+            sni: get_str(map, "sni"),
+            obfs: get_str(map, "obfs"),
+        })
+    }
+
+    fn write(&self, map: &mut Mapping) {
+        put_str(map, "password", &self.password);
+        put_opt_str(map, "sni", self.sni.as_deref());
+        put_opt_str(map, "obfs", self.obfs.as_deref());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tuic {
+    pub uuid: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>, // tuic v4
+}
+
+impl Fields for Tuic {
+    fn read(map: &Mapping) -> Option<Self> {
+        Some(Tuic {
+            uuid: get_str(map, "uuid"),
+            password: get_str(map, "password"),
+            token: get_str(map, "token"),
+        })
+    }
+
+    fn write(&self, map: &mut Mapping) {
+        put_opt_str(map, "uuid", self.uuid.as_deref());
+        put_opt_str(map, "password", self.password.as_deref());
+        put_opt_str(map, "token", self.token.as_deref());
+    }
+}
+
+/// socks5、http 节点
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Auth {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: Option<bool>,
+}
+
+impl Fields for Auth {
+    fn read(map: &Mapping) -> Option<Self> {
+        Some(Auth {
+            username: get_str(map, "username"),
+            password: get_str(map, "password"),
+            tls: get_bool(map, "tls"),
+        })
+    }
+
+    fn write(&self, map: &mut Mapping) {
+        put_opt_str(map, "username", self.username.as_deref());
+        put_opt_str(map, "password", self.password.as_deref());
+        put_opt_bool(map, "tls", self.tls);
+    }
+}
+
+/// 一个已识别协议的节点：通用字段、协议特有的字段，以及原始的映射（保留没有建模的字段和字段的顺序）
+#[derive(Debug, Clone, PartialEq)]
+pub struct Typed<T> {
+    pub common: Common,
+    pub fields: T,
+    raw: Mapping,
+}
+
+impl<T: Fields> Typed<T> {
+    fn read(map: Mapping) -> Result<Self, Mapping> {
+        match (Common::read(&map), T::read(&map)) {
+            (Some(common), Some(fields)) => Ok(Typed {
+                common,
+                fields,
+                raw: map,
+            }),
+            _ => Err(map),
+        }
+    }
+
+    fn to_mapping(&self) -> Mapping {
+        let mut map = self.raw.clone();
+        self.common.write(&mut map);
+        self.fields.write(&mut map);
+        map
+    }
+
+    fn into_mapping(mut self) -> Mapping {
+        self.common.write(&mut self.raw);
+        self.fields.write(&mut self.raw);
+        self.raw
+    }
+}
+
+/// clash 的节点，常见的协议解析为强类型的字段
+///
+/// 不认识的协议、缺少必须字段的节点（以及不是映射的值）为 Other，原样保留，写回时不会丢失任何内容
+#[derive(Debug, Clone, PartialEq)]
+pub enum Proxy {
+    Ss(Typed<Ss>),
+    Ssr(Typed<Ssr>),
+    Vmess(Typed<Vmess>),
+    Vless(Typed<Vless>),
+    Trojan(Typed<Trojan>),
+    Hysteria2(Typed<Hysteria2>),
+    Tuic(Typed<Tuic>),
+    Socks5(Typed<Auth>),
+    Http(Typed<Auth>),
+    Other(YamlValue),
+}
+
+// 对每个强类型的变体执行同一个表达式
+macro_rules! each_typed {
+    ($proxy:expr, $node:ident => $body:expr, $other:ident => $fallback:expr) => {
+        match $proxy {
+            Proxy::Ss($node) => $body,
+            Proxy::Ssr($node) => $body,
+            Proxy::Vmess($node) => $body,
+            Proxy::Vless($node) => $body,
+            Proxy::Trojan($node) => $body,
+            Proxy::Hysteria2($node) => $body,
+            Proxy::Tuic($node) => $body,
+            Proxy::Socks5($node) => $body,
+            Proxy::Http($node) => $body,
+            Proxy::Other($other) => $fallback,
+        }
+    };
+}
+
+impl Proxy {
+    pub fn from_value(value: YamlValue) -> Proxy {
+        let YamlValue::Mapping(map) = value else {
+            return Proxy::Other(value);
+        };
+        let proxy_type = get_str(&map, "type").unwrap_or_default().to_lowercase();
+        let typed = match proxy_type.as_str() {
+            "ss" => Typed::read(map).map(Proxy::Ss),
+            "ssr" => Typed::read(map).map(Proxy::Ssr),
+            "vmess" => Typed::read(map).map(Proxy::Vmess),
+            "vless" => Typed::read(map).map(Proxy::Vless),
+            "trojan" => Typed::read(map).map(Proxy::Trojan),
+            "hysteria2" => Typed::read(map).map(Proxy::Hysteria2),
+            "tuic" => Typed::read(map).map(Proxy::Tuic),
+            "socks5" => Typed::read(map).map(Proxy::Socks5),
+            "http" => Typed::read(map).map(Proxy::Http),
+            _ => Err(map),
+        };
+        typed.unwrap_or_else(|map| Proxy::Other(YamlValue::Mapping(map)))
+    }
+
+    pub fn to_value(&self) -> YamlValue {
+        each_typed!(self, node => YamlValue::Mapping(node.to_mapping()), value => value.clone())
+    }
+
+    pub fn into_value(self) -> YamlValue {
+        each_typed!(self, node => YamlValue::Mapping(node.into_mapping()), value => value)
+    }
+
+    fn common(&self) -> Option<&Common> {
+        each_typed!(self, node => Some(&node.common), _value => None)
+    }
+
+    /// 原始的 type 字段（保留大小写）
+    pub fn proxy_type(&self) -> &str {
+        let value = each_typed!(self, node => node.raw.get("type"), value => value.get("type"));
+        value.and_then(|v| v.as_str()).unwrap_or("")
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Proxy::Other(value) => value.get("name").and_then(|v| v.as_str()),
+            _ => self.common().map(|c| c.name.as_str()),
+        }
+    }
+
+    /// 修改名称，没有名称（不是有效节点）的不修改
+    pub fn set_name(&mut self, name: String) {
+        each_typed!(self, node => node.common.name = name, value => {
+            if value.get("name").is_some_and(|v| v.is_string()) {
+                value["name"] = YamlValue::String(name);
+            }
+        })
+    }
+
+    pub fn server(&self) -> Option<&str> {
+        match self {
+            Proxy::Other(value) => value.get("server").and_then(|v| v.as_str()),
+            _ => self.common().map(|c| c.server.as_str()),
+        }
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Proxy::Other(value) => value.get("port").and_then(parse_port),
+            _ => self.common().map(|c| c.port),
+        }
+    }
+
+    /// 是否开启了 UDP 转发：udp 为 true，或者是基于 UDP 的协议（udp 为 false 时也不算）
+    pub fn supports_udp(&self) -> bool {
+        let udp = match self {
+            Proxy::Other(value) => value.get("udp").and_then(|v| v.as_bool()),
+            _ => self.common().and_then(|c| c.udp),
+        };
+        udp.unwrap_or_else(|| {
+            NATIVE_UDP_TYPES
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(self.proxy_type()))
+        })
+    }
+
+    /// 是否开启了 TCP Fast Open（tfo 为 true）
+    pub fn supports_tfo(&self) -> bool {
+        let tfo = match self {
+            Proxy::Other(value) => value.get("tfo").and_then(|v| v.as_bool()),
+            _ => self.common().and_then(|c| c.tfo),
+        };
+        tfo == Some(true)
+    }
+}
+
+impl From<YamlValue> for Proxy {
+    fn from(value: YamlValue) -> Self {
+        Proxy::from_value(value)
+    }
+}
+
+impl From<Proxy> for YamlValue {
+    fn from(proxy: Proxy) -> Self {
+        proxy.into_value()
+    }
+}
+
+impl Serialize for Proxy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_value().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Proxy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        YamlValue::deserialize(deserializer).map(Proxy::from_value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每种协议一个节点，都带有没有建模的字段、嵌套的映射和列表，以及写成字符串的端口；
+    // 使用 serde_yaml 输出的格式，写回的文本可以直接比较
+    const NODES: &str = r#"- name: 香港 01
+  type: ss
+  server: hk.example.com
+  port: 8388
+  cipher: aes-128-gcm
+  password: pass
+  plugin: obfs
+  plugin-opts:
+    mode: tls
+    host: bing.com
+  udp: true
+  x-unknown: keep
+- name: 日本 01
+  type: vmess
+  server: jp.example.com
+  port: '443'
+  uuid: 6f1c2b7e-2f6a-4a3b-9a1e-0c6c2a1f2b3d
+  alterId: 0
+  cipher: auto
+  tls: true
+  network: ws
+  ws-opts:
+    path: /ws
+    headers:
+      Host: cdn.example.com
+    max-early-data: 2048
+  servername: cdn.example.com
+- name: 美国 01
+  type: vless
+  server: us.example.com
+  port: 443
+  uuid: 6f1c2b7e-2f6a-4a3b-9a1e-0c6c2a1f2b3d
+  flow: xtls-rprx-vision
+  network: tcp
+  reality-opts:
+    public-key: abc
+    short-id: '01'
+  client-fingerprint: chrome
+  tfo: false
+- name: 新加坡 01
+  type: trojan
+  server: sg.example.com
+  port: 443
+  password: pass
+  sni: sg.example.com
+  alpn:
+  - h2
+  - http/1.1
+  skip-cert-verify: false
+- name: 台湾 01
+  type: hysteria2
+  server: tw.example.com
+  port: 8443
+  password: pass
+  obfs: salamander
+  obfs-password: secret
+  ports: 20000-30000
+- name: 韩国 01
+  type: tuic
+  server: kr.example.com
+  port: 443
+  uuid: 6f1c2b7e-2f6a-4a3b-9a1e-0c6c2a1f2b3d
+  password: pass
+  alpn:
+  - h3
+  congestion-controller: bbr
+- name: 公司
+  type: socks5
+  server: 192.168.1.2
+  port: 1080
+  username: user
+  password: pass
+  dialer-proxy: 香港 01
+- name: 未知协议
+  type: wireguard
+  server: wg.example.com
+  port: 51820
+  peers:
+  - public-key: abc
+    allowed-ips:
+    - 0.0.0.0/0
+"#;
+
+    #[test]
+    fn serde_round_trip_keeps_every_key() {
+        let proxies: Vec<Proxy> = serde_yaml::from_str(NODES).unwrap();
+        let typed = proxies
+            .iter()
+            .filter(|p| !matches!(p, Proxy::Other(_)))
+            .count();
+        assert_eq!(typed, 7, "{:#?}", proxies);
+
+        // 嵌套的映射、没有建模的字段和字段的顺序都不变，写回的文本跟输入相同
+        let original: YamlValue = serde_yaml::from_str(NODES).unwrap();
+        assert_eq!(serde_yaml::to_value(&proxies).unwrap(), original);
+        assert_eq!(serde_yaml::to_string(&proxies).unwrap(), NODES);
+
+        // json 也一样
+        let json = serde_json::to_string(&proxies).unwrap();
+        let from_json: Vec<Proxy> = serde_json::from_str(&json).unwrap();
+        assert_eq!(from_json, proxies);
+        assert_eq!(
+            serde_json::to_value(&from_json).unwrap(),
+            serde_json::to_value(&original).unwrap()
+        );
+    }
+
+    #[test]
+    fn changing_a_field_keeps_the_others() {
+        let mut proxies: Vec<Proxy> = serde_yaml::from_str(NODES).unwrap();
+        proxies[1].set_name("日本 02".to_string());
+        let mut expected: YamlValue = serde_yaml::from_str(NODES).unwrap();
+        expected[1]["name"] = "日本 02".into();
+        assert_eq!(serde_yaml::to_value(&proxies).unwrap(), expected);
+        // 字符串的端口没有改变时不会改写为数字
+        assert_eq!(proxies[1].port(), Some(443));
+        assert_eq!(proxies[1].to_value()["port"], YamlValue::from("443"));
+    }
+}
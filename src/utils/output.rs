@@ -0,0 +1,141 @@
+use crate::{
+    build::{constants, ini::SelectGroup},
+    utils::i18n::tr,
+};
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+
+// Stash 不认识的节点字段（mihomo 才有的），写入时去掉
+const STASH_PROXY_KEYS: [&str; 9] = [
+    "dialer-proxy",
+    "smux",
+    "ip-version",
+    "interface-name",
+    "routing-mark",
+    "mptcp",
+    "ech-opts",
+    "xhttp-opts",
+    "udp-over-tcp-version",
+];
+
+/// 输出的目标客户端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Mihomo,
+    Stash, // 会拒绝 mihomo 才有的字段和规则类型
+}
+
+/// 解析 --target 参数
+pub fn parse_target(s: &str) -> Result<Target, String> {
+    match s.trim().to_lowercase().as_str() {
+        "mihomo" | "clash" => Ok(Target::Mihomo),
+        "stash" => Ok(Target::Stash),
+        _ => Err(tr!(
+            "未知的目标客户端：{}，可选 mihomo、stash",
+            "unknown target client: {}, expected mihomo or stash",
+            s
+        )),
+    }
+}
+
+impl Target {
+    /// 该客户端不支持、需要过滤掉的规则类型
+    pub fn drop_rule_types(&self) -> &'static [&'static str] {
+        match self {
+            Target::Mihomo => &[],
+            Target::Stash => &constants::STASH_DROP_KEY,
+        }
+    }
+
+    /// 去掉节点中该客户端不支持的字段
+    fn strip_proxy(&self, proxy: &mut YamlValue) {
+        if let (Target::Stash, YamlValue::Mapping(map)) = (self, proxy) {
+            for key in STASH_PROXY_KEYS {
+                map.remove(key);
+            }
+        }
+    }
+
+    /// 去掉一页节点中该客户端不支持的字段，没有需要去掉的字段时直接共享原来的节点
+    pub fn strip_proxies(&self, proxies: &Arc<[YamlValue]>) -> Arc<[YamlValue]> {
+        if *self != Target::Stash {
+            return Arc::clone(proxies);
+        }
+        proxies
+            .iter()
+            .map(|proxy| {
+                let mut proxy = proxy.clone();
+                self.strip_proxy(&mut proxy);
+                proxy
+            })
+            .collect()
+    }
+
+    /// 去掉代理组中该客户端不支持的字段
+    pub fn strip_group(&self, group: &mut SelectGroup) {
+        if *self == Target::Stash {
+            group.lazy = None;
+            group.disable_udp = None;
+            group.hidden = None;
+        }
+    }
+}
+
+/// 将 base.yaml、节点、代理组和规则合并为一个完整的配置（保持字段的顺序）
+///
+/// rules_yaml 为 build_rules 生成的 rules（以及 rule-providers）的yaml字符串
+pub fn build_document(
+    base: &YamlValue,
+    proxies: &[&YamlValue],
+    proxy_groups: &[SelectGroup],
+    rules_yaml: &str,
+) -> YamlValue {
+    let mut doc = match base {
+        YamlValue::Mapping(map) => map.clone(),
+        _ => Mapping::new(),
+    };
+    doc.insert(
+        YamlValue::String("proxies".to_string()),
+        YamlValue::Sequence(proxies.iter().map(|&p| p.clone()).collect()),
+    );
+    doc.insert(
+        YamlValue::String("proxy-groups".to_string()),
+        serde_yaml::to_value(proxy_groups).unwrap(),
+    );
+    let rules: Mapping = serde_yaml::from_str(rules_yaml).expect("Invalid rules YAML");
+    doc.extend(rules);
+    YamlValue::Mapping(doc)
+}
+
+/// 序列化为json，键的顺序与yaml一致
+pub fn to_json(doc: &YamlValue) -> String {
+    serde_json::to_string_pretty(doc).unwrap_or_else(|e| {
+        panic!(
+            "{}",
+            tr!(
+                "配置中含有无法转为json的值（例如非字符串的键）：{}",
+                "the config has values that can't be converted to json (e.g. non-string keys): {}",
+                e
+            )
+        )
+    })
+}
+
+/// 依次写入配置的各部分，各部分之间用换行隔开
+pub fn write_parts<W: Write>(writer: &mut W, parts: &[&str]) -> io::Result<()> {
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b"\n")?;
+        }
+        writer.write_all(part.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// 各部分写入后的总字节数
+pub fn parts_len(parts: &[&str]) -> usize {
+    parts.iter().map(|p| p.len()).sum::<usize>() + parts.len().saturating_sub(1)
+}
@@ -0,0 +1,45 @@
+use crate::utils::i18n::tr;
+use serde_yaml::{Mapping, Value as YamlValue};
+
+/// 解析 --override 参数，例如 "mixed-port=7893"、"dns.enable=true"，值按yaml解析（数字、布尔值、列表等）
+pub fn parse_override(s: &str) -> Result<(String, YamlValue), String> {
+    let (key, value) = s.split_once('=').ok_or_else(|| {
+        tr!(
+            "格式错误，应为 key=value：{}",
+            "invalid format, expected key=value: {}",
+            s
+        )
+    })?;
+    let key = key.trim();
+    if key.is_empty() || key.split('.').any(str::is_empty) {
+        return Err(tr!("无效的键：{}", "invalid key: {}", key));
+    }
+    let value: YamlValue = serde_yaml::from_str(value.trim())
+        .map_err(|e| tr!("无效的值 {}：{}", "invalid value {}: {}", value, e))?;
+    Ok((key.to_string(), value))
+}
+
+/// 按点号分隔的路径修改yaml的值，路径中不存在的键会自动创建，列表可以使用下标（例如 dns.nameserver.0）
+pub fn apply_override(root: &mut YamlValue, key: &str, value: YamlValue) {
+    let mut current = root;
+    for part in key.split('.') {
+        let index = match (&*current, part.parse::<usize>()) {
+            (YamlValue::Sequence(seq), Ok(i)) if i < seq.len() => Some(i),
+            _ => None,
+        };
+        current = match index {
+            Some(i) => &mut current.as_sequence_mut().unwrap()[i],
+            None => {
+                if !current.is_mapping() {
+                    *current = YamlValue::Mapping(Mapping::new());
+                }
+                current
+                    .as_mapping_mut()
+                    .unwrap()
+                    .entry(YamlValue::String(part.to_string()))
+                    .or_insert(YamlValue::Null)
+            }
+        };
+    }
+    *current = value;
+}
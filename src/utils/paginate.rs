@@ -1,16 +1,132 @@
+use crate::utils::i18n::tr;
 use blake3::Hasher;
+use indexmap::IndexMap;
 use serde::Serialize;
 use serde_json::{Map as JsonMap, Value as JsonValue};
 use std::{
     collections::{HashMap, HashSet},
-    hash::{DefaultHasher, Hash, Hasher as OtherHasher},
+    sync::Arc,
 };
 
 /// 分页结构体，带names和items
+///
+/// 分页后内容不再修改，用 Arc 共享，各页并行构建时不需要复制节点
 #[derive(Debug)]
 pub struct Page<T> {
-    pub names: Vec<String>,
-    pub items: Vec<T>,
+    pub names: Arc<[String]>,
+    pub items: Arc<[T]>,
+}
+
+/// 节点分配到各页的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    Sequential, // 按输入的顺序填满一页再到下一页
+    RoundRobin, // 按输入的顺序轮流放入每一页
+    Balanced,   // 按分组（例如地区、协议）轮流放入每一页，每一页都有各个分组的节点
+    Stable,     // 按稳定的标识（例如 server:port）的哈希分页，每次构建同一个节点都在同一页
+}
+
+/// 解析 --distribute 参数
+pub fn parse_distribution(s: &str) -> Result<Distribution, String> {
+    match s.trim().to_lowercase().as_str() {
+        "sequential" => Ok(Distribution::Sequential),
+        "round-robin" => Ok(Distribution::RoundRobin),
+        "balanced" => Ok(Distribution::Balanced),
+        "stable" => Ok(Distribution::Stable),
+        _ => Err(tr!(
+            "无效的分页方式：{}，可选 sequential、round-robin、balanced、stable",
+            "invalid distribution: {}, expected sequential, round-robin, balanced or stable",
+            s
+        )),
+    }
+}
+
+/// 轮流放入每一页，页数不变（每页最多 page_size 个），每页的数量最多相差一个
+fn deal<T>(items: impl IntoIterator<Item = T>, page_count: usize) -> Vec<Vec<T>> {
+    let mut pages: Vec<Vec<T>> = (0..page_count).map(|_| Vec::new()).collect();
+    for (i, item) in items.into_iter().enumerate() {
+        pages[i % page_count].push(item);
+    }
+    pages
+}
+
+/// 跨版本、跨平台都不变的哈希（DefaultHasher 不保证不同版本的结果相同）
+fn stable_hash(key: &str) -> u64 {
+    let hash = blake3::hash(key.as_bytes());
+    u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap())
+}
+
+/// Jump 一致性哈希：页数变化时，只有少量的数据会换到其它页
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let (mut b, mut j) = (-1i64, 0i64);
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as usize
+}
+
+/// 按哈希分到各页，超出 page_size 的（哈希值最大的）放到后面有空位的页
+fn assign_stable<T>(
+    items: Vec<T>,
+    page_size: usize,
+    page_count: usize,
+    key: impl Fn(&T) -> String,
+) -> Vec<Vec<T>> {
+    let mut pages: Vec<Vec<(u64, T)>> = (0..page_count).map(|_| Vec::new()).collect();
+    for item in items {
+        let hash = stable_hash(&key(&item));
+        pages[jump_hash(hash, page_count)].push((hash, item));
+    }
+
+    let mut overflow = Vec::new();
+    for page in pages.iter_mut().filter(|p| p.len() > page_size) {
+        page.sort_by_key(|(hash, _)| *hash);
+        overflow.extend(page.drain(page_size..));
+    }
+    overflow.sort_by_key(|(hash, _)| *hash);
+    for item in overflow {
+        // 总容量 page_count * page_size 不少于数据量，一定有空位
+        let page = pages.iter_mut().find(|p| p.len() < page_size).unwrap();
+        page.push(item);
+    }
+
+    pages
+        .into_iter()
+        .map(|page| page.into_iter().map(|(_, item)| item).collect())
+        .collect()
+}
+
+/// 按 key 分组，分组按第一次出现的顺序，组内保持原来的顺序
+pub fn group_by<T>(items: Vec<T>, key: impl Fn(&T) -> String) -> IndexMap<String, Vec<T>> {
+    let mut groups: IndexMap<String, Vec<T>> = IndexMap::new();
+    for item in items {
+        groups.entry(key(&item)).or_default().push(item);
+    }
+    groups
+}
+
+/// 按分页方式把去重后的数据分到各页
+fn distribute<T: Clone>(
+    items: Vec<T>,
+    page_size: usize,
+    distribution: Distribution,
+    group_key: impl Fn(&T) -> String,
+) -> Vec<Vec<T>> {
+    let page_count = items.len().div_ceil(page_size);
+    match distribution {
+        Distribution::Sequential => items.chunks(page_size).map(|c| c.to_vec()).collect(),
+        Distribution::RoundRobin => deal(items, page_count),
+        Distribution::Balanced => {
+            // 同一分组的排在一起（分组按第一次出现的顺序），再轮流放入每一页
+            deal(
+                group_by(items, group_key).into_values().flatten(),
+                page_count,
+            )
+        }
+        Distribution::Stable => assign_stable(items, page_size, page_count, group_key),
+    }
 }
 
 /// 移除指定字段
@@ -67,13 +183,99 @@ fn base62_encode(mut n: u64) -> String {
     s.chars().rev().collect()
 }
 
-/// 通用分页去重 + 提取标题 + 使用哈希后缀重命名重复 name
+/// 重名节点的改名方式：同名的第 index 个重复（从1开始，第一次出现的不改名）改为什么名称
+///
+/// 改出的名称跟其它名称重复时，会用更大的 index 再试，所以不同的 index 应返回不同的名称。
+/// 内置了 HashSuffix、Counter、EmojiCounter，也可以自己实现后传给 dedup_and_paginate
+pub trait NameDeduper {
+    fn rename(&self, name: &str, index: usize) -> String;
+}
+
+/// 哈希后缀，例如 HK-01-a1B2c3，使用 blake3 计算，不同版本、不同平台生成的名称都相同
+pub struct HashSuffix;
+
+impl NameDeduper for HashSuffix {
+    fn rename(&self, name: &str, index: usize) -> String {
+        let mut hasher = Hasher::new();
+        hasher.update(name.as_bytes());
+        // 第一个重复只用名称计算
+        if index > 1 {
+            hasher.update(&(index as u64).to_le_bytes());
+        }
+        let hash = hasher.finalize();
+        let base62 = base62_encode(u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap()));
+        let short_id = &base62[..6.min(base62.len())]; // 截取6位
+        format!("{}-{}", name, short_id)
+    }
+}
+
+/// 数字序号，例如 HK-01-2、HK-01-3
+pub struct Counter;
+
+impl NameDeduper for Counter {
+    fn rename(&self, name: &str, index: usize) -> String {
+        format!("{}-{}", name, index + 1)
+    }
+}
+
+/// emoji 序号，例如 HK-01 2️⃣、HK-01 1️⃣0️⃣
+pub struct EmojiCounter;
+
+impl NameDeduper for EmojiCounter {
+    fn rename(&self, name: &str, index: usize) -> String {
+        let digits: String = (index + 1)
+            .to_string()
+            .chars()
+            .flat_map(|d| [d, '\u{FE0F}', '\u{20E3}'])
+            .collect();
+        format!("{} {}", name, digits)
+    }
+}
+
+/// --dedup-names 可选的内置改名方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupStyle {
+    Hash,
+    Counter,
+    Emoji,
+}
+
+impl NameDeduper for DedupStyle {
+    fn rename(&self, name: &str, index: usize) -> String {
+        match self {
+            DedupStyle::Hash => HashSuffix.rename(name, index),
+            DedupStyle::Counter => Counter.rename(name, index),
+            DedupStyle::Emoji => EmojiCounter.rename(name, index),
+        }
+    }
+}
+
+/// 解析 --dedup-names 参数
+pub fn parse_dedup_style(s: &str) -> Result<DedupStyle, String> {
+    match s.trim().to_lowercase().as_str() {
+        "hash" => Ok(DedupStyle::Hash),
+        "counter" => Ok(DedupStyle::Counter),
+        "emoji" => Ok(DedupStyle::Emoji),
+        _ => Err(tr!(
+            "无效的重名改名方式：{}，可选 hash、counter、emoji",
+            "invalid dedup style: {}, expected hash, counter or emoji",
+            s
+        )),
+    }
+}
+
+/// 通用分页去重 + 提取标题 + 使用 deduper 重命名重复 name
+///
+/// name_mut 返回数据中可以修改的名称，没有名称的返回 None；
+/// group_key 为 Balanced 分页方式中数据所属的分组，或者 Stable 分页方式中数据的稳定标识
 pub fn dedup_and_paginate<T: Serialize + Clone>(
     items: Vec<T>,
     page_size: usize,
+    distribution: Distribution,
+    deduper: &dyn NameDeduper,
+    group_key: impl Fn(&T) -> String,
     fields_to_remove: &[&str],
-    extract_name: impl Fn(&T) -> Option<String>,
-    set_name: impl Fn(&mut T, String),
+    name_mut: impl Fn(&mut T) -> Option<&mut String>,
 ) -> Vec<Page<T>> {
     let mut seen = HashSet::new();
     let mut unique_items = Vec::new();
@@ -86,36 +288,126 @@ pub fn dedup_and_paginate<T: Serialize + Clone>(
         }
     }
 
+    let mut paged = distribute(unique_items, page_size, distribution, group_key);
+    // 所有页中原来的名称，改出的名称不能跟它们重复（包括排在后面的）
+    let original_names: HashSet<String> = paged
+        .iter_mut()
+        .flatten()
+        .filter_map(|item| name_mut(item).cloned())
+        .collect();
+    // 已经使用的名称（包括改出的），所有页共用，保证整个构建中的名称都不重复
+    let mut used_names: HashSet<String> = HashSet::new();
     let mut name_counts: HashMap<String, usize> = HashMap::new();
     let mut pages = Vec::new();
 
     // 分页和处理重复名称
-    for chunk in unique_items.chunks(page_size) {
-        let mut items = chunk.to_vec();
+    for mut items in paged {
         let mut names = Vec::new();
 
         for item in items.iter_mut() {
-            if let Some(name) = extract_name(&item.clone()) {
-                let count = name_counts.entry(name.clone()).or_insert(0);
-                if *count > 0 {
-                    // 使用哈希值作为后缀
-                    let mut hasher = DefaultHasher::new();
-                    name.hash(&mut hasher);
-                    let hash = hasher.finish();
-                    let base62 = base62_encode(hash);
-                    let short_id = &base62[..6.min(base62.len())]; // 截取6位
-                    let new_name = format!("{}-{}", name, short_id);
-                    set_name(item, new_name.clone());
-                    names.push(new_name);
+            if let Some(name) = name_mut(item) {
+                if !used_names.contains(name.as_str()) {
+                    name_counts.insert(name.clone(), 1);
                 } else {
-                    names.push(name.clone());
+                    let count = name_counts.entry(name.clone()).or_insert(1);
+                    let mut renamed = deduper.rename(name, *count);
+                    let mut new_name = renamed.clone();
+                    while used_names.contains(&new_name) || original_names.contains(&new_name) {
+                        *count += 1;
+                        let next = deduper.rename(name, *count);
+                        // index 变了名称也不变的改名方式，改为加上序号
+                        new_name = if next == renamed {
+                            Counter.rename(&next, *count)
+                        } else {
+                            next.clone()
+                        };
+                        renamed = next;
+                    }
+                    *count += 1;
+                    *name = new_name;
                 }
-                *count += 1;
+                used_names.insert(name.clone());
+                names.push(name.clone());
             }
         }
 
-        pages.push(Page { items, names });
+        pages.push(Page {
+            items: items.into(),
+            names: names.into(),
+        });
     }
 
     pages
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// 每页 page_size 个节点，返回各页的名称
+    fn page_names(names: &[&str], page_size: usize, deduper: &dyn NameDeduper) -> Vec<Vec<String>> {
+        let nodes: Vec<JsonValue> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| json!({ "name": name, "server": format!("s{}.example.com", i) }))
+            .collect();
+        dedup_and_paginate(
+            nodes,
+            page_size,
+            Distribution::Sequential,
+            deduper,
+            |_| String::new(),
+            &[],
+            |node| match node.get_mut("name") {
+                Some(JsonValue::String(name)) => Some(name),
+                _ => None,
+            },
+        )
+        .into_iter()
+        .map(|page| page.names.to_vec())
+        .collect()
+    }
+
+    #[test]
+    fn counter_skips_names_used_on_later_pages() {
+        assert_eq!(
+            page_names(&["HK-01", "HK-01", "HK-01-2", "HK-01"], 2, &Counter),
+            [["HK-01", "HK-01-3"], ["HK-01-2", "HK-01-4"]]
+        );
+    }
+
+    #[test]
+    fn emoji_counter_skips_names_used_on_later_pages() {
+        assert_eq!(
+            page_names(&["HK-01", "HK-01", "HK-01 2️⃣", "HK-01"], 2, &EmojiCounter),
+            [["HK-01", "HK-01 3️⃣"], ["HK-01 2️⃣", "HK-01 4️⃣"]]
+        );
+        assert_eq!(EmojiCounter.rename("HK-01", 9), "HK-01 1️⃣0️⃣");
+    }
+
+    #[test]
+    fn hash_suffix_is_stable_and_skips_collisions() {
+        // 固定的结果：换了 Rust 版本后生成的名称也不能变
+        assert_eq!(HashSuffix.rename("HK-01", 1), "HK-01-laXizI");
+        assert_eq!(HashSuffix.rename("HK-01", 2), "HK-01-35pEbO");
+        assert_eq!(
+            page_names(&["HK-01", "HK-01", "HK-01-laXizI", "HK-01"], 2, &HashSuffix),
+            [["HK-01", "HK-01-35pEbO"], ["HK-01-laXizI", "HK-01-59i9A8"]]
+        );
+    }
+
+    #[test]
+    fn deduper_ignoring_index_falls_back_to_counter() {
+        struct Suffix;
+        impl NameDeduper for Suffix {
+            fn rename(&self, name: &str, _index: usize) -> String {
+                format!("{}-dup", name)
+            }
+        }
+        assert_eq!(
+            page_names(&["HK", "HK", "HK"], 2, &Suffix),
+            [vec!["HK", "HK-dup"], vec!["HK-dup-4"]]
+        );
+    }
+}
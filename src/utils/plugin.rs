@@ -0,0 +1,154 @@
+use crate::utils::{
+    exit::{ErrorKind, Failure},
+    i18n::tr,
+};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    thread,
+};
+
+/// 插件运行的阶段，每个阶段的输入和输出都是 json
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginStage {
+    PostMerge, // 合并所有输入之后的节点列表
+    PostGroup, // 每一页生成的代理组（已经放入节点）
+    PreWrite,  // 写入之前的规则列表（所有页共用）
+}
+
+impl PluginStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PluginStage::PostMerge => "post-merge",
+            PluginStage::PostGroup => "post-group",
+            PluginStage::PreWrite => "pre-write",
+        }
+    }
+}
+
+/// 一个插件：在某个阶段运行的外部命令
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    pub stage: PluginStage,
+    pub command: String,
+}
+
+/// 解析 --plugin 参数，格式为 阶段=命令，例如 post-merge=./filter.py
+pub fn parse_plugin(s: &str) -> Result<Plugin, String> {
+    let (stage, command) = s.split_once('=').ok_or_else(|| {
+        tr!(
+            "插件的格式为 阶段=命令：{}",
+            "a plugin is written as stage=command: {}",
+            s
+        )
+    })?;
+    let stage = match stage.trim() {
+        "post-merge" => PluginStage::PostMerge,
+        "post-group" => PluginStage::PostGroup,
+        "pre-write" => PluginStage::PreWrite,
+        other => {
+            return Err(tr!(
+                "未知的插件阶段：{}，可选 post-merge、post-group、pre-write",
+                "unknown plugin stage: {}, expected post-merge, post-group or pre-write",
+                other
+            ));
+        }
+    };
+    let command = command.trim();
+    if command.is_empty() {
+        return Err(tr!(
+            "插件的命令不能为空",
+            "the plugin command can't be empty"
+        ));
+    }
+    Ok(Plugin {
+        stage,
+        command: command.to_string(),
+    })
+}
+
+/// 运行一个插件：json 从 stdin 传入，插件从 stdout 输出修改后的 json（stderr 直接显示）
+///
+/// 环境变量 CLASH_TOOL_STAGE 为阶段，post-group 阶段还有 CLASH_TOOL_PAGE（页码，从1开始）
+fn run_plugin(plugin: &Plugin, input: Vec<u8>, page: Option<usize>) -> Result<Vec<u8>, String> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(&plugin.command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(&plugin.command);
+        cmd
+    };
+    cmd.env("CLASH_TOOL_STAGE", plugin.stage.as_str())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    if let Some(page) = page {
+        cmd.env("CLASH_TOOL_PAGE", page.to_string());
+    }
+    let mut child = cmd.spawn().map_err(|e| {
+        tr!(
+            "执行 {} 失败：{}",
+            "failed to run {}: {}",
+            plugin.command,
+            e
+        )
+    })?;
+    // 在另一个线程写入 stdin，插件边读边输出时不会因为管道写满而互相等待；
+    // 插件可能不读取 stdin 就退出，写入失败时以插件的退出状态为准
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let output = child.wait_with_output();
+    let _ = writer.join();
+    let output = output.map_err(|e| {
+        tr!(
+            "执行 {} 失败：{}",
+            "failed to run {}: {}",
+            plugin.command,
+            e
+        )
+    })?;
+    if !output.status.success() {
+        return Err(tr!(
+            "{} 执行失败：{}",
+            "{} failed: {}",
+            plugin.command,
+            output.status
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// 依次运行这个阶段的所有插件（前一个的输出是后一个的输入），没有插件时原样返回
+///
+/// 插件失败或者输出的 json 格式不对时构建失败
+pub fn run_stage<T: Serialize + DeserializeOwned>(
+    plugins: &[Plugin],
+    stage: PluginStage,
+    value: T,
+    page: Option<usize>,
+) -> Result<T, Failure> {
+    let mut plugins = plugins.iter().filter(|p| p.stage == stage).peekable();
+    if plugins.peek().is_none() {
+        return Ok(value);
+    }
+    let mut data = serde_json::to_vec(&value).unwrap();
+    for plugin in plugins {
+        data = run_plugin(plugin, data, page).map_err(|e| Failure::new(ErrorKind::Other, e))?;
+    }
+    serde_json::from_slice(&data).map_err(|e| {
+        Failure::new(
+            ErrorKind::Other,
+            tr!(
+                "插件({})输出的 json 格式错误：{}",
+                "invalid json from the {} plugins: {}",
+                stage.as_str(),
+                e
+            ),
+        )
+    })
+}
@@ -0,0 +1,161 @@
+use crate::utils::{
+    exit::{ErrorKind, Failure},
+    expand,
+    i18n::tr,
+};
+use std::fs;
+use toml::{Table, Value as TomlValue};
+
+/// 没有指定 --profiles 时读取的配置方案文件
+pub const DEFAULT_PROFILES_FILE: &str = "profiles.toml";
+
+// 值为英文逗号分隔的列表的参数，数组值用英文逗号拼接为一个参数
+const JOINED_KEYS: &[&str] = &["base", "inputs"];
+
+// 参数写在子命令之后的构建类子命令，其它子命令只使用方案中的全局参数（例如 user-agent、header）
+const BUILD_COMMANDS: &[&str] = &["build", "serve", "bench"];
+
+/// 命令行中某个长参数的值，支持 --name value 和 --name=value 两种写法
+fn find_option(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    args.iter()
+        .take_while(|arg| *arg != "--")
+        .enumerate()
+        .find_map(|(i, arg)| {
+            if arg == name {
+                args.get(i + 1).cloned()
+            } else {
+                arg.strip_prefix(&prefix).map(str::to_string)
+            }
+        })
+}
+
+fn scalar(profile: &str, key: &str, value: &TomlValue) -> Result<String, Failure> {
+    Ok(match value {
+        TomlValue::String(s) => expand::expand_path(s),
+        TomlValue::Integer(n) => n.to_string(),
+        TomlValue::Float(n) => n.to_string(),
+        TomlValue::Boolean(b) => b.to_string(),
+        TomlValue::Datetime(d) => d.to_string(),
+        TomlValue::Array(_) | TomlValue::Table(_) => {
+            return Err(Failure::new(
+                ErrorKind::Config,
+                tr!(
+                    "配置方案 [{}] 的 {} 不支持嵌套的值",
+                    "profile [{}]: {} doesn't support nested values",
+                    profile,
+                    key
+                ),
+            ));
+        }
+    })
+}
+
+/// 配置方案转为命令行参数，键必须是 command 中的长参数名，字符串中的环境变量和 ~ 会展开；
+/// only_global 时只转换全局参数，其它的跳过
+fn profile_args(
+    name: &str,
+    profile: &Table,
+    command: &clap::Command,
+    only_global: bool,
+) -> Result<Vec<String>, Failure> {
+    let mut args = Vec::new();
+    for (key, value) in profile {
+        let key = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()));
+        let Some(arg) = arg.filter(|_| key != "profile" && key != "profiles") else {
+            return Err(Failure::new(
+                ErrorKind::Config,
+                tr!(
+                    "配置方案 [{}] 中不认识的参数：{}",
+                    "unknown option in profile [{}]: {}",
+                    name,
+                    key
+                ),
+            ));
+        };
+        if only_global && !arg.is_global_set() {
+            continue;
+        }
+        let joined = JOINED_KEYS.contains(&key.as_str());
+        match value {
+            TomlValue::Boolean(false) => {}
+            TomlValue::Boolean(true) => args.push(format!("--{}", key)),
+            TomlValue::Array(items) if joined => {
+                let joined = items
+                    .iter()
+                    .map(|v| scalar(name, &key, v))
+                    .collect::<Result<Vec<String>, Failure>>()?;
+                args.push(format!("--{}={}", key, joined.join(",")));
+            }
+            // 可以多次使用的参数，每一项作为一次
+            TomlValue::Array(items) => {
+                for item in items {
+                    args.push(format!("--{}={}", key, scalar(name, &key, item)?));
+                }
+            }
+            value => args.push(format!("--{}={}", key, scalar(name, &key, value)?)),
+        }
+    }
+    Ok(args)
+}
+
+/// 命令行中使用了 --profile 时，把配置方案文件中该方案的参数插入到程序名（或者子命令）之后，
+/// 命令行中同时指定的参数排在后面，覆盖方案中的值（可以多次使用的参数则是追加）
+pub fn apply_profile(args: Vec<String>, command: &clap::Command) -> Result<Vec<String>, Failure> {
+    let Some(name) = find_option(&args, "--profile") else {
+        return Ok(args);
+    };
+    let path = expand::expand_path(
+        &find_option(&args, "--profiles").unwrap_or_else(|| DEFAULT_PROFILES_FILE.to_string()),
+    );
+    let data = fs::read_to_string(&path).map_err(|e| {
+        Failure::new(
+            ErrorKind::Config,
+            tr!(
+                "读取配置方案文件失败：{}，{}",
+                "failed to read the profiles file {}: {}",
+                path,
+                e
+            ),
+        )
+    })?;
+    let mut profiles: Table = toml::from_str(&data).map_err(|e| {
+        Failure::new(
+            ErrorKind::Config,
+            tr!(
+                "配置方案文件格式错误：{}，{}",
+                "invalid profiles file {}: {}",
+                path,
+                e
+            ),
+        )
+    })?;
+    let Some(TomlValue::Table(profile)) = profiles.remove(&name) else {
+        return Err(Failure::new(
+            ErrorKind::Config,
+            tr!(
+                "配置方案文件 {} 中没有 [{}]",
+                "the profiles file {} has no [{}]",
+                path,
+                name
+            ),
+        ));
+    };
+
+    let subcommand = args.get(1).filter(|arg| {
+        command
+            .get_subcommands()
+            .any(|sub| sub.get_name() == *arg || sub.get_all_aliases().any(|a| a == *arg))
+    });
+    let (at, only_global) = match subcommand {
+        Some(sub) => (2, !BUILD_COMMANDS.contains(&sub.as_str())),
+        None => (1, false),
+    };
+    let mut expanded = args[..at].to_vec();
+    expanded.extend(profile_args(&name, &profile, command, only_global)?);
+    expanded.extend(args.into_iter().skip(at));
+    Ok(expanded)
+}
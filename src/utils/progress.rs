@@ -0,0 +1,86 @@
+use crate::{
+    build::download::Transfer,
+    utils::{i18n::tr, report},
+};
+use std::{
+    io::{self, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+// 刷新进度的最短间隔
+const REFRESH: Duration = Duration::from_millis(200);
+
+/// 下载的进度：stderr 是终端时在同一行刷新已下载的大小、速度和预计剩余时间，完成后输出一行汇总
+pub struct Progress<'a> {
+    label: &'a str,
+    start: Instant,
+    last_draw: Option<Instant>,
+    interactive: bool,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(label: &'a str) -> Self {
+        Progress {
+            label,
+            start: Instant::now(),
+            last_draw: None,
+            interactive: io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn update(&mut self, done: u64, total: Option<u64>) {
+        if !self.interactive || self.last_draw.is_some_and(|t| t.elapsed() < REFRESH) {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let speed = if elapsed > 0.0 {
+            (done as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        let mut line = tr!(
+            "{} 已下载 {}，{}/s",
+            "{} downloaded {}, {}/s",
+            self.label,
+            report::human_size(done as usize),
+            report::human_size(speed as usize)
+        );
+        if let Some(total) = total.filter(|total| *total >= done) {
+            line.push_str(&tr!(
+                " / 共 {}",
+                " / {} total",
+                report::human_size(total as usize)
+            ));
+            if speed > 0 {
+                line.push_str(&tr!(
+                    "，剩余 {}s",
+                    ", {}s left",
+                    (total - done).div_ceil(speed)
+                ));
+            }
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", line);
+        let _ = stderr.flush();
+    }
+
+    /// 下载完成：清除进度行，输出大小、平均速度和耗时
+    pub fn finish(&self, transfer: &Transfer) {
+        if self.last_draw.is_some() {
+            eprint!("\r\x1b[2K");
+        }
+        eprintln!("{} {}", self.label, describe(transfer));
+    }
+}
+
+/// 下载的汇总，例如 "下载 1.20 MB，平均 600.00 KB/s，耗时 2.00s"
+fn describe(transfer: &Transfer) -> String {
+    tr!(
+        "下载 {}，平均 {}/s，耗时 {:.2?}",
+        "downloaded {}, average {}/s, took {:.2?}",
+        report::human_size(transfer.bytes as usize),
+        report::human_size(transfer.speed() as usize),
+        transfer.elapsed
+    )
+}
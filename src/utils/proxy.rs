@@ -1,66 +1,490 @@
-use crate::utils::read;
+use crate::{
+    build::download,
+    utils::{
+        exit::{ErrorKind, Failure},
+        i18n::tr,
+        model::Proxy,
+        paginate::Page,
+        progress::Progress,
+        read, redact,
+        sniff::{self, InputFormat},
+        userinfo::{self, SubscriptionInfo},
+    },
+};
 use chardetng::EncodingDetector;
-use serde::Deserialize;
-use serde_yaml::{Deserializer, Value as YamlValue};
-use std::{fs, path::Path};
+use serde_yaml::Value as YamlValue;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::Path,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
-/// 提取并合并多个 YAML 文件中某个字段的数组值（例如 name 字段）
-pub fn extract_and_merge_proxies(paths_str: &str, field_name: &str) -> Vec<YamlValue> {
-    let mut result = Vec::new();
+/// 判断输入是否为订阅链接
+pub fn is_url(path: &str) -> bool {
+    ["https://", "http://"].iter().any(|p| path.starts_with(p))
+}
+
+/// 判断输入是否为标准输入(-f -)，例如 curl 订阅链接 | clash_subscription_tool -f -
+pub fn is_stdin(path: &str) -> bool {
+    path == "-"
+}
+
+/// 标准输入只能读取一次，读取的内容保存下来给之后的构建(--interval、多个 -b)使用
+static STDIN_CONTENT: OnceLock<Vec<u8>> = OnceLock::new();
+
+fn read_stdin() -> io::Result<Vec<u8>> {
+    if let Some(data) = STDIN_CONTENT.get() {
+        return Ok(data.clone());
+    }
+    let mut data = Vec::new();
+    io::stdin().lock().read_to_end(&mut data)?;
+    Ok(STDIN_CONTENT.get_or_init(|| data).clone())
+}
+
+/// 拆分 -f 中的一个输入为 (路径, 最多取的节点数)，例如 main.yaml:100
+///
+/// 只有最后一个冒号后面全是数字时才当作数量，订阅链接中的端口（后面没有路径时）不会被误认为数量
+pub fn parse_source(source: &str) -> (&str, Option<usize>) {
+    let source = source.trim();
+    if let Some((path, cap)) = source.rsplit_once(':')
+        && !cap.is_empty()
+        && cap.bytes().all(|b| b.is_ascii_digit())
+        && (!is_url(path)
+            || path
+                .split_once("://")
+                .is_some_and(|(_, rest)| rest.contains('/')))
+    {
+        return (path, cap.parse().ok());
+    }
+    (source, None)
+}
+
+/// 订阅链接的下载结果：流量信息（响应头中有 subscription-userinfo 时）以及下载的大小、耗时
+#[derive(Debug, Clone)]
+pub struct SourceFetch {
+    pub source: String,
+    pub info: Option<SubscriptionInfo>,
+    pub transfer: download::Transfer,
+}
+
+/// 读取本地文件或者下载订阅链接的内容，订阅链接同时返回下载结果（流量信息、耗时）
+pub async fn read_source(path: &str) -> Result<(Vec<u8>, Option<SourceFetch>), Failure> {
+    let shown = redact::source(path);
+    let msg = format!("Failed to read file: {}", shown);
+    if is_url(path) {
+        let mut progress = Progress::new(&shown);
+        let (data, header, transfer) =
+            download::download_subscription(path, |done, total| progress.update(done, total))
+                .await
+                .map_err(|e| {
+                    // 下载的错误信息中带有完整的链接
+                    let error = e.to_string();
+                    let error = match reqwest::Url::parse(path) {
+                        Ok(url) if redact::is_enabled() => {
+                            error.replace(url.as_str(), &shown).replace(path, &shown)
+                        }
+                        _ => error,
+                    };
+                    Failure::new(ErrorKind::Network, format!("{}: {}", msg, error))
+                })?;
+        progress.finish(&transfer);
+        let fetch = SourceFetch {
+            source: path.to_string(),
+            info: header.as_deref().and_then(userinfo::parse_userinfo),
+            transfer,
+        };
+        Ok((data, Some(fetch)))
+    } else if is_stdin(path) {
+        let data = read_stdin().map_err(|e| {
+            Failure::new(
+                ErrorKind::Config,
+                tr!("读取标准输入失败：{}", "Failed to read stdin: {}", e),
+            )
+        })?;
+        Ok((data, None))
+    } else {
+        let data = fs::read(Path::new(path))
+            .map_err(|e| Failure::new(ErrorKind::Config, format!("{}: {}", msg, e)))?;
+        Ok((data, None))
+    }
+}
+
+/// 订阅内容转为字符串：自动识别编码（包括 UTF-8、GBK、ISO-8859-1、Big5 等），移除 UTF-8 BOM
+pub fn decode_content(raw_bytes: &[u8]) -> String {
+    let mut detector = EncodingDetector::new();
+    detector.feed(raw_bytes, true);
+    let encoding = detector.guess(None, true);
+    let (cow, _, _) = encoding.decode(raw_bytes);
+    cow.strip_prefix('\u{FEFF}').unwrap_or(&cow).to_string()
+}
 
-    for path in paths_str.split(',').map(str::trim) {
-        let msg = format!("Failed to read file: {}", path);
-        let raw_bytes = fs::read(Path::new(path)).expect(&msg);
+/// 提取并合并多个 YAML 文件（或订阅链接）中某个字段的数组值（例如 name 字段）
+///
+/// 输入可以写为 路径:数量，只取该输入的前 N 个节点；输入的顺序即优先级，重复的节点保留前面的。
+/// 同时返回每个输入读取到的数量，以及订阅链接的下载结果（流量信息、耗时）
+pub async fn extract_and_merge_proxies(
+    sources: &[&str],
+    field_name: &str,
+) -> Result<(Vec<YamlValue>, Vec<(String, usize)>, Vec<SourceFetch>), Failure> {
+    let mut result = Vec::new();
+    let mut source_counts = Vec::new();
+    let mut subscriptions = Vec::new();
 
-        // 1、自动识别编码（包括 UTF-8、GBK、ISO-8859-1、Big5 等）
-        let mut detector = EncodingDetector::new();
-        detector.feed(&raw_bytes, true);
-        let encoding = detector.guess(None, true);
+    for (path, cap) in sources.iter().map(|s| parse_source(s)) {
+        let before = result.len();
+        let shown = redact::source(path);
+        let (raw_bytes, fetch) = read_source(path).await?;
+        if let Some(fetch) = fetch {
+            if let Some(info) = &fetch.info {
+                eprintln!("{} {}", shown, info.summary());
+            }
+            subscriptions.push(fetch);
+        }
 
-        // 2、解码为 UTF-8
-        let (cow, _, _) = encoding.decode(&raw_bytes);
-        let mut content = cow.to_string();
+        let content = decode_content(&raw_bytes);
 
-        // 3、移除 UTF-8 BOM（如果是 UTF-8 并带 BOM）
-        const BOM: &str = "\u{FEFF}";
-        if content.starts_with(BOM) {
-            content = content[BOM.len()..].to_string();
+        // 识别格式（yaml、json、base64、分享链接）并解析，跳过无效的节点
+        let (format, entries, malformed) = sniff::extract_entries(&content, field_name);
+        if format != InputFormat::Yaml {
+            eprintln!(
+                "{}",
+                tr!(
+                    "{} 识别为 {} 格式",
+                    "{} detected as {} format",
+                    shown,
+                    format.label()
+                )
+            );
         }
+        if !malformed.is_empty() {
+            eprintln!(
+                "{}",
+                tr!(
+                    "{} 中有 {} 个无效的节点，已跳过：",
+                    "{} has {} invalid nodes, skipped:",
+                    shown,
+                    malformed.len()
+                )
+            );
+            for m in &malformed {
+                eprintln!("  {}", m.describe());
+            }
+        }
+        result.extend(entries);
+        if let Some(cap) = cap
+            && result.len() - before > cap
+        {
+            eprintln!(
+                "{}",
+                tr!(
+                    "{} 共有 {} 个节点，只取前 {} 个",
+                    "{} has {} nodes, only the first {} are used",
+                    shown,
+                    result.len() - before,
+                    cap
+                )
+            );
+            result.truncate(before + cap);
+        }
+        source_counts.push((path.to_string(), result.len() - before));
+    }
 
-        // 4、解析 YAML
-        let docs: Vec<YamlValue> = Deserializer::from_str(&content)
-            .map(|doc| YamlValue::deserialize(doc).expect("Invalid YAML"))
-            .collect();
+    Ok((result, source_counts, subscriptions))
+}
 
-        for doc in docs {
-            if let Some(field_value) = doc.get(field_name) {
-                match field_value {
-                    YamlValue::Sequence(seq) => result.extend(seq.clone()),
-                    other => result.push(other.clone()),
-                }
+/// 输入的默认标签：本地文件使用文件名（不含扩展名），订阅链接使用域名，标准输入为 stdin
+fn source_label(path: &str) -> String {
+    if is_stdin(path) {
+        "stdin".to_string()
+    } else if is_url(path) {
+        let host = path.split_once("://").map_or(path, |(_, rest)| rest);
+        host.split(['/', '?', '#', ':'])
+            .next()
+            .unwrap_or(host)
+            .to_string()
+    } else {
+        Path::new(path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string())
+    }
+}
+
+/// 给节点名称加上来源的前缀，例如 `[A] 香港01`，便于在客户端中区分节点来自哪个订阅
+///
+/// source_counts 为 extract_and_merge_proxies 返回的每个输入的节点数量（与节点的顺序一致），
+/// labels 按输入的顺序对应，没有提供的使用文件名（订阅链接使用域名）
+pub fn tag_proxies_by_source(
+    proxies: &mut [Proxy],
+    source_counts: &[(String, usize)],
+    labels: &[String],
+) {
+    let mut start = 0;
+    for (i, (path, count)) in source_counts.iter().enumerate() {
+        let label = labels
+            .get(i)
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .map_or_else(|| source_label(path), str::to_string);
+        for proxy in &mut proxies[start..start + count] {
+            if let Some(name) = proxy.name() {
+                let name = format!("[{}] {}", label, name);
+                proxy.set_name(name);
             }
         }
+        start += count;
     }
+}
+
+/// 按节点的协议类型过滤：allow 不为空时只保留其中的类型，再去掉 deny 中的类型（不区分大小写）
+///
+/// 返回保留的节点和被过滤掉的数量
+pub fn filter_proxy_types(
+    proxies: Vec<Proxy>,
+    allow: &[String],
+    deny: &[String],
+) -> (Vec<Proxy>, usize) {
+    let contains =
+        |types: &[String], t: &str| types.iter().any(|x| x.trim().eq_ignore_ascii_case(t));
+    let total = proxies.len();
+    let kept: Vec<Proxy> = proxies
+        .into_iter()
+        .filter(|proxy| {
+            let proxy_type = proxy.proxy_type();
+            (allow.is_empty() || contains(allow, proxy_type)) && !contains(deny, proxy_type)
+        })
+        .collect();
+    let dropped = total - kept.len();
+    (kept, dropped)
+}
 
-    result
+/// 按节点的能力过滤：require_udp 时去掉不支持 UDP 转发的，require_tfo 时去掉没有开启 TFO 的
+///
+/// 返回保留的节点和被过滤掉的数量
+pub fn filter_capabilities(
+    proxies: Vec<Proxy>,
+    require_udp: bool,
+    require_tfo: bool,
+) -> (Vec<Proxy>, usize) {
+    let total = proxies.len();
+    let kept: Vec<Proxy> = proxies
+        .into_iter()
+        .filter(|proxy| {
+            (!require_udp || proxy.supports_udp()) && (!require_tfo || proxy.supports_tfo())
+        })
+        .collect();
+    let dropped = total - kept.len();
+    (kept, dropped)
 }
 
 #[allow(dead_code)]
 fn get_proxies_names_and_values(file_path: &str) -> (Vec<String>, Vec<YamlValue>) {
     let mut names: Vec<String> = Vec::new();
     let mut proxies_value: Vec<YamlValue> = Vec::new();
-    if let Some(yamlvalue) = read::read_yaml(file_path).get("proxies") {
-        if let YamlValue::Sequence(seq) = yamlvalue {
-            proxies_value = seq.clone();
-            for item in &proxies_value {
-                let name: String = item
-                    .get("name")
-                    .and_then(|v| v.as_str())
-                    .map(|s| s.to_string())
-                    .unwrap_or_default();
-                names.push(name);
-            }
+    if let Some(YamlValue::Sequence(seq)) = read::read_yaml(file_path).get("proxies") {
+        proxies_value = seq.clone();
+        for item in &proxies_value {
+            let name: String = item
+                .get("name")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+                .unwrap_or_default();
+            names.push(name);
         }
     }
     (names, proxies_value)
 }
+
+/// 常见地区的识别关键字：(地区代码, 名称中的关键字)，两个字母的代码需要是单独的单词
+const REGIONS: &[(&str, &[&str])] = &[
+    ("HK", &["香港", "🇭🇰", "Hong Kong", "HongKong"]),
+    ("TW", &["台湾", "臺灣", "🇹🇼", "Taiwan"]),
+    ("JP", &["日本", "东京", "大阪", "🇯🇵", "Japan", "Tokyo"]),
+    ("SG", &["新加坡", "狮城", "🇸🇬", "Singapore"]),
+    ("KR", &["韩国", "首尔", "🇰🇷", "Korea", "Seoul"]),
+    ("US", &["美国", "🇺🇸", "United States", "America"]),
+    ("GB", &["英国", "伦敦", "🇬🇧", "United Kingdom", "London"]),
+    ("DE", &["德国", "🇩🇪", "Germany"]),
+    ("FR", &["法国", "🇫🇷", "France"]),
+    ("NL", &["荷兰", "🇳🇱", "Netherlands"]),
+    ("RU", &["俄罗斯", "🇷🇺", "Russia"]),
+    ("CA", &["加拿大", "🇨🇦", "Canada"]),
+    ("AU", &["澳大利亚", "澳洲", "🇦🇺", "Australia"]),
+    ("IN", &["印度", "🇮🇳", "India"]),
+    ("TR", &["土耳其", "🇹🇷", "Turkey"]),
+];
+
+/// 根据节点名称识别地区，例如 "香港 01"、"HK-01" => HK，识别不了时返回 None
+pub fn region_of(proxy: &YamlValue) -> Option<&'static str> {
+    let name = proxy.get("name")?.as_str()?;
+    let words: Vec<&str> = name
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .filter(|w| !w.is_empty())
+        .collect();
+    REGIONS
+        .iter()
+        .find(|(code, keywords)| {
+            keywords.iter().any(|k| name.contains(k))
+                || words
+                    .iter()
+                    .any(|w| w.eq_ignore_ascii_case(code) && *w == w.to_uppercase())
+        })
+        .map(|(code, _)| *code)
+}
+
+/// 一组节点中最多的地区，例如用于文件名模板中的 {region}，都识别不了时返回 "other"
+pub fn main_region(proxies: &[YamlValue]) -> &'static str {
+    let mut counts: Vec<(&'static str, usize)> = Vec::new();
+    for region in proxies.iter().filter_map(region_of) {
+        match counts.iter_mut().find(|(r, _)| *r == region) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((region, 1)),
+        }
+    }
+    // 数量相同时取先出现的
+    counts
+        .iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map_or("other", |(region, _)| region)
+}
+
+/// 均衡分页时节点所属的分组：能识别地区的按地区，否则按协议类型
+pub fn balance_key(proxy: &YamlValue) -> String {
+    match region_of(proxy) {
+        Some(region) => region.to_string(),
+        None => proxy
+            .get("type")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_lowercase(),
+    }
+}
+
+/// 节点稳定的标识 server:port，改名后也不变，用于稳定分页
+pub fn stable_key(proxy: &YamlValue) -> String {
+    let field = |key: &str| match proxy.get(key) {
+        Some(YamlValue::String(s)) => s.clone(),
+        Some(YamlValue::Number(n)) => n.to_string(),
+        _ => String::new(),
+    };
+    format!("{}:{}", field("server"), field("port"))
+}
+
+/// 节点的标识（类型 server:port），改名后不变，用于判断节点是新增、删除还是改名，以及保存选择的节点
+pub fn node_key(proxy: &YamlValue) -> String {
+    let proxy_type = proxy
+        .get("type")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    format!("{} {}", proxy_type, stable_key(proxy))
+}
+
+/// 每页内节点的排序方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeSort {
+    None,    // 保持输入的顺序
+    Name,    // 按名称，名称中的数字按大小比较（节点 2 排在 节点 10 前面）
+    Region,  // 按地区（REGIONS 中的顺序，识别不了的排在最后），同一地区的按名称
+    Latency, // 按本机到节点服务器的 TCP 连接耗时，无法连接的排在最后
+}
+
+/// 解析 --sort-nodes 参数
+pub fn parse_node_sort(s: &str) -> Result<NodeSort, String> {
+    match s.trim().to_lowercase().as_str() {
+        "none" => Ok(NodeSort::None),
+        "name" => Ok(NodeSort::Name),
+        "region" => Ok(NodeSort::Region),
+        "latency" => Ok(NodeSort::Latency),
+        _ => Err(tr!(
+            "无效的节点排序方式：{}，可选 none、name、region、latency",
+            "invalid node sort: {}, expected none, name, region or latency",
+            s
+        )),
+    }
+}
+
+fn proxy_name(proxy: &YamlValue) -> &str {
+    proxy
+        .get("name")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+}
+
+/// 比较名称，连续的数字按数值比较
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(ca), Some(cb)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let da = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let db = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let (na, nb) = (
+                a[..da].trim_start_matches('0'),
+                b[..db].trim_start_matches('0'),
+            );
+            let ord = na.len().cmp(&nb.len()).then_with(|| na.cmp(nb));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            (a, b) = (&a[da..], &b[db..]);
+        } else {
+            if ca != cb {
+                return ca.cmp(&cb);
+            }
+            (a, b) = (&a[ca.len_utf8()..], &b[cb.len_utf8()..]);
+        }
+    }
+}
+
+fn region_rank(proxy: &YamlValue) -> usize {
+    region_of(proxy)
+        .and_then(|region| REGIONS.iter().position(|(code, _)| *code == region))
+        .unwrap_or(REGIONS.len())
+}
+
+/// 一页内的节点排序，流量信息节点总是排在最前面；latencies 为 latency::measure 的结果
+pub fn sort_page(
+    page: &mut Page<YamlValue>,
+    sort: NodeSort,
+    latencies: &HashMap<String, Duration>,
+) {
+    if sort == NodeSort::None {
+        return;
+    }
+    let items = &page.items;
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    // 稳定排序，相同的保持原来的顺序
+    order.sort_by(|&a, &b| {
+        let (pa, pb) = (&items[a], &items[b]);
+        let by_name = || natural_cmp(proxy_name(pa), proxy_name(pb));
+        userinfo::is_info_node(pb)
+            .cmp(&userinfo::is_info_node(pa))
+            .then_with(|| match sort {
+                NodeSort::None => Ordering::Equal,
+                NodeSort::Name => by_name(),
+                NodeSort::Region => region_rank(pa).cmp(&region_rank(pb)).then_with(by_name),
+                NodeSort::Latency => {
+                    let latency = |p| {
+                        latencies
+                            .get(&stable_key(p))
+                            .copied()
+                            .unwrap_or(Duration::MAX)
+                    };
+                    latency(pa).cmp(&latency(pb))
+                }
+            })
+    });
+    let items: Arc<[YamlValue]> = order.iter().map(|&i| page.items[i].clone()).collect();
+    // 名称跟节点一一对应时一起调整顺序（没有名称的节点不会有对应的名称）
+    if page.names.len() == page.items.len() {
+        page.names = order.iter().map(|&i| page.names[i].clone()).collect();
+    }
+    page.items = items;
+}
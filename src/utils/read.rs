@@ -1,7 +1,231 @@
-use serde_yaml::Value as YamlValue;
+use crate::{
+    build::presets,
+    utils::{
+        exit::{ErrorKind, Failure},
+        expand,
+        i18n::tr,
+    },
+};
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// base.yaml 中引用其它 yaml 片段的键，例如 `__include: [dns.yaml, tun.yaml]`
+pub const INCLUDE_KEY: &str = "__include";
+
+// yaml 的合并键
+const MERGE_KEY: &str = "<<";
 
 pub fn read_yaml(file_path: &str) -> YamlValue {
     let content = std::fs::read_to_string(file_path).unwrap();
-    let yaml: YamlValue = serde_yaml::from_str(&content).unwrap();
+    let mut yaml: YamlValue = serde_yaml::from_str(&content).unwrap();
+    apply_merge_keys(&mut yaml).unwrap_or_else(|e| panic!("{}：{}", file_path, e));
     yaml
 }
+
+/// 展开合并键 `<<: *anchor`（别名在解析时已经展开）
+///
+/// 合并进来的键放在 `<<` 所在的位置，当前映射中已有的键优先；`<<` 的值为列表时，前面的映射优先
+pub fn apply_merge_keys(value: &mut YamlValue) -> Result<(), String> {
+    match value {
+        YamlValue::Sequence(items) => items.iter_mut().try_for_each(apply_merge_keys),
+        YamlValue::Tagged(tagged) => apply_merge_keys(&mut tagged.value),
+        YamlValue::Mapping(mapping) => {
+            // 先展开里层的（包括 << 引用的映射中的合并键）
+            mapping.values_mut().try_for_each(apply_merge_keys)?;
+            if !mapping.contains_key(MERGE_KEY) {
+                return Ok(());
+            }
+            let own = std::mem::take(mapping);
+            let own_keys: HashSet<YamlValue> = own.keys().cloned().collect();
+            for (key, value) in own {
+                if key.as_str() != Some(MERGE_KEY) {
+                    mapping.insert(key, value);
+                    continue;
+                }
+                let sources = match value {
+                    YamlValue::Sequence(items) => items,
+                    other => vec![other],
+                };
+                for source in sources {
+                    let YamlValue::Mapping(source) = source else {
+                        return Err(tr!(
+                            "合并键(<<)的值只能是映射或映射的列表",
+                            "the value of a merge key (<<) must be a mapping or a list of mappings"
+                        ));
+                    };
+                    for (k, v) in source {
+                        if !own_keys.contains(&k) && !mapping.contains_key(&k) {
+                            mapping.insert(k, v);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// 读取 yaml 文件，并合并 `__include` 中列出的片段（路径相对于当前文件，片段中也可以再引用其它片段）
+///
+/// 片段的内容放在 `__include` 所在的位置；多个片段按顺序合并，后面的优先，当前文件中的内容最优先。
+/// 映射逐层合并，列表和其它值直接覆盖。同时返回读取到的所有片段文件，用于监听文件变化
+pub fn read_yaml_with_includes(file_path: &str) -> Result<(YamlValue, Vec<PathBuf>), Failure> {
+    let mut fragments = Vec::new();
+    let yaml = resolve_includes(Path::new(file_path), &mut Vec::new(), &mut fragments)?;
+    Ok((yaml, fragments))
+}
+
+/// 读取 base.yaml（合并引用的片段），文件不存在时使用内置的默认 base.yaml
+pub fn read_base_yaml(file_path: &str) -> Result<(YamlValue, Vec<PathBuf>), Failure> {
+    if Path::new(file_path).exists() {
+        return read_yaml_with_includes(file_path);
+    }
+    eprintln!(
+        "{}",
+        tr!(
+            "base.yaml 不存在：{}，使用内置的默认配置，可以用 --dump-defaults 写出后修改",
+            "base.yaml not found: {}, using the built-in default, write it out with --dump-defaults to edit",
+            file_path
+        )
+    );
+    let mut yaml: YamlValue = serde_yaml::from_str(presets::DEFAULT_BASE_YAML).unwrap();
+    apply_merge_keys(&mut yaml).unwrap();
+    Ok((yaml, Vec::new()))
+}
+
+fn resolve_includes(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    fragments: &mut Vec<PathBuf>,
+) -> Result<YamlValue, Failure> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&canonical) {
+        let chain: Vec<String> = stack
+            .iter()
+            .chain([&canonical])
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(Failure::new(
+            ErrorKind::Config,
+            tr!(
+                "yaml 片段循环引用：{}",
+                "yaml fragments include each other: {}",
+                chain.join(" -> ")
+            ),
+        ));
+    }
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        Failure::new(
+            ErrorKind::Config,
+            tr!(
+                "读取yaml文件失败：{}，{}",
+                "failed to read yaml file {}: {}",
+                path.display(),
+                e
+            ),
+        )
+    })?;
+    let mut yaml: YamlValue = serde_yaml::from_str(&content).map_err(|e| {
+        Failure::new(
+            ErrorKind::Config,
+            tr!(
+                "yaml格式错误：{}，{}",
+                "invalid yaml {}: {}",
+                path.display(),
+                e
+            ),
+        )
+    })?;
+    apply_merge_keys(&mut yaml)
+        .map_err(|e| Failure::new(ErrorKind::Config, format!("{}：{}", path.display(), e)))?;
+    let YamlValue::Mapping(own) = yaml else {
+        return Ok(yaml);
+    };
+    let Some(include) = own.get(INCLUDE_KEY) else {
+        return Ok(YamlValue::Mapping(own));
+    };
+    let not_paths = || {
+        Failure::new(
+            ErrorKind::Config,
+            tr!(
+                "{} 中 {} 的值只能是文件路径",
+                "{}: the value of {} must be file paths",
+                path.display(),
+                INCLUDE_KEY
+            ),
+        )
+    };
+    let include_paths: Vec<&str> = match include {
+        YamlValue::String(s) => vec![s.as_str()],
+        YamlValue::Sequence(items) => items
+            .iter()
+            .map(|v| v.as_str().ok_or_else(not_paths))
+            .collect::<Result<_, _>>()?,
+        _ => return Err(not_paths()),
+    };
+
+    stack.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut included = YamlValue::Mapping(Mapping::new());
+    for include_path in include_paths {
+        let fragment_path = dir.join(expand::expand_path(include_path));
+        let fragment = resolve_includes(&fragment_path, stack, fragments)?;
+        if !fragment.is_mapping() {
+            return Err(Failure::new(
+                ErrorKind::Config,
+                tr!(
+                    "yaml 片段的顶层必须是映射：{}",
+                    "the top level of a yaml fragment must be a mapping: {}",
+                    fragment_path.display()
+                ),
+            ));
+        }
+        merge_yaml(&mut included, fragment);
+        fragments.push(fragment_path);
+    }
+    stack.pop();
+    let YamlValue::Mapping(mut included) = included else {
+        unreachable!()
+    };
+
+    let own_keys: HashSet<YamlValue> = own.keys().cloned().collect();
+    let mut result = Mapping::new();
+    for (key, value) in own {
+        if key.as_str() == Some(INCLUDE_KEY) {
+            for (k, v) in included.iter().filter(|(k, _)| !own_keys.contains(*k)) {
+                result.insert(k.clone(), v.clone());
+            }
+        } else {
+            let value = match included.remove(&key) {
+                Some(mut base) => {
+                    merge_yaml(&mut base, value);
+                    base
+                }
+                None => value,
+            };
+            result.insert(key, value);
+        }
+    }
+    Ok(YamlValue::Mapping(result))
+}
+
+/// over 合并到 base：两者都是映射时逐个键合并，否则 over 覆盖 base
+fn merge_yaml(base: &mut YamlValue, over: YamlValue) {
+    match (base, over) {
+        (YamlValue::Mapping(base), YamlValue::Mapping(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(old) => merge_yaml(old, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
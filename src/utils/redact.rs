@@ -0,0 +1,135 @@
+use crate::utils::proxy;
+use reqwest::Url;
+use serde_yaml::Value as YamlValue;
+use std::{
+    borrow::Cow,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// 节点（以及 base.yaml）中的凭据字段
+const SECRET_KEYS: &[&str] = &[
+    "password",
+    "uuid",
+    "psk",
+    "pre-shared-key",
+    "private-key",
+    "auth",
+    "auth-str",
+    "obfs-password",
+    "token",
+    "secret",
+];
+
+const MASK: &str = "***";
+
+// --redact：日志、清单中的订阅链接和节点内容也隐藏凭据
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 输入的来源（本地文件或订阅链接），--redact 时订阅链接只保留协议和域名，路径和参数中常带有 token
+pub fn source(path: &str) -> Cow<'_, str> {
+    if !is_enabled() || !proxy::is_url(path) {
+        return Cow::Borrowed(path);
+    }
+    match Url::parse(path) {
+        Ok(url) => {
+            let host = url.host_str().unwrap_or_default();
+            let port = url.port().map(|p| format!(":{}", p)).unwrap_or_default();
+            let rest = if url.path() == "/" && url.query().is_none() {
+                ""
+            } else {
+                "/***"
+            };
+            Cow::Owned(format!("{}://{}{}{}", url.scheme(), host, port, rest))
+        }
+        Err(_) => Cow::Borrowed(MASK),
+    }
+}
+
+/// 节点中凭据字段的值替换为 ***（包括 plugin-opts 等里层的）
+pub fn redact_value(value: &mut YamlValue) {
+    match value {
+        YamlValue::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                if key.as_str().is_some_and(|k| SECRET_KEYS.contains(&k)) && !value.is_mapping() {
+                    *value = YamlValue::String(MASK.to_string());
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        YamlValue::Sequence(items) => items.iter_mut().for_each(redact_value),
+        YamlValue::Tagged(tagged) => redact_value(&mut tagged.value),
+        _ => {}
+    }
+}
+
+/// --redact 时隐藏节点中的凭据，用于在日志中输出节点的内容
+pub fn for_log(value: &YamlValue) -> Cow<'_, YamlValue> {
+    if !is_enabled() {
+        return Cow::Borrowed(value);
+    }
+    let mut value = value.clone();
+    redact_value(&mut value);
+    Cow::Owned(value)
+}
+
+/// 一行 `key: value`（yaml 块格式）或 `"key": value,`（格式化的 json）中凭据的值替换为 ***
+fn redact_line(line: &str) -> Option<String> {
+    let trimmed = line.trim_start();
+    let body = trimmed.strip_prefix("- ").unwrap_or(trimmed);
+    let (key, rest, quoted) = match body.strip_prefix('"') {
+        Some(body) => {
+            let (key, rest) = body.split_once('"')?;
+            (key, rest, true)
+        }
+        None => {
+            let i = body.find(':')?;
+            (&body[..i], &body[i..], false)
+        }
+    };
+    let value = rest.strip_prefix(':')?;
+    // 值在下面几行的（映射、列表）不处理
+    if !SECRET_KEYS.contains(&key) || value.trim().trim_end_matches(',').is_empty() {
+        return None;
+    }
+    let prefix = &line[..line.len() - value.len()];
+    let comma = if value.trim_end().ends_with(',') {
+        ","
+    } else {
+        ""
+    };
+    let mask = if quoted {
+        format!("\"{}\"", MASK)
+    } else {
+        format!("'{}'", MASK)
+    };
+    Some(format!("{} {}{}", prefix, mask, comma))
+}
+
+/// 配置文本（缩进的yaml或者格式化的json）中凭据字段的值替换为 ***，输出到终端时使用，总是生效
+pub fn redact_text(text: &str) -> Cow<'_, str> {
+    if !text.lines().any(|line| redact_line(line).is_some()) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        match redact_line(content) {
+            Some(redacted) => out.push_str(&redacted),
+            None => out.push_str(content),
+        }
+        out.push_str(newline);
+    }
+    Cow::Owned(out)
+}
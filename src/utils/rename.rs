@@ -0,0 +1,102 @@
+use crate::utils::{i18n::tr, model::Proxy};
+use fancy_regex::Regex as FancyRegex;
+
+/// 一条节点改名规则：把名称中匹配 pattern 的部分全部替换为 replacement（可以使用 $1 引用分组）
+#[derive(Debug, Clone)]
+pub struct RenameRule {
+    pub pattern: FancyRegex,
+    pub replacement: String,
+}
+
+/// 按分隔符切分 s/正则/替换/ 的各部分，`\/` 表示分隔符本身
+fn split_delimited(s: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek() == Some(&delimiter) {
+            parts.last_mut().unwrap().push(chars.next().unwrap());
+        } else if c == delimiter {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    parts
+}
+
+/// 解析 --rename 参数，支持 s/香港/HK/ 的写法（分隔符可以换成其它符号），也支持 subconverter 的 香港@HK 写法
+pub fn parse_rename_rule(s: &str) -> Result<RenameRule, String> {
+    let delimiter = s
+        .strip_prefix('s')
+        .and_then(|rest| rest.chars().next())
+        .filter(|c| !c.is_alphanumeric() && !c.is_whitespace() && *c != '\\');
+    let (pattern, replacement) = match delimiter {
+        Some(delimiter) => {
+            let parts = split_delimited(&s[1 + delimiter.len_utf8()..], delimiter);
+            // 末尾的分隔符可以省略
+            match parts.as_slice() {
+                [pattern, replacement] => (pattern.clone(), replacement.clone()),
+                [pattern, replacement, end] if end.is_empty() => {
+                    (pattern.clone(), replacement.clone())
+                }
+                _ => {
+                    return Err(tr!(
+                        "格式错误，应为 s/正则/替换/：{}",
+                        "invalid format, expected s/regex/replacement/: {}",
+                        s
+                    ));
+                }
+            }
+        }
+        None => {
+            let (pattern, replacement) = s.split_once('@').ok_or_else(|| {
+                tr!(
+                    "格式错误，应为 s/正则/替换/ 或者 正则@替换：{}",
+                    "invalid format, expected s/regex/replacement/ or regex@replacement: {}",
+                    s
+                )
+            })?;
+            (pattern.to_string(), replacement.to_string())
+        }
+    };
+    if pattern.is_empty() {
+        return Err(tr!(
+            "正则表达式不能为空：{}",
+            "the regex can't be empty: {}",
+            s
+        ));
+    }
+    let pattern = FancyRegex::new(&pattern).map_err(|e| {
+        tr!(
+            "无效的正则表达式 {}：{}",
+            "invalid regex {}: {}",
+            pattern,
+            e
+        )
+    })?;
+    Ok(RenameRule {
+        pattern,
+        replacement,
+    })
+}
+
+/// 按顺序对每个节点的名称执行改名规则，返回名称被修改的节点数量
+pub fn rename_proxies(proxies: &mut [Proxy], rules: &[RenameRule]) -> usize {
+    let mut renamed = 0;
+    for proxy in proxies.iter_mut() {
+        let Some(name) = proxy.name() else {
+            continue;
+        };
+        let new_name = rules.iter().fold(name.to_string(), |name, rule| {
+            rule.pattern
+                .replace_all(&name, rule.replacement.as_str())
+                .trim()
+                .to_string()
+        });
+        if new_name != name && !new_name.is_empty() {
+            proxy.set_name(new_name);
+            renamed += 1;
+        }
+    }
+    renamed
+}
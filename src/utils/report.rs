@@ -0,0 +1,232 @@
+use crate::{
+    build::{
+        conflict::{IssueKind, RuleIssue},
+        rules::RulesetReport,
+    },
+    utils::i18n::{self, tr},
+};
+use indexmap::IndexMap;
+
+/// 试运行（--dry-run）的统计报告
+#[derive(Debug, Default)]
+pub struct DryRunReport {
+    pub sources: Vec<(String, usize)>,        // 每个输入读取到的节点数
+    pub unique_nodes: usize,                  // 去重后的节点数
+    pub pages: usize,                         // 分页数
+    pub groups_kept: IndexMap<String, usize>, // 代理组名称 => 保留该分组的页数
+    pub rulesets: Vec<RulesetReport>,         // 每个规则集的处理情况
+    pub rules_count: usize,                   // 规则总数
+    pub estimated_bytes: usize,               // 预计输出的总大小
+}
+
+/// 转为便于阅读的文件大小
+pub fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.2} {}", size, UNITS[unit])
+    }
+}
+
+/// 输出每个规则集的来源、规则数、下载耗时和状态
+pub fn print_ruleset_table(reports: &[RulesetReport], rules_count: usize) {
+    if i18n::is_zh() {
+        println!(
+            "{:<6} {:>8} {:>9} {:<7} 规则集",
+            "来源", "规则数", "耗时", "状态"
+        );
+    } else {
+        println!(
+            "{:<8} {:>11} {:>11} {:<9} ruleset",
+            "source", "rules", "time", "status"
+        );
+    }
+    for report in reports {
+        let duration = report
+            .duration
+            .map(|d| format!("{:.2}s", d.as_secs_f64()))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<8} {:>11} {:>11} {:<9} {} ({})",
+            report.source.as_str(),
+            report.rules,
+            duration,
+            report.status.as_str(),
+            report.name,
+            report.path
+        );
+        if report.overridden > 0 {
+            println!(
+                "{}",
+                tr!(
+                    "{:>8}被优先级更高的规则集覆盖：{} 条",
+                    "{:>8}overridden by higher priority rulesets: {}",
+                    "",
+                    report.overridden
+                )
+            );
+        }
+    }
+    println!(
+        "{}",
+        tr!(
+            "规则去重后共：{} 条",
+            "rules after deduplication: {}",
+            rules_count
+        )
+    );
+}
+
+fn ruleset_source(reports: &[RulesetReport], index: Option<usize>) -> String {
+    index
+        .and_then(|i| reports.get(i))
+        .map(|r| format!("{} ({})", r.name, r.path))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// 输出规则冲突和被覆盖的规则，以及它们所在的规则集
+pub fn print_rule_issues(issues: &[RuleIssue], reports: &[RulesetReport]) {
+    let source = |index: Option<usize>| ruleset_source(reports, index);
+    let issues: Vec<&RuleIssue> = issues
+        .iter()
+        .filter(|i| i.kind != IssueKind::Overridden)
+        .collect();
+    for issue in &issues {
+        println!("[{}] {}  <=  {}", issue.kind.as_str(), issue.rule, issue.by);
+        println!(
+            "{}",
+            tr!(
+                "    规则集：{}  <=  {}",
+                "    rulesets: {}  <=  {}",
+                source(issue.rule_source),
+                source(issue.by_source)
+            )
+        );
+    }
+    println!(
+        "{}",
+        tr!(
+            "规则检测：发现 {} 个问题",
+            "rule check: {} issues found",
+            issues.len()
+        )
+    );
+}
+
+/// 输出按规则集优先级解决的冲突（相同内容的规则指向不同的代理组）：按 (丢弃的规则集, 保留的规则集) 汇总，
+/// 每组列出前几条
+pub fn print_resolved_conflicts(issues: &[RuleIssue], reports: &[RulesetReport]) {
+    const EXAMPLES: usize = 3;
+    let mut pairs: IndexMap<(Option<usize>, Option<usize>), Vec<&RuleIssue>> = IndexMap::new();
+    for issue in issues.iter().filter(|i| i.kind == IssueKind::Overridden) {
+        pairs
+            .entry((issue.rule_source, issue.by_source))
+            .or_default()
+            .push(issue);
+    }
+    if pairs.is_empty() {
+        return;
+    }
+    let total: usize = pairs.values().map(Vec::len).sum();
+    println!(
+        "{}",
+        tr!(
+            "规则冲突：按规则集的优先级解决了 {} 条",
+            "rule conflicts: {} resolved by ruleset priority",
+            total
+        )
+    );
+    for ((rule_source, by_source), list) in &pairs {
+        println!(
+            "{}",
+            tr!(
+                "  {}  <=  {}：{} 条",
+                "  {}  <=  {}: {}",
+                ruleset_source(reports, *rule_source),
+                ruleset_source(reports, *by_source),
+                list.len()
+            )
+        );
+        for issue in list.iter().take(EXAMPLES) {
+            println!("    {}  <=  {}", issue.rule, issue.by);
+        }
+        if list.len() > EXAMPLES {
+            println!("    ……");
+        }
+    }
+}
+
+impl DryRunReport {
+    pub fn print(&self) {
+        let total_read: usize = self.sources.iter().map(|(_, n)| n).sum();
+
+        println!(
+            "{}",
+            tr!(
+                "========== 试运行报告（未写入任何文件） ==========",
+                "========== Dry run report (no files written) =========="
+            )
+        );
+        println!("{}", tr!("读取节点：", "nodes read:"));
+        for (source, count) in &self.sources {
+            println!("{}", tr!("  {}：{} 个", "  {}: {}", source, count));
+        }
+        println!(
+            "{}",
+            tr!(
+                "节点合计：{} 个，去除重复：{} 个，剩余：{} 个，分为 {} 页",
+                "nodes: {} in total, {} duplicates removed, {} left, in {} pages",
+                total_read,
+                total_read.saturating_sub(self.unique_nodes),
+                self.unique_nodes,
+                self.pages
+            )
+        );
+
+        let dropped: Vec<(&String, &usize)> = self
+            .groups_kept
+            .iter()
+            .filter(|(_, kept)| **kept < self.pages)
+            .collect();
+        println!(
+            "{}",
+            tr!(
+                "代理组：共 {} 个，所有页都保留 {} 个，部分或全部页被移除 {} 个",
+                "groups: {} in total, {} kept on every page, {} removed from some or all pages",
+                self.groups_kept.len(),
+                self.groups_kept.len() - dropped.len(),
+                dropped.len()
+            )
+        );
+        for (name, kept) in dropped {
+            println!(
+                "{}",
+                tr!(
+                    "  {}：{} 页中被移除 {} 页",
+                    "  {}: removed from {2} of {1} pages",
+                    name,
+                    self.pages,
+                    self.pages - kept
+                )
+            );
+        }
+
+        print_ruleset_table(&self.rulesets, self.rules_count);
+
+        println!(
+            "{}",
+            tr!(
+                "预计输出大小：{}",
+                "estimated output size: {}",
+                human_size(self.estimated_bytes)
+            )
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use crate::utils::i18n::tr;
+use serde_yaml::{Mapping, Value as YamlValue};
+
+// 所有类型都可以使用的字段
+const COMMON_KEYS: [&str; 12] = [
+    "name",
+    "type",
+    "server",
+    "port",
+    "ip-version",
+    "udp",
+    "interface-name",
+    "routing-mark",
+    "tfo",
+    "mptcp",
+    "dialer-proxy",
+    "smux",
+];
+
+// TLS 相关的字段
+const TLS_KEYS: [&str; 11] = [
+    "tls",
+    "sni",
+    "servername",
+    "fingerprint",
+    "alpn",
+    "skip-cert-verify",
+    "client-fingerprint",
+    "reality-opts",
+    "ech-opts",
+    "certificate",
+    "private-key",
+];
+
+// 传输层相关的字段
+const TRANSPORT_KEYS: [&str; 6] = [
+    "network",
+    "ws-opts",
+    "h2-opts",
+    "http-opts",
+    "grpc-opts",
+    "xhttp-opts",
+];
+
+/// 每种类型：(类型, 必须有的字段, 特有的字段, 是否使用 TLS/传输层的字段)
+const PROXY_TYPES: [(&str, &[&str], &[&str], bool); 15] = [
+    (
+        "ss",
+        &["cipher", "password"],
+        &[
+            "cipher",
+            "password",
+            "udp-over-tcp",
+            "udp-over-tcp-version",
+            "plugin",
+            "plugin-opts",
+            "client-fingerprint",
+        ],
+        false,
+    ),
+    (
+        "ssr",
+        &["cipher", "password", "obfs", "protocol"],
+        &[
+            "cipher",
+            "password",
+            "obfs",
+            "protocol",
+            "obfs-param",
+            "protocol-param",
+        ],
+        false,
+    ),
+    (
+        "vmess",
+        &["uuid"],
+        &[
+            "uuid",
+            "alterId",
+            "cipher",
+            "packet-encoding",
+            "global-padding",
+            "authenticated-length",
+        ],
+        true,
+    ),
+    (
+        "vless",
+        &["uuid"],
+        &["uuid", "flow", "packet-encoding", "encryption"],
+        true,
+    ),
+    ("trojan", &["password"], &["password", "ss-opts"], true),
+    (
+        "hysteria",
+        &[],
+        &[
+            "ports",
+            "auth-str",
+            "auth",
+            "obfs",
+            "protocol",
+            "up",
+            "down",
+            "up-speed",
+            "down-speed",
+            "recv-window-conn",
+            "recv-window",
+            "disable_mtu_discovery",
+            "fast-open",
+            "hop-interval",
+            "ca",
+            "ca-str",
+        ],
+        true,
+    ),
+    (
+        "hysteria2",
+        &["password"],
+        &[
+            "ports",
+            "password",
+            "up",
+            "down",
+            "obfs",
+            "obfs-password",
+            "hop-interval",
+            "ca",
+            "ca-str",
+        ],
+        true,
+    ),
+    (
+        "tuic",
+        &[],
+        &[
+            "token",
+            "uuid",
+            "password",
+            "ip",
+            "heartbeat-interval",
+            "reduce-rtt",
+            "request-timeout",
+            "udp-relay-mode",
+            "congestion-controller",
+            "disable-sni",
+            "max-udp-relay-packet-size",
+            "fast-open",
+            "max-open-streams",
+            "cwnd",
+            "recv-window-conn",
+            "recv-window",
+            "max-datagram-frame-size",
+            "udp-over-stream",
+            "udp-over-stream-version",
+        ],
+        true,
+    ),
+    (
+        "wireguard",
+        &["private-key"],
+        &[
+            "ip",
+            "ipv6",
+            "private-key",
+            "public-key",
+            "pre-shared-key",
+            "reserved",
+            "workers",
+            "mtu",
+            "allowed-ips",
+            "peers",
+            "remote-dns-resolve",
+            "dns",
+            "amnezia-wg-option",
+            "persistent-keepalive",
+        ],
+        false,
+    ),
+    ("socks5", &[], &["username", "password"], true),
+    ("http", &[], &["username", "password", "headers"], true),
+    ("snell", &["psk"], &["psk", "version", "obfs-opts"], false),
+    (
+        "ssh",
+        &[],
+        &[
+            "username",
+            "password",
+            "private-key",
+            "private-key-passphrase",
+            "host-key",
+            "host-key-algorithms",
+        ],
+        false,
+    ),
+    (
+        "anytls",
+        &["password"],
+        &[
+            "password",
+            "idle-session-check-interval",
+            "idle-session-timeout",
+            "min-idle-session",
+        ],
+        true,
+    ),
+    (
+        "mieru",
+        &[],
+        &[
+            "port-range",
+            "transport",
+            "username",
+            "password",
+            "multiplexing",
+        ],
+        false,
+    ),
+];
+
+// 默认开启 udp 的类型（http、ssh 不支持 udp）
+const UDP_TYPES: [&str; 6] = ["ss", "ssr", "vmess", "vless", "trojan", "socks5"];
+
+/// 节点缺少的必要字段（server、port 和该类型必须有的字段），不认识的类型只检查 server、port
+pub fn missing_fields(proxy: &YamlValue) -> Vec<&'static str> {
+    let proxy_type = proxy
+        .get("type")
+        .and_then(YamlValue::as_str)
+        .map(|t| t.trim().to_lowercase())
+        .unwrap_or_default();
+    let required = PROXY_TYPES
+        .iter()
+        .find(|(t, ..)| *t == proxy_type)
+        .map_or(&[][..], |(_, required, ..)| *required);
+    let mut fields = vec!["server"];
+    if proxy_type != "wireguard" {
+        fields.push("port");
+    }
+    fields
+        .into_iter()
+        .chain(required.iter().copied())
+        .filter(|k| proxy.get(k).is_none_or(YamlValue::is_null))
+        .collect()
+}
+
+fn key(s: &str) -> YamlValue {
+    YamlValue::String(s.to_string())
+}
+
+/// 端口转为整数，字符串 "443" 也可以
+fn normalize_port(value: &YamlValue) -> Option<u16> {
+    match value {
+        YamlValue::Number(n) => n.as_u64().and_then(|p| u16::try_from(p).ok()),
+        YamlValue::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+    .filter(|p| *p > 0)
+}
+
+/// 修复一个节点，无法修复时返回错误原因
+fn sanitize_proxy(proxy: YamlValue) -> Result<YamlValue, String> {
+    let YamlValue::Mapping(mut map) = proxy else {
+        return Err(tr!("不是键值对", "not a mapping"));
+    };
+
+    let name = match map.get("name") {
+        Some(YamlValue::String(s)) if !s.trim().is_empty() => s.clone(),
+        Some(YamlValue::Number(n)) => n.to_string(),
+        _ => return Err(tr!("缺少 name", "missing name")),
+    };
+    map.insert(key("name"), YamlValue::String(name));
+
+    let proxy_type = map
+        .get("type")
+        .and_then(YamlValue::as_str)
+        .map(|t| t.trim().to_lowercase())
+        .ok_or_else(|| tr!("缺少 type", "missing type"))?;
+    let Some((_, required, own_keys, use_tls)) =
+        PROXY_TYPES.iter().find(|(t, ..)| *t == proxy_type)
+    else {
+        return Err(tr!("不支持的类型 {}", "unsupported type {}", proxy_type));
+    };
+    map.insert(key("type"), YamlValue::String(proxy_type.clone()));
+
+    match map.get("server").and_then(YamlValue::as_str) {
+        Some(server) if !server.trim().is_empty() => {}
+        _ => return Err(tr!("缺少 server", "missing server")),
+    }
+    // wireguard 可以不写 port（使用 peers），其它类型必须有
+    match map.get("port").map(normalize_port) {
+        Some(Some(port)) => {
+            map.insert(key("port"), YamlValue::Number(port.into()));
+        }
+        Some(None) => return Err(tr!("port 无效", "invalid port")),
+        None if proxy_type != "wireguard" => return Err(tr!("缺少 port", "missing port")),
+        None => {}
+    }
+    if let Some(missing) = required.iter().find(|k| !map.contains_key(**k)) {
+        return Err(tr!("缺少 {}", "missing {}", missing));
+    }
+
+    if UDP_TYPES.contains(&proxy_type.as_str()) && !map.contains_key("udp") {
+        map.insert(key("udp"), YamlValue::Bool(true));
+    }
+
+    // 去掉该类型不认识的字段
+    let is_known = |k: &str| {
+        COMMON_KEYS.contains(&k)
+            || own_keys.contains(&k)
+            || (*use_tls && (TLS_KEYS.contains(&k) || TRANSPORT_KEYS.contains(&k)))
+    };
+    let map: Mapping = map
+        .into_iter()
+        .filter(|(k, _)| k.as_str().is_some_and(is_known))
+        .collect();
+
+    Ok(YamlValue::Mapping(map))
+}
+
+/// 规范化合并后的节点：port 转为整数、type 转为小写、补全 udp、去掉不认识的字段，
+/// 结构无效的节点直接丢弃并输出警告，避免 mihomo 启动失败
+pub fn sanitize_proxies(proxies: Vec<YamlValue>) -> Vec<YamlValue> {
+    proxies
+        .into_iter()
+        .filter_map(|proxy| {
+            let name = proxy
+                .get("name")
+                .map(|n| {
+                    serde_yaml::to_string(n)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string()
+                })
+                .unwrap_or_else(|| tr!("(无名称)", "(no name)"));
+            match sanitize_proxy(proxy) {
+                Ok(proxy) => Some(proxy),
+                Err(reason) => {
+                    eprintln!(
+                        "{}",
+                        tr!(
+                            "丢弃无效的节点 {}：{}",
+                            "dropped invalid node {}: {}",
+                            name,
+                            reason
+                        )
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
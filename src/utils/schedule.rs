@@ -0,0 +1,128 @@
+use crate::utils::{exit::ErrorKind, i18n::tr};
+use std::{
+    future::Future,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// 解析时间间隔，支持 "90s"、"30m"、"6h"、"1d" 以及组合写法 "1h30m"，纯数字按秒处理
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(tr!("时间间隔不能为空", "the interval can't be empty"));
+    }
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => {
+                return Err(tr!(
+                    "无效的时间单位 '{}'，可选：s、m、h、d",
+                    "invalid time unit '{}', expected s, m, h or d",
+                    c
+                ));
+            }
+        };
+        let value: u64 = number
+            .parse()
+            .map_err(|_| tr!("无效的时间间隔：{}", "invalid interval: {}", s))?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|v| total.checked_add(v))
+            .ok_or_else(|| tr!("时间间隔太长：{}", "the interval is too long: {}", s))?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(tr!(
+            "时间间隔缺少单位：{}",
+            "the interval is missing a unit: {}",
+            s
+        ));
+    }
+    if total == 0 {
+        return Err(tr!(
+            "时间间隔必须大于0",
+            "the interval must be greater than 0"
+        ));
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// unix时间戳转为 UTC 日期，例如 2030-01-01
+pub fn format_date(timestamp: u64) -> String {
+    // 公历日期的换算（Howard Hinnant 的 civil_from_days 算法）
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// unix时间戳转为 UTC 时间，例如 2030-01-01_083000，按名称排序即按时间排序
+pub fn format_datetime(timestamp: u64) -> String {
+    let secs = timestamp % 86400;
+    format!(
+        "{}_{:02}{:02}{:02}",
+        format_date(timestamp),
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// 定时任务：每隔 interval 调用一次 task（返回本次的退出码），每一轮输出一行 key=value 格式的日志
+pub async fn run_every<F, Fut>(interval: Duration, mut task: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = i32>,
+{
+    let mut cycle: u64 = 0;
+    loop {
+        tokio::time::sleep(interval).await;
+        cycle += 1;
+
+        let started = unix_now();
+        let start_time = Instant::now();
+        println!("[daemon] cycle={} event=start ts={}", cycle, started);
+
+        let code = task().await;
+
+        println!(
+            "[daemon] cycle={} event=finish status={} code={} ts={} elapsed_ms={} next_in_s={}",
+            cycle,
+            match code {
+                0 => "ok",
+                code if code == ErrorKind::Partial.code() => "partial",
+                _ => "failed",
+            },
+            code,
+            unix_now(),
+            start_time.elapsed().as_millis(),
+            interval.as_secs()
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::utils::i18n::tr;
+use fancy_regex::Regex as FancyRegex;
+use rhai::{AST, Dynamic, Engine, Scope};
+use serde_yaml::Value as YamlValue;
+
+// 每个节点的脚本最多执行的操作数，避免死循环的脚本让构建卡住
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// --script 的节点转换脚本（rhai）：每个节点运行一次，变量 proxy 为节点（可以直接修改），index 为节点的序号（从0开始）；
+/// 脚本最后的值为 false 时丢弃这个节点，例如：
+/// ```rhai
+/// if proxy.name.contains("过期") { return false; }
+/// proxy.name = regex_replace(proxy.name, "^香港", "HK");
+/// proxy.udp = true;
+/// ```
+pub struct NodeScript {
+    engine: Engine,
+    ast: AST,
+}
+
+/// 脚本处理后的节点数量
+#[derive(Debug, Default)]
+pub struct ScriptStats {
+    pub changed: usize,
+    pub dropped: usize,
+}
+
+fn regex(pattern: &str) -> Result<FancyRegex, Box<rhai::EvalAltResult>> {
+    FancyRegex::new(pattern).map_err(|e| e.to_string().into())
+}
+
+impl NodeScript {
+    /// 读取并编译脚本文件，语法错误时返回错误信息
+    pub fn load(path: &str) -> Result<NodeScript, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        // 跟 --rename 一样使用正则（fancy-regex 的语法）
+        engine.register_fn(
+            "regex_match",
+            |text: &str, pattern: &str| -> Result<bool, Box<rhai::EvalAltResult>> {
+                regex(pattern)?
+                    .is_match(text)
+                    .map_err(|e| e.to_string().into())
+            },
+        );
+        engine.register_fn(
+            "regex_replace",
+            |text: &str,
+             pattern: &str,
+             replacement: &str|
+             -> Result<String, Box<rhai::EvalAltResult>> {
+                Ok(regex(pattern)?.replace_all(text, replacement).into_owned())
+            },
+        );
+        let ast = engine.compile_file(path.into()).map_err(|e| {
+            tr!(
+                "读取节点脚本失败 {}：{}",
+                "failed to load the node script {}: {}",
+                path,
+                e
+            )
+        })?;
+        Ok(NodeScript { engine, ast })
+    }
+
+    /// 对一个节点运行脚本，返回修改后的节点，脚本返回 false 时为 None
+    fn run(&self, proxy: &YamlValue, index: usize) -> Result<Option<YamlValue>, String> {
+        let name = proxy
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or_default();
+        let error = |e: &dyn std::fmt::Display| {
+            tr!(
+                "节点脚本处理 {} 出错：{}",
+                "the node script failed on {}: {}",
+                name,
+                e
+            )
+        };
+        let mut scope = Scope::new();
+        scope.push(
+            "proxy",
+            rhai::serde::to_dynamic(proxy).map_err(|e| error(&e))?,
+        );
+        scope.push("index", index as i64);
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| error(&e))?;
+        if result.as_bool() == Ok(false) {
+            return Ok(None);
+        }
+        let proxy: Dynamic = scope.get_value("proxy").unwrap_or_default();
+        match rhai::serde::from_dynamic(&proxy).map_err(|e| error(&e))? {
+            proxy @ YamlValue::Mapping(_) => Ok(Some(proxy)),
+            _ => Err(error(&tr!(
+                "proxy 必须是一个节点(对象)",
+                "proxy must stay a node (object map)"
+            ))),
+        }
+    }
+
+    /// 依次处理所有节点，去掉脚本丢弃的节点
+    pub fn apply(&self, proxies: Vec<YamlValue>) -> Result<(Vec<YamlValue>, ScriptStats), String> {
+        let mut stats = ScriptStats::default();
+        let mut kept = Vec::with_capacity(proxies.len());
+        for (index, proxy) in proxies.into_iter().enumerate() {
+            match self.run(&proxy, index)? {
+                Some(changed) => {
+                    if changed != proxy {
+                        stats.changed += 1;
+                    }
+                    kept.push(changed);
+                }
+                None => stats.dropped += 1,
+            }
+        }
+        Ok((kept, stats))
+    }
+}
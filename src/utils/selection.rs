@@ -0,0 +1,81 @@
+use crate::utils::{
+    exit::{ErrorKind, Failure},
+    i18n::tr,
+    proxy,
+};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value as YamlValue;
+use std::{collections::HashSet, fs, io, path::Path};
+
+/// 一个不要的节点，名称只是方便查看，按 key（类型 server:port）匹配
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExcludedNode {
+    pub key: String,
+    pub name: String,
+}
+
+/// 保存的节点选择（--interactive 中选择的结果），只记录不要的节点，之后订阅中新增的节点默认保留
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Selection {
+    pub excluded: Vec<ExcludedNode>,
+}
+
+/// 读取保存的选择，文件不存在时返回 None
+pub fn read_selection<P: AsRef<Path>>(path: P) -> Result<Option<Selection>, Failure> {
+    let Ok(data) = fs::read(&path) else {
+        return Ok(None);
+    };
+    serde_json::from_slice(&data).map(Some).map_err(|e| {
+        Failure::new(
+            ErrorKind::Config,
+            tr!(
+                "节点选择文件格式错误：{}，{}",
+                "invalid node selection file {}: {}",
+                path.as_ref().display(),
+                e
+            ),
+        )
+    })
+}
+
+pub fn write_selection<P: AsRef<Path>>(path: P, selection: &Selection) -> io::Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(selection).unwrap())
+}
+
+impl Selection {
+    /// 根据每个节点是否选中生成
+    pub fn from_checked(proxies: &[YamlValue], checked: &[bool]) -> Self {
+        let excluded = proxies
+            .iter()
+            .zip(checked)
+            .filter(|(_, checked)| !**checked)
+            .map(|(p, _)| ExcludedNode {
+                key: proxy::node_key(p),
+                name: p
+                    .get("name")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+            })
+            .collect();
+        Selection { excluded }
+    }
+
+    /// 节点是否被选中（没有记录为不要的）
+    pub fn is_selected(&self, proxy: &YamlValue) -> bool {
+        let key = proxy::node_key(proxy);
+        !self.excluded.iter().any(|e| e.key == key)
+    }
+
+    /// 去掉不要的节点，返回保留的节点和去掉的数量
+    pub fn apply(&self, proxies: Vec<YamlValue>) -> (Vec<YamlValue>, usize) {
+        let excluded: HashSet<&str> = self.excluded.iter().map(|e| e.key.as_str()).collect();
+        let total = proxies.len();
+        let kept: Vec<YamlValue> = proxies
+            .into_iter()
+            .filter(|p| !excluded.contains(proxy::node_key(p).as_str()))
+            .collect();
+        let dropped = total - kept.len();
+        (kept, dropped)
+    }
+}
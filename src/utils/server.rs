@@ -0,0 +1,203 @@
+use crate::utils::{
+    exit::{ErrorKind, Failure},
+    i18n::tr,
+    output::{self, Target},
+    proxy,
+};
+use std::{future::Future, net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// subconverter 的默认端口，客户端/面板只需要改主机名
+pub const DEFAULT_PORT: u16 = 25500;
+
+// 请求头的最大长度，只处理 GET 请求，不读取请求体
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// 解析 --listen 参数：地址:端口，只写端口时只监听本机(127.0.0.1)
+pub fn parse_listen(s: &str) -> Result<SocketAddr, String> {
+    let s = s.trim();
+    if let Ok(port) = s.trim_start_matches(':').parse::<u16>() {
+        return Ok(SocketAddr::from(([127, 0, 0, 1], port)));
+    }
+    s.parse().map_err(|_| {
+        tr!(
+            "无效的监听地址：{}，例如 127.0.0.1:{} 或者 {}",
+            "invalid listen address: {}, e.g. 127.0.0.1:{} or {}",
+            s,
+            DEFAULT_PORT,
+            DEFAULT_PORT
+        )
+    })
+}
+
+/// /sub 接口的一次转换请求
+#[derive(Debug, Clone)]
+pub struct SubRequest {
+    pub target: Target,
+    pub urls: Vec<String>,      // 订阅链接
+    pub links: Vec<String>,     // 直接写在 url 参数中的分享链接(ss://、vmess:// 等)
+    pub config: Option<String>, // ini 的链接，不写时使用 serve 的 -c/--preset
+}
+
+/// 解析 /sub 的查询参数，跟 subconverter 相同：target=clash&url=订阅链接(多个用 | 隔开)&config=ini的链接；
+/// 其它参数(emoji、list 等)被忽略，不能读取服务器上的本地文件
+pub fn parse_sub_query(query: &str) -> Result<SubRequest, String> {
+    let (mut target, mut url, mut config) = (None, None, None);
+    for (key, value) in form_pairs(query) {
+        match key.as_str() {
+            "target" => target = Some(value),
+            "url" => url = Some(value),
+            "config" => config = Some(value).filter(|v| !v.trim().is_empty()),
+            _ => {}
+        }
+    }
+    let target = match target.as_deref().map(str::trim) {
+        Some("clash" | "clashr" | "mihomo" | "clash.meta" | "clashmeta") => Target::Mihomo,
+        Some(other) => output::parse_target(other)?,
+        None => return Err(tr!("缺少参数 target", "missing parameter: target")),
+    };
+    let url = url.ok_or_else(|| tr!("缺少参数 url", "missing parameter: url"))?;
+    let (mut urls, mut links) = (Vec::new(), Vec::new());
+    for part in url.split('|').map(str::trim).filter(|s| !s.is_empty()) {
+        if proxy::is_url(part) {
+            urls.push(part.to_string());
+        } else if part.contains("://") {
+            links.push(part.to_string());
+        } else {
+            return Err(tr!(
+                "url 只支持订阅链接和分享链接：{}",
+                "url only accepts subscription and share links: {}",
+                part
+            ));
+        }
+    }
+    if urls.is_empty() && links.is_empty() {
+        return Err(tr!("参数 url 不能为空", "the url parameter can't be empty"));
+    }
+    if let Some(config) = config.as_deref().filter(|c| !proxy::is_url(c)) {
+        return Err(tr!(
+            "config 只支持ini的链接：{}",
+            "config only accepts ini links: {}",
+            config
+        ));
+    }
+    Ok(SubRequest {
+        target,
+        urls,
+        links,
+        config,
+    })
+}
+
+/// application/x-www-form-urlencoded 格式的查询参数（+ 为空格，%XX 解码）
+fn form_pairs(query: &str) -> Vec<(String, String)> {
+    reqwest::Url::parse(&format!("http://localhost/?{}", query))
+        .map(|url| url.query_pairs().into_owned().collect())
+        .unwrap_or_default()
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text(status: &'static str, body: String) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+}
+
+/// 读取请求行，返回 (方法, 路径带查询参数)
+async fn read_request_line(stream: &mut TcpStream) -> Option<(String, String)> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !data.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 || data.len() + n > MAX_HEADER_SIZE {
+            return None;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    let text = String::from_utf8_lossy(&data);
+    let mut parts = text.lines().next()?.split_whitespace();
+    Some((parts.next()?.to_string(), parts.next()?.to_string()))
+}
+
+async fn handle<F, Fut>(mut stream: TcpStream, peer: SocketAddr, handler: Arc<F>)
+where
+    F: Fn(SubRequest) -> Fut,
+    Fut: Future<Output = Result<String, String>>,
+{
+    let Some((method, target)) = read_request_line(&mut stream).await else {
+        return;
+    };
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let response = match (method.as_str(), path) {
+        ("GET", "/sub") => match parse_sub_query(query) {
+            Ok(request) => match handler(request).await {
+                Ok(config) => Response {
+                    status: "200 OK",
+                    content_type: "text/yaml; charset=utf-8",
+                    body: config,
+                },
+                Err(e) => Response::text("500 Internal Server Error", e),
+            },
+            Err(e) => Response::text("400 Bad Request", e),
+        },
+        // 面板用来检测后端是否可用
+        ("GET", "/version") => Response::text(
+            "200 OK",
+            format!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        ),
+        ("GET", _) => Response::text("404 Not Found", "Not Found".to_string()),
+        _ => Response::text("405 Method Not Allowed", "Method Not Allowed".to_string()),
+    };
+    println!("[serve] {} {} {} {}", peer, method, path, response.status);
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(response.body.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// 启动 HTTP 服务，提供跟 subconverter 兼容的 GET /sub 接口，每个请求调用一次 handler(返回生成的配置)；
+/// 只在无法监听时返回
+pub async fn listen<F, Fut>(addr: SocketAddr, handler: F) -> Result<(), Failure>
+where
+    F: Fn(SubRequest) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<String, String>> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr).await.map_err(|e| {
+        Failure::new(
+            ErrorKind::Config,
+            tr!("监听 {} 失败：{}", "failed to listen on {}: {}", addr, e),
+        )
+    })?;
+    println!(
+        "{}",
+        tr!(
+            "转换接口：http://{}/sub?target=clash&url=...",
+            "conversion API: http://{}/sub?target=clash&url=...",
+            listener.local_addr().unwrap_or(addr)
+        )
+    );
+    let handler = Arc::new(handler);
+    loop {
+        let Ok((stream, peer)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(handle(stream, peer, handler.clone()));
+    }
+}
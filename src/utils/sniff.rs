@@ -0,0 +1,126 @@
+use crate::utils::{
+    i18n::tr,
+    links,
+    validate::{self, Malformed},
+};
+use serde_json::Value as JsonValue;
+use serde_yaml::Value as YamlValue;
+
+/// 订阅内容的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Yaml,   // clash 配置（可以有多个文档），或者只有节点列表
+    Json,   // {"proxies": [...]} 或者节点数组
+    Base64, // base64 编码的分享链接，每行一个
+    Lines,  // 分享链接，每行一个
+}
+
+impl InputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InputFormat::Yaml => "yaml",
+            InputFormat::Json => "json",
+            InputFormat::Base64 => "base64",
+            InputFormat::Lines => "分享链接",
+        }
+    }
+
+    /// 日志中显示的名称，跟 --lang 的语言一致（as_str 的值也用在 stats 的 json 中，不随语言变化）
+    pub fn label(&self) -> String {
+        match self {
+            InputFormat::Lines => tr!("分享链接", "share links"),
+            _ => self.as_str().to_string(),
+        }
+    }
+}
+
+/// 第一个非空、非注释的行是分享链接
+fn starts_with_link(content: &str) -> bool {
+    content
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .is_some_and(links::is_share_link)
+}
+
+/// 根据内容识别订阅的格式
+pub fn sniff_format(content: &str) -> InputFormat {
+    let trimmed = content.trim();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<JsonValue>(trimmed).is_ok()
+    {
+        InputFormat::Json
+    } else if starts_with_link(trimmed) {
+        InputFormat::Lines
+    } else if !trimmed.is_empty()
+        && trimmed
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+/=-_".contains(c) || c.is_whitespace())
+        && links::decode_base64(trimmed)
+            .and_then(|data| String::from_utf8(data).ok())
+            .is_some_and(|text| starts_with_link(&text))
+    {
+        InputFormat::Base64
+    } else {
+        InputFormat::Yaml
+    }
+}
+
+/// 每行一个分享链接，转为节点
+fn extract_links(content: &str) -> (Vec<YamlValue>, Vec<Malformed>) {
+    let mut entries = Vec::new();
+    let mut malformed = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match links::parse_link(line)
+            .and_then(|node| validate::check_entry(node, Some(i + 1)).map_err(|m| m.reason))
+        {
+            Ok(node) => entries.push(node),
+            Err(reason) => malformed.push(Malformed {
+                line: Some(i + 1),
+                reason,
+            }),
+        }
+    }
+    (entries, malformed)
+}
+
+/// 按识别到的格式解析订阅内容中的节点（yaml/json 为 field_name 字段的列表），跳过无效的节点
+pub fn extract_entries(
+    content: &str,
+    field_name: &str,
+) -> (InputFormat, Vec<YamlValue>, Vec<Malformed>) {
+    let format = sniff_format(content);
+    let (entries, malformed) = match format {
+        InputFormat::Yaml => validate::extract_entries(content, field_name),
+        InputFormat::Json => {
+            let json: JsonValue = serde_json::from_str(content.trim()).unwrap();
+            let items = match serde_yaml::to_value(json).unwrap() {
+                YamlValue::Sequence(items) => items,
+                doc => match doc.get(field_name) {
+                    Some(YamlValue::Sequence(items)) => items.clone(),
+                    Some(other) => vec![other.clone()],
+                    None => Vec::new(),
+                },
+            };
+            let mut entries = Vec::new();
+            let mut malformed = Vec::new();
+            for item in items {
+                match validate::check_entry(item, None) {
+                    Ok(entry) => entries.push(entry),
+                    Err(m) => malformed.push(m),
+                }
+            }
+            (entries, malformed)
+        }
+        InputFormat::Base64 => {
+            let data = links::decode_base64(content).unwrap();
+            extract_links(&String::from_utf8_lossy(&data))
+        }
+        InputFormat::Lines => extract_links(content),
+    };
+    (format, entries, malformed)
+}
@@ -0,0 +1,110 @@
+use crate::{
+    build::ini as MyIni,
+    utils::{paginate, proxy},
+};
+use serde_yaml::Value as YamlValue;
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+/// 节点用于拆分的值：type（协议类型）、region（根据名称识别的地区），
+/// 或者用点号分隔的字段路径，例如 network、ws-opts.path，没有该字段时为 unknown
+pub fn field_value(proxy: &YamlValue, selector: &str) -> String {
+    if selector == "region" {
+        return proxy::region_of(proxy).unwrap_or("other").to_string();
+    }
+    let mut value = proxy;
+    for key in selector.split('.') {
+        match value.get(key) {
+            Some(v) => value = v,
+            None => return "unknown".to_string(),
+        }
+    }
+    match value {
+        YamlValue::String(s) if selector == "type" => s.to_lowercase(),
+        YamlValue::String(s) => s.clone(),
+        YamlValue::Number(n) => n.to_string(),
+        YamlValue::Bool(b) => b.to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+/// 代理组中只保留还存在的节点和代理组，没有剩下任何节点时放入 DIRECT（避免客户端拒绝加载）
+fn prune_groups(config: &mut YamlValue, names: &HashSet<&str>) {
+    let Some(groups) = config
+        .get_mut("proxy-groups")
+        .and_then(|v| v.as_sequence_mut())
+    else {
+        return;
+    };
+    let group_names: HashSet<String> = groups
+        .iter()
+        .filter_map(|g| g.get("name").and_then(|v| v.as_str()))
+        .map(str::to_string)
+        .collect();
+    for group in groups {
+        let has_use = group.get("use").is_some();
+        let Some(members) = group.get_mut("proxies").and_then(|v| v.as_sequence_mut()) else {
+            continue;
+        };
+        members.retain(|m| {
+            m.as_str().is_some_and(|m| {
+                names.contains(m) || group_names.contains(m) || MyIni::is_special_proxy(m)
+            })
+        });
+        if members.is_empty() && !has_use {
+            members.push(YamlValue::String("DIRECT".to_string()));
+        }
+    }
+}
+
+/// 按节点字段的值拆分配置：每个值一份配置，只含有该值的节点，其它内容（代理组、规则等）保持不变
+///
+/// 返回 (字段的值, 配置, 节点数)，按值第一次出现的顺序
+pub fn split_config(config: &YamlValue, selector: &str) -> Vec<(String, YamlValue, usize)> {
+    let proxies: Vec<YamlValue> = config
+        .get("proxies")
+        .and_then(|v| v.as_sequence())
+        .cloned()
+        .unwrap_or_default();
+
+    paginate::group_by(proxies, |p| field_value(p, selector))
+        .into_iter()
+        .map(|(key, nodes)| {
+            let mut part = config.clone();
+            {
+                let names: HashSet<&str> = nodes
+                    .iter()
+                    .filter_map(|p| p.get("name").and_then(|v| v.as_str()))
+                    .collect();
+                prune_groups(&mut part, &names);
+            }
+            let count = nodes.len();
+            part["proxies"] = YamlValue::Sequence(nodes);
+            (key, part, count)
+        })
+        .collect()
+}
+
+/// 拆分后的文件名，例如 split.yaml + vmess => split_vmess.yaml，值中不能用于文件名的字符改为 _
+pub fn split_filename<P: AsRef<Path>>(base_path: P, key: &str) -> PathBuf {
+    let base = base_path.as_ref();
+    let file_stem = base.file_stem().and_then(OsStr::to_str).unwrap_or("file");
+    let key: String = key
+        .chars()
+        .map(|c| {
+            if c.is_control() || "/\\:*?\"<>| ".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let name = match base.extension().and_then(OsStr::to_str) {
+        Some(ext) => format!("{}_{}.{}", file_stem, key, ext),
+        None => format!("{}_{}", file_stem, key),
+    };
+    base.with_file_name(name)
+}
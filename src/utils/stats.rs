@@ -0,0 +1,142 @@
+use crate::utils::{
+    exit::Failure,
+    i18n::{self, tr},
+    proxy, redact, sanitize, sniff,
+};
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_yaml::Value as YamlValue;
+use std::{cmp::Reverse, collections::HashSet};
+
+/// 缺少必要字段的节点
+#[derive(Debug, Serialize)]
+pub struct MissingFields {
+    pub name: String,
+    pub fields: Vec<&'static str>,
+}
+
+/// 一个输入的节点统计
+#[derive(Debug, Serialize)]
+pub struct SourceStats {
+    pub source: String,
+    pub format: &'static str,
+    pub total: usize,
+    pub duplicates: usize, // 类型和地址跟前面的节点相同
+    pub malformed: usize,  // 无法解析或结构无效，读取时已跳过
+    pub missing_fields: Vec<MissingFields>,
+    pub protocols: IndexMap<String, usize>, // 按数量从多到少
+    pub regions: IndexMap<String, usize>,   // 按数量从多到少，没有识别出地区的为 "-"
+}
+
+fn sorted_counts(counts: IndexMap<String, usize>) -> IndexMap<String, usize> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by_key(|(_, count)| Reverse(*count));
+    counts.into_iter().collect()
+}
+
+/// 读取一个输入并统计（不合并、不去重，也不做过滤和改名）
+pub async fn source_stats(path: &str, cap: Option<usize>) -> Result<SourceStats, Failure> {
+    let (raw_bytes, _) = proxy::read_source(path).await?;
+    let content = proxy::decode_content(&raw_bytes);
+    let (format, mut entries, malformed) = sniff::extract_entries(&content, "proxies");
+    if let Some(cap) = cap {
+        entries.truncate(cap);
+    }
+
+    let mut seen = HashSet::new();
+    let mut duplicates = 0;
+    let mut missing_fields = Vec::new();
+    let mut protocols = IndexMap::new();
+    let mut regions = IndexMap::new();
+    for entry in &entries {
+        if !seen.insert(proxy::node_key(entry)) {
+            duplicates += 1;
+        }
+        let fields = sanitize::missing_fields(entry);
+        if !fields.is_empty() {
+            missing_fields.push(MissingFields {
+                name: field_str(entry, "name"),
+                fields,
+            });
+        }
+        *protocols.entry(field_str(entry, "type")).or_insert(0) += 1;
+        *regions
+            .entry(proxy::region_of(entry).unwrap_or("-").to_string())
+            .or_insert(0) += 1;
+    }
+    Ok(SourceStats {
+        source: redact::source(path).into_owned(),
+        format: format.as_str(),
+        total: entries.len(),
+        duplicates,
+        malformed: malformed.len(),
+        missing_fields,
+        protocols: sorted_counts(protocols),
+        regions: sorted_counts(regions),
+    })
+}
+
+fn field_str(proxy: &YamlValue, key: &str) -> String {
+    proxy
+        .get(key)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn join_counts(counts: &IndexMap<String, usize>) -> String {
+    counts
+        .iter()
+        .map(|(key, count)| format!("{} {}", key, count))
+        .collect::<Vec<_>>()
+        .join("，")
+}
+
+/// 表格形式输出：先是每个输入的汇总，然后是各自的协议、地区分布和缺少字段的节点
+pub fn print_stats_table(stats: &[SourceStats]) {
+    if i18n::is_zh() {
+        println!(
+            "{:>6} {:>6} {:>6} {:>6}  {:<8} 输入",
+            "节点", "重复", "缺字段", "无效", "格式"
+        );
+    } else {
+        println!(
+            "{:>8} {:>8} {:>9} {:>8}  {:<10} input",
+            "nodes", "dups", "missing", "invalid", "format"
+        );
+    }
+    for s in stats {
+        println!(
+            "{:>8} {:>8} {:>9} {:>8}  {:<10} {}",
+            s.total,
+            s.duplicates,
+            s.missing_fields.len(),
+            s.malformed,
+            s.format,
+            s.source
+        );
+    }
+    for s in stats {
+        println!();
+        println!("{}", s.source);
+        println!(
+            "{}",
+            tr!("  协议：{}", "  protocols: {}", join_counts(&s.protocols))
+        );
+        println!(
+            "{}",
+            tr!("  地区：{}", "  regions: {}", join_counts(&s.regions))
+        );
+        for missing in &s.missing_fields {
+            println!(
+                "{}",
+                tr!(
+                    "  缺少 {}：{}",
+                    "  missing {}: {}",
+                    missing.fields.join(if i18n::is_zh() { "、" } else { ", " }),
+                    missing.name
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::utils::{
+    i18n::{self, tr},
+    proxy,
+};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, Paragraph, Row, Table, TableState},
+};
+use serde_yaml::Value as YamlValue;
+use std::{collections::HashSet, io};
+
+// PageUp/PageDown 一次移动的行数
+const PAGE: usize = 20;
+
+/// 列表中的一行（一个节点）
+pub struct NodeRow {
+    pub name: String,
+    pub region: String,
+    pub proto: String,
+    pub address: String,
+    pub duplicate: bool, // 跟前面的节点类型和地址都相同
+}
+
+/// 合并后的节点转为列表的行
+pub fn node_rows(proxies: &[YamlValue]) -> Vec<NodeRow> {
+    let mut seen = HashSet::new();
+    proxies
+        .iter()
+        .map(|p| {
+            let field = |key: &str| {
+                p.get(key)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            NodeRow {
+                name: field("name"),
+                region: proxy::region_of(p).unwrap_or("-").to_string(),
+                proto: field("type"),
+                address: proxy::stable_key(p),
+                duplicate: !seen.insert(proxy::node_key(p)),
+            }
+        })
+        .collect()
+}
+
+/// 在终端中勾选要放入配置的节点，返回每个节点是否选中，取消（q/Esc）时返回 None
+pub fn select_nodes(rows: &[NodeRow], checked: Vec<bool>) -> io::Result<Option<Vec<bool>>> {
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, rows, checked);
+    ratatui::restore();
+    result
+}
+
+fn run_app(
+    terminal: &mut DefaultTerminal,
+    rows: &[NodeRow],
+    mut checked: Vec<bool>,
+) -> io::Result<Option<Vec<bool>>> {
+    let mut state = TableState::default().with_selected(Some(0));
+    let last = rows.len().saturating_sub(1);
+    loop {
+        terminal.draw(|frame| draw(frame, rows, &checked, &mut state))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let current = state.selected().unwrap_or(0).min(last);
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => state.select(Some(current.saturating_sub(1))),
+            KeyCode::Down | KeyCode::Char('j') => state.select(Some((current + 1).min(last))),
+            KeyCode::PageUp => state.select(Some(current.saturating_sub(PAGE))),
+            KeyCode::PageDown => state.select(Some((current + PAGE).min(last))),
+            KeyCode::Home => state.select(Some(0)),
+            KeyCode::End => state.select(Some(last)),
+            KeyCode::Char(' ') => {
+                if let Some(c) = checked.get_mut(current) {
+                    *c = !*c;
+                }
+                state.select(Some((current + 1).min(last)));
+            }
+            KeyCode::Char('a') => checked.iter_mut().for_each(|c| *c = true),
+            KeyCode::Char('n') => checked.iter_mut().for_each(|c| *c = false),
+            KeyCode::Char('d') => rows
+                .iter()
+                .zip(checked.iter_mut())
+                .filter(|(row, _)| row.duplicate)
+                .for_each(|(_, c)| *c = false),
+            KeyCode::Enter => return Ok(Some(checked)),
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, rows: &[NodeRow], checked: &[bool], state: &mut TableState) {
+    let [table_area, help_area] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+
+    let selected = checked.iter().filter(|c| **c).count();
+    let table_rows = rows.iter().zip(checked).map(|(row, checked)| {
+        Row::new([
+            if *checked { "[x]" } else { "[ ]" },
+            row.name.as_str(),
+            row.region.as_str(),
+            row.proto.as_str(),
+            row.address.as_str(),
+            if !row.duplicate {
+                ""
+            } else if i18n::is_zh() {
+                "重复"
+            } else {
+                "dup"
+            },
+        ])
+    });
+    let table = Table::new(
+        table_rows,
+        [
+            Constraint::Length(3),
+            Constraint::Fill(1),
+            Constraint::Length(6),
+            Constraint::Length(12),
+            Constraint::Length(30),
+            Constraint::Length(4),
+        ],
+    )
+    .header(
+        Row::new(if i18n::is_zh() {
+            ["", "名称", "地区", "协议", "地址", ""]
+        } else {
+            ["", "name", "region", "proto", "address", ""]
+        })
+        .style(Style::new().add_modifier(Modifier::BOLD)),
+    )
+    .block(Block::bordered().title(tr!(
+        " 选择节点：已选 {} / {} ",
+        " Select nodes: {} / {} selected ",
+        selected,
+        rows.len()
+    )))
+    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    .highlight_symbol("> ");
+    frame.render_stateful_widget(table, table_area, state);
+
+    frame.render_widget(
+        Paragraph::new(tr!(
+            "↑↓ 移动  空格 选择/取消  a 全选  n 全不选  d 取消重复的节点  Enter 确认  q 取消构建",
+            "↑↓ move  space toggle  a all  n none  d unselect duplicates  Enter confirm  q cancel build"
+        )),
+        help_area,
+    );
+}
@@ -0,0 +1,121 @@
+use crate::utils::{i18n, report::human_size, schedule::format_date};
+use serde::Serialize;
+use serde_yaml::{Mapping, Value as YamlValue};
+
+/// 订阅链接响应头 subscription-userinfo 中的流量信息（单位：字节，到期时间为unix时间戳）
+///
+/// 例如：upload=1024; download=2048; total=1073741824; expire=1893456000
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriptionInfo {
+    pub upload: u64,
+    pub download: u64,
+    pub total: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire: Option<u64>,
+}
+
+/// 解析 subscription-userinfo 响应头，没有任何已知的字段时返回 None
+pub fn parse_userinfo(header: &str) -> Option<SubscriptionInfo> {
+    let mut info = SubscriptionInfo::default();
+    let mut found = false;
+    for field in header.split(';') {
+        let Some((key, value)) = field.split_once('=') else {
+            continue;
+        };
+        // 有的机场返回小数，例如 total=1.073741824E9
+        let value = value.trim();
+        let Some(value) = value
+            .parse::<u64>()
+            .ok()
+            .or_else(|| value.parse::<f64>().ok().map(|v| v.max(0.0) as u64))
+        else {
+            continue;
+        };
+        match key.trim().to_lowercase().as_str() {
+            "upload" => info.upload = value,
+            "download" => info.download = value,
+            "total" => info.total = value,
+            "expire" => info.expire = (value > 0).then_some(value),
+            _ => continue,
+        }
+        found = true;
+    }
+    found.then_some(info)
+}
+
+impl SubscriptionInfo {
+    /// 剩余流量
+    pub fn remaining(&self) -> u64 {
+        self.total.saturating_sub(self.upload + self.download)
+    }
+
+    /// 合并多个订阅的流量信息：流量相加，到期时间取最早的
+    pub fn merge<'a>(infos: impl IntoIterator<Item = &'a SubscriptionInfo>) -> Option<Self> {
+        infos.into_iter().fold(None, |acc, info| {
+            let mut acc: SubscriptionInfo = acc.unwrap_or_default();
+            acc.upload += info.upload;
+            acc.download += info.download;
+            acc.total += info.total;
+            acc.expire = match (acc.expire, info.expire) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            Some(acc)
+        })
+    }
+
+    /// 转回响应头的格式，方便再提供给客户端
+    pub fn to_header(&self) -> String {
+        let mut header = format!(
+            "upload={}; download={}; total={}",
+            self.upload, self.download, self.total
+        );
+        if let Some(expire) = self.expire {
+            header.push_str(&format!("; expire={}", expire));
+        }
+        header
+    }
+
+    /// 便于阅读的描述，例如：剩余流量：1.00 GB | 到期：2030-01-01（用作信息节点的名称，不随 --lang 变化）
+    pub fn describe(&self) -> String {
+        let mut text = format!("剩余流量：{}", human_size(self.remaining() as usize));
+        if let Some(expire) = self.expire {
+            text.push_str(&format!(" | 到期：{}", format_date(expire)));
+        }
+        text
+    }
+
+    /// 日志中的描述，跟 --lang 的语言一致
+    pub fn summary(&self) -> String {
+        if i18n::is_zh() {
+            return self.describe();
+        }
+        let mut text = format!("remaining: {}", human_size(self.remaining() as usize));
+        if let Some(expire) = self.expire {
+            text.push_str(&format!(" | expires: {}", format_date(expire)));
+        }
+        text
+    }
+}
+
+/// 是否为 info_node 生成的信息节点
+pub fn is_info_node(proxy: &YamlValue) -> bool {
+    proxy.get("server").and_then(|v| v.as_str()) == Some("127.0.0.1")
+        && proxy.get("port").and_then(|v| v.as_u64()) == Some(1)
+        && proxy.get("password").and_then(|v| v.as_str()) == Some("info")
+}
+
+/// 显示剩余流量和到期时间的信息节点（跟 subconverter 一样，只用于在客户端中查看，不能连接）
+pub fn info_node(info: &SubscriptionInfo) -> YamlValue {
+    let mut node = Mapping::new();
+    let mut insert = |key: &str, value: YamlValue| {
+        node.insert(YamlValue::String(key.to_string()), value);
+    };
+    insert("name", YamlValue::String(info.describe()));
+    insert("type", YamlValue::String("ss".to_string()));
+    insert("server", YamlValue::String("127.0.0.1".to_string()));
+    insert("port", YamlValue::Number(1.into()));
+    insert("cipher", YamlValue::String("aes-128-gcm".to_string()));
+    insert("password", YamlValue::String("info".to_string()));
+    YamlValue::Mapping(node)
+}
@@ -0,0 +1,205 @@
+use crate::utils::{i18n::tr, model, read, redact};
+use serde::Deserialize;
+use serde_yaml::{Deserializer, Value as YamlValue};
+
+/// 一个无效的节点
+pub struct Malformed {
+    pub line: Option<usize>, // 所在的行号（从 1 开始），找不到时为 None
+    pub reason: String,
+}
+
+impl Malformed {
+    pub fn describe(&self) -> String {
+        match self.line {
+            Some(line) => tr!("第 {} 行：{}", "line {}: {}", line, self.reason),
+            None => self.reason.clone(),
+        }
+    }
+}
+
+/// 字段（例如 proxies）的列表中每一项所在的行（从 0 开始，不含结束行），只识别块格式的列表
+fn entry_ranges(lines: &[&str], field_name: &str) -> Vec<(usize, usize)> {
+    let header = format!("{}:", field_name);
+    let Some(start) = lines.iter().position(|l| l.trim_end() == header) else {
+        return Vec::new();
+    };
+    let mut indent = None;
+    let mut starts = Vec::new();
+    let mut end = lines.len();
+    for (i, line) in lines.iter().enumerate().skip(start + 1) {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let n = line.len() - trimmed.len();
+        let is_item = trimmed == "-" || trimmed.starts_with("- ");
+        match indent {
+            None if is_item => indent = Some(n),
+            Some(m) if n > m => continue,
+            Some(m) if n == m && is_item => {}
+            _ => {
+                end = i;
+                break;
+            }
+        }
+        starts.push(i);
+    }
+    starts
+        .iter()
+        .enumerate()
+        .map(|(k, &s)| (s, starts.get(k + 1).copied().unwrap_or(end)))
+        .collect()
+}
+
+/// serde_yaml 的错误去掉位置信息，位置另外以行号输出
+fn yaml_error_reason(e: &serde_yaml::Error) -> String {
+    let message = e.to_string();
+    let reason = message.split(" at line ").next().unwrap_or(&message);
+    tr!("yaml格式错误：{}", "invalid yaml: {}", reason)
+}
+
+/// 检查节点：必须是映射，name、type 为字符串，port 为 1-65535 的数字，不能有自定义标签
+fn check_proxy(proxy: &YamlValue) -> Result<(), String> {
+    if let Some(tag) = find_tag(proxy) {
+        return Err(tr!("不支持的标签：{}", "unsupported tag: {}", tag));
+    }
+    let YamlValue::Mapping(map) = proxy else {
+        return Err(tr!(
+            "不是一个节点：{}",
+            "not a node: {}",
+            describe_value(proxy)
+        ));
+    };
+    let name = match map.get("name") {
+        Some(YamlValue::String(s)) if !s.trim().is_empty() => s,
+        Some(other) => {
+            return Err(tr!(
+                "name 无效：{}",
+                "invalid name: {}",
+                describe_value(other)
+            ));
+        }
+        None => return Err(tr!("缺少 name", "missing name")),
+    };
+    match map.get("type") {
+        Some(YamlValue::String(_)) => {}
+        Some(other) => {
+            return Err(tr!(
+                "{} 的 type 无效：{}",
+                "{} has an invalid type: {}",
+                name,
+                describe_value(other)
+            ));
+        }
+        None => return Err(tr!("{} 缺少 type", "{} is missing type", name)),
+    }
+    if let Some(port) = map.get("port")
+        && model::parse_port(port).is_none_or(|p| p == 0)
+    {
+        return Err(tr!(
+            "{} 的 port 无效：{}",
+            "{} has an invalid port: {}",
+            name,
+            describe_value(port)
+        ));
+    }
+    Ok(())
+}
+
+/// 节点中的第一个自定义标签（例如 !<tag:example.com,2000:x>）
+fn find_tag(value: &YamlValue) -> Option<String> {
+    match value {
+        YamlValue::Tagged(tagged) => Some(tagged.tag.to_string()),
+        YamlValue::Sequence(items) => items.iter().find_map(find_tag),
+        YamlValue::Mapping(map) => map.values().find_map(find_tag),
+        _ => None,
+    }
+}
+
+fn describe_value(value: &YamlValue) -> String {
+    serde_yaml::to_string(&*redact::for_log(value))
+        .map(|s| s.trim_end().replace('\n', " "))
+        .unwrap_or_default()
+}
+
+/// 展开合并键并检查一个节点
+pub fn check_entry(mut entry: YamlValue, line: Option<usize>) -> Result<YamlValue, Malformed> {
+    read::apply_merge_keys(&mut entry)
+        .and_then(|_| check_proxy(&entry))
+        .map_err(|reason| Malformed { line, reason })?;
+    Ok(entry)
+}
+
+/// 解析订阅内容中某个字段（例如 proxies）的列表，跳过无效的节点
+///
+/// 整个文件无法解析时，列表中的每一项单独解析（这时不能引用其它位置定义的锚点）
+pub fn extract_entries(content: &str, field_name: &str) -> (Vec<YamlValue>, Vec<Malformed>) {
+    let lines: Vec<&str> = content.lines().collect();
+    let ranges = entry_ranges(&lines, field_name);
+    let mut entries = Vec::new();
+    let mut malformed = Vec::new();
+
+    let docs: Result<Vec<YamlValue>, _> = Deserializer::from_str(content)
+        .map(YamlValue::deserialize)
+        .collect();
+    match docs {
+        Ok(docs) => {
+            let mut index = 0;
+            for doc in docs {
+                let items = match doc {
+                    // 只有节点列表的文件
+                    YamlValue::Sequence(seq) => seq,
+                    doc => match doc.get(field_name) {
+                        Some(YamlValue::Sequence(seq)) => seq.clone(),
+                        Some(other) => vec![other.clone()],
+                        None => continue,
+                    },
+                };
+                // 行号只对应第一个有该字段的文档
+                let doc_ranges = if index == 0 && ranges.len() == items.len() {
+                    ranges.as_slice()
+                } else {
+                    &[]
+                };
+                index += 1;
+                for (i, item) in items.into_iter().enumerate() {
+                    let line = doc_ranges.get(i).map(|(start, _)| start + 1);
+                    match check_entry(item, line) {
+                        Ok(entry) => entries.push(entry),
+                        Err(m) => malformed.push(m),
+                    }
+                }
+            }
+        }
+        Err(e) if ranges.is_empty() => malformed.push(Malformed {
+            line: e.location().map(|l| l.line()),
+            reason: yaml_error_reason(&e),
+        }),
+        Err(_) => {
+            for (start, end) in ranges {
+                let indent = lines[start].len() - lines[start].trim_start().len();
+                let text: String = lines[start..end]
+                    .iter()
+                    .map(|l| {
+                        let spaces = l.len() - l.trim_start().len();
+                        format!("{}\n", &l[spaces.min(indent)..])
+                    })
+                    .collect();
+                let parsed = serde_yaml::from_str::<Vec<YamlValue>>(&text).map_err(|e| Malformed {
+                    line: Some(start + e.location().map_or(1, |l| l.line())),
+                    reason: yaml_error_reason(&e),
+                });
+                match parsed.and_then(|items| {
+                    items
+                        .into_iter()
+                        .map(|item| check_entry(item, Some(start + 1)))
+                        .collect::<Result<Vec<_>, _>>()
+                }) {
+                    Ok(items) => entries.extend(items),
+                    Err(m) => malformed.push(m),
+                }
+            }
+        }
+    }
+    (entries, malformed)
+}
@@ -0,0 +1,110 @@
+use crate::utils::i18n::tr;
+use notify::{Event, RecursiveMode, Watcher};
+use std::{
+    collections::HashSet,
+    future::Future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+/// 文件变动后等待的时间，合并编辑器保存时产生的多次事件
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// 转为绝对路径，方便跟事件中的路径比较
+fn absolute_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| {
+        std::env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    })
+}
+
+/// 监听文件变化，发生变化（防抖之后）就调用一次 rebuild
+///
+/// 监听的是文件所在的目录而不是文件本身，因为很多编辑器保存时会先删除再创建文件。
+pub async fn watch_files<F, Fut>(paths: &[PathBuf], mut rebuild: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let targets: HashSet<PathBuf> = paths.iter().map(|p| absolute_path(p)).collect();
+    let dirs: HashSet<PathBuf> = targets
+        .iter()
+        .filter_map(|p| p.parent().map(Path::to_path_buf))
+        .collect();
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<Event>();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        if let Ok(event) = res {
+            let _ = tx.send(event);
+        }
+    })
+    .unwrap_or_else(|e| {
+        panic!(
+            "{}",
+            tr!(
+                "创建文件监听器失败：{}",
+                "failed to create the file watcher: {}",
+                e
+            )
+        )
+    });
+
+    for dir in &dirs {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .unwrap_or_else(|e| {
+                panic!(
+                    "{}",
+                    tr!(
+                        "监听目录失败 {:?}: {}",
+                        "failed to watch directory {:?}: {}",
+                        dir,
+                        e
+                    )
+                )
+            });
+    }
+    println!(
+        "{}",
+        tr!(
+            "正在监听 {} 个文件的变化，按 Ctrl+C 退出...",
+            "watching {} files for changes, press Ctrl+C to exit...",
+            targets.len()
+        )
+    );
+
+    let is_target = |event: &Event| {
+        !event.kind.is_access()
+            && event
+                .paths
+                .iter()
+                .any(|p| targets.contains(&absolute_path(p)))
+    };
+
+    while let Some(event) = rx.recv().await {
+        if !is_target(&event) {
+            continue;
+        }
+        let mut changed: Vec<PathBuf> = event.paths;
+
+        // 防抖：在 DEBOUNCE 时间内不再有新事件，才开始重新构建
+        while let Ok(Some(event)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+            if is_target(&event) {
+                changed.extend(event.paths);
+            }
+        }
+        changed.dedup();
+
+        println!(
+            "{}",
+            tr!(
+                "检测到文件变化：{:?}，重新构建中...",
+                "files changed: {:?}, rebuilding...",
+                changed
+            )
+        );
+        rebuild().await;
+    }
+}
@@ -0,0 +1,1871 @@
+//! 端到端测试：运行编译好的程序，规则集和订阅由本地的 HTTP 服务提供，生成的配置与 tests/golden 中的文件比较
+//!
+//! 修改了输出格式之后，使用 `UPDATE_GOLDEN=1 cargo test --test e2e` 重新生成 golden 文件，检查差异后一起提交
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_clash_subscription_tool");
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/e2e")
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// 本地的 HTTP 服务：HEAD 返回长度，GET 支持 Range 分片下载
+struct MockServer {
+    url: String,
+}
+
+impl MockServer {
+    /// routes 为 路径 -> (响应头, 内容)
+    fn start(routes: HashMap<String, (Vec<String>, Vec<u8>)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let routes = routes.clone();
+                thread::spawn(move || {
+                    let _ = handle(stream, &routes);
+                });
+            }
+        });
+        MockServer { url }
+    }
+}
+
+fn handle(
+    mut stream: TcpStream,
+    routes: &HashMap<String, (Vec<String>, Vec<u8>)>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("range")
+        {
+            range = value.trim().strip_prefix("bytes=").and_then(|r| {
+                let (start, end) = r.split_once('-')?;
+                Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+            });
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+
+    let Some((headers, body)) = routes.get(path) else {
+        stream.write_all(
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )?;
+        return Ok(());
+    };
+    let (status, content) = match range {
+        Some((start, end)) if start <= end && end < body.len() => {
+            ("206 Partial Content", &body[start..=end])
+        }
+        _ => ("200 OK", &body[..]),
+    };
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n",
+        status,
+        content.len()
+    );
+    if let Some((start, end)) = range.filter(|_| status.starts_with("206")) {
+        response.push_str(&format!(
+            "Content-Range: bytes {}-{}/{}\r\n",
+            start,
+            end,
+            body.len()
+        ));
+    }
+    for header in headers {
+        response.push_str(header);
+        response.push_str("\r\n");
+    }
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes())?;
+    if method != "HEAD" {
+        stream.write_all(content)?;
+    }
+    // 丢弃剩下的请求内容，避免客户端收到 RST
+    let _ = stream.set_read_timeout(Some(std::time::Duration::from_millis(50)));
+    let _ = reader.read(&mut [0; 1024]);
+    Ok(())
+}
+
+/// 规则集的路由，rules 目录下的每个文件对应 /rules/文件名
+fn rule_routes() -> HashMap<String, (Vec<String>, Vec<u8>)> {
+    std::fs::read_dir(fixtures_dir().join("rules"))
+        .unwrap()
+        .flatten()
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            (
+                format!("/rules/{}", name),
+                (Vec::new(), std::fs::read(entry.path()).unwrap()),
+            )
+        })
+        .collect()
+}
+
+/// 每个测试单独的临时目录
+fn temp_dir(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "clash_tool_e2e_{}_{}_{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        name
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    // 输出目录和规则的保存目录需要已经存在
+    std::fs::create_dir_all(dir.join("out")).unwrap();
+    std::fs::create_dir_all(dir.join("rules")).unwrap();
+    dir
+}
+
+/// ini 中的 {server} 替换为本地服务的地址，写入临时目录
+fn write_ini(dir: &Path, server: &MockServer) -> PathBuf {
+    let template = std::fs::read_to_string(fixtures_dir().join("rules.ini")).unwrap();
+    let path = dir.join("rules.ini");
+    std::fs::write(&path, template.replace("{server}", &server.url)).unwrap();
+    path
+}
+
+/// 要运行的程序：不使用代理，输出信息为中文(测试中检查的提示信息)
+fn command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(BIN);
+    command
+        .args(args)
+        .current_dir(dir)
+        .env("LANG", "zh_CN.UTF-8")
+        .env_remove("LC_ALL")
+        .env_remove("LC_MESSAGES")
+        .env("NO_PROXY", "127.0.0.1,localhost")
+        .env("no_proxy", "127.0.0.1,localhost")
+        .env_remove("HTTP_PROXY")
+        .env_remove("HTTPS_PROXY")
+        .env_remove("ALL_PROXY")
+        .env_remove("http_proxy")
+        .env_remove("https_proxy")
+        .env_remove("all_proxy");
+    command
+}
+
+/// 运行程序，不检查退出码
+fn execute(dir: &Path, args: &[&str]) -> Output {
+    command(dir, args).output().unwrap()
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    let output = execute(dir, args);
+    assert!(
+        output.status.success(),
+        "运行失败：{:?}\nstdout:\n{}\nstderr:\n{}",
+        args,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// 与 golden 文件比较，UPDATE_GOLDEN=1 时改为写入 golden 文件
+fn assert_golden(name: &str, actual: &str) {
+    let path = golden_dir().join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "读取 {} 失败：{}（使用 UPDATE_GOLDEN=1 生成）",
+            path.display(),
+            e
+        )
+    });
+    if expected != actual {
+        let line = expected
+            .lines()
+            .zip(actual.lines())
+            .position(|(e, a)| e != a)
+            .unwrap_or_else(|| expected.lines().count().min(actual.lines().count()));
+        panic!(
+            "{} 与生成的内容不同，第 {} 行：\n期望：{:?}\n实际：{:?}\n（输出格式有意修改时使用 UPDATE_GOLDEN=1 重新生成）",
+            name,
+            line + 1,
+            expected.lines().nth(line),
+            actual.lines().nth(line)
+        );
+    }
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn build_matches_golden() {
+    let server = MockServer::start(rule_routes());
+    let dir = temp_dir("build");
+    let ini = write_ini(&dir, &server);
+    let output = dir.join("out/output.yaml");
+    run(
+        &dir,
+        &[
+            "-c",
+            path_str(&ini),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&output),
+            "-n",
+            "4",
+            "-k",
+            "3",
+            "--chunk-min-size",
+            "1",
+        ],
+    );
+    for page in 1..=2 {
+        let name = format!("output_snap_{}.yaml", page);
+        let content = std::fs::read_to_string(dir.join("out").join(&name)).unwrap();
+        assert_golden(&format!("build_{}", name), &content);
+    }
+    assert!(!dir.join("out/output_snap_3.yaml").exists());
+
+    // 输入没有变化时跳过生成，规则使用已下载的缓存
+    let second = run(
+        &dir,
+        &[
+            "-c",
+            path_str(&ini),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&output),
+            "-n",
+            "4",
+        ],
+    );
+    let stdout = String::from_utf8_lossy(&second.stdout);
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(
+        stdout.contains("没有变化") || stderr.contains("没有变化"),
+        "stdout:\n{}\nstderr:\n{}",
+        stdout,
+        stderr
+    );
+
+    // 规则集超过大小限制时跳过，使用之前保存的规则文件，输出不变
+    let limited = run(
+        &dir,
+        &[
+            "-c",
+            path_str(&ini),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&output),
+            "-n",
+            "4",
+            "--rule-max-size",
+            "1",
+            "--force",
+        ],
+    );
+    let stderr = String::from_utf8_lossy(&limited.stderr);
+    assert!(stderr.contains("超过大小限制"), "stderr:\n{}", stderr);
+    for page in 1..=2 {
+        let name = format!("output_snap_{}.yaml", page);
+        let content = std::fs::read_to_string(dir.join("out").join(&name)).unwrap();
+        assert_golden(&format!("build_{}", name), &content);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rule_providers_match_golden() {
+    let server = MockServer::start(rule_routes());
+    let dir = temp_dir("providers");
+    let ini = write_ini(&dir, &server);
+    run(
+        &dir,
+        &[
+            "-c",
+            path_str(&ini),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+            "--rule-providers",
+        ],
+    );
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    assert_golden(
+        "rule_providers.yaml",
+        &content.replace(&server.url, "{server}"),
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn anchored_proxies_are_expanded() {
+    let dir = temp_dir("anchors");
+    let output = run(
+        &dir,
+        &[
+            "proxies",
+            "-f",
+            path_str(&fixtures_dir().join("anchors.yaml")),
+        ],
+    );
+    assert_golden(
+        "proxies_anchors.yaml",
+        &String::from_utf8_lossy(&output.stdout),
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn merge_keys_and_aliases_are_resolved() {
+    let dir = temp_dir("merge_keys");
+    std::fs::write(
+        dir.join("anchors.yaml"),
+        r#"x-ws: &ws
+  path: /ws
+  headers:
+    Host: cdn.example.com
+x-vmess: &vmess
+  type: vmess
+  uuid: 2a4b6c8d-1111-2222-3333-444455556666
+  alterId: 0
+  cipher: auto
+  network: ws
+  ws-opts: *ws
+proxies:
+  - name: 美国 A
+    <<: *vmess
+    server: a.example.com
+    port: 443
+  - name: 美国 B
+    <<: *vmess
+    server: b.example.com
+    port: 8443
+    cipher: none
+  - name: 美国 C
+    <<: &nested
+      <<: *vmess
+      network: grpc
+    server: c.example.com
+    port: 443
+"#,
+    )
+    .unwrap();
+    let output = run(
+        &dir,
+        &[
+            "proxies",
+            "-f",
+            path_str(&dir.join("anchors.yaml")),
+            "--output-format",
+            "json",
+        ],
+    );
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let proxies = json["proxies"].as_array().unwrap();
+    // 每个节点都是完整的（不共享、不丢失字段），已有的键优先于合并进来的
+    assert_eq!(proxies.len(), 3, "{:?}", proxies);
+    for proxy in proxies {
+        assert!(proxy.get("<<").is_none(), "{}", proxy);
+        assert_eq!(proxy["type"], "vmess");
+        assert_eq!(proxy["ws-opts"]["headers"]["Host"], "cdn.example.com");
+    }
+    assert_eq!(proxies[0]["cipher"], "auto");
+    assert_eq!(proxies[1]["cipher"], "none");
+    assert_eq!(proxies[1]["port"], 8443);
+    // 合并键中的映射也可以有合并键
+    assert_eq!(proxies[2]["network"], "grpc");
+    assert_eq!(proxies[2]["uuid"], "2a4b6c8d-1111-2222-3333-444455556666");
+
+    // base.yaml 中的合并键也展开
+    std::fs::write(
+        dir.join("base.yaml"),
+        "x-dns: &dns\n  enable: true\n  ipv6: false\nmixed-port: 7890\ndns:\n  <<: *dns\n  ipv6: true\n",
+    )
+    .unwrap();
+    run(
+        &dir,
+        &[
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&dir.join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+        ],
+    );
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    assert!(!content.contains("<<"), "{}", content);
+    let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap();
+    assert_eq!(config["dns"]["enable"].as_bool(), Some(true));
+    assert_eq!(config["dns"]["ipv6"].as_bool(), Some(true));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn malformed_proxies_are_skipped() {
+    let dir = temp_dir("malformed");
+    let output = run(
+        &dir,
+        &[
+            "proxies",
+            "-f",
+            path_str(&fixtures_dir().join("malformed.yaml")),
+        ],
+    );
+    assert_golden(
+        "proxies_malformed.yaml",
+        &String::from_utf8_lossy(&output.stdout),
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("第 3 行"), "stderr:\n{}", stderr);
+    assert!(stderr.contains("第 4 行"), "stderr:\n{}", stderr);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn share_link_subscription_is_converted() {
+    let links = std::fs::read(fixtures_dir().join("links.txt")).unwrap();
+    let encoded = base64_encode(&links);
+    let routes = HashMap::from([(
+        "/sub".to_string(),
+        (
+            vec!["subscription-userinfo: upload=1024; download=2048; total=1073741824".to_string()],
+            encoded.into_bytes(),
+        ),
+    )]);
+    let server = MockServer::start(routes);
+    let dir = temp_dir("links");
+    let output = run(&dir, &["proxies", "-f", &format!("{}/sub", server.url)]);
+    assert_golden(
+        "proxies_links.yaml",
+        &String::from_utf8_lossy(&output.stdout),
+    );
+    // 每个订阅链接输出下载的大小、速度和耗时
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("/sub 下载 ") && stderr.contains("/s，耗时 "),
+        "stderr:\n{}",
+        stderr
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn proxies_are_read_from_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let links = std::fs::read(fixtures_dir().join("links.txt")).unwrap();
+    let dir = temp_dir("stdin");
+    let mut child = command(&dir, &["proxies", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(base64_encode(&links).as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // 与下载同样内容的订阅链接结果相同
+    assert_golden(
+        "proxies_links.yaml",
+        &String::from_utf8_lossy(&output.stdout),
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// 订阅内容的 base64 编码（测试中不引入依赖）
+fn base64_encode(data: &[u8]) -> String {
+    const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+        out.extend(std::iter::repeat_n('=', 3 - chunk.len()));
+    }
+    out
+}
+
+#[test]
+fn minimal_preset_needs_no_ini() {
+    let dir = temp_dir("preset");
+    run(
+        &dir,
+        &[
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+        ],
+    );
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    assert_golden("preset_minimal.yaml", &content);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn build_subcommand_accepts_long_names() {
+    let dir = temp_dir("subcommand");
+    run(
+        &dir,
+        &[
+            "build",
+            "--preset",
+            "minimal",
+            "--base",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "--inputs",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "--rules-dir",
+            path_str(&dir.join("rules")),
+            "--output",
+            path_str(&dir.join("out/output.yaml")),
+        ],
+    );
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    assert_golden("preset_minimal.yaml", &content);
+
+    // convert 子命令(原来的 proxies 子命令)
+    let output = run(
+        &dir,
+        &[
+            "convert",
+            "--inputs",
+            path_str(&fixtures_dir().join("anchors.yaml")),
+        ],
+    );
+    assert_golden(
+        "proxies_anchors.yaml",
+        &String::from_utf8_lossy(&output.stdout),
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn completions_and_man_page_use_cli_definition() {
+    let dir = temp_dir("completions");
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = run(&dir, &["completions", shell]);
+        let script = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            script.contains("rules-dir") && script.contains("serve"),
+            "{}:\n{}",
+            shell,
+            script
+        );
+    }
+    let output = run(&dir, &["--man"]);
+    let man = String::from_utf8_lossy(&output.stdout);
+    assert!(man.starts_with(".ie") && man.contains(".TH"), "{}", man);
+    assert!(man.contains("\\-\\-inputs"), "{}", man);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn group_health_check_numbers_follow_subconverter() {
+    let dir = temp_dir("groups");
+    run(
+        &dir,
+        &[
+            "-c",
+            path_str(&fixtures_dir().join("groups.ini")),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+        ],
+    );
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap();
+    let group = |name: &str| {
+        config["proxy-groups"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .find(|g| g["name"].as_str() == Some(name))
+            .unwrap_or_else(|| panic!("没有代理组 {}:\n{}", name, content))
+            .clone()
+    };
+    let number = |group: &serde_yaml::Value, key: &str| group[key].as_u64();
+
+    // (代理组, interval, timeout(毫秒), tolerance)
+    let expected = [
+        ("♻️ 自动选择", Some(300), None, Some(50)),
+        ("🔯 故障转移", Some(180), None, None),
+        ("🔮 负载均衡", Some(300), Some(5000), Some(50)),
+        ("🇭🇰 香港转移", Some(15), Some(3000), None),
+        ("🇯🇵 日本节点", Some(300), None, Some(100)),
+    ];
+    for (name, interval, timeout, tolerance) in expected {
+        let group = group(name);
+        assert_eq!(number(&group, "interval"), interval, "{}", name);
+        assert_eq!(number(&group, "timeout"), timeout, "{}", name);
+        assert_eq!(number(&group, "tolerance"), tolerance, "{}", name);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn ini_group_exclude_works_with_several_regexes() {
+    let dir = temp_dir("group_exclude");
+    let write = |group: &str| {
+        let ini = format!(
+            "[custom]\nruleset=🐟 漏网之鱼,[]FINAL\ncustom_proxy_group={}\n\
+             custom_proxy_group=🐟 漏网之鱼`select`[]🇭🇰 香港节点`[]DIRECT\n",
+            group
+        );
+        std::fs::write(dir.join("exclude.ini"), ini).unwrap();
+    };
+    let (ini, base, proxies) = (
+        dir.join("exclude.ini"),
+        fixtures_dir().join("base.yaml"),
+        fixtures_dir().join("proxies.yaml"),
+    );
+    let (rules, output) = (dir.join("rules"), dir.join("out/output.yaml"));
+    let args = [
+        "-c",
+        path_str(&ini),
+        "-b",
+        path_str(&base),
+        "-f",
+        path_str(&proxies),
+        "-s",
+        path_str(&rules),
+        "-o",
+        path_str(&output),
+    ];
+
+    // 两个正则同时匹配，再用 !! 排除
+    write("🇭🇰 香港节点`select`(香港|日本)`^((?!02).)*$`!!日本");
+    run(&dir, &args);
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap();
+    let group = config["proxy-groups"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .find(|g| g["name"].as_str() == Some("🇭🇰 香港节点"))
+        .unwrap();
+    let members: Vec<&str> = group["proxies"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .filter_map(|p| p.as_str())
+        .collect();
+    assert_eq!(members.len(), 2, "{:?}", members);
+    assert!(
+        members.iter().all(|name| name.starts_with("香港 01")),
+        "{:?}",
+        members
+    );
+
+    // 错误的排除正则是配置错误，不能 panic
+    write("🇭🇰 香港节点`select`(香港|日本)`exclude=(");
+    let output = execute(&dir, &args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(stderr.contains("exclude=("), "{}", stderr);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rules_of_removed_groups_point_to_the_fallback_per_page() {
+    let dir = temp_dir("rule_rewrites");
+    let ini = dir.join("rewrite.ini");
+    std::fs::write(
+        &ini,
+        "[custom]\n\
+         ruleset=🇯🇵 日本节点,[]DOMAIN-SUFFIX,jp.example.com\n\
+         ruleset=🐟 漏网之鱼,[]FINAL\n\
+         custom_proxy_group=🇯🇵 日本节点`select`(日本)\n\
+         custom_proxy_group=🐟 漏网之鱼`select`[]🇯🇵 日本节点`[]DIRECT\n",
+    )
+    .unwrap();
+    run(
+        &dir,
+        &[
+            "-c",
+            path_str(&ini),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+            "-n",
+            "3",
+        ],
+    );
+    // 日本的节点只在其中一页，其它页移除了这个代理组，规则改为指向兜底的代理组
+    let mut targets = Vec::new();
+    for page in 1..=2 {
+        let path = dir.join(format!("out/output_snap_{}.yaml", page));
+        let config: serde_yaml::Value =
+            serde_yaml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let has_group = config["proxy-groups"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .any(|g| g["name"].as_str() == Some("🇯🇵 日本节点"));
+        let rule = config["rules"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(|r| r.as_str())
+            .find(|r| r.starts_with("DOMAIN-SUFFIX,jp.example.com"))
+            .unwrap()
+            .to_string();
+        let expected = if has_group {
+            "🇯🇵 日本节点"
+        } else {
+            "🐟 漏网之鱼"
+        };
+        assert_eq!(rule, format!("DOMAIN-SUFFIX,jp.example.com,{}", expected));
+        targets.push(expected);
+    }
+    targets.sort();
+    assert_eq!(targets, ["🇯🇵 日本节点", "🐟 漏网之鱼"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn outputs_in_one_directory_keep_their_own_manifest() {
+    let dir = temp_dir("shared_out");
+    let (base, proxies) = (
+        fixtures_dir().join("base.yaml"),
+        fixtures_dir().join("proxies.yaml"),
+    );
+    let (rules, a, b) = (
+        dir.join("rules"),
+        dir.join("out/a.yaml"),
+        dir.join("out/b.yaml"),
+    );
+    let build = |output: &Path, page_size: &str, force: bool| {
+        let mut args = vec![
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&base),
+            "-f",
+            path_str(&proxies),
+            "-s",
+            path_str(&rules),
+            "-o",
+            path_str(output),
+            "-n",
+            page_size,
+        ];
+        if force {
+            args.push("--force");
+        }
+        String::from_utf8_lossy(&run(&dir, &args).stdout).into_owned()
+    };
+    build(&a, "4", false);
+    build(&b, "2", false);
+    for name in [
+        "a.index.json",
+        ".a.build_fingerprint",
+        "b.index.json",
+        ".b.build_fingerprint",
+    ] {
+        assert!(dir.join("out").join(name).exists(), "{}", name);
+    }
+
+    // 另一个输出没有覆盖 a 的指纹，输入没有变化时仍然跳过
+    let stdout = build(&a, "4", false);
+    assert!(stdout.contains("跳过生成"), "{}", stdout);
+
+    // 重新生成 a 时只删除 a 上次的输出，不会删除清单中 b 的文件
+    build(&a, "4", true);
+    assert!(dir.join("out/a_snap_1.yaml").exists());
+    assert!(dir.join("out/b_snap_1.yaml").exists());
+    assert!(dir.join("out/b_snap_3.yaml").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn ini_extra_proxies_are_appended() {
+    let dir = temp_dir("extra_proxies");
+    run(
+        &dir,
+        &[
+            "-c",
+            path_str(&fixtures_dir().join("extra_proxies.ini")),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+        ],
+    );
+    let names = output_proxy_names(&dir.join("out"));
+    assert_eq!(
+        names[names.len() - 4..],
+        ["公司", "家里中转", "本机直连", "链接节点"],
+        "{:?}",
+        names
+    );
+
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap();
+    let proxies = config["proxies"].as_sequence().unwrap();
+    let find = |name: &str| {
+        proxies
+            .iter()
+            .find(|p| p["name"].as_str() == Some(name))
+            .unwrap()
+    };
+    assert_eq!(find("家里中转")["server"].as_str(), Some("192.168.1.2"));
+    assert_eq!(find("本机直连")["type"].as_str(), Some("direct"));
+    assert_eq!(find("链接节点")["type"].as_str(), Some("trojan"));
+    // 额外的节点也可以被代理组的正则匹配到
+    let home = config["proxy-groups"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .find(|g| g["name"].as_str() == Some("🏠 回家"))
+        .unwrap();
+    assert_eq!(
+        home["proxies"],
+        serde_yaml::from_str::<serde_yaml::Value>("[公司, 家里中转]").unwrap()
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn extra_rules_are_inserted_at_position() {
+    let dir = temp_dir("extra_rules");
+    let rules_of = |extra: &str, banners: bool| {
+        let path = dir.join("extra_rules.yaml");
+        std::fs::write(&path, extra).unwrap();
+        let base = fixtures_dir().join("base.yaml");
+        let proxies = fixtures_dir().join("proxies.yaml");
+        let rules_dir = dir.join("rules");
+        let output = dir.join("out/output.yaml");
+        let mut args = vec![
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&base),
+            "-f",
+            path_str(&proxies),
+            "-s",
+            path_str(&rules_dir),
+            "-o",
+            path_str(&output),
+            "--force",
+            "--extra-rules",
+            path_str(&path),
+        ];
+        if banners {
+            args.push("--ruleset-banners");
+        }
+        run(&dir, &args);
+        let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+        let rules = content.split_once("\nrules:\n").unwrap().1;
+        rules
+            .lines()
+            .map(|line| line.trim().trim_start_matches("- ").to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // 不写位置时放在最后的 MATCH 规则之前，其它地方相同的规则被去掉
+    let rules = rules_of(
+        "- DOMAIN-SUFFIX,example.com,🚀 节点选择\n- IP-CIDR,192.168.0.0/16,🎯 全球直连,no-resolve\n",
+        false,
+    );
+    assert_eq!(
+        rules,
+        [
+            "IP-CIDR,127.0.0.0/8,🎯 全球直连,no-resolve",
+            "IP-CIDR,10.0.0.0/8,🎯 全球直连,no-resolve",
+            "IP-CIDR,172.16.0.0/12,🎯 全球直连,no-resolve",
+            "GEOIP,CN,🎯 全球直连",
+            "DOMAIN-SUFFIX,example.com,🚀 节点选择",
+            "IP-CIDR,192.168.0.0/16,🎯 全球直连,no-resolve",
+            "MATCH,🐟 漏网之鱼",
+        ]
+    );
+
+    // top 放在最前面，分块输出时带有注释
+    let rules = rules_of(
+        "position: top\nrules:\n  - DOMAIN,nas.example.com,DIRECT\n  - IP-CIDR,10.0.0.0/8,🎯 全球直连,no-resolve\n",
+        true,
+    );
+    assert_eq!(rules[0], "# >>> extra rules (2 rules)");
+    assert_eq!(rules[1], "DOMAIN,nas.example.com,DIRECT");
+    assert_eq!(rules[2], "IP-CIDR,10.0.0.0/8,🎯 全球直连,no-resolve");
+    assert_eq!(rules[3], "# >>> final");
+    assert_eq!(
+        rules
+            .iter()
+            .filter(|r| r.starts_with("IP-CIDR,10.0.0.0/8,"))
+            .count(),
+        1
+    );
+
+    // 没有写代理组的规则是配置错误
+    let output = execute(
+        &dir,
+        &[
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+            "--extra-rules",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn extra_groups_replace_and_append() {
+    let dir = temp_dir("extra_groups");
+    let groups_path = dir.join("groups.yaml");
+    std::fs::write(
+        &groups_path,
+        r#"proxy-groups:
+  - name: ♻️ 自动选择
+    type: fallback
+    filter: 香港
+  - name: 🎮 Gaming
+    type: url-test
+    filter: (香港|日本)
+    exclude-filter: "02"
+    tolerance: 50
+    expected-status: 204
+"#,
+    )
+    .unwrap();
+    let (base, proxies) = (
+        fixtures_dir().join("base.yaml"),
+        fixtures_dir().join("proxies.yaml"),
+    );
+    let (rules, output) = (dir.join("rules"), dir.join("out/output.yaml"));
+    let args = [
+        "--preset",
+        "minimal",
+        "-b",
+        path_str(&base),
+        "-f",
+        path_str(&proxies),
+        "-s",
+        path_str(&rules),
+        "-o",
+        path_str(&output),
+        "--extra-groups",
+        path_str(&groups_path),
+    ];
+    run(&dir, &args);
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap();
+    let groups = config["proxy-groups"].as_sequence().unwrap();
+    let names: Vec<&str> = groups.iter().map(|g| g["name"].as_str().unwrap()).collect();
+    // 同名的代理组位置不变，新的加在后面
+    assert_eq!(
+        names,
+        [
+            "🚀 节点选择",
+            "♻️ 自动选择",
+            "🎯 全球直连",
+            "🐟 漏网之鱼",
+            "🎮 Gaming"
+        ]
+    );
+    let members = |group: &serde_yaml::Value| -> Vec<String> {
+        group["proxies"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|p| p.as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let auto = &groups[1];
+    assert_eq!(auto["type"].as_str(), Some("fallback"));
+    assert!(members(auto).iter().all(|name| name.contains("香港")));
+    // 健康检查的地址和间隔使用默认值
+    assert_eq!(auto["interval"].as_u64(), Some(300));
+    assert!(auto["url"].as_str().is_some());
+
+    let gaming = &groups[4];
+    let gaming_members = members(gaming);
+    assert!(gaming_members.contains(&"日本 01".to_string()));
+    assert!(!gaming_members.iter().any(|name| name.contains("02")));
+    assert_eq!(gaming["tolerance"].as_u64(), Some(50));
+    assert_eq!(gaming["expected-status"].as_str(), Some("204"));
+
+    // 只修改了额外的代理组时也重新生成
+    let changed = std::fs::read_to_string(&groups_path)
+        .unwrap()
+        .replace("tolerance: 50", "tolerance: 80");
+    std::fs::write(&groups_path, changed).unwrap();
+    run(&dir, &args);
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap();
+    assert_eq!(config["proxy-groups"][4]["tolerance"].as_u64(), Some(80));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn plugins_transform_each_stage() {
+    let dir = temp_dir("plugins");
+    let build = |plugins: &[&str]| {
+        let mut args = vec![
+            "--preset".to_string(),
+            "minimal".to_string(),
+            "-b".to_string(),
+            path_str(&fixtures_dir().join("base.yaml")).to_string(),
+            "-f".to_string(),
+            path_str(&fixtures_dir().join("proxies.yaml")).to_string(),
+            "-s".to_string(),
+            path_str(&dir.join("rules")).to_string(),
+            "-o".to_string(),
+            path_str(&dir.join("out/output.yaml")).to_string(),
+            "--json-errors".to_string(),
+        ];
+        for plugin in plugins {
+            args.push("--plugin".to_string());
+            args.push(plugin.to_string());
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        execute(&dir, &args)
+    };
+
+    let output = build(&[
+        "post-merge=sed 's/香港/HK/g'",
+        "post-merge=sed 's/日本/JP/g'",
+        r#"post-group=sed "s/\"type\":\"url-test\"/\"type\":\"fallback\"/; s/♻️ 自动选择/♻️ 自动选择 $CLASH_TOOL_PAGE/g""#,
+        r#"pre-write=sed 's/^\[/["DOMAIN,plugin.example.com,DIRECT",/'"#,
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let names = output_proxy_names(&dir.join("out"));
+    assert!(names.iter().any(|n| n.starts_with("HK ")), "{:?}", names);
+    assert!(names.contains(&"JP 01".to_string()), "{:?}", names);
+    assert!(!names.iter().any(|n| n.contains("香港")), "{:?}", names);
+
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap();
+    let auto = &config["proxy-groups"][1];
+    assert_eq!(auto["name"].as_str(), Some("♻️ 自动选择 1"));
+    assert_eq!(auto["type"].as_str(), Some("fallback"));
+    assert_eq!(
+        config["rules"][0].as_str(),
+        Some("DOMAIN,plugin.example.com,DIRECT")
+    );
+
+    // 插件失败、输出的不是 json 时构建失败
+    for plugin in ["post-merge=exit 3", "pre-write=echo not-json"] {
+        let output = build(&[plugin]);
+        assert_eq!(output.status.code(), Some(1), "{}", plugin);
+        assert_eq!(json_reports(&output)[0]["kind"], "other", "{}", plugin);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn script_transforms_and_drops_nodes() {
+    let dir = temp_dir("script");
+    let script = dir.join("transform.rhai");
+    std::fs::write(
+        &script,
+        r#"
+if proxy.type == "hysteria2" { return false; }
+proxy.name = regex_replace(proxy.name, "^香港 *", "HK-");
+if index == 0 { proxy.udp = false; }
+"#,
+    )
+    .unwrap();
+    let output = run(
+        &dir,
+        &[
+            "proxies",
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "--output-format",
+            "json",
+            "--script",
+            path_str(&script),
+        ],
+    );
+    let proxies: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let proxies = proxies["proxies"].as_array().unwrap();
+    let names: Vec<&str> = proxies
+        .iter()
+        .map(|p| p["name"].as_str().unwrap())
+        .collect();
+    assert!(names.contains(&"HK-02"), "{:?}", names);
+    assert!(!names.iter().any(|n| n.contains("新加坡")), "{:?}", names);
+    assert_eq!(proxies[0]["udp"], false);
+    // 脚本修改的值保持原来的类型
+    assert!(proxies[0]["port"].is_u64());
+
+    // 脚本出错是配置错误
+    std::fs::write(&script, "proxy.name = ;").unwrap();
+    let output = execute(
+        &dir,
+        &[
+            "proxies",
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "--script",
+            path_str(&script),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn keep_history_only_prunes_build_snapshots() {
+    let dir = temp_dir("history");
+    let history = dir.join("out/hist");
+    for name in ["2020-01-01_000000", "2020-01-02_000000", "keepme"] {
+        std::fs::create_dir_all(history.join(name)).unwrap();
+    }
+    let (base, proxies) = (
+        fixtures_dir().join("base.yaml"),
+        fixtures_dir().join("proxies.yaml"),
+    );
+    let (rules, output) = (dir.join("rules"), dir.join("out/output.yaml"));
+    let args = |history_dir: &'static str| {
+        vec![
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&base),
+            "-f",
+            path_str(&proxies),
+            "-s",
+            path_str(&rules),
+            "-o",
+            path_str(&output),
+            "--history-dir",
+            history_dir,
+            "--keep-history",
+            "1",
+        ]
+    };
+    run(&dir, &args("hist"));
+    // 只删除较早的构建，其它目录不动
+    assert!(!history.join("2020-01-01_000000").exists());
+    assert!(history.join("2020-01-02_000000").exists());
+    assert!(history.join("keepme").exists());
+    assert!(dir.join("out/output_snap_1.yaml").exists());
+
+    // 历史目录不能在输出目录之外，也不能是输出目录本身
+    for history_dir in ["..", "hist/../..", ".", "/"] {
+        let output = execute(&dir, &args(history_dir));
+        assert!(!output.status.success(), "{}", history_dir);
+    }
+    assert!(dir.join("rules").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn name_template_only_removes_previous_outputs() {
+    let dir = temp_dir("template_cleanup");
+    let out = dir.join("out");
+    // 输出目录中其它的 yaml 文件（例如输入文件）不能被删除
+    for name in ["clash.yaml", "base.yaml", "other.yaml"] {
+        std::fs::write(out.join(name), "proxies: []\n").unwrap();
+    }
+    let (base, proxies) = (
+        fixtures_dir().join("base.yaml"),
+        fixtures_dir().join("duplicates.yaml"),
+    );
+    let (rules, output) = (dir.join("rules"), out.join("output.yaml"));
+    let args = |per_page: &'static str| {
+        vec![
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&base),
+            "-f",
+            path_str(&proxies),
+            "-s",
+            path_str(&rules),
+            "-o",
+            path_str(&output),
+            "-n",
+            per_page,
+            "--name-template",
+            "{index}.{ext}",
+        ]
+    };
+    run(&dir, &args("2"));
+    assert!(out.join("4.yaml").exists());
+    // 页数变少后，只删除上次生成的文件
+    run(&dir, &args("7"));
+    assert!(out.join("1.yaml").exists());
+    for name in ["2.yaml", "3.yaml", "4.yaml"] {
+        assert!(!out.join(name).exists(), "{}", name);
+    }
+    for name in ["clash.yaml", "base.yaml", "other.yaml"] {
+        assert!(out.join(name).exists(), "{}", name);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// 记录收到的请求头的 HTTP 服务，所有请求都返回 200，用于检查 webhook、上传的请求
+fn start_recorder() -> (String, std::sync::mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            while reader.read_line(&mut head).unwrap_or(0) > 0 && !head.ends_with("\r\n\r\n") {}
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+            let _ = stream.set_read_timeout(Some(std::time::Duration::from_millis(50)));
+            let _ = reader.read(&mut [0; 4096]);
+            let _ = sender.send(head);
+        }
+    });
+    (url, receiver)
+}
+
+#[test]
+fn webhook_does_not_send_download_headers() {
+    let dir = temp_dir("webhook");
+    let (url, requests) = start_recorder();
+    let webhook = format!("{}/hook", url);
+    run(
+        &dir,
+        &[
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+            "--header",
+            "Authorization: Bearer sub-secret",
+            "--user-agent",
+            "clash-sub-ua",
+            "--webhook",
+            &webhook,
+        ],
+    );
+    let request = requests
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert!(request.starts_with("POST /hook "), "{}", request);
+    // --header、--user-agent 只用于下载订阅和规则
+    assert!(!request.contains("sub-secret"), "{}", request);
+    assert!(!request.contains("clash-sub-ua"), "{}", request);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn upload_does_not_send_download_headers() {
+    let dir = temp_dir("upload");
+    let (url, requests) = start_recorder();
+    let config = dir.join("upload.yaml");
+    std::fs::write(
+        &config,
+        format!("targets:\n  - type: webdav\n    url: {}/dav/\n", url),
+    )
+    .unwrap();
+    run(
+        &dir,
+        &[
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+            "--header",
+            "Authorization: Bearer sub-secret",
+            "--upload",
+            path_str(&config),
+        ],
+    );
+    let request = requests
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert!(
+        request.starts_with("PUT /dav/output_snap_1.yaml "),
+        "{}",
+        request
+    );
+    assert!(!request.contains("sub-secret"), "{}", request);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn merge_cidr_keeps_overlapping_rules_of_other_groups_in_order() {
+    let dir = temp_dir("merge_cidr");
+    let files = [
+        ("a.list", "IP-CIDR,1.0.0.0/24\n"),
+        ("b.list", "IP-CIDR,10.1.0.0/16\n"),
+        ("c.list", "IP-CIDR,10.0.0.0/8\nIP-CIDR,1.0.1.0/24\n"),
+    ];
+    for (name, content) in files {
+        std::fs::write(dir.join(name), content).unwrap();
+    }
+    let ini = dir.join("cidr.ini");
+    std::fs::write(
+        &ini,
+        format!(
+            "[custom]\nruleset=DIRECT,{}\nruleset=REJECT,{}\nruleset=DIRECT,{}\nruleset=DIRECT,[]FINAL\n",
+            path_str(&dir.join("a.list")),
+            path_str(&dir.join("b.list")),
+            path_str(&dir.join("c.list"))
+        ),
+    )
+    .unwrap();
+    run(
+        &dir,
+        &[
+            "-c",
+            path_str(&ini),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+            "--keep-rule-order",
+            "--merge-cidr",
+        ],
+    );
+    let content = std::fs::read_to_string(dir.join("out/output_snap_1.yaml")).unwrap();
+    let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap();
+    let rules: Vec<&str> = config["rules"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .filter_map(|r| r.as_str())
+        .collect();
+    // 1.0.1.0/24 前移时没有越过重叠的规则，可以合并；10.0.0.0/8 不能越过 REJECT 的 10.1.0.0/16
+    assert_eq!(
+        rules,
+        [
+            "IP-CIDR,1.0.0.0/23,DIRECT",
+            "IP-CIDR,10.1.0.0/16,REJECT",
+            "IP-CIDR,10.0.0.0/8,DIRECT",
+            "MATCH,DIRECT",
+        ]
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// 不压缩的 zstd 帧（raw block），mrs 文件是 zstd 压缩的
+fn zstd_raw_frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x28, 0xB5, 0x2F, 0xFD, 0x00, 0x00];
+    let header = (data.len() as u32) << 3 | 1; // 最后一个块，类型为 raw
+    frame.extend(&header.to_le_bytes()[..3]);
+    frame.extend(data);
+    frame
+}
+
+#[test]
+fn corrupt_mrs_lengths_are_rejected() {
+    let dir = temp_dir("mrs");
+    // domain 类型：leaves 的长度乘以8会溢出，label_bitmap 的长度远大于文件
+    let mut cases = Vec::new();
+    for len in [1i64 << 61, 1i64 << 40] {
+        let mut data = b"MRS\x01\x00".to_vec();
+        data.extend(1i64.to_be_bytes()); // 规则数
+        data.extend(0i64.to_be_bytes()); // 扩展数据
+        data.push(1); // 数据版本
+        data.extend(len.to_be_bytes());
+        cases.push(zstd_raw_frame(&data));
+    }
+    let mut ini = String::from("[custom]\n");
+    for (i, data) in cases.iter().enumerate() {
+        let path = dir.join(format!("bad{}.mrs", i));
+        std::fs::write(&path, data).unwrap();
+        ini.push_str(&format!("ruleset=DIRECT,{}\n", path_str(&path)));
+    }
+    ini.push_str("ruleset=DIRECT,[]FINAL\n");
+    std::fs::write(dir.join("mrs.ini"), ini).unwrap();
+    let output = execute(
+        &dir,
+        &[
+            "-c",
+            path_str(&dir.join("mrs.ini")),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+        ],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // 解析失败的规则集跳过，不会 panic 或者耗尽内存
+    assert!(output.status.code().is_some(), "{}", stderr);
+    assert_eq!(stderr.matches("解析失败").count(), 2, "{}", stderr);
+    assert!(dir.join("out/output_snap_1.yaml").exists(), "{}", stderr);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// 查询参数中的值按 url 编码
+fn url_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// 发送 GET 请求，返回 (状态码, 内容)；服务还没启动时重试
+fn http_get(addr: &str, path: &str) -> (u16, String) {
+    let mut stream = (0..100)
+        .find_map(|_| {
+            TcpStream::connect(addr)
+                .inspect_err(|_| thread::sleep(std::time::Duration::from_millis(100)))
+                .ok()
+        })
+        .unwrap_or_else(|| panic!("连接 {} 失败", addr));
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+#[test]
+fn sub_api_is_compatible_with_subconverter() {
+    let links = std::fs::read(fixtures_dir().join("links.txt")).unwrap();
+    let ini = std::fs::read(fixtures_dir().join("extra_proxies.ini")).unwrap();
+    // 订阅链接中的逗号不能拆分成多个输入
+    let routes = HashMap::from([
+        (
+            "/sub?token=a,b".to_string(),
+            (Vec::new(), base64_encode(&links).into_bytes()),
+        ),
+        ("/config.ini".to_string(), (Vec::new(), ini)),
+        (
+            "/local.ini".to_string(),
+            (
+                Vec::new(),
+                b"[custom]\nruleset=DIRECT,rules/local.list\nruleset=DIRECT,[]FINAL\n".to_vec(),
+            ),
+        ),
+    ]);
+    let server = MockServer::start(routes);
+    let dir = temp_dir("sub_api");
+    // 先占用一个空闲端口再释放，给服务使用
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let mut child = command(
+        &dir,
+        &[
+            "serve",
+            "--listen",
+            &addr,
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+        ],
+    )
+    .stdout(std::process::Stdio::null())
+    .stderr(std::process::Stdio::null())
+    .spawn()
+    .unwrap();
+
+    let (status, body) = http_get(&addr, "/version");
+    assert_eq!(status, 200, "{}", body);
+    // 订阅链接和分享链接用 | 隔开，ini 使用请求中的 config
+    let url = format!(
+        "{}/sub?token=a,b|trojan://pass@t.example.com:443?sni=t.example.com#直接写的链接",
+        server.url
+    );
+    let sub = |url: &str, config: &str| {
+        http_get(
+            &addr,
+            &format!(
+                "/sub?target=clash&url={}&config={}&emoji=true",
+                url_encode(url),
+                url_encode(config)
+            ),
+        )
+    };
+    let (status, body) = sub(&url, &format!("{}/config.ini", server.url));
+    let parsed: Option<serde_yaml::Value> = serde_yaml::from_str(&body).ok();
+    let names: Vec<&str> = parsed
+        .as_ref()
+        .and_then(|c| c["proxies"].as_sequence())
+        .map(|proxies| proxies.iter().filter_map(|p| p["name"].as_str()).collect())
+        .unwrap_or_default();
+    let groups: Vec<&str> = parsed
+        .as_ref()
+        .and_then(|c| c["proxy-groups"].as_sequence())
+        .map(|groups| groups.iter().filter_map(|g| g["name"].as_str()).collect())
+        .unwrap_or_default();
+    // 不能读取服务器上的本地文件：输入、ini 以及 ini 中的规则集；不支持的 target 返回 400
+    let proxies_path = fixtures_dir().join("proxies.yaml");
+    let (local_status, _) = sub(path_str(&proxies_path), "");
+    let (local_ini_status, _) = sub(&url, path_str(&fixtures_dir().join("extra_proxies.ini")));
+    let (local_ruleset_status, local_ruleset_body) =
+        sub(&url, &format!("{}/local.ini", server.url));
+    let (target_status, _) = http_get(&addr, "/sub?target=surge&url=ss%3A%2F%2Fx");
+    let _ = child.kill();
+    let _ = child.wait();
+
+    assert_eq!(status, 200, "{}", body);
+    assert!(names.contains(&"直接写的链接"), "{:?}", names);
+    assert!(names.contains(&"家里中转"), "{:?}", names);
+    assert!(names.len() > 3, "{:?}", names);
+    assert!(groups.contains(&"🏠 回家"), "{:?}", groups);
+    assert_eq!(local_status, 400);
+    assert_eq!(local_ini_status, 400);
+    assert_eq!(local_ruleset_status, 500, "{}", local_ruleset_body);
+    assert_eq!(target_status, 400);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// 所有输出文件中的节点名称（按页的顺序）
+fn output_proxy_names(out_dir: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+    for page in 1.. {
+        let path = out_dir.join(format!("output_snap_{}.yaml", page));
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            break;
+        };
+        let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap();
+        let proxies = config["proxies"].as_sequence().unwrap();
+        names.extend(
+            proxies
+                .iter()
+                .map(|p| p["name"].as_str().unwrap().to_string()),
+        );
+    }
+    names
+}
+
+#[test]
+fn duplicate_names_are_unique_across_pages() {
+    for style in ["hash", "counter", "emoji"] {
+        let dir = temp_dir("dedup");
+        run(
+            &dir,
+            &[
+                "--preset",
+                "minimal",
+                "-b",
+                path_str(&fixtures_dir().join("base.yaml")),
+                "-f",
+                path_str(&fixtures_dir().join("duplicates.yaml")),
+                "-s",
+                path_str(&dir.join("rules")),
+                "-o",
+                path_str(&dir.join("out/output.yaml")),
+                "-n",
+                "2",
+                "--dedup-names",
+                style,
+            ],
+        );
+        let names = output_proxy_names(&dir.join("out"));
+        assert_eq!(names.len(), 7, "{}: {:?}", style, names);
+        let unique: std::collections::HashSet<&String> = names.iter().collect();
+        assert_eq!(
+            unique.len(),
+            names.len(),
+            "{} 有重复的名称：{:?}",
+            style,
+            names
+        );
+        // 原来就不重复的名称保持不变
+        for name in ["HK-01", "HK-01-2", "HK-01-laXizI", "HK-01 2️⃣"] {
+            assert!(names.iter().any(|n| n == name), "{}: {:?}", style, names);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
+
+/// stderr 中 --json-errors 输出的 json 行
+fn json_reports(output: &Output) -> Vec<serde_json::Value> {
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[test]
+fn exit_codes_distinguish_failures() {
+    // 输入文件不存在：配置错误
+    let dir = temp_dir("exit_codes");
+    let missing = execute(
+        &dir,
+        &[
+            "--preset",
+            "minimal",
+            "--json-errors",
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&dir.join("missing.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+        ],
+    );
+    assert_eq!(missing.status.code(), Some(2));
+    let reports = json_reports(&missing);
+    let errors: Vec<&serde_json::Value> =
+        reports.iter().filter(|r| r["level"] == "error").collect();
+    assert!(
+        matches!(errors[..], [r] if r["kind"] == "config" && r["code"] == 2),
+        "{:?}",
+        reports
+    );
+
+    // 有规则集下载失败（404）并且没有保存的规则文件：部分成功，仍然生成配置
+    let mut routes = rule_routes();
+    routes.remove("/rules/reject.list");
+    let server = MockServer::start(routes);
+    let ini = write_ini(&dir, &server);
+    let partial = execute(
+        &dir,
+        &[
+            "--json-errors",
+            "-c",
+            path_str(&ini),
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+        ],
+    );
+    assert_eq!(partial.status.code(), Some(4));
+    let reports = json_reports(&partial);
+    assert!(
+        reports.iter().any(|r| r["kind"] == "partial"
+            && r["source"]
+                .as_str()
+                .is_some_and(|s| s.ends_with("/rules/reject.list"))),
+        "{:?}",
+        reports
+    );
+    assert!(dir.join("out/output_snap_1.yaml").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn huge_intervals_are_rejected() {
+    let dir = temp_dir("interval");
+    for interval in ["99999999999999999d", "5124095576030431h1h"] {
+        let output = execute(&dir, &["--json-errors", "--interval", interval]);
+        assert_eq!(output.status.code(), Some(1), "{}", interval);
+        let reports = json_reports(&output);
+        let message = reports[0]["message"].as_str().unwrap();
+        assert!(message.contains("时间间隔太长"), "{}", message);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn has_cjk(text: &str) -> bool {
+    text.chars().any(|c| ('\u{4e00}'..='\u{9fff}').contains(&c))
+}
+
+#[test]
+fn messages_follow_lang_and_locale() {
+    let dir = temp_dir("lang");
+    let help = |args: &[&str], lang: &str| {
+        let output = command(&dir, args).env("LANG", lang).output().unwrap();
+        assert!(output.status.success(), "{:?}", args);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // 不是中文环境时默认为英文，包括每个子命令的帮助
+    let subcommands = [
+        "build",
+        "serve",
+        "lint",
+        "convert",
+        "split",
+        "bench",
+        "decrypt",
+        "completions",
+        "stats",
+    ];
+    let top = help(&["--help"], "C");
+    assert!(
+        top.contains("Output path of the generated clash config"),
+        "{}",
+        top
+    );
+    assert!(!has_cjk(&top), "{}", top);
+    for subcommand in subcommands {
+        let text = help(&[subcommand, "--help"], "en_US.UTF-8");
+        assert!(!has_cjk(&text), "{}", text);
+    }
+
+    // 中文环境默认为中文，--lang 优先于环境变量
+    assert!(help(&["--help"], "zh_CN.UTF-8").contains("生成的clash文件输出路径"));
+    assert!(!has_cjk(&help(&["--lang", "en", "--help"], "zh_CN.UTF-8")));
+    assert!(help(&["--lang=zh", "--help"], "C").contains("生成的clash文件输出路径"));
+
+    // 参数的错误信息也使用对应的语言
+    let output = command(
+        &dir,
+        &["--lang", "en", "--json-errors", "--rule-max-size", "0"],
+    )
+    .output()
+    .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let reports = json_reports(&output);
+    let message = reports[0]["message"].as_str().unwrap();
+    assert!(
+        message.contains("size must be greater than 0"),
+        "{}",
+        message
+    );
+
+    // 构建过程中的提示
+    let output = command(
+        &dir,
+        &[
+            "--preset",
+            "minimal",
+            "-b",
+            path_str(&fixtures_dir().join("base.yaml")),
+            "-f",
+            path_str(&fixtures_dir().join("proxies.yaml")),
+            "-s",
+            path_str(&dir.join("rules")),
+            "-o",
+            path_str(&dir.join("out/output.yaml")),
+        ],
+    )
+    .env("LANG", "C")
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("rules after deduplication: "), "{}", stdout);
+    assert!(stdout.contains("manifest written: "), "{}", stdout);
+    let _ = std::fs::remove_dir_all(&dir);
+}